@@ -196,7 +196,10 @@ Run the full build pipeline:
         }
         mkdirSync(resolve('crates/engine/src/generated'), { recursive: true });
         for (const fbs of fbsFiles) {
-          const args = ['--rust', '-o', 'crates/engine/src/generated/', `schema/${fbs}`];
+          // --gen-all: a schema that includes others (composite.fbs) gets
+          // their types in its own module; flatc can't import them from
+          // another one.
+          const args = ['--rust', '--gen-all', '-o', 'crates/engine/src/generated/', `schema/${fbs}`];
           console.log(dim(`    $ flatc ${args.join(' ')}`));
           execFileSync('flatc', args, { stdio: 'inherit' });
         }
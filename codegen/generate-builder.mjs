@@ -173,7 +173,7 @@ export function parseFbs(source) {
       } else {
         i++;
         while (i < lines.length) {
-          const sLine = lines[i].trim();
+          const sLine = lines[i].replace(/\/\/.*$/, '').trim();
           if (sLine.startsWith('}')) { i++; break; }
           const fMatch = sLine.match(/^(\w+)\s*:\s*([^;=]+?)(?:\s*=\s*([^;]+?))?\s*;?\s*$/);
           if (fMatch) {
@@ -204,7 +204,8 @@ export function parseFbs(source) {
       } else {
         i++;
         while (i < lines.length) {
-          const tLine = lines[i].trim();
+          // Trailing comments: `message: string;  // detail`
+          const tLine = lines[i].replace(/\/\/.*$/, '').trim();
           if (tLine.startsWith('}')) { i++; break; }
           // Skip comments
           if (tLine.startsWith('//') || tLine.startsWith('///') || tLine === '') {
@@ -649,9 +650,38 @@ export function generateHook(schema, options = {}) {
 
 // ─── Rust Handler Generator ──────────────────────────────────────────────
 
+/**
+ * Find the table a server answers commands with: `id`, `ok`, `code` (an
+ * enum with `None`, `Malformed` and `UnknownCommand`) and `message` — the
+ * shape of `CommandResponse` in commands.fbs.
+ *
+ * @param {ReturnType<typeof parseFbs>} schema
+ * @returns {{ table: object, errorEnum: string } | null}
+ */
+function findResponseTable(schema) {
+  const enumMap = new Map(schema.enums.map((e) => [e.name, e]));
+  for (const table of schema.tables) {
+    const field = (name) => table.fields.find((f) => f.name === name);
+    const code = field('code');
+    const codeEnum = code && enumMap.get(code.type);
+    if (
+      field('id') && field('ok')?.type === 'bool' && field('message')?.type === 'string' &&
+      codeEnum && ['None', 'Malformed', 'UnknownCommand'].every((m) => codeEnum.members.includes(m))
+    ) {
+      return { table, errorEnum: codeEnum.name };
+    }
+  }
+  return null;
+}
+
 /**
  * Generate Rust source for a CommandHandler trait + dispatch function.
  *
+ * When the schema has a response table (see `findResponseTable`), handlers
+ * return `{Union}Result` and dispatch answers every command with an encoded
+ * response — the shape of server/command-handler-template.rs. Otherwise
+ * handlers return the response bytes themselves (`Option<Vec<u8>>`).
+ *
  * @param {ReturnType<typeof parseFbs>} schema
  * @param {{ traitName?: string, cratePath?: string }} options
  * @returns {string}
@@ -670,85 +700,156 @@ export function generateRustHandler(schema, options = {}) {
   const traitName = options.traitName || `${union.name}Handler`;
   const snakeNs = namespace ? namespace.split('.').map(toSnakeCase).join('::') : '';
   const cratePath = options.cratePath || `crate::generated::${snakeNs.replace(/::/g, '_')}`;
+  const response = findResponseTable(schema);
+  const errorName = `${union.name}Error`;
+  const resultName = `${union.name}Result`;
+  const returnType = response ? resultName : 'Option<Vec<u8>>';
 
   // Find the id field on root table
   const idField = rootTable.fields.find((f) => f.name === 'id');
   const idRustType = idField ? (RUST_SCALAR_TYPES.get(idField.type) || 'u64') : 'u64';
 
+  // Handler parameters and dispatch arguments, per union member
+  const members = union.members.map((memberName) => {
+    const memberTable = tableMap.get(memberName);
+    const params = [`id: ${idRustType}`];
+    const callArgs = ['id'];
+    for (const field of memberTable ? memberTable.fields : []) {
+      const snakeFieldName = toSnakeCase(field.name);
+      if (field.type === 'string') {
+        params.push(`${snakeFieldName}: &str`);
+        callArgs.push(`cmd.${snakeFieldName}().unwrap_or("")`);
+      } else {
+        const rustType = RUST_SCALAR_TYPES.get(field.type);
+        if (rustType) {
+          params.push(`${snakeFieldName}: ${rustType}`);
+          callArgs.push(`cmd.${snakeFieldName}()`);
+        }
+      }
+    }
+    return {
+      name: memberName,
+      snakeName: toSnakeCase(memberName),
+      accessor: `${toSnakeCase(unionField.name)}_as_${toSnakeCase(memberName)}`,
+      params,
+      callArgs,
+      // Empty table — no cmd variable needed
+      readsCmd: callArgs.length > 1,
+    };
+  });
+
   const lines = [];
   lines.push(`//! Auto-generated by org-asm gen-handler — do not edit.`);
   lines.push('');
-  lines.push(`use flatbuffers;`);
+  if (response) {
+    lines.push(`use flatbuffers::FlatBufferBuilder;`);
+  } else {
+    lines.push(`use flatbuffers;`);
+  }
   lines.push(`use ${cratePath}::*;`);
   lines.push('');
 
+  // ── Result type ───────────────────────────────────────────────────────
+  if (response) {
+    lines.push(`/// File identifier every \`${response.table.name}\` is finished with.`);
+    lines.push(`pub const RESPONSE_IDENTIFIER: &str = "OARS";`);
+    lines.push('');
+    lines.push(`/// Why a command was rejected, sent back as \`${response.table.name}.code\` and \`.message\`.`);
+    lines.push(`#[derive(Debug, Clone)]`);
+    lines.push(`pub struct ${errorName} {`);
+    lines.push(`    pub code: ${response.errorEnum},`);
+    lines.push(`    pub message: String,`);
+    lines.push(`}`);
+    lines.push('');
+    lines.push(`impl ${errorName} {`);
+    lines.push(`    pub fn new(code: ${response.errorEnum}, message: impl Into<String>) -> Self {`);
+    lines.push(`        Self { code, message: message.into() }`);
+    lines.push(`    }`);
+    lines.push(`}`);
+    lines.push('');
+    lines.push(`pub type ${resultName} = Result<(), ${errorName}>;`);
+    lines.push('');
+  }
+
   // ── Trait ──────────────────────────────────────────────────────────────
   lines.push(`pub trait ${traitName} {`);
-
-  for (const memberName of union.members) {
-    const memberTable = tableMap.get(memberName);
-    const snakeName = toSnakeCase(memberName);
-
-    const params = [`id: ${idRustType}`];
-
-    if (memberTable && memberTable.fields.length > 0) {
-      for (const field of memberTable.fields) {
-        const snakeFieldName = toSnakeCase(field.name);
-        if (field.type === 'string') {
-          params.push(`${snakeFieldName}: &str`);
-        } else {
-          const rustType = RUST_SCALAR_TYPES.get(field.type);
-          if (rustType) {
-            params.push(`${snakeFieldName}: ${rustType}`);
-          }
-        }
-      }
-    }
-
-    lines.push(`    fn handle_${snakeName}(&mut self, ${params.join(', ')}) -> Option<Vec<u8>>;`);
+  for (const m of members) {
+    lines.push(`    fn handle_${m.snakeName}(&mut self, ${m.params.join(', ')}) -> ${returnType};`);
   }
-
   lines.push(`}`);
   lines.push('');
 
   // ── Dispatch function ─────────────────────────────────────────────────
-  lines.push(`pub fn dispatch_${toSnakeCase(union.name)}<H: ${traitName}>(bytes: &[u8], handler: &mut H) -> Option<Vec<u8>> {`);
-  lines.push(`    let msg = flatbuffers::root::<${schema.rootType}>(bytes).ok()?;`);
-  lines.push(`    let id = msg.id();`);
-
-  // Generate the union type accessor name: command_type for field named "command"
-  const unionFieldName = unionField.name;
-  lines.push(`    match msg.${toSnakeCase(unionFieldName)}_type() {`);
-
-  for (const memberName of union.members) {
-    const memberTable = tableMap.get(memberName);
-    const snakeName = toSnakeCase(memberName);
-    const accessorName = `${toSnakeCase(unionFieldName)}_as_${toSnakeCase(memberName)}`;
-
-    if (memberTable && memberTable.fields.length > 0) {
-      lines.push(`        ${union.name}::${memberName} => {`);
-      lines.push(`            let cmd = msg.${accessorName}()?;`);
-
-      const callArgs = ['id'];
-      for (const field of memberTable.fields) {
-        const snakeFieldName = toSnakeCase(field.name);
-        if (field.type === 'string') {
-          callArgs.push(`cmd.${snakeFieldName}().unwrap_or("")`);
-        } else {
-          callArgs.push(`cmd.${snakeFieldName}()`);
-        }
+  const dispatchName = `dispatch_${toSnakeCase(union.name)}`;
+  const unionTypeAccessor = `${toSnakeCase(unionField.name)}_type`;
+
+  if (response) {
+    lines.push(`pub fn ${dispatchName}<H: ${traitName}>(bytes: &[u8], handler: &mut H) -> Option<Vec<u8>> {`);
+    lines.push(`    ${dispatchName}_with(bytes, handler, |_, _| {})`);
+    lines.push(`}`);
+    lines.push('');
+    lines.push(`/// \`${dispatchName}\`, also reporting each command's type and result to \`observe\`.`);
+    lines.push(`pub fn ${dispatchName}_with<H: ${traitName}>(`);
+    lines.push(`    bytes: &[u8],`);
+    lines.push(`    handler: &mut H,`);
+    lines.push(`    observe: impl FnOnce(${union.name}, &${resultName}),`);
+    lines.push(`) -> Option<Vec<u8>> {`);
+    lines.push(`    let msg = flatbuffers::root::<${schema.rootType}>(bytes).ok()?;`);
+    lines.push(`    let id = msg.id();`);
+    lines.push(`    let malformed = || ${errorName}::new(${response.errorEnum}::Malformed, "missing command body");`);
+    lines.push(`    let result = match msg.${unionTypeAccessor}() {`);
+    for (const m of members) {
+      if (m.readsCmd) {
+        lines.push(`        ${union.name}::${m.name} => msg`);
+        lines.push(`            .${m.accessor}()`);
+        lines.push(`            .ok_or_else(malformed)`);
+        lines.push(`            .and_then(|cmd| handler.handle_${m.snakeName}(${m.callArgs.join(', ')})),`);
+      } else {
+        lines.push(`        ${union.name}::${m.name} => msg`);
+        lines.push(`            .${m.accessor}()`);
+        lines.push(`            .ok_or_else(malformed)`);
+        lines.push(`            .and_then(|_| handler.handle_${m.snakeName}(id)),`);
       }
-
-      lines.push(`            handler.handle_${snakeName}(${callArgs.join(', ')})`);
-      lines.push(`        }`);
-    } else {
-      // Empty table — no cmd variable needed
-      lines.push(`        ${union.name}::${memberName} => {`);
-      lines.push(`            handler.handle_${snakeName}(id)`);
-      lines.push(`        }`);
     }
+    lines.push(`        other => Err(${errorName}::new(`);
+    lines.push(`            ${response.errorEnum}::UnknownCommand,`);
+    lines.push(`            format!("unknown command type {}", other.0),`);
+    lines.push(`        )),`);
+    lines.push(`    };`);
+    lines.push(`    observe(msg.${unionTypeAccessor}(), &result);`);
+    lines.push(`    Some(encode_response(id, &result))`);
+    lines.push(`}`);
+    lines.push('');
+    lines.push(`/// Encode a \`${response.table.name}\` for command \`id\`.`);
+    lines.push(`pub fn encode_response(id: ${idRustType}, result: &${resultName}) -> Vec<u8> {`);
+    lines.push(`    let mut builder = FlatBufferBuilder::with_capacity(128);`);
+    lines.push(`    let (code, message) = match result {`);
+    lines.push(`        Ok(()) => (${response.errorEnum}::None, None),`);
+    lines.push(`        Err(e) => (e.code, Some(builder.create_string(&e.message))),`);
+    lines.push(`    };`);
+    lines.push(`    let response = ${response.table.name}::create(`);
+    lines.push(`        &mut builder,`);
+    lines.push(`        &${response.table.name}Args { id, ok: result.is_ok(), code, message },`);
+    lines.push(`    );`);
+    lines.push(`    builder.finish(response, Some(RESPONSE_IDENTIFIER));`);
+    lines.push(`    builder.finished_data().to_vec()`);
+    lines.push(`}`);
+    lines.push('');
+    return lines.join('\n');
   }
 
+  lines.push(`pub fn ${dispatchName}<H: ${traitName}>(bytes: &[u8], handler: &mut H) -> Option<Vec<u8>> {`);
+  lines.push(`    let msg = flatbuffers::root::<${schema.rootType}>(bytes).ok()?;`);
+  lines.push(`    let id = msg.id();`);
+  lines.push(`    match msg.${unionTypeAccessor}() {`);
+  for (const m of members) {
+    lines.push(`        ${union.name}::${m.name} => {`);
+    if (m.readsCmd) {
+      lines.push(`            let cmd = msg.${m.accessor}()?;`);
+    }
+    lines.push(`            handler.handle_${m.snakeName}(${m.callArgs.join(', ')})`);
+    lines.push(`        }`);
+  }
   lines.push(`        _ => None,`);
   lines.push(`    }`);
   lines.push(`}`);
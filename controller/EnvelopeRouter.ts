@@ -0,0 +1,157 @@
+/**
+ * EnvelopeRouter — dispatch server messages by kind and topic.
+ *
+ * The server wraps every binary message in an `Envelope` (schema/envelope.fbs):
+ * engine frames, snapshots and command responses share one socket, and the
+ * envelope says which is which and which topic a frame belongs to. The router
+ * reads that header — without parsing the payload — and hands the message to
+ * the right handler, so one connection can feed an orderbook engine, a trade
+ * engine and the ResponseRegistry.
+ *
+ * Topic ids are `topicId(topic)`, the same FNV-1a hash the server computes
+ * (`my_shared::topic_id`), so no id mapping is exchanged. Orderbook topics
+ * carry the subscribed depth: `depthTopic('BTC-USD', 20)` is 'BTC-USD@20'.
+ *
+ * Usage:
+ *   const router = new EnvelopeRouter()
+ *     .onTopic(depthTopic('BTC-USD', 20), (data) => bookParser.ingestFrame(data))
+ *     .onTopic('BTC-USD.trades', (data) => tradeParser.ingestFrame(data))
+ *     .onResponse((payload) => registry.handleMessage(payload));
+ *   ws.onBinaryMessage((data) => router.handleMessage(data));
+ *
+ * Topic handlers receive the whole message; the WASM engine's
+ * `ingest_frame()` reads the envelope and parses the payload itself.
+ * Messages the router can't read (compressed envelopes, see
+ * server/compression.rs) or whose topic has no handler go to `onUnrouted`.
+ */
+
+import { ByteBuffer } from 'flatbuffers';
+
+/** `Envelope.kind` values (schema/envelope.fbs). */
+export const FrameKind = {
+  Unknown: 0,
+  Frame: 1,
+  Snapshot: 2,
+  Response: 3,
+} as const;
+export type FrameKind = (typeof FrameKind)[keyof typeof FrameKind];
+
+/** File identifier every envelope is finished with. */
+export const ENVELOPE_IDENTIFIER = 'OAEN';
+
+/** A decoded envelope. `payload` is a view into the message, not a copy. */
+export interface Envelope {
+  kind: number;
+  topicId: number;
+  sequence: bigint;
+  schemaVersion: number;
+  delta: boolean;
+  payload: Uint8Array;
+}
+
+export type TopicHandler = (data: ArrayBuffer, envelope: Envelope) => void;
+
+const encoder = new TextEncoder();
+
+/** Id of a topic in `Envelope.topicId`: 32-bit FNV-1a of its UTF-8 name, never 0. */
+export function topicId(topic: string): number {
+  let hash = 0x811c9dc5;
+  for (const byte of encoder.encode(topic)) {
+    hash = Math.imul(hash ^ byte, 0x01000193) >>> 0;
+  }
+  return hash === 0 ? 1 : hash;
+}
+
+/**
+ * Topic of a symbol's orderbook at one depth, as the server's command
+ * handler subscribes it (`my_shared::depth_topic`). Depth 0 is the bare
+ * symbol, the topic of engines without levels (candles).
+ */
+export function depthTopic(symbol: string, depth: number): string {
+  return depth === 0 ? symbol : `${symbol}@${depth}`;
+}
+
+/** Decode an envelope, or return null if `data` isn't one. */
+export function readEnvelope(data: ArrayBuffer | Uint8Array): Envelope | null {
+  const bytes = data instanceof Uint8Array ? data : new Uint8Array(data);
+  if (bytes.length < 8) return null;
+  const bb = new ByteBuffer(bytes);
+  if (!bb.__has_identifier(ENVELOPE_IDENTIFIER)) return null;
+
+  const table = bb.readInt32(bb.position()) + bb.position();
+  // Vtable slots in schema field order: kind, topic_id, sequence,
+  // schema_version, delta, payload
+  const read = <T>(slot: number, get: (at: number) => T, fallback: T): T => {
+    const offset = bb.__offset(table, 4 + 2 * slot);
+    return offset ? get(table + offset) : fallback;
+  };
+
+  return {
+    kind: read(0, (at) => bb.readUint8(at), FrameKind.Unknown),
+    topicId: read(1, (at) => bb.readUint32(at), 0),
+    sequence: read(2, (at) => bb.readUint64(at), 0n),
+    schemaVersion: read(3, (at) => bb.readUint32(at), 0),
+    delta: read(4, (at) => bb.readUint8(at) !== 0, false),
+    payload: read(
+      5,
+      (at) => {
+        const start = bb.__vector(at);
+        return bytes.subarray(start, start + bb.__vector_len(at));
+      },
+      new Uint8Array(0),
+    ),
+  };
+}
+
+export class EnvelopeRouter {
+  private topics = new Map<number, TopicHandler>();
+  private responseHandler: ((payload: ArrayBuffer) => void) | null = null;
+  private unroutedHandler: ((data: ArrayBuffer) => void) | null = null;
+
+  /** Route frames and snapshots for `topic` to `handler`. Replaces any previous handler. */
+  onTopic(topic: string, handler: TopicHandler): this {
+    this.topics.set(topicId(topic), handler);
+    return this;
+  }
+
+  /** Stop routing `topic` (e.g. after unsubscribing). */
+  offTopic(topic: string): void {
+    this.topics.delete(topicId(topic));
+  }
+
+  /**
+   * Receive command responses. The handler gets a copy of the payload (the
+   * CommandResponse FlatBuffer), ready for `ResponseRegistry.handleMessage()`.
+   */
+  onResponse(handler: (payload: ArrayBuffer) => void): this {
+    this.responseHandler = handler;
+    return this;
+  }
+
+  /** Receive messages that aren't readable envelopes or have no handler. */
+  onUnrouted(handler: (data: ArrayBuffer) => void): this {
+    this.unroutedHandler = handler;
+    return this;
+  }
+
+  /** Dispatch one binary message. Returns true if a handler received it. */
+  handleMessage(data: ArrayBuffer): boolean {
+    const envelope = readEnvelope(data);
+    if (envelope) {
+      if (envelope.kind === FrameKind.Response && this.responseHandler) {
+        this.responseHandler(envelope.payload.slice().buffer);
+        return true;
+      }
+      const handler = this.topics.get(envelope.topicId);
+      if (handler && (envelope.kind === FrameKind.Frame || envelope.kind === FrameKind.Snapshot)) {
+        handler(data, envelope);
+        return true;
+      }
+    }
+    if (this.unroutedHandler) {
+      this.unroutedHandler(data);
+      return true;
+    }
+    return false;
+  }
+}
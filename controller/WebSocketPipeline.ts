@@ -10,6 +10,10 @@
  *
  * This decouples the connection lifecycle from data processing.
  * The same pipeline works for any WebSocket data source.
+ *
+ * A server shutting down gracefully closes with 1012 (Service Restart) and
+ * reason `reconnect-after=<ms>`; the pipeline then waits that long instead
+ * of its backoff delay, and the restart doesn't count as a failed attempt.
  */
 
 import { ConnectionState } from '../core/types';
@@ -391,7 +395,7 @@ export class WebSocketPipeline implements IConnectionPipeline {
       }
     };
 
-    ws.onclose = () => {
+    ws.onclose = (event) => {
       // Skip if this is an old ws that we intentionally abandoned (e.g., during StrictMode unmount)
       if (this.ws !== ws) return;
       this.stopBackpressureLoop();
@@ -403,6 +407,14 @@ export class WebSocketPipeline implements IConnectionPipeline {
         return;
       }
 
+      // This build speaks a different schema than the server; reconnecting
+      // would be refused the same way
+      if (event.code === SCHEMA_MISMATCH) {
+        this.emitError('schema_mismatch', event.reason || 'Schema mismatch');
+        this.setState(ConnectionState.Disconnected);
+        return;
+      }
+
       // Determine error type based on whether we ever connected
       if (this._state === ConnectionState.Connected || this._state === ConnectionState.Reconnecting) {
         this.emitError('connection_lost', `Connection lost (attempt ${this.reconnectAttempts})`);
@@ -412,8 +424,9 @@ export class WebSocketPipeline implements IConnectionPipeline {
 
       if (this.reconnectAttempts < this.config.maxReconnectAttempts) {
         this.setState(ConnectionState.Reconnecting);
-        const delay = this.computeReconnectDelay();
-        this.reconnectAttempts++;
+        const hinted = reconnectAfterHint(event);
+        const delay = hinted ?? this.computeReconnectDelay();
+        if (hinted === null) this.reconnectAttempts++;
         this.reconnectTimeout = setTimeout(() => {
           this.createConnection();
         }, delay);
@@ -431,3 +444,16 @@ export class WebSocketPipeline implements IConnectionPipeline {
     };
   }
 }
+
+/** Close code a server uses when it restarts (RFC 6455 registry). */
+const SERVICE_RESTART = 1012;
+
+/** Close code for a client whose `schemas` don't match the server's (my_shared::schema). */
+const SCHEMA_MISMATCH = 4001;
+
+/** Delay requested by a restarting server's `reconnect-after=<ms>` close reason. */
+function reconnectAfterHint(event: CloseEvent): number | null {
+  if (event.code !== SERVICE_RESTART) return null;
+  const match = /^reconnect-after=(\d+)$/.exec(event.reason);
+  return match ? Number(match[1]) : null;
+}
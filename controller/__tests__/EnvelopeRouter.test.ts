@@ -0,0 +1,147 @@
+import { Builder } from 'flatbuffers';
+import {
+  EnvelopeRouter,
+  FrameKind,
+  ENVELOPE_IDENTIFIER,
+  depthTopic,
+  readEnvelope,
+  topicId,
+} from '../EnvelopeRouter';
+
+interface EnvelopeFields {
+  kind?: number;
+  topicId?: number;
+  sequence?: bigint;
+  schemaVersion?: number;
+  delta?: boolean;
+  payload?: number[];
+}
+
+/** Build an Envelope the way flatc's generated TS (and the server) lays it out. */
+function buildEnvelope(fields: EnvelopeFields, identifier = ENVELOPE_IDENTIFIER): ArrayBuffer {
+  const builder = new Builder(64);
+  const payload = fields.payload ? builder.createByteVector(new Uint8Array(fields.payload)) : 0;
+  builder.startObject(6);
+  builder.addFieldInt8(0, fields.kind ?? 0, 0);
+  builder.addFieldInt32(1, fields.topicId ?? 0, 0);
+  builder.addFieldInt64(2, fields.sequence ?? 0n, 0n);
+  builder.addFieldInt32(3, fields.schemaVersion ?? 0, 0);
+  builder.addFieldInt8(4, +(fields.delta ?? false), +false);
+  if (payload) builder.addFieldOffset(5, payload, 0);
+  builder.finish(builder.endObject(), identifier);
+  return builder.asUint8Array().slice().buffer;
+}
+
+describe('topicId', () => {
+  it('matches the FNV-1a reference values', () => {
+    expect(topicId('')).toBe(0x811c9dc5);
+    expect(topicId('a')).toBe(0xe40c292c);
+  });
+
+  it('matches my_shared::topic_id for BTC-USD', () => {
+    expect(topicId('BTC-USD')).toBe(0x76ed0931);
+  });
+
+  it('names depth topics like my_shared::depth_topic', () => {
+    expect(depthTopic('BTC-USD', 20)).toBe('BTC-USD@20');
+    expect(topicId(depthTopic('BTC-USD', 20))).not.toBe(topicId(depthTopic('BTC-USD', 5)));
+    expect(depthTopic('BTC-USD', 0)).toBe('BTC-USD');
+  });
+});
+
+describe('readEnvelope', () => {
+  it('reads every field', () => {
+    const data = buildEnvelope({
+      kind: FrameKind.Frame,
+      topicId: topicId('BTC-USD'),
+      sequence: 2n ** 40n + 7n,
+      schemaVersion: 0xdeadbeef,
+      delta: true,
+      payload: [1, 2, 3],
+    });
+
+    const envelope = readEnvelope(data);
+    expect(envelope).not.toBeNull();
+    expect(envelope!.kind).toBe(FrameKind.Frame);
+    expect(envelope!.topicId).toBe(0x76ed0931);
+    expect(envelope!.sequence).toBe(2n ** 40n + 7n);
+    expect(envelope!.schemaVersion).toBe(0xdeadbeef);
+    expect(envelope!.delta).toBe(true);
+    expect(Array.from(envelope!.payload)).toEqual([1, 2, 3]);
+  });
+
+  it('falls back to schema defaults for absent fields', () => {
+    const envelope = readEnvelope(buildEnvelope({}));
+    expect(envelope).toEqual({
+      kind: FrameKind.Unknown,
+      topicId: 0,
+      sequence: 0n,
+      schemaVersion: 0,
+      delta: false,
+      payload: new Uint8Array(0),
+    });
+  });
+
+  it('returns the payload as a view into the message', () => {
+    const data = new Uint8Array(buildEnvelope({ kind: FrameKind.Snapshot, payload: [9, 8] }));
+    const envelope = readEnvelope(data)!;
+    expect(envelope.payload.buffer).toBe(data.buffer);
+  });
+
+  it('rejects messages without the envelope identifier', () => {
+    expect(readEnvelope(buildEnvelope({ kind: FrameKind.Frame }, 'OARS'))).toBeNull();
+    expect(readEnvelope(new Uint8Array([1, 2, 3]))).toBeNull();
+  });
+});
+
+describe('EnvelopeRouter', () => {
+  const btc = topicId('BTC-USD');
+
+  it('routes frames and snapshots by topic', () => {
+    const book = vi.fn();
+    const trades = vi.fn();
+    const router = new EnvelopeRouter()
+      .onTopic('BTC-USD', book)
+      .onTopic('BTC-USD.trades', trades);
+
+    const frame = buildEnvelope({ kind: FrameKind.Frame, topicId: btc, sequence: 1n });
+    const snapshot = buildEnvelope({ kind: FrameKind.Snapshot, topicId: btc, sequence: 2n });
+    expect(router.handleMessage(frame)).toBe(true);
+    expect(router.handleMessage(snapshot)).toBe(true);
+
+    expect(trades).not.toHaveBeenCalled();
+    expect(book).toHaveBeenCalledTimes(2);
+    expect(book.mock.calls[0][0]).toBe(frame);
+    expect(book.mock.calls[0][1].sequence).toBe(1n);
+    expect(book.mock.calls[1][1].kind).toBe(FrameKind.Snapshot);
+  });
+
+  it('hands responses a copy of the payload', () => {
+    const onResponse = vi.fn();
+    const router = new EnvelopeRouter().onResponse(onResponse);
+
+    expect(router.handleMessage(buildEnvelope({ kind: FrameKind.Response, payload: [4, 5, 6] }))).toBe(true);
+    const payload: ArrayBuffer = onResponse.mock.calls[0][0];
+    expect(Array.from(new Uint8Array(payload))).toEqual([4, 5, 6]);
+  });
+
+  it('sends unknown topics, unhandled kinds and non-envelopes to onUnrouted', () => {
+    const book = vi.fn();
+    const unrouted = vi.fn();
+    const router = new EnvelopeRouter().onTopic('BTC-USD', book).onUnrouted(unrouted);
+
+    router.handleMessage(buildEnvelope({ kind: FrameKind.Frame, topicId: topicId('ETH-USD') }));
+    router.handleMessage(buildEnvelope({ kind: FrameKind.Unknown, topicId: btc }));
+    router.handleMessage(buildEnvelope({ kind: FrameKind.Response }));
+    router.handleMessage(new Uint8Array([0, 1, 2, 3, 4, 5, 6, 7, 8]).buffer);
+
+    expect(book).not.toHaveBeenCalled();
+    expect(unrouted).toHaveBeenCalledTimes(4);
+  });
+
+  it('returns false when nothing handles a message', () => {
+    const router = new EnvelopeRouter().onTopic('BTC-USD', vi.fn());
+    router.offTopic('BTC-USD');
+    expect(router.handleMessage(buildEnvelope({ kind: FrameKind.Frame, topicId: btc }))).toBe(false);
+  });
+});
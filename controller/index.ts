@@ -32,6 +32,8 @@ export { MessageParser, WasmIngestParser, BinaryFrameParser } from './MessagePar
 export type { EngineDataTarget } from './MessageParser';
 export { CommandBuilder, CommandSender } from './CommandSender';
 export { ResponseRegistry } from './ResponseRegistry';
+export { EnvelopeRouter, FrameKind, ENVELOPE_IDENTIFIER, depthTopic, readEnvelope, topicId } from './EnvelopeRouter';
+export type { Envelope, TopicHandler } from './EnvelopeRouter';
 export { SubscriptionManager } from './SubscriptionManager';
 export type { BinaryMiddleware } from './WebSocketPipeline';
 
@@ -93,6 +93,12 @@ export class FrameBufferFactory {
   }
 }
 
+/**
+ * Change mask with every field set: reported for engines whose tick()
+ * returns no mask, so consumers treat every frame as fully changed.
+ */
+export const ALL_FIELDS_CHANGED = 0xffffffff;
+
 /**
  * Create a tick adapter that reads a FlatBuffer frame from WASM linear memory.
  *
@@ -103,6 +109,12 @@ export class FrameBufferFactory {
  *
  * Plugs directly into AnimationLoop<F> which expects { tick(nowMs): F }.
  *
+ * If the engine's tick() returns a change mask (bit i set: frame field i
+ * changed), the adapter exposes it as changeMask, and AnimationLoop hands it
+ * to consumers. A mask of 0 means the engine skipped the builder: the
+ * previous frame object is returned as is, without re-reading the bytes.
+ * Engines whose tick() returns nothing report ALL_FIELDS_CHANGED.
+ *
  * Usage:
  *   import { Frame } from './generated/frame';
  *   import { ByteBuffer } from 'flatbuffers';
@@ -115,15 +127,28 @@ export class FrameBufferFactory {
  *   effects.bindCSSProperty('root', '--glow', f => f.valueA());
  */
 export function flatBufferTickAdapter<F>(
-  engine: { tick(nowMs: number): void; frame_ptr(): number; frame_len(): number },
+  engine: { tick(nowMs: number): number | void; frame_ptr(): number; frame_len(): number },
   memory: WebAssembly.Memory,
   rootFn: (bytes: Uint8Array) => F,
-): { tick(nowMs: number): F } {
+): { tick(nowMs: number): F; readonly changeMask: number } {
+  let frame: F | undefined;
+  let buffer: ArrayBuffer | null = null;
+  let changeMask = ALL_FIELDS_CHANGED;
   return {
     tick(nowMs: number): F {
-      engine.tick(nowMs);
-      const bytes = new Uint8Array(memory.buffer, engine.frame_ptr(), engine.frame_len());
-      return rootFn(bytes);
+      const mask = engine.tick(nowMs);
+      changeMask = typeof mask === 'number' ? mask : ALL_FIELDS_CHANGED;
+      // Unchanged frames are still in the builder, but a view into a
+      // detached buffer (memory grew since) must be recreated.
+      if (changeMask === 0 && frame !== undefined && memory.buffer === buffer) {
+        return frame;
+      }
+      buffer = memory.buffer;
+      frame = rootFn(new Uint8Array(buffer, engine.frame_ptr(), engine.frame_len()));
+      return frame;
+    },
+    get changeMask(): number {
+      return changeMask;
     },
   };
 }
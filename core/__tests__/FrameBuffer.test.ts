@@ -0,0 +1,87 @@
+import { ALL_FIELDS_CHANGED, flatBufferTickAdapter } from '../FrameBuffer';
+
+interface TestFrame {
+  bytes: Uint8Array;
+}
+
+function setup(masks: (number | undefined)[]) {
+  const memory = new WebAssembly.Memory({ initial: 1 });
+  let nextMask = 0;
+  const engine = {
+    tick: vi.fn((): number | void => masks[nextMask++]),
+    frame_ptr: () => 16,
+    frame_len: () => 8,
+  };
+  const rootFn = vi.fn((bytes: Uint8Array): TestFrame => ({ bytes }));
+  const adapter = flatBufferTickAdapter(engine, memory, rootFn);
+  return { memory, engine, rootFn, adapter };
+}
+
+describe('flatBufferTickAdapter', () => {
+  it('reads the frame from the engine buffer in linear memory', () => {
+    const { memory, engine, rootFn, adapter } = setup([0b101]);
+
+    const frame = adapter.tick(16.7);
+    expect(engine.tick).toHaveBeenCalledWith(16.7);
+    expect(rootFn).toHaveBeenCalledTimes(1);
+    expect(frame.bytes.buffer).toBe(memory.buffer);
+    expect(frame.bytes.byteOffset).toBe(16);
+    expect(frame.bytes.length).toBe(8);
+    expect(adapter.changeMask).toBe(0b101);
+  });
+
+  it('reuses the cached frame when the mask is 0', () => {
+    const { rootFn, adapter } = setup([0b1, 0, 0]);
+
+    const first = adapter.tick(0);
+    expect(adapter.tick(16)).toBe(first);
+    expect(adapter.tick(32)).toBe(first);
+    expect(adapter.changeMask).toBe(0);
+    expect(rootFn).toHaveBeenCalledTimes(1);
+  });
+
+  it('reads the first frame even if its mask is 0', () => {
+    const { rootFn, adapter } = setup([0]);
+
+    expect(adapter.tick(0).bytes.length).toBe(8);
+    expect(rootFn).toHaveBeenCalledTimes(1);
+  });
+
+  it('re-reads the frame on every non-zero mask', () => {
+    const { rootFn, adapter } = setup([0b1, 0b10]);
+
+    const first = adapter.tick(0);
+    const second = adapter.tick(16);
+    expect(second).not.toBe(first);
+    expect(adapter.changeMask).toBe(0b10);
+    expect(rootFn).toHaveBeenCalledTimes(2);
+  });
+
+  it('re-reads an unchanged frame after memory.buffer changes', () => {
+    const { memory, rootFn, adapter } = setup([0b1, 0, 0]);
+
+    const first = adapter.tick(0);
+    const oldBuffer = memory.buffer;
+    memory.grow(1);
+    expect(memory.buffer).not.toBe(oldBuffer);
+
+    const second = adapter.tick(16);
+    expect(second).not.toBe(first);
+    expect(second.bytes.buffer).toBe(memory.buffer);
+    expect(rootFn).toHaveBeenCalledTimes(2);
+
+    // The new view is cached again
+    expect(adapter.tick(32)).toBe(second);
+    expect(rootFn).toHaveBeenCalledTimes(2);
+  });
+
+  it('falls back to ALL_FIELDS_CHANGED for engines that return no mask', () => {
+    const { rootFn, adapter } = setup([undefined, undefined]);
+
+    expect(adapter.changeMask).toBe(ALL_FIELDS_CHANGED);
+    const first = adapter.tick(0);
+    expect(adapter.changeMask).toBe(ALL_FIELDS_CHANGED);
+    expect(adapter.tick(16)).not.toBe(first);
+    expect(rootFn).toHaveBeenCalledTimes(2);
+  });
+});
@@ -1,3 +1,3 @@
 export * from './types';
 export * from './interfaces';
-export { FrameBufferFactory, flatBufferTickAdapter } from './FrameBuffer';
+export { FrameBufferFactory, flatBufferTickAdapter, ALL_FIELDS_CHANGED } from './FrameBuffer';
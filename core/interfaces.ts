@@ -73,8 +73,15 @@ export interface IAnimationLoop<F = Float64Array> {
  * Heavy work should be done in the WASM engine, not here.
  */
 export interface IFrameConsumer<F = Float64Array> {
-  /** Process a new frame. Called at 60fps. Must be fast. */
-  onFrame(frame: F, nowMs: number): void;
+  /**
+   * Process a new frame. Called at 60fps. Must be fast.
+   *
+   * `changed` is the engine's change mask for this frame: bit i is set if
+   * field i moved since the previous frame. 0 means the frame is the same
+   * object as last time. ALL_FIELDS_CHANGED when the engine doesn't track
+   * changes.
+   */
+  onFrame(frame: F, nowMs: number, changed?: number): void;
 
   /** Priority for ordering (lower = earlier). Default 0. */
   readonly priority: number;
@@ -243,10 +250,15 @@ export interface IWasmIngestEngine {
  *
  * Pair with `BinaryFrameParser` from the controller to wire into WebSocketPipeline.
  *
+ * Server messages arrive wrapped in an `Envelope` (schema/envelope.fbs);
+ * the engine parses the payload with its own frame schema.
+ *
  * Implementation in Rust:
  *   #[wasm_bindgen]
  *   pub fn ingest_frame(&mut self, bytes: &[u8]) {
- *       let frame = flatbuffers::root::<OrderbookFrame>(bytes).unwrap();
+ *       let envelope = flatbuffers::root::<Envelope>(bytes).unwrap();
+ *       let payload = envelope.payload().unwrap().bytes();
+ *       let frame = flatbuffers::root::<OrderbookFrame>(payload).unwrap();
  *       self.best_bid = frame.best_bid();
  *       self.data_version += 1;
  *   }
@@ -113,7 +113,7 @@ export type WasmResult<T> = WasmOk<T> | WasmErr;
 
 /** Structured connection error surfaced via onError handler */
 export interface ConnectionError {
-  readonly type: 'connect_failed' | 'connection_lost' | 'max_retries_exhausted';
+  readonly type: 'connect_failed' | 'connection_lost' | 'max_retries_exhausted' | 'schema_mismatch';
   readonly message: string;
   readonly attempt: number;
   readonly timestamp: number;
@@ -0,0 +1,312 @@
+//! # Composite Engine Template (FlatBuffers)
+//!
+//! One WASM engine that owns several engines — here a price engine, a volume
+//! engine and an orderbook — and writes all their frames into one FlatBuffer
+//! per tick.
+//!
+//! ## Why?
+//! Driving N engines from JS costs N `tick()` calls, N `frame_ptr()` /
+//! `frame_len()` pairs and N frame reads per animation frame, and the
+//! boundary crossings are the cost this framework exists to remove. A
+//! composite makes it one call and one frame, however many engines it owns:
+//!
+//!   - JS: `composite.tick(now)` → `CompositeFrame` with `price()`,
+//!     `volume()` and `orderbook()`, each the sub-engine's own frame table
+//!   - Rust: each sub-engine's `update()` advances it and reports what
+//!     changed; `write_frame()` writes its table into the composite's
+//!     builder; one `finish()` for the lot
+//!
+//! Sub-engines stay ordinary engines: the same `Engine` (engine-template.rs)
+//! works standalone with its own `tick()`, or as a member here.
+//!
+//! ## How to use this template
+//! 1. Define the combined frame in `schema/composite.fbs` — one member per
+//!    sub-engine, typed with that engine's frame table
+//! 2. Generate code with `--gen-all` (see the schema) and import the
+//!    sub-engines' frame types from `composite_generated` as well, so
+//!    `Engine::write_frame()` returns the type `CompositeFrameArgs` expects
+//! 3. Give each sub-engine `update()` and `write_frame()` (engine-template.rs
+//!    has both; its `tick()` is built from them)
+//! 4. Copy this file (e.g. `src/composite.rs`), declare `mod composite;`, and
+//!    replace the members with your engines
+//! 5. In JS, tick the composite only:
+//!    `flatBufferTickAdapter(composite, memory, bytes =>
+//!    CompositeFrame.getRootAsCompositeFrame(new ByteBuffer(bytes)))`
+
+use wasm_bindgen::prelude::*;
+use flatbuffers::FlatBufferBuilder;
+use my_shared::orderbook::{BookStats, BookUpdate, Level, OrderBook};
+
+use crate::engine::Engine;
+
+// Import generated FlatBuffer types from your schema.
+// use crate::generated::composite_generated::org_asm::*;
+// use crate::generated::composite_generated::org_asm::orderbook::{OrderbookFrame, OrderbookFrameArgs, PriceLevel};
+// use flatbuffers::WIPOffset;
+
+/// Orderbook levels per side in each frame.
+const DEFAULT_DEPTH: usize = 20;
+
+/// Members of the composite frame, numbered by their bit in the mask
+/// tick() returns: member p changed if `mask & (1 << p)`. Bind effects with
+/// `dependsOn(CompositePart.Price)`; part_mask() has the member's own
+/// field mask for finer checks.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum CompositePart {
+    Price = 0,
+    Volume = 1,
+    Orderbook = 2,
+}
+
+const PARTS: usize = 3;
+
+// ============================================
+// The composite engine
+//
+// Owns its sub-engines outright: JS never holds them, so every call goes
+// through the composite and the sub-engines can't be ticked twice. Methods
+// JS needs from a sub-engine (setters, series pointers) are forwarded one
+// line each.
+// ============================================
+
+#[wasm_bindgen]
+pub struct CompositeEngine {
+    // --- Sub-engines ---
+    price: Engine,
+    volume: Engine,
+
+    // --- Orderbook ---
+    // No animation to run: the book changes only when a frame arrives, so
+    // it lives here as data. The top `depth` levels and their stats are
+    // refreshed when it changes, so tick() writes them without walking
+    // the book.
+    book: OrderBook,
+    depth: usize,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+    stats: BookStats,
+    book_sequence: u64,
+    book_timestamp_ms: u64,
+    book_changed: bool,
+
+    // Field masks of the last tick, per CompositePart.
+    masks: [u32; PARTS],
+
+    // One builder for the combined frame. The sub-engines' own builders
+    // stay empty.
+    builder: FlatBufferBuilder<'static>,
+}
+
+#[wasm_bindgen]
+impl CompositeEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CompositeEngine {
+        CompositeEngine {
+            price: Engine::new(),
+            volume: Engine::new(),
+            book: OrderBook::new(),
+            depth: DEFAULT_DEPTH,
+            bids: Vec::with_capacity(DEFAULT_DEPTH),
+            asks: Vec::with_capacity(DEFAULT_DEPTH),
+            stats: BookStats::default(),
+            book_sequence: 0,
+            book_timestamp_ms: 0,
+            book_changed: true,
+            masks: [0; PARTS],
+            builder: FlatBufferBuilder::with_capacity(1024),
+        }
+    }
+
+    // --- Data input, routed to the sub-engine that owns it ---
+
+    #[wasm_bindgen]
+    pub fn add_trade(&mut self, price: f64, volume: f64, timestamp_sec: f64, now_ms: f64) {
+        self.price.add_trade(price, volume, timestamp_sec, now_ms);
+        self.volume.add_data_point(volume, timestamp_sec, now_ms);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.refresh_book();
+    }
+
+    // Orderbook frames from the server, unwrapped from their Envelope the
+    // way Engine::ingest_frame() does it. Returns false when the book
+    // needs a snapshot: send RequestSnapshot; deltas are buffered until it
+    // arrives.
+    //
+    // #[wasm_bindgen]
+    // pub fn ingest_orderbook(&mut self, payload: &[u8]) -> bool {
+    //     let Ok(frame) = flatbuffers::root::<OrderbookFrame>(payload) else { return false };
+    //     let levels = |v: Option<flatbuffers::Vector<PriceLevel>>| {
+    //         v.map(|v| v.iter().map(|l| Level::new(l.price(), l.size_())).collect()).unwrap_or_default()
+    //     };
+    //     let update = BookUpdate::new(frame.sequence(), levels(frame.bids()), levels(frame.asks()));
+    //     self.apply_book(!frame.is_delta(), update, frame.timestamp_ms())
+    // }
+
+    // --- Forwarded sub-engine methods ---
+
+    #[wasm_bindgen]
+    pub fn set_price_config_a(&mut self, v: f64) {
+        self.price.set_config_a(v);
+    }
+
+    #[wasm_bindgen]
+    pub fn price_data_version(&self) -> u32 {
+        self.price.data_version()
+    }
+
+    #[wasm_bindgen]
+    pub fn price_timestamps_ptr(&self) -> *const f64 {
+        self.price.timestamps_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn price_values_ptr(&self) -> *const f64 {
+        self.price.values_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn price_len(&self) -> usize {
+        self.price.values_len()
+    }
+
+    // ========================================
+    // tick(): every sub-engine, one frame
+    //
+    // Same contract as Engine::tick(): returns a change mask, 0 when
+    // nothing changed — then the builder isn't touched and JS keeps the
+    // previous frame. Here the mask has one bit per CompositePart.
+    //
+    // When any part changed, every part is written: a FlatBuffer can't
+    // patch one table in place. The unchanged parts' bits stay clear, so
+    // JS still skips their DOM writes.
+    // ========================================
+    #[wasm_bindgen]
+    pub fn tick(&mut self, now_ms: f64) -> u32 {
+        // 1. Advance every sub-engine, whether or not it will be written
+        self.masks = [
+            self.price.update(now_ms),
+            self.volume.update(now_ms),
+            u32::from(std::mem::take(&mut self.book_changed)),
+        ];
+        let changed = self
+            .masks
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (part, &fields)| if fields != 0 { mask | 1 << part } else { mask });
+        if changed == 0 {
+            return 0;
+        }
+
+        // 2. Write each part's table, then the composite table referencing
+        //    them. Sub-tables must be finished before the table that holds
+        //    them is started, so they come first.
+        self.builder.reset();
+        // let price = self.price.write_frame(&mut self.builder);
+        // let volume = self.volume.write_frame(&mut self.builder);
+        // let orderbook = self.write_orderbook();
+        // let frame = CompositeFrame::create(&mut self.builder, &CompositeFrameArgs {
+        //     price: Some(price),
+        //     volume: Some(volume),
+        //     orderbook: Some(orderbook),
+        // });
+        // // file_identifier of schema/composite.fbs, for bufferHasIdentifier()
+        // self.builder.finish(frame, Some("OACP"));
+
+        changed
+    }
+
+    /// Field mask of one part from the last tick(): the sub-engine's own
+    /// change mask (its FrameField bits). The orderbook reports 1 when it
+    /// changed.
+    #[wasm_bindgen]
+    pub fn part_mask(&self, part: CompositePart) -> u32 {
+        self.masks[part as usize]
+    }
+
+    /// Report every part as changed on the next tick().
+    #[wasm_bindgen]
+    pub fn invalidate_frame(&mut self) {
+        self.price.invalidate_frame();
+        self.volume.invalidate_frame();
+        self.book_changed = true;
+    }
+
+    // --- Zero-copy FlatBuffer access: the combined frame ---
+    #[wasm_bindgen]
+    pub fn frame_ptr(&self) -> *const u8 {
+        self.builder.finished_data().as_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn frame_len(&self) -> usize {
+        self.builder.finished_data().len()
+    }
+}
+
+// Rust-only helpers (not exported to JS)
+impl CompositeEngine {
+    /// Apply a snapshot (replace the book) or a delta. Returns false when
+    /// the book needs a snapshot.
+    pub fn apply_book(&mut self, snapshot: bool, update: BookUpdate, timestamp_ms: u64) -> bool {
+        let sequence = update.sequence;
+        let result = if snapshot {
+            self.book.apply_snapshot(sequence, &update.bids, &update.asks).map(|()| true)
+        } else {
+            self.book.apply_update(update)
+        };
+        // Ok(false) is a stale update: nothing changed. A crossed book was
+        // still applied, so it's shown until the snapshot replaces it.
+        if !matches!(result, Ok(false)) {
+            self.book_sequence = self.book.sequence();
+            self.book_timestamp_ms = timestamp_ms;
+            self.refresh_book();
+        }
+        result.is_ok()
+    }
+
+    /// Copy the top `depth` levels and their stats out of the book.
+    fn refresh_book(&mut self) {
+        self.bids.clear();
+        self.bids.extend(self.book.bids().take(self.depth));
+        self.asks.clear();
+        self.asks.extend(self.book.asks().take(self.depth));
+        self.stats = self.book.stats(self.depth);
+        self.book_changed = true;
+    }
+
+    // The orderbook part: an OrderbookFrame of the cached levels, the same
+    // table the server sends (always full, never a delta). Vectors are
+    // written back to front, without a temporary Vec.
+    //
+    // fn write_orderbook(&mut self) -> WIPOffset<OrderbookFrame<'static>> {
+    //     let builder = &mut self.builder;
+    //     let mut levels = |levels: &[Level]| {
+    //         builder.start_vector::<PriceLevel>(levels.len());
+    //         for l in levels.iter().rev() {
+    //             builder.push(PriceLevel::new(l.price, l.size));
+    //         }
+    //         builder.end_vector::<PriceLevel>(levels.len())
+    //     };
+    //     let bids = levels(&self.bids);
+    //     let asks = levels(&self.asks);
+    //     OrderbookFrame::create(builder, &OrderbookFrameArgs {
+    //         best_bid: self.stats.best_bid,
+    //         best_ask: self.stats.best_ask,
+    //         mid_price: self.stats.mid_price,
+    //         spread: self.stats.spread,
+    //         bids: Some(bids),
+    //         asks: Some(asks),
+    //         timestamp_ms: self.book_timestamp_ms,
+    //         sequence: self.book_sequence,
+    //         bid_total_size: self.stats.bid_total_size,
+    //         ask_total_size: self.stats.ask_total_size,
+    //         imbalance: self.stats.imbalance,
+    //         is_delta: false,
+    //     })
+    // }
+}
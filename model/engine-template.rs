@@ -7,6 +7,8 @@
 //! - Engine owns ALL mutable state (no shared state with JS)
 //! - `tick(now_ms)` serializes state into a FlatBuffer frame
 //! - JS reads the frame zero-copy from WASM linear memory via `frame_ptr()`
+//! - `tick()` returns a mask of the fields that changed (0: none, and the
+//!   previous frame is reused), so JS skips DOM writes for the rest
 //! - One WASM call per animation frame (minimizes boundary crossings)
 //! - JS becomes a thin rendering layer: read frame -> apply to DOM
 //!
@@ -28,10 +30,17 @@
 //! 6. Define your engine struct with all state fields (Step 2)
 //! 7. Implement constructor, data input, tick(), and data access (Step 3)
 //! 8. Use `flatBufferTickAdapter()` from the framework to wire into AnimationLoop
+//!
+//! Or skip steps 1-3: `#[derive(OrgFrame)]` (`tools/org-frame`) on a plain
+//! Frame struct writes the FlatBuffer and emits the `.fbs` and TS reader, so
+//! struct, schema and `tick()` can't drift apart. See step 5 of `tick()`.
 
 use wasm_bindgen::prelude::*;
 use flatbuffers::FlatBufferBuilder;
 use serde::Deserialize;
+use my_shared::delta::ChangeTracker;
+use my_shared::indicators::IndicatorSet;
+use my_shared::timeseries::{downsample_lttb, downsample_minmax, Series, TimeSeriesStore};
 
 // Import generated FlatBuffer types from your schema.
 // Replace this path with your actual generated module.
@@ -60,6 +69,41 @@ const SMOOTHING_FACTOR: f64 = 0.08;
 /// Older data is pruned on each `add_data_point()` call.
 const HISTORY_WINDOW_SEC: f64 = 30.0;
 
+/// Points kept per series, whatever the window. Memory is bounded by
+/// 32 bytes per point (timestamp + value, mirrored): 32MB for 1M points.
+const SERIES_CAPACITY: usize = 100_000;
+
+/// Index of the series add_data_point() appends to. Series added with
+/// add_series() follow it (1, 2, ...).
+const VALUE_SERIES: usize = 0;
+
+/// Smallest change in value_a/value_b worth redrawing. Smoothing approaches
+/// its target forever; below this step the frame stops changing.
+const VALUE_EPSILON: f64 = 1e-4;
+
+/// Frame fields, numbered by their bit in the mask tick() returns: field f
+/// changed if `mask & (1 << f)`. wasm_bindgen exports the enum, so JS binds
+/// with the same numbers: `effects.bindStyle(...).dependsOn(FrameField.ValueA)`.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum FrameField {
+    ValueA = 0,
+    ValueB = 1,
+    StateFlag = 2,
+    ColorR = 3,
+    ColorG = 4,
+    ColorB = 5,
+    Indicators = 6,
+}
+
+const FRAME_FIELDS: usize = 7;
+
+/// Change threshold per field, in FrameField order. Bools and bytes change
+/// in whole steps. Indicators is a struct: it has no single value to
+/// compare, so add_trade() marks it instead.
+const FRAME_EPSILONS: [f64; FRAME_FIELDS] =
+    [VALUE_EPSILON, VALUE_EPSILON, 0.0, 0.0, 0.0, 0.0, f64::INFINITY];
+
 // ============================================
 // STEP 2: Define the engine struct
 //
@@ -73,16 +117,21 @@ const HISTORY_WINDOW_SEC: f64 = 30.0;
 //   - Animation state: persists across frames, smoothly transitions
 //   - Configuration: set from JS, changes infrequently
 //   - FlatBufferBuilder: reused across tick() calls (no allocation per frame)
+//   - Change tracker: what JS has already seen
 // ============================================
 
 #[wasm_bindgen]
 pub struct Engine {
     // --- Time-series data (owned by engine) ---
-    // These grow with each data point and are pruned by age.
-    // Stored as parallel Vec<f64> for cache-friendly iteration
-    // and easy return via get_timestamps()/get_values().
-    timestamps: Vec<f64>,
-    values: Vec<f64>,
+    // One fixed-capacity ring buffer per series (my_shared::timeseries),
+    // pruned by age. Each series is one contiguous slice of WASM memory,
+    // so JS reads it through a Float64Array view without copying.
+    store: TimeSeriesStore,
+
+    // Downsampled copy of one series for the chart, refilled by
+    // downsample_minmax()/downsample_lttb(). Reused across calls.
+    chart_timestamps: Vec<f64>,
+    chart_values: Vec<f64>,
 
     // Monotonically increasing version number. JS compares this to
     // its cached version to know when to re-read chart data.
@@ -94,6 +143,10 @@ pub struct Engine {
     current_value: f64,
     prev_value: f64,
 
+    // Indicators over VALUE_SERIES (my_shared::indicators), updated per
+    // data point in O(1) and copied into the frame by tick().
+    indicators: IndicatorSet,
+
     // --- Animation state (persists across frames) ---
     // These are NOT reset each frame. They accumulate over time
     // to produce smooth animations.
@@ -111,6 +164,11 @@ pub struct Engine {
     // reset() clears it without deallocating, so tick() is allocation-free
     // after the first call.
     builder: FlatBufferBuilder<'static>,
+
+    // --- Change tracking (my_shared::delta) ---
+    // The field values of the last frame JS was told about. tick()
+    // compares against them to build its change mask.
+    changes: ChangeTracker<FRAME_FIELDS>,
 }
 
 // ============================================
@@ -122,7 +180,7 @@ pub struct Engine {
 //   - Data input: add_data_point(), load_history() — called on WS messages
 //   - Frame output: tick() — called once per rAF (60fps)
 //   - Frame access: frame_ptr(), frame_len() — zero-copy read from JS
-//   - Data access: get_*() — called when version changes
+//   - Data access: get_*(), *_ptr(), downsample_*() — called when version changes
 //
 // The key insight: tick() is the hot path. Everything else is cold.
 // Optimize tick() relentlessly. Other methods can be straightforward.
@@ -135,17 +193,22 @@ impl Engine {
     // Sets all state to sensible defaults.
     #[wasm_bindgen(constructor)]
     pub fn new() -> Engine {
+        let mut store = TimeSeriesStore::new();
+        store.add_series(SERIES_CAPACITY); // VALUE_SERIES
         Engine {
-            timestamps: Vec::new(),
-            values: Vec::new(),
+            store,
+            chart_timestamps: Vec::new(),
+            chart_values: Vec::new(),
             data_version: 0,
             current_value: 0.0,
             prev_value: 0.0,
+            indicators: IndicatorSet::new(),
             smooth_value: 0.0,
             blend_factor: 0.0,
             config_a: 1.0,
             config_b: 1.0,
             builder: FlatBufferBuilder::with_capacity(256),
+            changes: ChangeTracker::new(FRAME_EPSILONS),
         }
     }
 
@@ -176,27 +239,28 @@ impl Engine {
     // Pattern: accept primitives (f64), not objects.
     #[wasm_bindgen]
     pub fn add_data_point(&mut self, value: f64, timestamp_sec: f64, now_ms: f64) {
+        self.add_trade(value, 0.0, timestamp_sec, now_ms);
+    }
+
+    /// A data point with a volume (trades): also feeds VWAP.
+    #[wasm_bindgen]
+    pub fn add_trade(&mut self, value: f64, volume: f64, timestamp_sec: f64, now_ms: f64) {
         // Track previous value for direction detection (up/down arrows, colors)
         self.prev_value = self.current_value;
         self.current_value = value;
 
-        // Append to time-series
-        self.timestamps.push(timestamp_sec);
-        self.values.push(value);
+        // Indicators fold in one point at a time. Never recompute them
+        // in JS: they arrive in every frame.
+        self.indicators.update(value, volume);
+        self.changes.mark(FrameField::Indicators as usize);
 
-        // Prune old data outside the history window.
-        // saturating_sub(1) ensures we always keep at least 1 data point.
-        let cutoff = now_ms / 1000.0 - HISTORY_WINDOW_SEC;
-        let mut cut = 0;
-        while cut < self.timestamps.len().saturating_sub(1) && self.timestamps[cut] < cutoff {
-            cut += 1;
-        }
-        if cut > 0 {
-            // drain(0..cut) removes elements and shifts the rest down.
-            // For very large datasets, consider a ring buffer instead.
-            self.timestamps.drain(0..cut);
-            self.values.drain(0..cut);
-        }
+        // Append to time-series. O(1): when the series is full the ring
+        // overwrites its oldest point instead of shifting.
+        self.store.push(VALUE_SERIES, timestamp_sec, value);
+
+        // Prune old data outside the history window, in every series.
+        // Dropping from the front of a ring only moves its head.
+        self.prune(now_ms);
 
         // Bump version so JS knows to re-read chart data
         self.data_version += 1;
@@ -212,10 +276,14 @@ impl Engine {
             return; // Silently reject mismatched arrays
         }
 
+        // Points must be in time order; older ones are skipped. The
+        // history also warms up the indicators.
         for i in 0..timestamps.len() {
-            self.timestamps.push(timestamps[i]);
-            self.values.push(values[i]);
+            if self.store.push(VALUE_SERIES, timestamps[i], values[i]) {
+                self.indicators.update(values[i], 0.0);
+            }
         }
+        self.changes.mark(FrameField::Indicators as usize);
 
         // Initialize current/prev from last historical value
         // so the first live data point has a valid previous value.
@@ -228,11 +296,39 @@ impl Engine {
         self.data_version += 1;
     }
 
+    // --- Additional series ---
+    //
+    // Volume, an indicator, a second symbol: each gets its own ring buffer
+    // and its own timestamps. add_series() returns the index to pass to
+    // push_point() and the series_*() accessors.
+    #[wasm_bindgen]
+    pub fn add_series(&mut self, capacity: usize) -> usize {
+        self.store.add_series(capacity)
+    }
+
+    /// Append to one series. Returns false for an unknown series or a point
+    /// older than its newest.
+    #[wasm_bindgen]
+    pub fn push_point(&mut self, series: usize, timestamp_sec: f64, value: f64, now_ms: f64) -> bool {
+        if !self.store.push(series, timestamp_sec, value) {
+            return false;
+        }
+        self.prune(now_ms);
+        self.data_version += 1;
+        true
+    }
+
     // ========================================
     // THE MAIN METHOD: tick()
     //
     // Called once per requestAnimationFrame (60fps).
-    // Serializes ALL computed values into a FlatBuffer frame.
+    // Serializes ALL computed values into a FlatBuffer frame, and returns
+    // the mask of fields that changed since the last frame JS saw.
+    //
+    // Most frames change nothing: the data is idle and the animations
+    // have settled. Then tick() returns 0 before touching the builder —
+    // the previous frame is still in it and JS keeps using it. With
+    // dozens of engines on screen, idle ones cost a comparison each.
     //
     // The builder is reused across calls — reset() clears the
     // internal buffer without deallocating. After the first tick,
@@ -242,49 +338,66 @@ impl Engine {
     // using the flatBufferTickAdapter() from the framework.
     //
     // Rules for tick():
-    //   1. Call builder.reset() first
+    //   1. Return 0 before touching the builder, or call builder.reset()
+    //      first
     //   2. No string operations
     //   3. No branching on data length (handle empty gracefully)
     //   4. All state mutations happen here (animations, smoothing)
     // ========================================
     #[wasm_bindgen]
-    pub fn tick(&mut self, _now_ms: f64) {
-        self.builder.reset();
+    pub fn tick(&mut self, now_ms: f64) -> u32 {
+        // 1-4. Advance the animations and diff against the last frame
+        let changed = self.update(now_ms);
+        if changed == 0 {
+            return 0;
+        }
 
-        // 1. Exponential smoothing (persistent state across frames)
+        // 5. Build FlatBuffer frame
         //
-        // smooth_value chases current_value at a rate controlled by SMOOTHING_FACTOR.
-        // This runs every frame regardless of whether new data arrived,
-        // producing smooth animation even with bursty data.
-        self.smooth_value += (self.current_value - self.smooth_value) * SMOOTHING_FACTOR;
-
-        // 2. Blend animation (approaches target asymptotically)
-        let blend_target = if self.current_value > SOME_THRESHOLD { 1.0 } else { 0.0 };
-        self.blend_factor += (blend_target - self.blend_factor) * 0.04;
-
-        // 3. Compute derived values
-        let normalized = if self.config_a != 0.0 {
-            (self.smooth_value / self.config_a).clamp(0.0, 1.0)
-        } else {
-            0.0
-        };
-        let color = compute_color(normalized);
-
-        // 4. Build FlatBuffer frame
+        // Every field is written, changed or not: the mask only tells JS
+        // which ones are worth applying. write_frame() takes the builder
+        // as an argument so a CompositeEngine can write this frame into
+        // its own; here it's our builder, moved out for the call.
+        let mut builder = std::mem::take(&mut self.builder);
+        builder.reset();
+        // let frame = self.write_frame(&mut builder);
+        // builder.finish(frame, None);
         //
-        // Replace with your generated Frame type and FrameArgs.
-        // The field types match the .fbs schema exactly:
-        //   double → f64, bool → bool, ubyte → u8
+        // With #[derive(OrgFrame)] the struct *is* the schema — no FrameArgs
+        // mapping, and Frame::fbs_schema() / Frame::ts_module() regenerate
+        // the .fbs and TS reader from it. Indicators here is an
+        // #[org_frame(inline)] struct with IndicatorValues' fields and a
+        // From<IndicatorValues> impl. serialize_into() finishes the buffer,
+        // so a derived frame is the root: it can't be nested in a
+        // CompositeEngine frame.
         //
-        // let frame = Frame::create(&mut self.builder, &FrameArgs {
+        // let color = self.color();
+        // Frame {
         //     value_a: self.smooth_value,
         //     value_b: self.blend_factor,
-        //     state_flag: self.current_value > self.prev_value,
+        //     state_flag: self.state_flag(),
         //     color_r: color.0,
         //     color_g: color.1,
         //     color_b: color.2,
-        // });
-        // self.builder.finish(frame, None);
+        //     indicators: self.indicators.values().into(),
+        // }
+        // .serialize_into(&mut builder);
+        self.builder = builder;
+
+        changed
+    }
+
+    /// Mask returned by the last tick().
+    #[wasm_bindgen]
+    pub fn change_mask(&self) -> u32 {
+        self.changes.mask()
+    }
+
+    /// Report every field as changed on the next tick(), e.g. after JS
+    /// remounts and needs to apply a full frame again.
+    #[wasm_bindgen]
+    pub fn invalidate_frame(&mut self) {
+        self.changes.mark_all();
     }
 
     // --- Zero-copy FlatBuffer access ---
@@ -320,14 +433,18 @@ impl Engine {
     //     values = engine.get_values();
     //     cachedVersion = engine.data_version();
     //   }
+    //
+    // These copy the whole series into a new Float64Array. Fine for small
+    // series; beyond a few thousand points use the pointer accessors or a
+    // downsampled view below.
     #[wasm_bindgen]
     pub fn get_timestamps(&self) -> Vec<f64> {
-        self.timestamps.clone()
+        self.series(VALUE_SERIES).timestamps().to_vec()
     }
 
     #[wasm_bindgen]
     pub fn get_values(&self) -> Vec<f64> {
-        self.values.clone()
+        self.series(VALUE_SERIES).values().to_vec()
     }
 
     /// Monotonically increasing version counter.
@@ -345,25 +462,98 @@ impl Engine {
     // Use framework's ChartDataConsumer.zeroCopy() to integrate.
     //
     // IMPORTANT: Views must be recreated after any operation that
-    // might grow WASM memory (Vec push causes reallocation).
+    // might grow WASM memory. A series reallocates until it first fills
+    // up; after that its pointer only moves within its ring, but any other
+    // allocation can still grow memory and detach memory.buffer. Recreate
+    // views on every data_version change (ChartDataConsumer does).
     #[wasm_bindgen]
     pub fn timestamps_ptr(&self) -> *const f64 {
-        self.timestamps.as_ptr()
+        self.series(VALUE_SERIES).timestamps().as_ptr()
     }
 
     #[wasm_bindgen]
     pub fn timestamps_len(&self) -> usize {
-        self.timestamps.len()
+        self.series(VALUE_SERIES).len()
     }
 
     #[wasm_bindgen]
     pub fn values_ptr(&self) -> *const f64 {
-        self.values.as_ptr()
+        self.series(VALUE_SERIES).values().as_ptr()
     }
 
     #[wasm_bindgen]
     pub fn values_len(&self) -> usize {
-        self.values.len()
+        self.series(VALUE_SERIES).len()
+    }
+
+    // The same for any series: timestamps and values share series_len().
+    //
+    //   const volume = engine.add_series(100_000);
+    //   const vs = new Float64Array(wasm.memory.buffer,
+    //     engine.series_values_ptr(volume), engine.series_len(volume));
+    #[wasm_bindgen]
+    pub fn series_timestamps_ptr(&self, series: usize) -> *const f64 {
+        self.series(series).timestamps().as_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn series_values_ptr(&self, series: usize) -> *const f64 {
+        self.series(series).values().as_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn series_len(&self, series: usize) -> usize {
+        self.series(series).len()
+    }
+
+    // --- Downsampled chart data ---
+    //
+    // A chart 1200 pixels wide can't show more than ~2400 distinct points,
+    // however many the series holds. Downsample to the chart's width when
+    // data_version or the visible range changes, then read the result
+    // zero-copy through chart_timestamps_ptr()/chart_values_ptr()/chart_len():
+    //
+    //   downsample_minmax(series, start, end, width)  min + max per pixel
+    //       column of [start, end]; keeps every spike (line/area charts)
+    //   downsample_lttb(series, points)  `points` points preserving the
+    //       shape of the whole series (sparklines, overviews)
+    //
+    // Both return the number of points written.
+    #[wasm_bindgen]
+    pub fn downsample_minmax(&mut self, series: usize, start_sec: f64, end_sec: f64, width: usize) -> usize {
+        let s = series_or_empty(&self.store, series);
+        downsample_minmax(
+            s.timestamps(),
+            s.values(),
+            start_sec,
+            end_sec,
+            width,
+            &mut self.chart_timestamps,
+            &mut self.chart_values,
+        );
+        self.chart_timestamps.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn downsample_lttb(&mut self, series: usize, points: usize) -> usize {
+        let s = series_or_empty(&self.store, series);
+        downsample_lttb(s.timestamps(), s.values(), points, &mut self.chart_timestamps, &mut self.chart_values);
+        self.chart_timestamps.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn chart_timestamps_ptr(&self) -> *const f64 {
+        self.chart_timestamps.as_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn chart_values_ptr(&self) -> *const f64 {
+        self.chart_values.as_ptr()
+    }
+
+    #[wasm_bindgen]
+    pub fn chart_len(&self) -> usize {
+        self.chart_timestamps.len()
     }
 
     // --- Getters for post-message reads ---
@@ -395,13 +585,15 @@ impl Engine {
         struct Msg {
             value: f64,
             timestamp: f64,
+            #[serde(default)]
+            volume: f64,
         }
 
         let msg: Msg = match serde_json::from_str(raw) {
             Ok(m) => m,
             Err(_) => return 0,
         };
-        self.add_data_point(msg.value, msg.timestamp, now_ms);
+        self.add_trade(msg.value, msg.volume, msg.timestamp, now_ms);
         1 // INGEST_DATA_UPDATED
     }
 
@@ -410,11 +602,40 @@ impl Engine {
     // When using a server engine that broadcasts FlatBuffer frames over binary
     // WebSocket, the client WASM engine receives pre-serialized bytes and
     // deserializes them to update its state. Use BinaryFrameParser from the
-    // framework to wire this into WebSocketPipeline.
+    // framework to wire this into WebSocketPipeline (or EnvelopeRouter, when
+    // one socket carries several topics). If the server compresses frames
+    // (connect with protocols: ['orgasm.lz4']), decode them first with a
+    // my_shared::compression::FrameDecoder field.
+    //
+    // Every server message is an Envelope (schema/envelope.fbs): check its
+    // kind and schema version, then parse the payload with this engine's
+    // frame schema.
     //
     // #[wasm_bindgen]
     // pub fn ingest_frame(&mut self, bytes: &[u8]) {
-    //     let frame = flatbuffers::root::<OrderbookFrame>(bytes).unwrap();
+    //     let Ok(Some(bytes)) = self.decoder.decode(bytes) else { return };
+    //     let Ok(envelope) = flatbuffers::root::<Envelope>(bytes) else { return };
+    //     if !matches!(envelope.kind(), FrameKind::Frame | FrameKind::Snapshot) {
+    //         return;
+    //     }
+    //     // Written with a different orderbook.fbs than this build's: the
+    //     // fields can't be trusted
+    //     if envelope.schema_version() != my_shared::schema::ORDERBOOK_SCHEMA_HASH {
+    //         return;
+    //     }
+    //     let payload = envelope.payload().map(|p| p.bytes()).unwrap_or_default();
+    //     let frame = flatbuffers::root::<OrderbookFrame>(payload).unwrap();
+    //
+    //     // Delta mode: delta frames patch the book (size 0 = remove level),
+    //     // full frames replace it — my_shared::orderbook::OrderBook does
+    //     // both (shared/orderbook-template.rs). The server resyncs gaps with
+    //     // a snapshot, so a delta that doesn't follow last_sequence is
+    //     // dropped here.
+    //     if envelope.delta() && envelope.sequence() != self.last_sequence + 1 {
+    //         return;
+    //     }
+    //     self.last_sequence = envelope.sequence();
+    //
     //     self.best_bid = frame.best_bid();
     //     self.best_ask = frame.best_ask();
     //     self.mid_price = frame.mid_price();
@@ -422,6 +643,114 @@ impl Engine {
     //     // ... update other fields from the FlatBuffer frame ...
     //     self.data_version += 1;
     // }
+    //
+    // Export the schema hashes this build was compiled with (a free function
+    // at crate level), so the TS side can send them when connecting, e.g.
+    // url: `wss://host/ws?schemas=${schemasParam()}`. The server closes a
+    // connection whose schemas differ from its own (code 4001).
+    //
+    // #[wasm_bindgen(js_name = schemasParam)]
+    // pub fn schemas_param() -> String {
+    //     my_shared::schema::schemas_param(my_shared::schema::SCHEMAS)
+    // }
+}
+
+// Rust-only helpers (not exported to JS)
+impl Engine {
+    /// Steps 1-4 of tick(): advance the animations and return the change
+    /// mask, without building a frame. A CompositeEngine calls this on
+    /// each engine it owns, then write_frame() on those that changed.
+    pub fn update(&mut self, _now_ms: f64) -> u32 {
+        // 1. Exponential smoothing (persistent state across frames)
+        //
+        // smooth_value chases current_value at a rate controlled by SMOOTHING_FACTOR.
+        // This runs every frame regardless of whether new data arrived,
+        // producing smooth animation even with bursty data.
+        self.smooth_value += (self.current_value - self.smooth_value) * SMOOTHING_FACTOR;
+
+        // 2. Blend animation (approaches target asymptotically)
+        let blend_target = if self.current_value > SOME_THRESHOLD { 1.0 } else { 0.0 };
+        self.blend_factor += (blend_target - self.blend_factor) * 0.04;
+
+        // 3. Compute derived values
+        let color = self.color();
+        let state_flag = self.state_flag();
+
+        // 4. Diff against the last frame JS saw, in FrameField order.
+        //    Indicators is only compared via mark(): its value is ignored.
+        self.changes.update([
+            self.smooth_value,
+            self.blend_factor,
+            f64::from(u8::from(state_flag)),
+            f64::from(color.0),
+            f64::from(color.1),
+            f64::from(color.2),
+            0.0,
+        ])
+    }
+
+    // Step 5 of tick(): write this engine's Frame table into `builder` and
+    // return its offset, without finishing the buffer — tick() finishes it
+    // as the root, a CompositeEngine nests it in its own frame.
+    //
+    // Replace with your generated Frame type and FrameArgs.
+    // The field types match the .fbs schema exactly:
+    //   double → f64, bool → bool, ubyte → u8, struct → &T
+    //
+    // Indicator values are read, not computed, here: add_trade() already
+    // updated them. JS reads them with frame.indicators()?.rsi() — NaN
+    // while an indicator is warming up.
+    //
+    // pub fn write_frame<'b>(&self, builder: &mut FlatBufferBuilder<'b>) -> WIPOffset<Frame<'b>> {
+    //     let color = self.color();
+    //     let v = self.indicators.values();
+    //     let indicators = Indicators::new(
+    //         v.ema, v.sma, v.vwap,
+    //         v.bollinger_upper, v.bollinger_middle, v.bollinger_lower,
+    //         v.rsi, v.macd, v.macd_signal, v.macd_histogram,
+    //         v.stddev, v.min, v.max,
+    //     );
+    //     Frame::create(builder, &FrameArgs {
+    //         value_a: self.smooth_value,
+    //         value_b: self.blend_factor,
+    //         state_flag: self.state_flag(),
+    //         color_r: color.0,
+    //         color_g: color.1,
+    //         color_b: color.2,
+    //         indicators: Some(&indicators),
+    //     })
+    // }
+
+    /// Frame color for the current smoothed value.
+    fn color(&self) -> (u8, u8, u8) {
+        let normalized = if self.config_a != 0.0 {
+            (self.smooth_value / self.config_a).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        compute_color(normalized)
+    }
+
+    /// Whether the last data point was higher than the one before.
+    fn state_flag(&self) -> bool {
+        self.current_value > self.prev_value
+    }
+
+    /// One series, or an empty one for an unknown index.
+    fn series(&self, index: usize) -> &Series {
+        series_or_empty(&self.store, index)
+    }
+
+    /// Drop points older than HISTORY_WINDOW_SEC from every series, always
+    /// keeping each series' newest point.
+    fn prune(&mut self, now_ms: f64) {
+        let cutoff = now_ms / 1000.0 - HISTORY_WINDOW_SEC;
+        for series in self.store.iter_mut() {
+            if let Some((newest, _)) = series.last() {
+                series.drop_before(cutoff.min(newest));
+            }
+        }
+    }
 }
 
 // ============================================
@@ -432,6 +761,14 @@ impl Engine {
 // often inline them into tick() for zero overhead.
 // ============================================
 
+/// A series from the store, or a shared empty one, so the pointer
+/// accessors can answer an unknown index with length 0 instead of panicking
+/// across the WASM boundary.
+fn series_or_empty(store: &TimeSeriesStore, index: usize) -> &Series {
+    static EMPTY: std::sync::OnceLock<Series> = std::sync::OnceLock::new();
+    store.series(index).unwrap_or_else(|| EMPTY.get_or_init(|| Series::new(1)))
+}
+
 /// Map a normalized 0..1 value to an RGB color.
 ///
 /// This example does a simple red-green gradient.
@@ -450,37 +787,17 @@ fn compute_color(value: f64) -> (u8, u8, u8) {
 // OPTIONAL: Advanced patterns
 // ============================================
 
-// --- Ring buffer alternative for high-frequency data ---
-//
-// If add_data_point() is called 1000+/sec, Vec::drain() becomes expensive.
-// Use a ring buffer instead:
-//
-// struct RingBuffer {
-//     data: Vec<f64>,
-//     head: usize,
-//     len: usize,
-//     capacity: usize,
-// }
-//
-// This avoids shifting elements on every prune.
-
 // --- Multi-engine composition ---
 //
-// For complex UIs with multiple independent data streams,
-// create multiple Engine instances in JS:
-//
-//   const priceEngine = new Engine();
-//   const volumeEngine = new Engine();
-//
-// Each engine has its own state and tick(). The animation loop
-// calls tick() on each and merges the frame buffers:
+// For complex UIs with multiple data streams (price, volume, orderbook),
+// compose engines in Rust rather than ticking each from JS:
+// composite-engine-template.rs owns several engines, calls update() and
+// write_frame() on each, and finishes one CompositeFrame
+// (schema/composite.fbs) — one tick(), one frame_ptr()/frame_len() per
+// animation frame, however many engines.
 //
-//   function animate(now) {
-//     priceEngine.tick(now);
-//     volumeEngine.tick(now);
-//     render(priceFrame, volumeFrame);
-//     requestAnimationFrame(animate);
-//   }
+// Independent engines in separate WASM modules can't be composed that
+// way; share one rAF between them with MultiAnimationLoop instead.
 
 // --- Testing ---
 //
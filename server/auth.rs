@@ -0,0 +1,445 @@
+//! # Client Authentication
+//!
+//! Identifies each WebSocket client before it can subscribe to anything.
+//! An `Authenticator` turns a bearer token into a `Principal` (who the
+//! client is and which symbols it may see); the principal lives in the
+//! client's `ClientState`, and `Subscribe` is rejected for symbols it
+//! doesn't grant.
+//!
+//! ## Token delivery
+//!
+//! Browsers can't set headers on a WebSocket upgrade, so a token is taken
+//! from, in order:
+//!
+//! 1. the `token` query parameter — `wss://host/ws?token=eyJ...`
+//! 2. an `Authorization: Bearer eyJ...` header (non-browser clients;
+//!    other schemes are ignored)
+//! 3. the first WebSocket message, sent as text right after connecting
+//!    (keeps the token out of URLs and proxy logs)
+//!
+//! A token in the URL or header that fails verification is rejected with
+//! HTTP 401 before the upgrade. Without one, the connection is upgraded
+//! and the client has `AUTH_TIMEOUT` to send its token; a bad or missing
+//! token closes the socket with code 1008 (policy violation).
+//!
+//! ## Usage
+//!
+//! ```rust
+//! // HS256 with a shared secret:
+//! let auth: Arc<dyn Authenticator> = Arc::new(JwtAuthenticator::hmac(secret.as_bytes()));
+//! // RS256 with the issuer's public key:
+//! let auth: Arc<dyn Authenticator> = Arc::new(JwtAuthenticator::rsa_pem_file("keys/jwt.pub.pem")?);
+//! // Development: everyone is anonymous and may see everything
+//! let auth: Arc<dyn Authenticator> = Arc::new(NoAuth);
+//!
+//! ServerState { broadcast, engines, outbound, auth }
+//! ```
+//!
+//! ## JWT claims
+//!
+//! ```json
+//! { "sub": "user-42", "exp": 1767225600, "symbols": ["BTC-*", "ETH-USD"] }
+//! ```
+//!
+//! `symbols` lists exact symbols, prefixes ending in `*`, or `"*"` for all.
+//! A token without `symbols` grants nothing. Tokens are checked once, at
+//! connect; reconnect to pick up new permissions.
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+/// How long a client without a URL/header token has to send one.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The authenticated identity of a connected client.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    /// Stable user id (the JWT `sub` claim), for logs.
+    pub subject: String,
+    pub permissions: Permissions,
+}
+
+impl Principal {
+    /// Unauthenticated client with access to every symbol. Only produced by
+    /// `NoAuth`.
+    pub fn anonymous() -> Self {
+        Self {
+            subject: "anonymous".to_string(),
+            permissions: Permissions::all(),
+        }
+    }
+}
+
+/// Symbols a principal may subscribe to.
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    /// Exact symbols, `PREFIX*` patterns, or `*`.
+    symbols: Vec<String>,
+}
+
+impl Permissions {
+    pub fn new(symbols: Vec<String>) -> Self {
+        Self { symbols }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            symbols: vec!["*".to_string()],
+        }
+    }
+
+    /// Whether `symbol` matches any granted pattern.
+    pub fn allows(&self, symbol: &str) -> bool {
+        self.symbols
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => symbol.starts_with(prefix),
+                None => pattern == symbol,
+            })
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// No token was presented (the client may still send one as its first
+    /// message).
+    MissingToken,
+    /// The token was presented but didn't verify (bad signature, expired,
+    /// malformed).
+    InvalidToken(String),
+    /// A key couldn't be loaded at startup.
+    Key(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingToken => write!(f, "missing token"),
+            AuthError::InvalidToken(reason) => write!(f, "invalid token: {reason}"),
+            AuthError::Key(reason) => write!(f, "key error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Verifies a bearer token. `token` is None when the client presented none.
+pub trait Authenticator: Send + Sync + 'static {
+    fn authenticate(&self, token: Option<&str>) -> Result<Principal, AuthError>;
+}
+
+/// Accepts every client as `Principal::anonymous()`. For development and
+/// for servers behind an authenticating proxy.
+pub struct NoAuth;
+
+impl Authenticator for NoAuth {
+    fn authenticate(&self, _token: Option<&str>) -> Result<Principal, AuthError> {
+        Ok(Principal::anonymous())
+    }
+}
+
+/// Claims read from the JWT payload. `exp` is checked by the validator.
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    symbols: Vec<String>,
+}
+
+/// Verifies JWTs signed with a locally loaded key: an HMAC secret (HS256)
+/// or an RSA public key (RS256). No network calls on the connect path.
+pub struct JwtAuthenticator {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtAuthenticator {
+    /// HS256 with a shared secret.
+    pub fn hmac(secret: &[u8]) -> Self {
+        Self {
+            key: DecodingKey::from_secret(secret),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+
+    /// RS256 with a PEM-encoded RSA public key.
+    pub fn rsa_pem(pem: &[u8]) -> Result<Self, AuthError> {
+        let key = DecodingKey::from_rsa_pem(pem).map_err(|e| AuthError::Key(e.to_string()))?;
+        Ok(Self {
+            key,
+            validation: Validation::new(Algorithm::RS256),
+        })
+    }
+
+    /// RS256 with a PEM-encoded RSA public key read from `path`.
+    pub fn rsa_pem_file(path: impl AsRef<Path>) -> Result<Self, AuthError> {
+        let pem = std::fs::read(path.as_ref())
+            .map_err(|e| AuthError::Key(format!("{}: {e}", path.as_ref().display())))?;
+        Self::rsa_pem(&pem)
+    }
+
+    /// Also require the `iss` claim to be present and match.
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.validation.set_issuer(&[issuer]);
+        self.validation
+            .required_spec_claims
+            .insert("iss".to_string());
+        self
+    }
+
+    /// Also require the `aud` claim to be present and match.
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&[audience]);
+        self.validation
+            .required_spec_claims
+            .insert("aud".to_string());
+        self
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate(&self, token: Option<&str>) -> Result<Principal, AuthError> {
+        let token = token.ok_or(AuthError::MissingToken)?;
+        let data = decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+        Ok(Principal {
+            subject: data.claims.sub,
+            permissions: Permissions::new(data.claims.symbols),
+        })
+    }
+}
+
+/// Strip an optional `Bearer ` prefix and surrounding whitespace.
+pub fn bearer(token: &str) -> &str {
+    let token = token.trim();
+    token.strip_prefix("Bearer ").unwrap_or(token).trim()
+}
+
+/// The token presented on the upgrade request: the `token` query parameter,
+/// else the `Authorization` header. A header with another scheme (`Basic`,
+/// say, meant for a proxy) or an empty token counts as no token.
+pub fn request_token<'a>(
+    query: Option<&'a str>,
+    authorization: Option<&'a str>,
+) -> Option<&'a str> {
+    let from_header = || {
+        let value = authorization?.trim();
+        let (scheme, token) = value.split_once(' ')?;
+        scheme.eq_ignore_ascii_case("Bearer").then(|| token.trim())
+    };
+    query
+        .map(bearer)
+        .or_else(from_header)
+        .filter(|token| !token.is_empty())
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn token(claims: Value, secret: &[u8]) -> String {
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    fn claims(extra: Value) -> Value {
+        let mut claims =
+            json!({ "sub": "user-42", "exp": now() + 3600, "symbols": ["BTC-*", "ETH-USD"] });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        claims
+    }
+
+    fn permissions(symbols: &[&str]) -> Permissions {
+        Permissions::new(symbols.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn test_allows_exact_symbol() {
+        let p = permissions(&["ETH-USD"]);
+        assert!(p.allows("ETH-USD"));
+        assert!(!p.allows("ETH-USDT"));
+        assert!(!p.allows("ETH"));
+    }
+
+    #[test]
+    fn test_allows_prefix_pattern() {
+        let p = permissions(&["BTC-*"]);
+        assert!(p.allows("BTC-USD"));
+        assert!(p.allows("BTC-"));
+        assert!(!p.allows("ETH-BTC"));
+    }
+
+    #[test]
+    fn test_allows_wildcard_and_denies_empty() {
+        assert!(Permissions::all().allows("ANY-THING"));
+        assert!(permissions(&["*"]).allows(""));
+        assert!(!Permissions::default().allows("BTC-USD"));
+        assert!(!permissions(&[]).allows(""));
+    }
+
+    #[test]
+    fn test_bearer_strips_prefix() {
+        assert_eq!(bearer("Bearer abc"), "abc");
+        assert_eq!(bearer("  abc \n"), "abc");
+        assert_eq!(bearer(" Bearer  abc "), "abc");
+    }
+
+    #[test]
+    fn test_request_token_missing() {
+        assert_eq!(request_token(None, None), None);
+        assert_eq!(request_token(Some(""), None), None);
+        assert_eq!(request_token(None, Some("Bearer ")), None);
+    }
+
+    #[test]
+    fn test_request_token_from_header() {
+        assert_eq!(request_token(None, Some("Bearer abc")), Some("abc"));
+        assert_eq!(request_token(None, Some("bearer abc")), Some("abc"));
+    }
+
+    #[test]
+    fn test_request_token_ignores_other_schemes() {
+        assert_eq!(request_token(None, Some("Basic dXNlcjpwYXNz")), None);
+        assert_eq!(request_token(None, Some("abc")), None);
+    }
+
+    #[test]
+    fn test_request_token_prefers_query() {
+        assert_eq!(request_token(Some("abc"), None), Some("abc"));
+        assert_eq!(
+            request_token(Some("Bearer abc"), Some("Bearer xyz")),
+            Some("abc")
+        );
+        assert_eq!(
+            request_token(Some("abc"), Some("Basic dXNlcjpwYXNz")),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn test_jwt_accepts_valid_token() {
+        let auth = JwtAuthenticator::hmac(SECRET);
+        let principal = auth
+            .authenticate(Some(&token(claims(json!({})), SECRET)))
+            .unwrap();
+        assert_eq!(principal.subject, "user-42");
+        assert!(principal.permissions.allows("BTC-EUR"));
+        assert!(principal.permissions.allows("ETH-USD"));
+        assert!(!principal.permissions.allows("SOL-USD"));
+    }
+
+    #[test]
+    fn test_jwt_missing_token() {
+        let auth = JwtAuthenticator::hmac(SECRET);
+        assert!(matches!(
+            auth.authenticate(None),
+            Err(AuthError::MissingToken)
+        ));
+    }
+
+    #[test]
+    fn test_jwt_rejects_bad_signature() {
+        let auth = JwtAuthenticator::hmac(SECRET);
+        let forged = token(claims(json!({})), b"other-secret");
+        assert!(matches!(
+            auth.authenticate(Some(&forged)),
+            Err(AuthError::InvalidToken(_))
+        ));
+        assert!(matches!(
+            auth.authenticate(Some("not.a.jwt")),
+            Err(AuthError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_jwt_rejects_expired_token() {
+        let auth = JwtAuthenticator::hmac(SECRET);
+        // Well past the validator's default 60s leeway
+        let expired = token(claims(json!({ "exp": now() - 3600 })), SECRET);
+        assert!(matches!(
+            auth.authenticate(Some(&expired)),
+            Err(AuthError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_jwt_checks_issuer() {
+        let auth = JwtAuthenticator::hmac(SECRET).with_issuer("https://issuer.example");
+        let good = token(claims(json!({ "iss": "https://issuer.example" })), SECRET);
+        let wrong = token(claims(json!({ "iss": "https://other.example" })), SECRET);
+        let missing = token(claims(json!({})), SECRET);
+        assert!(auth.authenticate(Some(&good)).is_ok());
+        assert!(matches!(
+            auth.authenticate(Some(&wrong)),
+            Err(AuthError::InvalidToken(_))
+        ));
+        assert!(matches!(
+            auth.authenticate(Some(&missing)),
+            Err(AuthError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_jwt_checks_audience() {
+        let auth = JwtAuthenticator::hmac(SECRET).with_audience("market-data");
+        let good = token(claims(json!({ "aud": "market-data" })), SECRET);
+        let wrong = token(claims(json!({ "aud": "billing" })), SECRET);
+        let missing = token(claims(json!({})), SECRET);
+        assert!(auth.authenticate(Some(&good)).is_ok());
+        assert!(matches!(
+            auth.authenticate(Some(&wrong)),
+            Err(AuthError::InvalidToken(_))
+        ));
+        assert!(matches!(
+            auth.authenticate(Some(&missing)),
+            Err(AuthError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_jwt_without_symbols_grants_nothing() {
+        let auth = JwtAuthenticator::hmac(SECRET);
+        let claims = json!({ "sub": "user-42", "exp": now() + 3600 });
+        let principal = auth.authenticate(Some(&token(claims, SECRET))).unwrap();
+        assert!(!principal.permissions.allows("BTC-USD"));
+    }
+
+    #[test]
+    fn test_jwt_rejects_malformed_symbols() {
+        let auth = JwtAuthenticator::hmac(SECRET);
+        for symbols in [json!("BTC-USD"), json!([1, 2]), json!({ "BTC-USD": true })] {
+            let bad = token(claims(json!({ "symbols": symbols })), SECRET);
+            assert!(matches!(
+                auth.authenticate(Some(&bad)),
+                Err(AuthError::InvalidToken(_))
+            ));
+        }
+    }
+}
@@ -1,168 +1,1556 @@
 //! # WebSocket Broadcast Handler
 //!
-//! Axum WebSocket handler that fans out binary FlatBuffer frames to all
-//! connected clients. Uses `tokio::sync::broadcast` for efficient one-to-many
-//! distribution: the server serializes once, all clients read the same `Arc<Vec<u8>>`.
+//! Axum WebSocket handler that fans out binary FlatBuffer frames to subscribed
+//! clients. Frames are published per topic (one `tokio::sync::broadcast`
+//! channel per symbol), so a client watching 5 of 400 symbols only receives
+//! bytes for those 5. The server serializes once per topic, all subscribers
+//! read the same `Bytes` allocation.
+//!
+//! Every message to a client — frame, snapshot or command response — is
+//! wrapped in an `Envelope` (envelope.rs) carrying its kind, topic id,
+//! sequence and schema version. `send()` wraps a frame once per topic.
 //!
 //! ## Architecture
 //!
 //! ```text
-//! Tick loop ──→ broadcast::send(Arc<Vec<u8>>)
-//!                    │
-//!              ┌─────┼─────┐
-//!              ▼     ▼     ▼
-//!          client1 client2 client3   (each has a broadcast::Receiver)
+//! Tick loop ──→ send("BTC-USD", bytes)     send("ETH-USD", bytes)
+//!                    │                          │
+//!              ┌─────┴─────┐                    │
+//!              ▼           ▼                    ▼
+//!          client1      client2              client3   (one Receiver per subscribed topic)
 //! ```
 //!
+//! Topics are refcounted. The first `subscribe(topic)` creates the channel,
+//! the last `unsubscribe(topic)` tears it down. Sending to a topic nobody is
+//! subscribed to is a cheap no-op: `send()` checks for subscribers before
+//! wrapping the frame.
+//!
+//! Create the state with `with_topic_events()` to be notified when a topic
+//! opens or closes — `EngineRegistry` (engine_registry.rs) uses this to
+//! create and drop per-topic engines.
+//!
+//! ## Delta frames and gap recovery
+//!
+//! Every `Frame` carries the engine's sequence number and whether it is a
+//! delta. Each client tracks the last sequence it received per topic. When
+//! the next delta doesn't follow on (the client lagged and the channel
+//! dropped frames), the client task fetches a fresh
+//! `snapshot()` through its `SnapshotSource`, sends that instead, and then
+//! drops buffered deltas already folded into the snapshot.
+//!
+//! The same `SnapshotSource` serves snapshot-on-subscribe and the
+//! `RequestSnapshot` command: the client gets the topic's full state before
+//! its live frames, without any broadcast frame being lost in between.
+//!
+//! ## Slow consumers
+//!
+//! Each client has a bounded `OutboundQueue` drained by its own writer task,
+//! so a slow socket never stalls the task reading broadcast frames and
+//! commands. When the queue is full, the `SlowConsumerPolicy` in
+//! `OutboundConfig` decides what gives: drop the oldest frame, coalesce to
+//! the latest full frame per topic, or disconnect after repeated lags.
+//! Queued items are `Bytes` handles to the shared frame, so a client's
+//! worst-case memory is `capacity` handles plus its pending responses and
+//! snapshots. A dropped frame breaks that topic's sequence, so the next
+//! delta resyncs the client from a snapshot.
+//!
+//! ## Per-client rates
+//!
+//! A client can cap the rate it receives a topic at (`Subscribe.max_rate_hz`,
+//! e.g. 5Hz for a background tab while the active chart takes every tick).
+//! Its `TopicStreams` then conflates: frames arriving before the topic's
+//! next slot are held, each replacing the last, and only the latest is sent
+//! when the slot comes. A conflated delta no longer follows the last frame
+//! sent, so it is resynced from a snapshot like any other gap — delta
+//! streams need a `SnapshotSource` to be rate-limited.
+//!
+//! ## Compression
+//!
+//! With `ServerState.compression` set, clients that request the
+//! `orgasm.lz4` subprotocol get frames and snapshots as LZ4 envelopes
+//! (see compression.rs), compressed by their writer task. Command
+//! responses are always sent as-is. Give the same `Compression` to
+//! `BroadcastState::with_compression()`: `send()` samples each frame for
+//! the shared dictionary once, rather than every writer task per client.
+//!
+//! ## Schema handshake
+//!
+//! A client may list the schema hashes it was built with in a `schemas`
+//! query parameter (`my_shared::schema::schemas_param`). If any differs
+//! from the server's `SCHEMAS`, the upgrade completes only to close the
+//! socket with 4001 and the mismatch as the reason — browsers can't read
+//! the status of a refused upgrade, and the client must not retry with the
+//! same build. Clients without the parameter aren't checked.
+//!
+//! ## Shutdown
+//!
+//! On `ServerState.shutdown` (see shutdown.rs), each client task queues the
+//! frames already broadcast for its topics — the engines' final tick —
+//! then closes with 1012 and a `reconnect-after` hint once its outbound
+//! queue has flushed.
+//!
 //! ## Usage
 //!
 //! ```rust
-//! let state = BroadcastState::new(1024);  // buffer 1024 frames
+//! let state = BroadcastState::new(1024);  // buffer 1024 frames per topic
 //!
 //! // In tick loop:
-//! state.send(bytes);
+//! state.send("BTC-USD", Frame::full(sequence, bytes));
 //!
-//! // In Axum router:
+//! // In Axum router (engines: anything implementing SnapshotSource):
 //! let app = Router::new()
 //!     .route("/ws", get(ws_handler))
-//!     .with_state(state);
+//!     .with_state(ServerState {
+//!         broadcast: state,
+//!         engines,
+//!         outbound: OutboundConfig::default(),
+//!         auth: Arc::new(NoAuth),
+//!         shutdown,
+//!         metrics,
+//!         compression: None,
+//!     });
 //! ```
 
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Query, State, WebSocketUpgrade,
     },
-    response::IntoResponse,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
-use futures_util::{SinkExt, StreamExt};
-use tokio::sync::broadcast;
+use bytes::Bytes;
+use futures_util::stream::SplitSink;
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use my_shared::schema::{check_schemas, SCHEMAS, SCHEMA_MISMATCH_CLOSE_CODE};
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::time::Instant;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::StreamMap;
+use tokio_util::task::task_tracker::TaskTrackerToken;
 use tracing::{info, warn};
 
+use crate::auth::{bearer, request_token, AuthError, Authenticator, Principal, AUTH_TIMEOUT};
+use crate::command_handler::{handle_client_message, ClientState};
+use crate::compression::{Compression, Compressor, LZ4_PROTOCOL};
+use crate::envelope::{self, FrameKind, Header};
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+
+/// A serialized frame plus the metadata the broadcast layer needs.
+///
+/// Cloning is cheap: the bytes are a refcounted `Bytes` shared by every
+/// subscriber of the topic, and handed to the WebSocket without copying.
+#[derive(Clone)]
+pub struct Frame {
+    /// Engine sequence number (`ServerEngine::sequence()`) for this frame.
+    pub sequence: u64,
+    /// True if the frame only carries changes since the previous frame.
+    pub delta: bool,
+    /// `ServerEngine::schema_version()` of the engine that built it.
+    pub schema_version: u32,
+    /// The engine's FlatBuffer when passed to `send()`; subscribers receive
+    /// it wrapped in an envelope.
+    pub bytes: Bytes,
+}
+
+impl Frame {
+    /// A self-contained frame. Clients can always apply it.
+    pub fn full(sequence: u64, bytes: impl Into<Bytes>) -> Self {
+        Self {
+            sequence,
+            delta: false,
+            schema_version: 0,
+            bytes: bytes.into(),
+        }
+    }
+
+    /// A delta frame. Only valid on top of frame `sequence - 1`.
+    pub fn delta(sequence: u64, bytes: impl Into<Bytes>) -> Self {
+        Self {
+            sequence,
+            delta: true,
+            schema_version: 0,
+            bytes: bytes.into(),
+        }
+    }
+
+    pub fn with_schema_version(mut self, schema_version: u32) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+}
+
+/// Full state of one topic, tagged with the sequence of the last frame
+/// folded into it.
+pub struct Snapshot {
+    pub sequence: u64,
+    pub schema_version: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Produces a snapshot of a topic on demand.
+///
+/// Client tasks call this when a client subscribes, sends RequestSnapshot,
+/// or needs a delta stream resynced after a gap. Implemented
+/// for the shared `EngineRegistry` handle in engine_registry.rs; `()` is a
+/// no-op source for servers that only send full frames.
+pub trait SnapshotSource: Clone + Send + Sync + 'static {
+    fn snapshot(&self, topic: &str) -> impl Future<Output = Option<Snapshot>> + Send;
+}
+
+impl SnapshotSource for () {
+    async fn snapshot(&self, _topic: &str) -> Option<Snapshot> {
+        None
+    }
+}
+
+/// Axum router state: the broadcast fan-out, a handle to the engines, the
+/// per-client outbound queue settings, how clients are authenticated, the
+/// shutdown signal client tasks follow, the metrics they record, and
+/// whether clients may negotiate compression.
+#[derive(Clone)]
+pub struct ServerState<S> {
+    pub broadcast: BroadcastState,
+    pub engines: S,
+    pub outbound: OutboundConfig,
+    pub auth: Arc<dyn Authenticator>,
+    pub shutdown: Shutdown,
+    pub metrics: Arc<Metrics>,
+    /// None disables compression: the subprotocol is never accepted. Its
+    /// dictionary is trained by the `BroadcastState` given the same
+    /// `Compression` (`with_compression()`).
+    pub compression: Option<Compression>,
+}
+
 /// Shared broadcast state. Clone this into Axum routes.
 ///
-/// Wraps a `broadcast::Sender` that distributes `Arc<Vec<u8>>` frames.
-/// Arc ensures the serialized bytes are shared (not copied) across all
-/// client tasks.
+/// Holds one `broadcast::Sender` per active topic, each distributing
+/// `Frame`s. The `Bytes` inside `Frame` ensures the serialized bytes are shared
+/// (not copied) across all client tasks subscribed to that topic.
 #[derive(Clone)]
 pub struct BroadcastState {
-    tx: broadcast::Sender<Arc<Vec<u8>>>,
+    topics: Arc<Mutex<HashMap<String, Topic>>>,
+    capacity: usize,
+    events: Option<mpsc::UnboundedSender<TopicEvent>>,
+    compression: Option<Compression>,
+}
+
+/// Topic lifecycle notification, emitted in order under the topics lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicEvent {
+    /// First subscriber arrived.
+    Opened(String),
+    /// Last subscriber left.
+    Closed(String),
+}
+
+/// A single topic channel and the number of clients subscribed to it.
+struct Topic {
+    tx: broadcast::Sender<Frame>,
+    subscribers: usize,
 }
 
 impl BroadcastState {
-    /// Create a new broadcast state with the given channel capacity.
+    /// Create a new broadcast state with the given per-topic channel capacity.
     ///
     /// Capacity determines how many frames can be buffered before slow
     /// clients start dropping frames (lagged). For a 50Hz tick rate,
     /// 1024 buffers ~20 seconds of data.
     pub fn new(capacity: usize) -> Self {
-        let (tx, _) = broadcast::channel(capacity);
-        Self { tx }
+        Self {
+            topics: Arc::new(Mutex::new(HashMap::new())),
+            capacity,
+            events: None,
+            compression: None,
+        }
+    }
+
+    /// Offer every frame sent to subscribers to `compression`'s dictionary
+    /// trainer. Pass the `Compression` in `ServerState.compression`.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Like `new()`, but also returns a receiver of topic open/close events.
+    ///
+    /// Drain it from the task that owns your engines so they are created on
+    /// the first Subscribe and dropped after the last Unsubscribe.
+    pub fn with_topic_events(capacity: usize) -> (Self, mpsc::UnboundedReceiver<TopicEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let state = Self {
+            events: Some(tx),
+            ..Self::new(capacity)
+        };
+        (state, rx)
+    }
+
+    fn emit(&self, event: TopicEvent) {
+        if let Some(events) = &self.events {
+            // Receiver gone means nobody cares about lifecycle anymore
+            let _ = events.send(event);
+        }
+    }
+
+    /// Send a frame to all clients subscribed to `topic`.
+    ///
+    /// The frame is wrapped in an envelope (kind `Frame`, the topic's id)
+    /// and all receivers share that allocation. Returns the number of
+    /// receivers that will receive the message, or 0 if nobody is subscribed
+    /// to the topic.
+    ///
+    /// With compression, a frame that reached subscribers is also offered
+    /// as a dictionary sample — once here, not once per client.
+    pub fn send(&self, topic: &str, mut frame: Frame) -> usize {
+        // Look the topic up before wrapping, so a frame nobody will receive
+        // costs a map lookup and nothing else
+        let tx = match self.topics.lock().unwrap().get(topic) {
+            Some(entry) if entry.tx.receiver_count() > 0 => entry.tx.clone(),
+            _ => return 0,
+        };
+        let header = Header {
+            kind: FrameKind::Frame,
+            topic_id: my_shared::topic_id(topic),
+            sequence: frame.sequence,
+            schema_version: frame.schema_version,
+            delta: frame.delta,
+        };
+        frame.bytes = envelope::wrap(header, &frame.bytes);
+        let bytes = frame.bytes.clone();
+        // Ignore error when the last receiver is mid-teardown
+        let receivers = tx.send(frame).unwrap_or(0);
+        if let Some(compression) = self.compression.as_ref().filter(|_| receivers > 0) {
+            compression.sample(&bytes);
+        }
+        receivers
+    }
+
+    /// Subscribe to a topic, creating its channel on first use.
+    ///
+    /// Every call must be paired with an `unsubscribe(topic)`. Prefer
+    /// `TopicStreams`, which does the bookkeeping for a client.
+    pub fn subscribe(&self, topic: &str) -> broadcast::Receiver<Frame> {
+        let mut topics = self.topics.lock().unwrap();
+        if !topics.contains_key(topic) {
+            let id = my_shared::topic_id(topic);
+            if let Some(other) = topics.keys().find(|other| my_shared::topic_id(other) == id) {
+                warn!("Topic id collision: {topic} and {other} are both {id:#010x}");
+            }
+        }
+        let entry = topics.entry(topic.to_string()).or_insert_with(|| {
+            info!("Topic opened: {topic}");
+            self.emit(TopicEvent::Opened(topic.to_string()));
+            let (tx, _) = broadcast::channel(self.capacity);
+            Topic { tx, subscribers: 0 }
+        });
+        entry.subscribers += 1;
+        entry.tx.subscribe()
+    }
+
+    /// Release one subscription to a topic. The channel is dropped when the
+    /// last subscriber leaves.
+    pub fn unsubscribe(&self, topic: &str) {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(entry) = topics.get_mut(topic) {
+            entry.subscribers -= 1;
+            if entry.subscribers == 0 {
+                topics.remove(topic);
+                info!("Topic closed: {topic}");
+                self.emit(TopicEvent::Closed(topic.to_string()));
+            }
+        }
+    }
+
+    /// Number of clients currently subscribed to `topic`.
+    ///
+    /// The tick loop can use this to skip serializing topics nobody watches.
+    pub fn subscriber_count(&self, topic: &str) -> usize {
+        let topics = self.topics.lock().unwrap();
+        topics.get(topic).map_or(0, |entry| entry.subscribers)
+    }
+
+    /// Names of all topics with at least one subscriber.
+    pub fn topics(&self) -> Vec<String> {
+        let topics = self.topics.lock().unwrap();
+        topics.keys().cloned().collect()
+    }
+
+    /// Every active topic with its subscriber count, read under one lock.
+    pub fn subscriber_counts(&self) -> Vec<(String, usize)> {
+        let topics = self.topics.lock().unwrap();
+        topics
+            .iter()
+            .map(|(topic, entry)| (topic.clone(), entry.subscribers))
+            .collect()
+    }
+}
+
+/// What a client task should do with the next frame on a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Send it.
+    Forward,
+    /// Already covered by a snapshot this client received.
+    Skip,
+    /// A delta that doesn't follow the last frame sent — resync first.
+    Resync,
+}
+
+/// One client's topic subscriptions, merged into a single stream.
+///
+/// Holds a broadcast receiver per subscribed topic, plus the sequence of the
+/// last frame sent to this client on each topic and any rate limits it
+/// asked for. Dropping this releases every topic refcount, so a
+/// disconnecting client can never leak a topic. Keys are `Arc<str>` so
+/// yielding a frame doesn't allocate the topic name.
+pub struct TopicStreams {
+    broadcast: BroadcastState,
+    streams: StreamMap<Arc<str>, BroadcastStream<Frame>>,
+    last_sequence: HashMap<Arc<str>, u64>,
+    rate_limits: HashMap<Arc<str>, RateLimit>,
+}
+
+/// A client's rate cap on one topic.
+struct RateLimit {
+    interval: Duration,
+    /// Earliest time the next frame may be yielded.
+    next_send: Instant,
+    /// Latest frame that arrived before `next_send`.
+    held: Option<Frame>,
+}
+
+impl TopicStreams {
+    pub fn new(broadcast: BroadcastState) -> Self {
+        Self {
+            broadcast,
+            streams: StreamMap::new(),
+            last_sequence: HashMap::new(),
+            rate_limits: HashMap::new(),
+        }
+    }
+
+    /// Subscribe to a topic. Returns false if already subscribed.
+    pub fn insert(&mut self, topic: &str) -> bool {
+        if self.streams.contains_key(topic) {
+            return false;
+        }
+        let rx = self.broadcast.subscribe(topic);
+        self.streams
+            .insert(Arc::from(topic), BroadcastStream::new(rx));
+        true
+    }
+
+    /// Unsubscribe from a topic. Returns false if not subscribed.
+    pub fn remove(&mut self, topic: &str) -> bool {
+        if self.streams.remove(topic).is_none() {
+            return false;
+        }
+        self.last_sequence.remove(topic);
+        self.rate_limits.remove(topic);
+        self.broadcast.unsubscribe(topic);
+        true
+    }
+
+    /// Yield at most `max_rate_hz` frames per second on `topic`, conflating
+    /// to the latest frame in between. 0 lifts the cap; a frame already
+    /// held is still sent at its slot.
+    pub fn set_max_rate(&mut self, topic: &str, max_rate_hz: u16) {
+        let interval = match max_rate_hz {
+            0 => Duration::ZERO,
+            hz => Duration::from_secs(1) / u32::from(hz),
+        };
+        match self.rate_limits.get_mut(topic) {
+            Some(limit) => limit.interval = interval,
+            None if max_rate_hz > 0 => {
+                let limit = RateLimit {
+                    interval,
+                    next_send: Instant::now(),
+                    held: None,
+                };
+                self.rate_limits.insert(Arc::from(topic), limit);
+            }
+            None => {}
+        }
+    }
+
+    /// Pass `frame` through if `topic` isn't rate-limited or its slot has
+    /// come; otherwise hold it in place of any earlier held frame.
+    fn conflate(&mut self, topic: &str, frame: Frame) -> Option<Frame> {
+        let Some(limit) = self.rate_limits.get_mut(topic) else {
+            return Some(frame);
+        };
+        let now = Instant::now();
+        if limit.held.is_none() && now >= limit.next_send {
+            limit.next_send = now + limit.interval;
+            return Some(frame);
+        }
+        limit.held = Some(frame);
+        None
+    }
+
+    /// The held frame whose slot came first, if any slot has come.
+    fn take_due(&mut self) -> Option<(Arc<str>, Frame)> {
+        let now = Instant::now();
+        let (topic, limit) = self
+            .rate_limits
+            .iter_mut()
+            .filter(|(_, limit)| limit.held.is_some() && limit.next_send <= now)
+            .min_by_key(|(_, limit)| limit.next_send)?;
+        limit.next_send = now + limit.interval;
+        Some((topic.clone(), limit.held.take()?))
+    }
+
+    /// Every held frame, regardless of its slot. Used on shutdown.
+    pub fn take_held(&mut self) -> Vec<(Arc<str>, Frame)> {
+        self.rate_limits
+            .iter_mut()
+            .filter_map(|(topic, limit)| Some((topic.clone(), limit.held.take()?)))
+            .collect()
+    }
+
+    /// Decide whether `frame` can be sent as-is on `topic`.
+    ///
+    /// Full frames always apply. A delta applies only if it directly follows
+    /// the last frame this client received; anything else (first frame after
+    /// subscribing, frames lost to lag) needs a snapshot first.
+    pub fn check(&self, topic: &str, frame: &Frame) -> Delivery {
+        // Engines without sequencing report 0 — nothing to compare against
+        if frame.sequence == 0 {
+            return Delivery::Forward;
+        }
+        let last = self.last_sequence.get(topic).copied();
+        if last.is_some_and(|last| frame.sequence <= last) {
+            return Delivery::Skip;
+        }
+        if frame.delta && last.map(|last| last + 1) != Some(frame.sequence) {
+            return Delivery::Resync;
+        }
+        Delivery::Forward
+    }
+
+    /// Record that the frame (or snapshot) with `sequence` was sent.
+    pub fn mark_sent(&mut self, topic: &Arc<str>, sequence: u64) {
+        self.last_sequence.insert(topic.clone(), sequence);
+    }
+
+    /// Forget what was sent on `topic`, after a queued frame was dropped.
+    /// The next delta on it will be resynced from a snapshot.
+    pub fn forget(&mut self, topic: &str) {
+        self.last_sequence.remove(topic);
+    }
+
+    pub fn contains(&self, topic: &str) -> bool {
+        self.streams.contains_key(topic)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Wait for the next frame on any subscribed topic: a broadcast frame,
+    /// or a held frame of a rate-limited topic once its slot comes.
+    /// Cancel-safe.
+    ///
+    /// Returns None immediately when there are no subscriptions, so guard
+    /// the `select!` branch with `if !streams.is_empty()`.
+    pub async fn next(&mut self) -> Option<(Arc<str>, Result<Frame, BroadcastStreamRecvError>)> {
+        loop {
+            if let Some((topic, frame)) = self.take_due() {
+                return Some((topic, Ok(frame)));
+            }
+            let due = self
+                .rate_limits
+                .values()
+                .filter(|limit| limit.held.is_some())
+                .map(|limit| limit.next_send)
+                .min();
+            let slot = async move {
+                match due {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let item = tokio::select! {
+                item = self.streams.next() => item,
+                _ = slot => continue,
+            };
+            match item? {
+                (topic, Ok(frame)) => match self.conflate(&topic, frame) {
+                    Some(frame) => return Some((topic, Ok(frame))),
+                    None => continue,
+                },
+                lagged => return Some(lagged),
+            }
+        }
+    }
+}
+
+impl Drop for TopicStreams {
+    fn drop(&mut self) {
+        for topic in self.streams.keys() {
+            self.broadcast.unsubscribe(topic);
+        }
+    }
+}
+
+/// How a client's outbound queue makes room when the client can't keep up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Evict the oldest queued frame.
+    DropOldest,
+    /// Keep only the latest full frame per topic: a new full frame replaces
+    /// any still-queued frames for its topic. Deltas can't be merged, so they
+    /// queue normally and fall back to dropping the oldest frame.
+    CoalesceLatest,
+    /// Drop the oldest frame, but close the connection once the client has
+    /// lagged `max_lags` times without draining its queue.
+    Disconnect { max_lags: u32 },
+}
+
+/// Per-client outbound queue settings.
+#[derive(Debug, Clone, Copy)]
+pub struct OutboundConfig {
+    /// Frames, responses and snapshots queued for one client.
+    pub capacity: usize,
+    pub policy: SlowConsumerPolicy,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            policy: SlowConsumerPolicy::CoalesceLatest,
+        }
+    }
+}
+
+/// Returned when a client is disconnected as a slow consumer.
+#[derive(Debug)]
+pub struct SlowConsumer;
+
+/// Outcome of queueing a broadcast frame.
+#[derive(Debug)]
+pub struct Enqueued {
+    /// The frame was queued (a delta whose topic just lost a frame is not).
+    pub queued: bool,
+    /// A queued frame for this topic was dropped to make room.
+    pub lost: Option<Arc<str>>,
+}
+
+struct Outbound {
+    /// Some for frames and snapshots, None for command responses.
+    topic: Option<Arc<str>>,
+    /// Broadcast frames may be dropped or coalesced; responses and snapshots
+    /// never are.
+    droppable: bool,
+    bytes: Bytes,
+}
+
+struct OutboundInner {
+    items: VecDeque<Outbound>,
+    /// Overflows (and broadcast lags) since the queue was last empty.
+    lags: u32,
+    closed: bool,
+    /// Set by `finish()`: the writer sends what's queued, then closes.
+    draining: bool,
+    /// Sent by the writer before it closes the socket.
+    close_frame: Option<CloseFrame>,
+}
+
+/// A client's bounded send queue, drained by its writer task.
+///
+/// The client task pushes without awaiting the socket; the writer pops and
+/// sends. Every drop decision happens in `push_frame()`, on the client
+/// task, so the caller can fix up its sequence tracking right away.
+pub struct OutboundQueue {
+    inner: Mutex<OutboundInner>,
+    notify: Notify,
+    config: OutboundConfig,
+}
+
+impl OutboundQueue {
+    pub fn new(config: OutboundConfig) -> Self {
+        Self {
+            inner: Mutex::new(OutboundInner {
+                items: VecDeque::with_capacity(config.capacity),
+                lags: 0,
+                closed: false,
+                draining: false,
+                close_frame: None,
+            }),
+            notify: Notify::new(),
+            config,
+        }
     }
 
-    /// Send a frame to all connected clients.
+    /// Queue a broadcast frame for `topic`, applying the slow-consumer policy
+    /// if the queue is full.
+    pub fn push_frame(&self, topic: &Arc<str>, frame: &Frame) -> Result<Enqueued, SlowConsumer> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Err(SlowConsumer);
+        }
+
+        if self.config.policy == SlowConsumerPolicy::CoalesceLatest && !frame.delta {
+            // The new full frame supersedes every frame for this topic queued
+            // after its last snapshot
+            let start = inner
+                .items
+                .iter()
+                .rposition(|item| !item.droppable && item.topic.as_ref() == Some(topic))
+                .map_or(0, |i| i + 1);
+            let mut index = 0;
+            inner.items.retain(|item| {
+                let superseded =
+                    index >= start && item.droppable && item.topic.as_ref() == Some(topic);
+                index += 1;
+                !superseded
+            });
+        }
+
+        let mut enqueued = Enqueued {
+            queued: true,
+            lost: None,
+        };
+        if inner.items.len() >= self.config.capacity {
+            let lost = self.overflow(&mut inner)?;
+            // A delta can't follow a dropped frame; the topic will be resynced
+            enqueued.queued = !(frame.delta && lost == *topic);
+            enqueued.lost = Some(lost);
+        }
+        if enqueued.queued {
+            inner.items.push_back(Outbound {
+                topic: Some(topic.clone()),
+                droppable: true,
+                bytes: frame.bytes.clone(),
+            });
+            self.notify.notify_one();
+        }
+        Ok(enqueued)
+    }
+
+    /// Queue a command response (`topic` None) or snapshot. These are never
+    /// dropped; if the queue is full, the oldest frame makes room. Returns
+    /// the topic that lost frames, if any.
     ///
-    /// Wraps the bytes in Arc so all receivers share the same allocation.
-    /// Returns the number of receivers that will receive the message.
-    pub fn send(&self, bytes: Vec<u8>) -> usize {
-        // Ignore error when no receivers are connected
-        self.tx.send(Arc::new(bytes)).unwrap_or(0)
+    /// A client that lets responses and snapshots alone fill the queue has
+    /// stopped reading and is disconnected.
+    pub fn push_control(
+        &self,
+        topic: Option<Arc<str>>,
+        bytes: Bytes,
+    ) -> Result<Option<Arc<str>>, SlowConsumer> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Err(SlowConsumer);
+        }
+        let lost = if inner.items.len() >= self.config.capacity {
+            Some(self.overflow(&mut inner)?)
+        } else {
+            None
+        };
+        inner.items.push_back(Outbound {
+            topic,
+            droppable: false,
+            bytes,
+        });
+        self.notify.notify_one();
+        Ok(lost)
+    }
+
+    /// Make room for one item: count a lag and drop the oldest frame (and
+    /// the frames queued after it on the same topic, which depend on it).
+    /// Returns the topic that lost frames.
+    fn overflow(&self, inner: &mut OutboundInner) -> Result<Arc<str>, SlowConsumer> {
+        inner.lags += 1;
+        if let SlowConsumerPolicy::Disconnect { max_lags } = self.config.policy {
+            if inner.lags >= max_lags {
+                return Err(self.kick(inner));
+            }
+        }
+        let Some(oldest) = inner.items.iter().position(|item| item.droppable) else {
+            // Nothing but responses and snapshots queued: the client has
+            // stopped reading altogether
+            return Err(self.kick(inner));
+        };
+        let lost = inner.items[oldest]
+            .topic
+            .clone()
+            .expect("frames have a topic");
+        let mut index = 0;
+        inner.items.retain(|item| {
+            let dropped = index >= oldest && item.droppable && item.topic.as_ref() == Some(&lost);
+            index += 1;
+            !dropped
+        });
+        Ok(lost)
+    }
+
+    /// Record a broadcast lag (frames skipped before they reached the
+    /// queue). Counts toward `SlowConsumerPolicy::Disconnect`.
+    pub fn record_lag(&self) -> Result<(), SlowConsumer> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.lags += 1;
+        match self.config.policy {
+            SlowConsumerPolicy::Disconnect { max_lags } if inner.lags >= max_lags => {
+                Err(self.kick(&mut inner))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Close the queue. The writer discards anything still queued, sends
+    /// `close_frame` if given, and exits.
+    pub fn close(&self, close_frame: Option<CloseFrame>) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.closed {
+            inner.closed = true;
+            inner.close_frame = close_frame;
+        }
+        self.notify.notify_one();
     }
 
-    /// Subscribe to the broadcast channel.
-    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<u8>>> {
-        self.tx.subscribe()
+    /// Close the queue once everything already queued has been sent. The
+    /// writer then sends `close_frame` and exits.
+    pub fn finish(&self, close_frame: CloseFrame) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.closed && !inner.draining {
+            inner.draining = true;
+            inner.close_frame = Some(close_frame);
+        }
+        self.notify.notify_one();
+    }
+
+    fn kick(&self, inner: &mut OutboundInner) -> SlowConsumer {
+        warn!("Disconnecting slow consumer after {} lags", inner.lags);
+        inner.closed = true;
+        inner.close_frame = Some(CloseFrame {
+            code: close_code::AGAIN,
+            reason: "slow consumer".into(),
+        });
+        self.notify.notify_one();
+        SlowConsumer
+    }
+
+    /// Wait for the next item to send. Returns None once the queue is closed.
+    pub async fn pop(&self) -> Option<Outgoing> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.closed {
+                    return None;
+                }
+                if let Some(item) = inner.items.pop_front() {
+                    if inner.items.is_empty() {
+                        // Caught up — lags only count while the backlog persists
+                        inner.lags = 0;
+                    }
+                    return Some(Outgoing {
+                        is_frame: item.topic.is_some(),
+                        bytes: item.bytes,
+                    });
+                }
+                if inner.draining {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn take_close_frame(&self) -> Option<CloseFrame> {
+        self.inner.lock().unwrap().close_frame.take()
+    }
+}
+
+/// An item popped from an `OutboundQueue`.
+pub struct Outgoing {
+    pub bytes: Bytes,
+    /// A frame or snapshot (compressible), as opposed to a command response.
+    pub is_frame: bool,
+}
+
+/// How long a disconnecting client's writer gets to send its close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Drain `queue` into the socket until the queue closes or the client goes
+/// away, then send the close frame (if any). With a `compressor`, frames and
+/// snapshots are sent as envelopes, preceded by a dictionary message when
+/// the shared dictionary changes.
+async fn write_outbound(
+    mut ws_tx: SplitSink<WebSocket, Message>,
+    queue: Arc<OutboundQueue>,
+    mut compressor: Option<Compressor>,
+) {
+    while let Some(item) = queue.pop().await {
+        let (dictionary, bytes) = match &mut compressor {
+            Some(compressor) if item.is_frame => compressor.encode(&item.bytes),
+            _ => (None, item.bytes),
+        };
+        for bytes in dictionary.into_iter().chain([bytes]) {
+            if ws_tx.send(Message::Binary(bytes)).await.is_err() {
+                queue.close(None);
+                return;
+            }
+        }
+    }
+    if let Some(frame) = queue.take_close_frame() {
+        let _ = ws_tx.send(Message::Close(Some(frame))).await;
     }
 }
 
 /// Axum handler that upgrades HTTP to WebSocket.
 ///
+/// A token in the `token` query parameter or `Authorization` header is
+/// verified before upgrading; a bad one gets HTTP 401. Without a token the
+/// upgrade proceeds and the client must send one as its first message,
+/// unless the authenticator admits anonymous clients. Once shutdown has
+/// begun, upgrades are refused with 503. A `schemas` parameter that doesn't
+/// match the server's schemas gets the socket closed with 4001. If
+/// compression is enabled, the `orgasm.lz4` subprotocol is accepted when
+/// the client offers it.
+///
 /// Mount on your router:
 /// ```rust
-/// Router::new().route("/ws", get(ws_handler)).with_state(state)
+/// Router::new().route("/ws", get(ws_handler)).with_state(ServerState { broadcast, engines, outbound, auth, shutdown })
 /// ```
-pub async fn ws_handler(
+pub async fn ws_handler<S: SnapshotSource>(
     ws: WebSocketUpgrade,
-    State(state): State<BroadcastState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_client(socket, state))
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    State(state): State<ServerState<S>>,
+) -> Response {
+    let Some(guard) = state.shutdown.client_guard() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response();
+    };
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let token = request_token(params.get("token").map(String::as_str), authorization);
+    let principal = match state.auth.authenticate(token) {
+        Ok(principal) => Some(principal),
+        // Expect the token as the first message
+        Err(AuthError::MissingToken) => None,
+        Err(e) => {
+            warn!("Rejected connection: {e}");
+            return (StatusCode::UNAUTHORIZED, e.to_string()).into_response();
+        }
+    };
+    if let Some(Err(e)) = params
+        .get("schemas")
+        .map(|offered| check_schemas(offered, SCHEMAS))
+    {
+        warn!("Rejected connection: {e}");
+        let frame = CloseFrame {
+            code: SCHEMA_MISMATCH_CLOSE_CODE,
+            reason: e.to_string().into(),
+        };
+        return ws.on_upgrade(move |mut socket| async move {
+            let _ = socket.send(Message::Close(Some(frame))).await;
+        });
+    }
+    let ws = match state.compression {
+        Some(_) => ws.protocols([LZ4_PROTOCOL]),
+        None => ws,
+    };
+    ws.on_upgrade(move |socket| handle_client(socket, state, principal, guard))
+}
+
+/// Wait for a token sent as the client's first (text) message. On failure
+/// the socket is closed with a policy-violation code and None is returned.
+async fn authenticate_first_message(
+    socket: &mut WebSocket,
+    auth: &dyn Authenticator,
+) -> Option<Principal> {
+    let result = match tokio::time::timeout(AUTH_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(token)))) => auth.authenticate(Some(bearer(token.as_str()))),
+        Ok(Some(Ok(_))) => Err(AuthError::InvalidToken(
+            "expected token as first message".into(),
+        )),
+        Ok(_) => return None, // Disconnected
+        Err(_) => Err(AuthError::MissingToken),
+    };
+    match result {
+        Ok(principal) => Some(principal),
+        Err(e) => {
+            warn!("Rejected connection: {e}");
+            let frame = CloseFrame {
+                code: close_code::POLICY,
+                reason: "unauthorized".into(),
+            };
+            let _ = socket.send(Message::Close(Some(frame))).await;
+            None
+        }
+    }
 }
 
 /// Per-client WebSocket forward loop.
 ///
-/// Subscribes to the broadcast channel and forwards every frame as a
-/// binary WebSocket message. Handles client disconnect gracefully.
+/// A new client starts with no subscriptions. `Subscribe` / `Unsubscribe`
+/// commands (see command_handler.rs) add and remove topics from the
+/// client's `TopicStreams`, and only frames for those topics are forwarded
+/// as binary WebSocket messages. Handles client disconnect gracefully.
 ///
-/// If the client falls behind (broadcast channel lags), we skip ahead
-/// to the latest frame rather than disconnecting.
+/// Frames, responses and snapshots go through the client's `OutboundQueue`
+/// to a separate writer task, so this loop never waits on the socket. If
+/// the client falls behind (broadcast channel lags, or its queue overflows),
+/// frames are dropped per `SlowConsumerPolicy` rather than buffered without
+/// bound. Full frames just resume; a delta stream is resynced with a
+/// snapshot (see `TopicStreams::check`).
 ///
-/// ## Snapshot-on-connect (gap-free startup)
+/// ## Snapshot-on-subscribe (gap-free startup)
 ///
-/// To ensure clients receive complete state on connect without missing
-/// frames, subscribe to broadcast FIRST, then send a snapshot, then enter
-/// the forward loop. Because the subscription is created before the
-/// snapshot is sent, any frames produced during snapshot serialization
-/// are buffered in the broadcast channel and forwarded after the snapshot.
+/// Command handlers queue topics in `ClientState.pending_snapshots`
+/// (Subscribe queues the new topic, RequestSnapshot the requested one).
+/// After each command, this loop sends those snapshots to this client only.
 ///
-/// ```rust
-/// // 1. Subscribe BEFORE snapshot — no frames can be missed
-/// let mut rx = state.subscribe();
+/// Subscribe inserts the topic into `TopicStreams` BEFORE the snapshot is
+/// taken, so frames produced while the snapshot is serialized are buffered
+/// in the broadcast channel. The snapshot's sequence is then recorded and
+/// buffered frames it already covers are skipped — no gap, no duplicates.
 ///
-/// // 2. Send snapshot (full current state)
-/// // if let Some(snapshot) = engine.lock().await.snapshot(&mut builder) {
-/// //     ws_tx.send(Message::Binary(snapshot.into())).await.ok();
-/// // }
+/// ## Shutdown
 ///
-/// // 3. Enter forward loop — buffered frames delivered in order
-/// ```
-async fn handle_client(socket: WebSocket, state: BroadcastState) {
-    let (mut ws_tx, mut ws_rx) = socket.split();
-    let mut rx = state.subscribe();
-
-    info!("Client connected");
-
-    // --- Snapshot-on-connect template ---
-    // Uncomment to send full state snapshot before forwarding live frames.
-    // The broadcast subscription is already active, so no gap is possible.
-    //
-    // if let Some(snapshot) = engine.lock().await.snapshot(&mut builder) {
-    //     if ws_tx.send(Message::Binary(snapshot.into())).await.is_err() {
-    //         info!("Client disconnected during snapshot");
-    //         return;
-    //     }
-    // }
+/// When clients are told to close, the engines have already made their
+/// final tick. Frames still buffered for this client's topics are queued,
+/// the outbound queue is flushed (bounded by the drain timeout), and the
+/// socket is closed with 1012 and a `reconnect-after` hint.
+///
+/// `_guard` keeps the connection counted until this task returns.
+async fn handle_client<S: SnapshotSource>(
+    mut socket: WebSocket,
+    state: ServerState<S>,
+    principal: Option<Principal>,
+    _guard: TaskTrackerToken,
+) {
+    let principal = match principal {
+        Some(principal) => principal,
+        None => match authenticate_first_message(&mut socket, state.auth.as_ref()).await {
+            Some(principal) => principal,
+            None => return,
+        },
+    };
+    let subject = principal.subject.clone();
+    let compressor = state
+        .compression
+        .clone()
+        .filter(|compression| compression.negotiated(socket.protocol()))
+        .map(Compressor::new);
+    let compressed = compressor.is_some();
+    let (ws_tx, mut ws_rx) = socket.split();
+    let mut client_state = ClientState::new(state.broadcast, principal);
+    let outbound = Arc::new(OutboundQueue::new(state.outbound));
+    let mut writer = tokio::spawn(write_outbound(ws_tx, outbound.clone(), compressor));
+
+    info!(
+        "Client connected: {subject}{}",
+        if compressed { " (lz4)" } else { "" }
+    );
+    state.metrics.client_connected();
+
+    let closing = state.shutdown.clients_closing();
+    tokio::pin!(closing);
+    let mut shutting_down = false;
 
     loop {
         tokio::select! {
-            // Forward broadcast frames to this client
-            result = rx.recv() => {
-                match result {
-                    Ok(bytes) => {
-                        if ws_tx.send(Message::Binary((*bytes).clone().into())).await.is_err() {
-                            break; // Client disconnected
+            _ = &mut closing => {
+                queue_remaining_frames(&outbound, &mut client_state.topics);
+                outbound.finish(state.shutdown.close_frame());
+                shutting_down = true;
+                break;
+            }
+            // Queue frames for subscribed topics for this client
+            Some((topic, result)) = client_state.topics.next(), if !client_state.topics.is_empty() => {
+                let frame = match result {
+                    Ok(frame) => frame,
+                    Err(BroadcastStreamRecvError::Lagged(n)) => {
+                        // The next delta will fail check() and trigger a resync
+                        warn!("Client lagged on {topic}, skipped {n} frames");
+                        state.metrics.record_lag(n);
+                        if outbound.record_lag().is_err() {
+                            break;
                         }
+                        continue;
                     }
-                    Err(broadcast::error::RecvError::Lagged(n)) => {
-                        warn!("Client lagged, skipped {n} frames");
-                        // Continue — next recv() gets the latest frame
-                    }
-                    Err(broadcast::error::RecvError::Closed) => {
-                        break; // Server shutting down
+                };
+
+                match client_state.topics.check(&topic, &frame) {
+                    Delivery::Skip => continue,
+                    Delivery::Forward => {}
+                    Delivery::Resync => {
+                        match send_snapshot(&outbound, &state.engines, &mut client_state.topics, &topic).await {
+                            Err(SlowConsumer) => break,
+                            // Forward this delta only if it follows the snapshot
+                            Ok(true) if client_state.topics.check(&topic, &frame) != Delivery::Forward => continue,
+                            Ok(true) => {}
+                            Ok(false) => {
+                                // No snapshot support — forward and let the client
+                                // detect the sequence gap itself
+                                warn!("No snapshot for {topic}, forwarding delta {}", frame.sequence);
+                            }
+                        }
                     }
                 }
+
+                let Ok(enqueued) = outbound.push_frame(&topic, &frame) else {
+                    break;
+                };
+                if let Some(lost) = enqueued.lost {
+                    state.metrics.record_outbound_drop();
+                    client_state.topics.forget(&lost);
+                }
+                if enqueued.queued {
+                    client_state.topics.mark_sent(&topic, frame.sequence);
+                } else {
+                    state.metrics.record_outbound_drop();
+                }
             }
-            // Handle client messages (close, ping, etc.)
+            // Handle client commands (binary) and control messages
             msg = ws_rx.next() => {
                 match msg {
+                    Some(Ok(Message::Binary(bytes))) => {
+                        if let Some(response) = handle_client_message(&bytes, &mut client_state, &state.metrics) {
+                            match outbound.push_control(None, response) {
+                                Ok(lost) => {
+                                    if let Some(lost) = lost {
+                                        state.metrics.record_outbound_drop();
+                                        client_state.topics.forget(&lost);
+                                    }
+                                }
+                                Err(SlowConsumer) => break,
+                            }
+                        }
+                        let mut disconnected = false;
+                        for topic in std::mem::take(&mut client_state.pending_snapshots) {
+                            let topic: Arc<str> = Arc::from(topic);
+                            if send_snapshot(&outbound, &state.engines, &mut client_state.topics, &topic).await.is_err() {
+                                disconnected = true;
+                                break;
+                            }
+                        }
+                        if disconnected {
+                            break;
+                        }
+                    }
                     Some(Ok(Message::Close(_))) | None => break,
                     Some(Err(_)) => break,
-                    _ => {} // Ignore client-to-server messages
+                    _ => {} // Ignore text, ping, pong
                 }
             }
         }
     }
 
-    info!("Client disconnected");
+    // Dropping client_state releases every topic this client subscribed to
+    drop(client_state);
+    let flush_timeout = if shutting_down {
+        state.shutdown.drain_timeout()
+    } else {
+        outbound.close(None);
+        CLOSE_TIMEOUT
+    };
+    // A client that stopped reading can block the writer mid-send
+    if tokio::time::timeout(flush_timeout, &mut writer)
+        .await
+        .is_err()
+    {
+        writer.abort();
+    }
+    state.metrics.client_disconnected();
+    info!("Client disconnected: {subject}");
+}
+
+/// Queue every frame already waiting in `topics` without blocking, plus the
+/// frames held back by rate limits. Used on shutdown; anything that would
+/// need a resync is skipped, since the client gets a snapshot when it
+/// reconnects.
+fn queue_remaining_frames(outbound: &OutboundQueue, topics: &mut TopicStreams) {
+    let mut remaining = Vec::new();
+    while let Some(Some((topic, result))) = topics.next().now_or_never() {
+        if let Ok(frame) = result {
+            remaining.push((topic, frame));
+        }
+    }
+    remaining.extend(topics.take_held());
+
+    for (topic, frame) in remaining {
+        if topics.check(&topic, &frame) != Delivery::Forward {
+            continue;
+        }
+        match outbound.push_frame(&topic, &frame) {
+            Ok(enqueued) if enqueued.queued => topics.mark_sent(&topic, frame.sequence),
+            Ok(_) => {}
+            Err(SlowConsumer) => return,
+        }
+    }
+}
+
+/// Queue `topic`'s current snapshot for this client only, and record its
+/// sequence so buffered frames it already covers are skipped.
+///
+/// Returns `Ok(false)` if the engine has no snapshot (e.g. the topic's
+/// engine hasn't been created yet), `Err` if the client was disconnected.
+async fn send_snapshot<S: SnapshotSource>(
+    outbound: &OutboundQueue,
+    engines: &S,
+    topics: &mut TopicStreams,
+    topic: &Arc<str>,
+) -> Result<bool, SlowConsumer> {
+    let Some(snapshot) = engines.snapshot(topic).await else {
+        return Ok(false);
+    };
+    let header = Header {
+        kind: FrameKind::Snapshot,
+        topic_id: my_shared::topic_id(topic),
+        sequence: snapshot.sequence,
+        schema_version: snapshot.schema_version,
+        delta: false,
+    };
+    if let Some(lost) =
+        outbound.push_control(Some(topic.clone()), envelope::wrap(header, &snapshot.bytes))?
+    {
+        topics.forget(&lost);
+    }
+    topics.mark_sent(topic, snapshot.sequence);
+    Ok(true)
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(capacity: usize, policy: SlowConsumerPolicy) -> OutboundQueue {
+        OutboundQueue::new(OutboundConfig { capacity, policy })
+    }
+
+    fn full(sequence: u64, bytes: &'static str) -> Frame {
+        Frame::full(sequence, Bytes::from_static(bytes.as_bytes()))
+    }
+
+    fn delta(sequence: u64, bytes: &'static str) -> Frame {
+        Frame::delta(sequence, Bytes::from_static(bytes.as_bytes()))
+    }
+
+    fn control(bytes: &'static str) -> Bytes {
+        Bytes::from_static(bytes.as_bytes())
+    }
+
+    /// Queued items, oldest first.
+    fn queued(queue: &OutboundQueue) -> Vec<String> {
+        let inner = queue.inner.lock().unwrap();
+        inner
+            .items
+            .iter()
+            .map(|item| String::from_utf8(item.bytes.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_drop_oldest_drops_the_oldest_frame_and_its_topic_followers() {
+        let (a, b): (Arc<str>, Arc<str>) = (Arc::from("A"), Arc::from("B"));
+        let queue = queue(3, SlowConsumerPolicy::DropOldest);
+        queue.push_frame(&a, &full(1, "a1")).unwrap();
+        queue.push_frame(&b, &full(1, "b1")).unwrap();
+        queue.push_frame(&a, &full(2, "a2")).unwrap();
+
+        // a1 is the oldest; a2 is dropped with it, since it came after
+        let enqueued = queue.push_frame(&b, &full(2, "b2")).unwrap();
+        assert!(enqueued.queued);
+        assert_eq!(enqueued.lost, Some(a.clone()));
+        assert_eq!(queued(&queue), ["b1", "b2"]);
+    }
+
+    #[test]
+    fn test_delta_after_its_topic_lost_a_frame_is_not_queued() {
+        let (a, b): (Arc<str>, Arc<str>) = (Arc::from("A"), Arc::from("B"));
+        let queue = queue(2, SlowConsumerPolicy::DropOldest);
+        queue.push_frame(&a, &full(1, "a1")).unwrap();
+        queue.push_frame(&b, &full(1, "b1")).unwrap();
+
+        // a2 can't follow the dropped a1: the topic is resynced instead
+        let enqueued = queue.push_frame(&a, &delta(2, "a2")).unwrap();
+        assert!(!enqueued.queued);
+        assert_eq!(enqueued.lost, Some(a.clone()));
+        assert_eq!(queued(&queue), ["b1"]);
+
+        // A delta is still queued when another topic lost the frame
+        let enqueued = queue.push_frame(&a, &full(3, "a3")).unwrap();
+        assert!(enqueued.queued && enqueued.lost.is_none());
+        let enqueued = queue.push_frame(&a, &delta(4, "a4")).unwrap();
+        assert!(enqueued.queued);
+        assert_eq!(enqueued.lost, Some(b.clone()));
+        assert_eq!(queued(&queue), ["a3", "a4"]);
+    }
+
+    #[test]
+    fn test_coalesce_latest_replaces_queued_frames_of_the_topic() {
+        let (a, b): (Arc<str>, Arc<str>) = (Arc::from("A"), Arc::from("B"));
+        let queue = queue(3, SlowConsumerPolicy::CoalesceLatest);
+        queue.push_frame(&a, &full(1, "a1")).unwrap();
+        queue.push_frame(&b, &full(1, "b1")).unwrap();
+        queue.push_frame(&a, &delta(2, "a2")).unwrap();
+
+        // A full frame supersedes a1 and a2, so nothing overflows
+        let enqueued = queue.push_frame(&a, &full(3, "a3")).unwrap();
+        assert!(enqueued.queued && enqueued.lost.is_none());
+        assert_eq!(queued(&queue), ["b1", "a3"]);
+        assert_eq!(queue.inner.lock().unwrap().lags, 0);
+    }
+
+    #[test]
+    fn test_coalesce_latest_keeps_frames_before_a_snapshot() {
+        let a: Arc<str> = Arc::from("A");
+        let queue = queue(4, SlowConsumerPolicy::CoalesceLatest);
+        queue.push_frame(&a, &full(1, "a1")).unwrap();
+        queue
+            .push_control(Some(a.clone()), control("snapshot"))
+            .unwrap();
+        queue.push_frame(&a, &delta(3, "a3")).unwrap();
+
+        // Only the frames after the snapshot are superseded
+        queue.push_frame(&a, &full(4, "a4")).unwrap();
+        assert_eq!(queued(&queue), ["a1", "snapshot", "a4"]);
+    }
+
+    #[test]
+    fn test_coalesce_latest_drops_the_oldest_when_full_of_deltas() {
+        let (a, b): (Arc<str>, Arc<str>) = (Arc::from("A"), Arc::from("B"));
+        let queue = queue(2, SlowConsumerPolicy::CoalesceLatest);
+        queue.push_frame(&a, &delta(1, "a1")).unwrap();
+        queue.push_frame(&a, &delta(2, "a2")).unwrap();
+
+        let enqueued = queue.push_frame(&b, &delta(1, "b1")).unwrap();
+        assert!(enqueued.queued);
+        assert_eq!(enqueued.lost, Some(a.clone()));
+        assert_eq!(queued(&queue), ["b1"]);
+    }
+
+    #[test]
+    fn test_disconnect_after_max_lags() {
+        let a: Arc<str> = Arc::from("A");
+        let queue = queue(1, SlowConsumerPolicy::Disconnect { max_lags: 2 });
+        queue.push_frame(&a, &full(1, "a1")).unwrap();
+        assert_eq!(
+            queue.push_frame(&a, &full(2, "a2")).unwrap().lost,
+            Some(a.clone())
+        );
+
+        assert!(queue.push_frame(&a, &full(3, "a3")).is_err());
+        assert!(queue.push_control(None, control("response")).is_err());
+        let close_frame = queue.take_close_frame().unwrap();
+        assert_eq!(close_frame.code, close_code::AGAIN);
+        assert!(queue.pop().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_draining_the_queue_resets_lags() {
+        let a: Arc<str> = Arc::from("A");
+        let queue = queue(1, SlowConsumerPolicy::Disconnect { max_lags: 2 });
+        queue.push_frame(&a, &full(1, "a1")).unwrap();
+        queue.push_frame(&a, &full(2, "a2")).unwrap();
+
+        // Caught up: the earlier lag no longer counts
+        assert_eq!(queue.pop().now_or_never().unwrap().unwrap().bytes, "a2");
+        queue.push_frame(&a, &full(3, "a3")).unwrap();
+        queue.push_frame(&a, &full(4, "a4")).unwrap();
+        assert_eq!(queued(&queue), ["a4"]);
+
+        // Broadcast lags count too
+        assert!(queue.record_lag().is_err());
+    }
+
+    #[test]
+    fn test_control_messages_are_never_dropped() {
+        let a: Arc<str> = Arc::from("A");
+        for policy in [
+            SlowConsumerPolicy::DropOldest,
+            SlowConsumerPolicy::CoalesceLatest,
+            SlowConsumerPolicy::Disconnect { max_lags: 10 },
+        ] {
+            let queue = queue(3, policy);
+            queue.push_control(None, control("response")).unwrap();
+            queue.push_frame(&a, &full(1, "a1")).unwrap();
+            queue
+                .push_control(Some(a.clone()), control("snapshot"))
+                .unwrap();
+
+            // Frames make room for frames and control messages alike
+            assert_eq!(
+                queue.push_frame(&a, &full(2, "a2")).unwrap().lost,
+                Some(a.clone())
+            );
+            assert_eq!(queued(&queue), ["response", "snapshot", "a2"], "{policy:?}");
+            assert_eq!(
+                queue.push_control(None, control("response2")).unwrap(),
+                Some(a.clone())
+            );
+            assert_eq!(
+                queued(&queue),
+                ["response", "snapshot", "response2"],
+                "{policy:?}"
+            );
+
+            // Nothing droppable left: the client is disconnected, and what
+            // was queued is left alone
+            assert!(queue.push_frame(&a, &full(3, "a3")).is_err());
+            assert_eq!(
+                queued(&queue),
+                ["response", "snapshot", "response2"],
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_finish_sends_queued_items_then_closes() {
+        let queue = queue(4, SlowConsumerPolicy::DropOldest);
+        queue.push_control(None, control("response")).unwrap();
+        queue.finish(CloseFrame {
+            code: close_code::RESTART,
+            reason: "restart".into(),
+        });
+
+        assert_eq!(
+            queue.pop().now_or_never().unwrap().unwrap().bytes,
+            "response"
+        );
+        assert!(queue.pop().now_or_never().unwrap().is_none());
+        assert_eq!(queue.take_close_frame().unwrap().code, close_code::RESTART);
+    }
+
+    // --- Per-client rate conflation ---
+
+    const TOPIC: &str = "BTC-USD";
+
+    fn streams(max_rate_hz: u16) -> TopicStreams {
+        let mut streams = TopicStreams::new(BroadcastState::new(16));
+        streams.insert(TOPIC);
+        streams.set_max_rate(TOPIC, max_rate_hz);
+        streams
+    }
+
+    fn sequence(frame: Option<Frame>) -> Option<u64> {
+        frame.map(|frame| frame.sequence)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_rate_passes_every_frame() {
+        let mut streams = streams(0);
+        for sequence in 1..=3 {
+            assert_eq!(
+                self::sequence(streams.conflate(TOPIC, full(sequence, ""))),
+                Some(sequence)
+            );
+        }
+        assert!(streams.take_due().is_none());
+
+        // Lifting a cap sends what's held at its slot, then passes through
+        streams.set_max_rate(TOPIC, 10);
+        streams.conflate(TOPIC, full(4, ""));
+        assert_eq!(sequence(streams.conflate(TOPIC, full(5, ""))), None);
+        streams.set_max_rate(TOPIC, 0);
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(streams.take_due().map(|(_, f)| f.sequence), Some(5));
+        assert_eq!(sequence(streams.conflate(TOPIC, full(6, ""))), Some(6));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latest_frame_wins_within_an_interval() {
+        let mut streams = streams(10);
+        assert_eq!(sequence(streams.conflate(TOPIC, full(1, ""))), Some(1));
+        assert_eq!(sequence(streams.conflate(TOPIC, full(2, ""))), None);
+        assert_eq!(sequence(streams.conflate(TOPIC, full(3, ""))), None);
+
+        tokio::time::advance(Duration::from_millis(99)).await;
+        assert!(streams.take_due().is_none());
+
+        // Frames arriving while one is held replace it, even once due
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(sequence(streams.conflate(TOPIC, full(4, ""))), None);
+        let (topic, frame) = streams.take_due().unwrap();
+        assert_eq!((&*topic, frame.sequence), (TOPIC, 4));
+        assert!(streams.take_due().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_next_releases_held_frames_when_due() {
+        let broadcast = BroadcastState::new(16);
+        let mut streams = TopicStreams::new(broadcast.clone());
+        streams.insert(TOPIC);
+        streams.set_max_rate(TOPIC, 10);
+        let start = Instant::now();
+
+        broadcast.send(TOPIC, full(1, "one"));
+        broadcast.send(TOPIC, full(2, "two"));
+        broadcast.send(TOPIC, full(3, "three"));
+        let (_, frame) = streams.next().await.unwrap();
+        assert_eq!(frame.unwrap().sequence, 1);
+
+        // 2 and 3 arrive before the next slot; only 3 is sent, at the slot
+        let (_, frame) = streams.next().await.unwrap();
+        assert_eq!(frame.unwrap().sequence, 3);
+        assert_eq!(Instant::now() - start, Duration::from_millis(100));
+
+        broadcast.send(TOPIC, full(4, "four"));
+        broadcast.send(TOPIC, full(5, "five"));
+        let (_, frame) = streams.next().await.unwrap();
+        assert_eq!(frame.unwrap().sequence, 5);
+        assert_eq!(Instant::now() - start, Duration::from_millis(200));
+        assert!(streams.take_held().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_conflated_delta_is_resynced() {
+        let topic: Arc<str> = Arc::from(TOPIC);
+        let mut streams = streams(10);
+        let first = streams.conflate(TOPIC, delta(1, "")).unwrap();
+        assert_eq!(streams.check(TOPIC, &first), Delivery::Resync);
+        streams.mark_sent(&topic, 0);
+        assert_eq!(streams.check(TOPIC, &first), Delivery::Forward);
+        streams.mark_sent(&topic, first.sequence);
+
+        // Delta 2 is conflated away: 3 doesn't follow 1
+        assert!(streams.conflate(TOPIC, delta(2, "")).is_none());
+        assert!(streams.conflate(TOPIC, delta(3, "")).is_none());
+        tokio::time::advance(Duration::from_millis(100)).await;
+        let (_, held) = streams.take_due().unwrap();
+        assert_eq!(held.sequence, 3);
+        assert_eq!(streams.check(TOPIC, &held), Delivery::Resync);
+
+        // After the snapshot, the next delta follows on
+        streams.mark_sent(&topic, 3);
+        tokio::time::advance(Duration::from_millis(100)).await;
+        let next = streams.conflate(TOPIC, delta(4, "")).unwrap();
+        assert_eq!(streams.check(TOPIC, &next), Delivery::Forward);
+    }
+
+    #[test]
+    fn test_send_reaches_only_subscribers() {
+        use crate::generated::envelope_generated::org_asm::envelope::Envelope;
+
+        let broadcast = BroadcastState::new(16);
+        assert_eq!(broadcast.send("BTC-USD", full(1, "a")), 0);
+
+        let mut rx = broadcast.subscribe("BTC-USD");
+        assert_eq!(broadcast.send("BTC-USD", delta(2, "b")), 1);
+        let frame = rx.try_recv().unwrap();
+        assert_eq!((frame.sequence, frame.delta), (2, true));
+        let envelope = flatbuffers::root::<Envelope>(&frame.bytes).unwrap();
+        assert_eq!(envelope.topic_id(), my_shared::topic_id("BTC-USD"));
+        assert_eq!(envelope.payload().unwrap().bytes(), b"b");
+
+        broadcast.unsubscribe("BTC-USD");
+        drop(rx);
+        assert_eq!(broadcast.send("BTC-USD", full(3, "c")), 0);
+    }
 }
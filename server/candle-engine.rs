@@ -0,0 +1,469 @@
+//! # Candle Engine
+//!
+//! Reference `ServerEngine` that ingests trades and maintains OHLCV bars at
+//! several resolutions at once (1s, 1m and 5m by default), serialized with
+//! `schema/candles.fbs`.
+//!
+//! ## Architecture
+//!
+//! ```text
+//! Exchange trades ──→ ingest(msg) ──→ add_trade(trade)
+//!                                        │  one bar update per resolution
+//!                                        ▼
+//!                  ┌── series ─────────────────────────────────────┐
+//!                  │  1s: [.., 12:00:41, 12:00:42, 12:00:43*]      │
+//!                  │  1m: [.., 11:59, 12:00*]                      │  * changed since
+//!                  │  5m: [.., 11:55, 12:00*]                      │    the last frame
+//!                  └───────────────────────────────────────────────┘
+//!                                        │
+//!              tick_delta() ──→ changed bars + new trades   (every tick)
+//!              snapshot()   ──→ full history + recent trades (subscribe, resync)
+//! ```
+//!
+//! Run it with delta frames: a full frame holds every bar of every
+//! resolution (tens of KB), a delta frame usually one bar per resolution.
+//! Clients get the history from the snapshot sent on subscribe, then apply
+//! each delta by replacing their bars from the first delta bar's
+//! `open_time_ms` onward. Gaps are resynced with a snapshot like any other
+//! delta stream.
+//!
+//! ```rust
+//! let registry = EngineRegistry::new(|symbol| CandleEngine::new(symbol, CandleConfig::default()), route_by_symbol)
+//!     .with_delta_frames(true);
+//! ```
+//!
+//! Candles have no depth, so the topic is the bare symbol: clients
+//! subscribe with `depth: 0` (`my_shared::depth_topic("BTC-USD", 0)` is
+//! "BTC-USD") and route it with `EnvelopeRouter.onTopic('BTC-USD', ...)`.
+//!
+//! ## Bars
+//!
+//! A trade at `t` lands in the bar opening at `t - t % resolution`. Bars
+//! are created by trades, so intervals without trades have no bar, and a
+//! bar is final once a newer one exists. Late trades update the high, low,
+//! volume and count of the bar they belong to if it is still in the
+//! history (the delta then starts there), but not its close; trades older
+//! than the history are dropped. Each resolution keeps its
+//! newest `CandleConfig::history` bars.
+//!
+//! ## Input
+//!
+//! `ingest()` expects one JSON trade per message, the format
+//! `route_by_symbol()` in main.rs already routes:
+//!
+//! ```text
+//! {"symbol": "BTC-USD", "price": 64000.5, "size": 0.25, "side": "buy", "timestamp_ms": 1700000000123}
+//! ```
+//!
+//! Adapt `parse_trade()` to your exchange, or call `add_trade()` directly.
+
+use std::time::Duration;
+
+use flatbuffers::FlatBufferBuilder;
+use my_shared::validate_positive;
+
+use crate::engine_trait::ServerEngine;
+// Generated from schema/candles.fbs. Replace this path with your actual
+// generated module.
+use crate::generated::candles_generated::org_asm::candles::{
+    Candle, CandleFrame, CandleFrameArgs, CandleSeries, CandleSeriesArgs, Side, Trade,
+};
+
+/// File identifier every candle frame is finished with.
+pub const CANDLES_IDENTIFIER: &str = "OACD";
+
+/// Which bars to keep.
+#[derive(Debug, Clone)]
+pub struct CandleConfig {
+    /// Bar sizes, each a whole number of milliseconds.
+    pub resolutions: Vec<Duration>,
+    /// Bars kept per resolution.
+    pub history: usize,
+    /// Trades included in full frames and snapshots.
+    pub recent_trades: usize,
+}
+
+impl Default for CandleConfig {
+    fn default() -> Self {
+        Self {
+            resolutions: vec![Duration::from_secs(1), Duration::from_secs(60), Duration::from_secs(300)],
+            history: 500,
+            recent_trades: 50,
+        }
+    }
+}
+
+/// Bars of one resolution, oldest first.
+struct Series {
+    resolution_ms: u64,
+    candles: Vec<Candle>,
+    /// Index of the oldest bar changed since the last frame.
+    changed_from: Option<usize>,
+}
+
+impl Series {
+    /// Fold a trade into its bar. `late`: an earlier trade than the newest
+    /// one seen, which mustn't move a close. Returns false if the trade is
+    /// older than the history.
+    fn apply(&mut self, trade: &Trade, late: bool, history: usize) -> bool {
+        let open_time = trade.timestamp_ms() - trade.timestamp_ms() % self.resolution_ms;
+        // flatc names the `size` accessor `size_()`
+        let (price, size) = (trade.price(), trade.size_());
+
+        let index = self.candles.partition_point(|c| c.open_time_ms() < open_time);
+        match self.candles.get_mut(index) {
+            Some(candle) if candle.open_time_ms() == open_time => {
+                candle.set_high(candle.high().max(price));
+                candle.set_low(candle.low().min(price));
+                if !late {
+                    candle.set_close(price);
+                }
+                candle.set_volume(candle.volume() + size);
+                candle.set_trade_count(candle.trade_count() + 1);
+            }
+            _ => {
+                if index == 0 && self.candles.len() >= history {
+                    return false;
+                }
+                let candle = Candle::new(open_time, price, price, price, price, size, 1);
+                self.candles.insert(index, candle);
+            }
+        }
+
+        let mut index = index;
+        if self.candles.len() > history {
+            self.candles.remove(0);
+            self.changed_from = self.changed_from.map(|from| from.saturating_sub(1));
+            index -= 1;
+        }
+        self.changed_from = Some(self.changed_from.map_or(index, |from| from.min(index)));
+        true
+    }
+}
+
+/// OHLCV aggregation for one symbol.
+pub struct CandleEngine {
+    series: Vec<Series>,
+    config: CandleConfig,
+    /// Trades since the last frame.
+    pending_trades: Vec<Trade>,
+    /// Most recent trades, oldest first. Trimmed to `recent_trades` lazily.
+    recent_trades: Vec<Trade>,
+    last_trade_ms: u64,
+    sequence: u64,
+}
+
+impl CandleEngine {
+    pub fn new(_symbol: &str, config: CandleConfig) -> Self {
+        let series = config
+            .resolutions
+            .iter()
+            .map(|resolution| Series {
+                resolution_ms: (resolution.as_millis() as u64).max(1),
+                candles: Vec::with_capacity(config.history + 1),
+                changed_from: None,
+            })
+            .collect();
+        Self {
+            series,
+            pending_trades: Vec::new(),
+            recent_trades: Vec::with_capacity(2 * config.recent_trades),
+            last_trade_ms: 0,
+            sequence: 0,
+            config,
+        }
+    }
+
+    /// Fold one trade into every resolution. Returns true if any bar
+    /// changed.
+    pub fn add_trade(&mut self, trade: Trade) -> bool {
+        let late = trade.timestamp_ms() < self.last_trade_ms;
+        let mut changed = false;
+        for series in &mut self.series {
+            changed |= series.apply(&trade, late, self.config.history);
+        }
+        if !changed {
+            return false;
+        }
+        self.last_trade_ms = self.last_trade_ms.max(trade.timestamp_ms());
+        self.pending_trades.push(trade);
+        self.recent_trades.push(trade);
+        if self.recent_trades.len() >= 2 * self.config.recent_trades.max(1) {
+            let excess = self.recent_trades.len() - self.config.recent_trades;
+            self.recent_trades.drain(..excess);
+        }
+        true
+    }
+
+    /// Bars of one resolution, oldest first.
+    pub fn candles(&self, resolution: Duration) -> &[Candle] {
+        let resolution_ms = resolution.as_millis() as u64;
+        self.series
+            .iter()
+            .find(|s| s.resolution_ms == resolution_ms)
+            .map_or(&[], |s| s.candles.as_slice())
+    }
+
+    /// Serialize a frame: full history, or only what changed since the
+    /// last frame.
+    fn build_frame(&self, builder: &mut FlatBufferBuilder<'static>, delta: bool) {
+        let mut series = Vec::with_capacity(self.series.len());
+        for s in &self.series {
+            let candles = match (delta, s.changed_from) {
+                (false, _) => &s.candles[..],
+                (true, Some(from)) => &s.candles[from..],
+                (true, None) => continue,
+            };
+            let candles = builder.create_vector(candles);
+            series.push(CandleSeries::create(
+                builder,
+                &CandleSeriesArgs { resolution_ms: s.resolution_ms as u32, candles: Some(candles) },
+            ));
+        }
+        let series = builder.create_vector(&series);
+        let trades = if delta {
+            &self.pending_trades[..]
+        } else {
+            let recent = &self.recent_trades;
+            &recent[recent.len().saturating_sub(self.config.recent_trades)..]
+        };
+        let trades = builder.create_vector(trades);
+        let frame = CandleFrame::create(
+            builder,
+            &CandleFrameArgs {
+                series: Some(series),
+                trades: Some(trades),
+                sequence: self.sequence,
+                timestamp_ms: self.last_trade_ms,
+                is_delta: delta,
+            },
+        );
+        builder.finish(frame, Some(CANDLES_IDENTIFIER));
+    }
+
+    fn clear_changes(&mut self) {
+        self.pending_trades.clear();
+        for series in &mut self.series {
+            series.changed_from = None;
+        }
+    }
+}
+
+impl ServerEngine for CandleEngine {
+    fn ingest(&mut self, msg: &[u8]) -> bool {
+        match parse_trade(msg) {
+            Some(trade) => self.add_trade(trade),
+            None => false,
+        }
+    }
+
+    fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
+        builder.reset();
+        self.sequence += 1;
+        self.build_frame(builder, false);
+        self.clear_changes();
+        builder.finished_data()
+    }
+
+    fn tick_delta<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> Option<&'a [u8]> {
+        builder.reset();
+        self.sequence += 1;
+        self.build_frame(builder, true);
+        self.clear_changes();
+        Some(builder.finished_data())
+    }
+
+    fn snapshot(&self, builder: &mut FlatBufferBuilder<'static>) -> Option<Vec<u8>> {
+        builder.reset();
+        self.build_frame(builder, false);
+        Some(builder.finished_data().to_vec())
+    }
+
+    fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    fn schema_version(&self) -> u32 {
+        my_shared::schema::CANDLES_SCHEMA_HASH
+    }
+}
+
+/// Parse one exchange trade message. Returns None for anything that isn't
+/// a valid trade (heartbeats, other channels, non-positive price or size).
+pub fn parse_trade(msg: &[u8]) -> Option<Trade> {
+    #[derive(serde::Deserialize)]
+    struct TradeMsg<'a> {
+        price: f64,
+        size: f64,
+        #[serde(borrow, default)]
+        side: Option<&'a str>,
+        timestamp_ms: u64,
+    }
+
+    let msg: TradeMsg = serde_json::from_slice(msg).ok()?;
+    if !validate_positive(msg.price) || !validate_positive(msg.size) {
+        return None;
+    }
+    let side = match msg.side {
+        Some("buy" | "b" | "BUY" | "Buy") => Side::Buy,
+        Some("sell" | "s" | "SELL" | "Sell") => Side::Sell,
+        _ => Side::Unknown,
+    };
+    Some(Trade::new(msg.price, msg.size, msg.timestamp_ms, side))
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    fn series(resolution_ms: u64) -> Series {
+        Series { resolution_ms, candles: Vec::new(), changed_from: None }
+    }
+
+    fn trade(timestamp_ms: u64, price: f64) -> Trade {
+        Trade::new(price, 1.0, timestamp_ms, Side::Buy)
+    }
+
+    fn open_times(series: &Series) -> Vec<u64> {
+        series.candles.iter().map(|c| c.open_time_ms()).collect()
+    }
+
+    /// `(resolution_ms, open times)` of every series in a frame.
+    fn frame_bars(frame: &[u8]) -> Vec<(u32, Vec<u64>)> {
+        let frame = flatbuffers::root::<CandleFrame>(frame).unwrap();
+        frame
+            .series()
+            .unwrap()
+            .iter()
+            .map(|s| (s.resolution_ms(), s.candles().unwrap().iter().map(|c| c.open_time_ms()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_trades_within_a_bar_update_it() {
+        let mut s = series(1000);
+        assert!(s.apply(&trade(1000, 100.0), false, 10));
+        assert!(s.apply(&trade(1500, 110.0), false, 10));
+        assert!(s.apply(&trade(1999, 90.0), false, 10));
+
+        assert_eq!(open_times(&s), [1000]);
+        let bar = &s.candles[0];
+        assert!((bar.open() - 100.0).abs() < EPSILON);
+        assert!((bar.high() - 110.0).abs() < EPSILON);
+        assert!((bar.low() - 90.0).abs() < EPSILON);
+        assert!((bar.close() - 90.0).abs() < EPSILON);
+        assert!((bar.volume() - 3.0).abs() < EPSILON);
+        assert_eq!(bar.trade_count(), 3);
+        assert_eq!(s.changed_from, Some(0));
+    }
+
+    #[test]
+    fn test_late_trade_updates_an_older_bar_but_not_its_close() {
+        let mut s = series(1000);
+        s.apply(&trade(1000, 100.0), false, 10);
+        s.apply(&trade(3000, 200.0), false, 10);
+        s.changed_from = None;
+
+        assert!(s.apply(&trade(1200, 300.0), true, 10));
+        let bar = &s.candles[0];
+        assert!((bar.high() - 300.0).abs() < EPSILON);
+        assert!((bar.close() - 100.0).abs() < EPSILON);
+        assert_eq!(bar.trade_count(), 2);
+        assert_eq!(s.changed_from, Some(0));
+
+        // A late trade in an interval without a bar creates it in place
+        s.changed_from = None;
+        assert!(s.apply(&trade(2500, 50.0), true, 10));
+        assert_eq!(open_times(&s), [1000, 2000, 3000]);
+        assert_eq!(s.changed_from, Some(1));
+        assert!((s.candles[1].close() - 50.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_trades_older_than_the_history_are_dropped() {
+        let mut s = series(1000);
+        // Until the history is full, older bars are still kept
+        s.apply(&trade(2000, 100.0), false, 2);
+        assert!(s.apply(&trade(1000, 100.0), true, 2));
+        assert_eq!(open_times(&s), [1000, 2000]);
+
+        s.changed_from = None;
+        assert!(!s.apply(&trade(500, 100.0), true, 2));
+        assert_eq!(open_times(&s), [1000, 2000]);
+        assert_eq!(s.changed_from, None);
+
+        // The oldest bar itself still takes late trades
+        assert!(s.apply(&trade(1500, 100.0), true, 2));
+        assert_eq!(s.candles[0].trade_count(), 2);
+    }
+
+    #[test]
+    fn test_eviction_shifts_changed_bars() {
+        let mut s = series(1000);
+        s.apply(&trade(1000, 100.0), false, 2);
+        s.apply(&trade(2000, 100.0), false, 2);
+
+        // Nothing changed before: only the new bar is
+        s.changed_from = None;
+        assert!(s.apply(&trade(3000, 100.0), false, 2));
+        assert_eq!(open_times(&s), [2000, 3000]);
+        assert_eq!(s.changed_from, Some(1));
+
+        // The changed 3000 bar moves down to index 0 with the eviction
+        assert!(s.apply(&trade(4000, 100.0), false, 2));
+        assert_eq!(open_times(&s), [3000, 4000]);
+        assert_eq!(s.changed_from, Some(0));
+
+        // An evicted changed bar leaves the delta starting at the oldest
+        s.changed_from = Some(0);
+        assert!(s.apply(&trade(5000, 100.0), false, 2));
+        assert_eq!(open_times(&s), [4000, 5000]);
+        assert_eq!(s.changed_from, Some(0));
+    }
+
+    #[test]
+    fn test_delta_frames_hold_changed_bars_onward() {
+        let config = CandleConfig {
+            resolutions: vec![Duration::from_secs(1), Duration::from_secs(60)],
+            history: 10,
+            recent_trades: 5,
+        };
+        let mut engine = CandleEngine::new("BTC-USD", config);
+        let mut builder = FlatBufferBuilder::new();
+        for ms in [61_000, 62_500, 63_100] {
+            engine.add_trade(trade(ms, 100.0));
+        }
+        let full = engine.tick(&mut builder).to_vec();
+        assert_eq!(frame_bars(&full), [(1000, vec![61_000, 62_000, 63_000]), (60_000, vec![60_000])]);
+
+        // A new bar, and a late trade two bars back
+        engine.add_trade(trade(64_000, 100.0));
+        engine.add_trade(trade(62_900, 100.0));
+        let delta = engine.tick_delta(&mut builder).unwrap().to_vec();
+        assert_eq!(frame_bars(&delta), [(1000, vec![62_000, 63_000, 64_000]), (60_000, vec![60_000])]);
+        let frame = flatbuffers::root::<CandleFrame>(&delta).unwrap();
+        assert!(frame.is_delta());
+        assert_eq!(frame.trades().unwrap().len(), 2);
+        assert_eq!(frame.sequence(), 2);
+
+        // A new minute opens a bar in both resolutions
+        engine.add_trade(trade(120_000, 100.0));
+        let delta = engine.tick_delta(&mut builder).unwrap().to_vec();
+        assert_eq!(frame_bars(&delta), [(1000, vec![120_000]), (60_000, vec![120_000])]);
+
+        // Nothing changed: no series, no trades
+        let delta = engine.tick_delta(&mut builder).unwrap().to_vec();
+        assert!(frame_bars(&delta).is_empty());
+        assert_eq!(flatbuffers::root::<CandleFrame>(&delta).unwrap().trades().unwrap().len(), 0);
+
+        // Snapshots still hold the full history
+        let snapshot = engine.snapshot(&mut builder).unwrap();
+        assert_eq!(frame_bars(&snapshot)[1], (60_000, vec![60_000, 120_000]));
+    }
+}
@@ -11,227 +11,335 @@
 //! ───────                          ──────
 //! CommandSender.send()
 //!   → FlatBuffer bytes ──ws──→  handle_client_message()
-//!                                  → parse CommandMessage
+//!                                  → dispatch_command(): parse CommandMessage
 //!                                  → match on Command union
-//!                                  → update engine / subscriptions
+//!                                  → CommandHandler::handle_*() (validate, update state)
+//!   ResponseRegistry  ◄──ws──  ← Envelope { kind: Response, CommandResponse { id, ok, code, message } }
 //! ```
 //!
+//! Every command is answered with a `CommandResponse` carrying the command's
+//! id, so `commands.subscribeAsync()` on the client resolves once the server
+//! has actually applied (or rejected) the subscription. Like frames, the
+//! response is sent inside an `Envelope` (envelope.rs), here with kind
+//! `Response`.
+//!
 //! ## How to use
 //!
 //! 1. Copy this file into your server crate
 //! 2. Generate Rust code from the commands schema:
-//!      flatc --rust -o src/generated/ schema/commands.fbs
+//!    `flatc --rust -o src/generated/ schema/commands.fbs`
 //! 3. Replace the generated import path with your actual module path
-//! 4. Wire `handle_client_message()` into your WebSocket receive loop
-//!    (see broadcast.rs — replace the `_ => {}` arm that ignores client messages)
-//! 5. Customize the Subscribe/Unsubscribe handlers for your domain
+//! 4. Customize the `CommandHandler` methods for your domain
+//! 5. For your own command unions, `npx org-asm gen-handler` generates the
+//!    typed part of this file — `CommandError`, `CommandResult`, the
+//!    `CommandHandler` trait, `dispatch_command_with()` and
+//!    `encode_response()` — from a schema with a response table like
+//!    `CommandResponse` (`id`, `ok`, `code`, `message`). The handler impls
+//!    and `handle_client_message()` stay yours
 //!
 //! ## Integration with broadcast.rs
 //!
-//! In `handle_client` (broadcast.rs), replace the catch-all arm:
+//! `handle_client` (broadcast.rs) owns a `ClientState` per connection and
+//! passes every binary message through `handle_client_message()`, queueing
+//! the returned response on that client's `OutboundQueue`. Subscribe and Unsubscribe add
+//! or remove topics from `ClientState.topics`, which controls exactly which
+//! broadcast frames reach this client:
 //!
 //! ```rust
-//! // Before:
-//! _ => {} // Ignore client-to-server messages
-//!
-//! // After:
 //! Some(Ok(Message::Binary(bytes))) => {
-//!     let response = handle_client_message(&bytes, &mut subscriptions);
-//!     // Optionally send response back to this client
+//!     if let Some(response) = handle_client_message(&bytes, &mut client_state, &state.metrics) {
+//!         // Queued behind the frames already sent; never dropped itself
+//!         match outbound.push_control(None, response) {
+//!             Ok(Some(lost)) => client_state.topics.forget(&lost), // a frame made room
+//!             Ok(None) => {}
+//!             Err(SlowConsumer) => break,
+//!         }
+//!     }
 //! }
 //! ```
 
 // Import generated FlatBuffer types from your commands schema.
 // Replace this path with your actual generated module.
-// use crate::generated::commands_generated::org_asm::commands::*;
+use crate::generated::commands_generated::org_asm::commands::*;
 
-use flatbuffers;
+use bytes::Bytes;
+use flatbuffers::FlatBufferBuilder;
+use my_shared::{depth_topic, validate_depth, validate_symbol, MAX_BOOK_DEPTH};
 use tracing::{info, warn};
 
+use crate::auth::Principal;
+use crate::broadcast::{BroadcastState, TopicStreams};
+use crate::envelope::{self, Header};
+use crate::metrics::Metrics;
+
+/// File identifier every `CommandResponse` is finished with. Clients check
+/// it to tell responses apart from data frames on the same socket.
+pub const RESPONSE_IDENTIFIER: &str = "OARS";
+
 // ============================================
 // Client message handler
 // ============================================
 
 /// Process a binary WebSocket message from a client.
 ///
-/// Parses the bytes as a FlatBuffer CommandMessage and dispatches
-/// to the appropriate handler based on the Command union type.
-///
-/// Returns an optional response (e.g., an ack or error) to send
-/// back to the requesting client. In many cases, commands are
-/// fire-and-forget and this returns None.
+/// Returns the `CommandResponse`, wrapped in an envelope, to send back to
+/// the requesting client, or None if the bytes aren't a readable
+/// CommandMessage (there is no id to answer to).
 ///
 /// # Arguments
 ///
 /// * `bytes` - Raw binary WebSocket message (FlatBuffer-encoded CommandMessage)
 /// * `state` - Mutable reference to per-client or shared server state
-///
-/// # Example
-///
-/// ```rust
-/// // In your WebSocket receive loop:
-/// match msg {
-///     Message::Binary(bytes) => {
-///         if let Some(response) = handle_client_message(&bytes, &mut client_state) {
-///             ws_tx.send(Message::Binary(response)).await.ok();
-///         }
-///     }
-///     _ => {}
-/// }
-/// ```
+/// * `metrics` - Counts each command by type and result
 pub fn handle_client_message(
     bytes: &[u8],
     state: &mut ClientState,
+    metrics: &Metrics,
+) -> Option<Bytes> {
+    let response = dispatch_command_with(bytes, state, |command, result| {
+        metrics.record_command(command.variant_name().unwrap_or("Unknown"), result.is_ok());
+    });
+    if response.is_none() {
+        metrics.record_command("Invalid", false);
+    }
+    response.map(|response| envelope::wrap(Header::response(), &response))
+}
+
+// ============================================
+// Typed dispatch
+// ============================================
+
+/// Why a command was rejected, sent back as `CommandResponse.code` and
+/// `CommandResponse.message`.
+#[derive(Debug, Clone)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
+
+/// One method per `Command` union member, with the member's fields already
+/// read out of the FlatBuffer. Return `Err` to reject the command; the
+/// error is reported to the client under the command's id.
+pub trait CommandHandler {
+    fn handle_subscribe(&mut self, id: u64, symbol: &str, depth: u16, max_rate_hz: u16) -> CommandResult;
+    fn handle_unsubscribe(&mut self, id: u64, symbol: &str) -> CommandResult;
+    fn handle_request_snapshot(&mut self, id: u64, symbol: &str) -> CommandResult;
+}
+
+/// Parse a CommandMessage, route it to `handler`, and encode the result.
+///
+/// `flatbuffers::root()` verifies the buffer first, so malformed input
+/// never reaches a handler.
+pub fn dispatch_command<H: CommandHandler>(bytes: &[u8], handler: &mut H) -> Option<Vec<u8>> {
+    dispatch_command_with(bytes, handler, |_, _| {})
+}
+
+/// `dispatch_command`, also reporting each command's type and result to
+/// `observe` (for metrics).
+pub fn dispatch_command_with<H: CommandHandler>(
+    bytes: &[u8],
+    handler: &mut H,
+    observe: impl FnOnce(Command, &CommandResult),
 ) -> Option<Vec<u8>> {
-    // Parse the FlatBuffer root. flatbuffers::root() verifies the buffer
-    // and returns a reference to the root table. Returns Err if the buffer
-    // is malformed.
-    //
-    // Replace CommandMessage with your generated type:
-    // let msg = match flatbuffers::root::<CommandMessage>(bytes) {
-    //     Ok(msg) => msg,
-    //     Err(e) => {
-    //         warn!("Invalid command buffer: {e}");
-    //         return None;
-    //     }
-    // };
-
-    // let id = msg.id();
-    //
-    // // Match on the Command union type.
-    // // FlatBuffers unions are represented as an enum + accessor method.
-    // // command_type() returns the discriminant, command_as_*() returns the variant.
-    // match msg.command_type() {
-    //     Command::Subscribe => {
-    //         let sub = msg.command_as_subscribe().unwrap();
-    //         handle_subscribe(id, sub, state)
-    //     }
-    //     Command::Unsubscribe => {
-    //         let unsub = msg.command_as_unsubscribe().unwrap();
-    //         handle_unsubscribe(id, unsub, state)
-    //     }
-    //     Command::RequestSnapshot => {
-    //         handle_request_snapshot(id, state)
-    //     }
-    //     Command::NONE => {
-    //         warn!("Command {id}: empty command union");
-    //         None
-    //     }
-    //     _ => {
-    //         // Forward compatibility: unknown command types are silently ignored.
-    //         // This lets older servers handle newer clients gracefully.
-    //         warn!("Command {id}: unknown command type");
-    //         None
-    //     }
-    // }
-
-    // Placeholder until generated types are available:
-    let _ = (bytes, state);
-    warn!("Command handler not yet wired to generated FlatBuffer types");
-    None
+    let msg = match flatbuffers::root::<CommandMessage>(bytes) {
+        Ok(msg) => msg,
+        Err(e) => {
+            warn!("Invalid command buffer: {e}");
+            return None;
+        }
+    };
+    let id = msg.id();
+
+    // FlatBuffers unions are represented as an enum + accessor method.
+    // command_type() returns the discriminant, command_as_*() returns the variant.
+    let malformed = || CommandError::new(ErrorCode::Malformed, "missing command body");
+    let result = match msg.command_type() {
+        Command::Subscribe => msg
+            .command_as_subscribe()
+            .ok_or_else(malformed)
+            .and_then(|cmd| {
+                handler.handle_subscribe(id, cmd.symbol().unwrap_or(""), cmd.depth(), cmd.max_rate_hz())
+            }),
+        Command::Unsubscribe => msg
+            .command_as_unsubscribe()
+            .ok_or_else(malformed)
+            .and_then(|cmd| handler.handle_unsubscribe(id, cmd.symbol().unwrap_or(""))),
+        Command::RequestSnapshot => msg
+            .command_as_request_snapshot()
+            .ok_or_else(malformed)
+            .and_then(|cmd| handler.handle_request_snapshot(id, cmd.symbol().unwrap_or(""))),
+        // NONE, or a command type added to the schema after this server was
+        // built. Rejecting (rather than dropping) lets newer clients fall back.
+        other => Err(CommandError::new(
+            ErrorCode::UnknownCommand,
+            format!("unknown command type {}", other.0),
+        )),
+    };
+
+    if let Err(e) = &result {
+        warn!("Command {id} rejected: {:?} {}", e.code, e.message);
+    }
+    observe(msg.command_type(), &result);
+    Some(encode_response(id, &result))
+}
+
+/// Encode a `CommandResponse` for command `id`.
+pub fn encode_response(id: u64, result: &CommandResult) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::with_capacity(128);
+    let (code, message) = match result {
+        Ok(()) => (ErrorCode::None, None),
+        Err(e) => (e.code, Some(builder.create_string(&e.message))),
+    };
+    let response = CommandResponse::create(
+        &mut builder,
+        &CommandResponseArgs { id, ok: result.is_ok(), code, message },
+    );
+    builder.finish(response, Some(RESPONSE_IDENTIFIER));
+    builder.finished_data().to_vec()
 }
 
 // ============================================
 // Command handlers
 //
 // Each handler receives the parsed command and
-// returns an optional response. Keep handlers
+// returns Ok or a CommandError. Keep handlers
 // focused on state mutation — no I/O here.
 // ============================================
 
-/// Handle a Subscribe command.
-///
-/// Adds the symbol to this client's subscription set and optionally
-/// tells the engine to start processing data for that symbol.
-///
-/// # Design decisions
-///
-/// - Multiple clients can subscribe to the same symbol. The engine
-///   tracks a reference count and only starts/stops processing when
-///   the first subscriber arrives / last subscriber leaves.
-///
-/// - The depth parameter controls how many orderbook levels this
-///   client wants. The server may broadcast more levels than requested;
-///   the client filters to its desired depth locally.
-///
-/// - Returns None (fire-and-forget). If you want acknowledgements,
-///   build a response FlatBuffer with the same id.
-fn handle_subscribe(
-    id: u64,
-    symbol: &str,
-    depth: u16,
-    state: &mut ClientState,
-) -> Option<Vec<u8>> {
-    // Validate inputs using shared crate helpers
-    // use my_shared::validate_identifier;
-    //
-    // if !validate_identifier(symbol, 32) {
-    //     warn!("Command {id}: invalid symbol '{symbol}'");
-    //     return None;
-    // }
-    // if depth == 0 || depth > 100 {
-    //     warn!("Command {id}: invalid depth {depth}");
-    //     return None;
-    // }
-
-    info!("Command {id}: subscribe symbol={symbol} depth={depth}");
-
-    state.subscriptions.insert(symbol.to_string(), depth);
-
-    // Tell the engine about the new subscription.
-    // In a real implementation, this might:
-    //   1. Add the symbol to the engine's active set
-    //   2. Connect to the exchange feed for that symbol
-    //   3. Send a snapshot of current state back to this client
-    //
-    // Example:
-    // engine.lock().await.add_subscription(symbol, depth);
-
-    None
-}
+impl CommandHandler for ClientState {
+    /// Handle a Subscribe command.
+    ///
+    /// Adds the symbol to this client's subscription set and subscribes the
+    /// client to the symbol's broadcast topic at the requested depth, so its
+    /// frames start flowing.
+    ///
+    /// # Design decisions
+    ///
+    /// - Symbol and depth are validated with the shared crate, so the
+    ///   client can run the same checks before sending. Depth 0 (no depth)
+    ///   is also accepted.
+    ///
+    /// - The symbol must be granted by the client's `Principal` (auth.rs).
+    ///   Unsubscribe and RequestSnapshot need an existing subscription, so
+    ///   this is the only check needed.
+    ///
+    /// - Multiple clients can subscribe to the same symbol. `BroadcastState`
+    ///   refcounts topics: the channel is created when the first subscriber
+    ///   arrives and torn down when the last one leaves.
+    ///
+    /// - Subscribing twice to the same symbol at the same depth only
+    ///   updates the rate; the topic refcount is taken once per client. A
+    ///   new depth moves the client to that depth's topic and queues its
+    ///   snapshot.
+    ///
+    /// - Opening a topic emits `TopicEvent::Opened`, which makes the
+    ///   `EngineRegistry` create that symbol's engine (engine_registry.rs).
+    ///
+    /// - A new subscription queues a snapshot, so the client receives the
+    ///   symbol's full state before its live frames.
+    ///
+    /// - The depth parameter controls how many orderbook levels this
+    ///   client wants. Each depth is its own topic, "BTC-USD@20"
+    ///   (`depth_topic()`), whose engine publishes exactly that many levels
+    ///   (`OrderbookEngine::for_topic()`); clients at the same depth share
+    ///   its frames. Engines without a depth (candles) publish on the bare
+    ///   symbol topic, which clients subscribe to with depth 0.
+    ///
+    /// - `max_rate_hz` (0 = every tick) caps how often this client gets the
+    ///   symbol's frames; `TopicStreams` conflates to the latest frame in
+    ///   between (broadcast.rs). A client lowers it for a hidden tab by
+    ///   subscribing again.
+    fn handle_subscribe(&mut self, id: u64, symbol: &str, depth: u16, max_rate_hz: u16) -> CommandResult {
+        if !validate_symbol(symbol) {
+            return Err(CommandError::new(
+                ErrorCode::InvalidSymbol,
+                format!("invalid symbol '{symbol}'"),
+            ));
+        }
+        if depth != 0 && !validate_depth(depth) {
+            return Err(CommandError::new(
+                ErrorCode::InvalidDepth,
+                format!("depth must be 0 or 1..={MAX_BOOK_DEPTH}, got {depth}"),
+            ));
+        }
+        if !self.principal.permissions.allows(symbol) {
+            return Err(CommandError::new(
+                ErrorCode::Forbidden,
+                format!("'{}' may not access '{symbol}'", self.principal.subject),
+            ));
+        }
 
-/// Handle a RequestSnapshot command.
-///
-/// The client requests a full state snapshot, typically after reconnecting.
-/// Delegates to `engine.snapshot()` which serializes the complete current
-/// state into a FlatBuffer response. The caller sends this response back
-/// to the requesting client only (not broadcast).
-///
-/// Returns the snapshot bytes, or None if the engine has no state to send.
-fn handle_request_snapshot(
-    id: u64,
-    state: &mut ClientState,
-) -> Option<Vec<u8>> {
-    info!("Command {id}: request snapshot");
+        info!("Command {id}: subscribe symbol={symbol} depth={depth} max_rate_hz={max_rate_hz}");
 
-    // Build a full state snapshot from the engine.
-    // let mut builder = flatbuffers::FlatBufferBuilder::with_capacity(4096);
-    // if let Some(snapshot) = engine.snapshot(&mut builder) {
-    //     return Some(snapshot);
-    // }
+        let topic = depth_topic(symbol, depth);
+        if let Some(previous) = self.subscriptions.insert(symbol.to_string(), depth) {
+            if previous != depth {
+                self.topics.remove(&depth_topic(symbol, previous));
+            }
+        }
 
-    let _ = state;
-    None
-}
+        // Subscribe first, then queue the snapshot: frames produced while the
+        // snapshot is serialized are buffered, so the client sees no gap.
+        if self.topics.insert(&topic) {
+            self.pending_snapshots.push(topic.clone());
+        }
+        self.topics.set_max_rate(&topic, max_rate_hz);
 
-/// Handle an Unsubscribe command.
-///
-/// Removes the symbol from this client's subscription set.
-/// If no other clients are subscribed, the engine can stop
-/// processing data for that symbol to save resources.
-fn handle_unsubscribe(
-    id: u64,
-    symbol: &str,
-    state: &mut ClientState,
-) -> Option<Vec<u8>> {
-    info!("Command {id}: unsubscribe symbol={symbol}");
+        Ok(())
+    }
 
-    state.subscriptions.remove(symbol);
+    /// Handle an Unsubscribe command.
+    ///
+    /// Removes the symbol from this client's subscription set and releases
+    /// its broadcast topic. If no other clients are subscribed, the topic
+    /// channel is torn down and the tick loop stops serializing it.
+    fn handle_unsubscribe(&mut self, id: u64, symbol: &str) -> CommandResult {
+        info!("Command {id}: unsubscribe symbol={symbol}");
 
-    // Tell the engine about the removed subscription.
-    // engine.lock().await.remove_subscription(symbol);
+        let Some(depth) = self.subscriptions.remove(symbol) else {
+            return Err(not_subscribed(symbol));
+        };
+        self.topics.remove(&depth_topic(symbol, depth));
 
-    None
+        Ok(())
+    }
+
+    /// Handle a RequestSnapshot command.
+    ///
+    /// The client requests a full state snapshot for one symbol (or, with an
+    /// empty symbol, every symbol it is subscribed to) — typically after it
+    /// detected a sequence gap itself. The topics are queued in
+    /// `pending_snapshots`; `handle_client` (broadcast.rs) fetches each
+    /// `engine.snapshot()` and sends it back to the requesting client only
+    /// (not broadcast), after this command's response.
+    ///
+    /// Snapshots are only served for symbols the client is subscribed to.
+    fn handle_request_snapshot(&mut self, id: u64, symbol: &str) -> CommandResult {
+        info!("Command {id}: request snapshot symbol={symbol}");
+
+        if symbol.is_empty() {
+            let topics = self.subscriptions.iter().map(|(symbol, &depth)| depth_topic(symbol, depth));
+            self.pending_snapshots.extend(topics);
+        } else if let Some(&depth) = self.subscriptions.get(symbol) {
+            self.pending_snapshots.push(depth_topic(symbol, depth));
+        } else {
+            return Err(not_subscribed(symbol));
+        }
+
+        Ok(())
+    }
+}
+
+fn not_subscribed(symbol: &str) -> CommandError {
+    CommandError::new(ErrorCode::NotSubscribed, format!("not subscribed to '{symbol}'"))
 }
 
 // ============================================
@@ -245,84 +353,227 @@ fn handle_unsubscribe(
 /// Per-client state managed by the WebSocket handler.
 ///
 /// Created when a client connects, dropped when they disconnect.
-/// The broadcast handler (broadcast.rs) can embed this in the
-/// `handle_client` function.
+/// `handle_client` in broadcast.rs owns one of these per connection.
 pub struct ClientState {
-    /// Active subscriptions: symbol -> requested depth.
-    /// When the client disconnects, all subscriptions are cleaned up.
+    /// Who this client authenticated as, and what it may subscribe to.
+    pub principal: Principal,
+
+    /// Active subscriptions: symbol -> requested depth (0 for none).
     pub subscriptions: std::collections::HashMap<String, u16>,
+
+    /// Broadcast topics this client receives frames for.
+    /// Dropping the client state releases every topic, so disconnect
+    /// cleanup needs no extra code.
+    pub topics: TopicStreams,
+
+    /// Topics whose snapshot should be sent to this client once the
+    /// current command has been handled. Drained by `handle_client`.
+    pub pending_snapshots: Vec<String>,
 }
 
 impl ClientState {
-    pub fn new() -> Self {
+    pub fn new(broadcast: BroadcastState, principal: Principal) -> Self {
         Self {
+            principal,
             subscriptions: std::collections::HashMap::new(),
+            topics: TopicStreams::new(broadcast),
+            pending_snapshots: Vec::new(),
         }
     }
 }
 
-impl Default for ClientState {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 // ============================================
-// Integration example: modified handle_client
-//
-// This shows how to modify broadcast.rs to handle
-// both downstream broadcasts and upstream commands.
+// Tests
 // ============================================
 
-// async fn handle_client(socket: WebSocket, broadcast: BroadcastState) {
-//     let (mut ws_tx, mut ws_rx) = socket.split();
-//     let mut rx = broadcast.subscribe();
-//     let mut client_state = ClientState::new();
-//
-//     info!("Client connected");
-//
-//     loop {
-//         tokio::select! {
-//             // Forward broadcast frames to this client
-//             result = rx.recv() => {
-//                 match result {
-//                     Ok(bytes) => {
-//                         // Only send frames for symbols this client is subscribed to.
-//                         // In practice, you'd check a symbol field in the frame header.
-//                         if ws_tx.send(Message::Binary((*bytes).clone().into())).await.is_err() {
-//                             break;
-//                         }
-//                     }
-//                     Err(broadcast::error::RecvError::Lagged(n)) => {
-//                         warn!("Client lagged, skipped {n} frames");
-//                     }
-//                     Err(broadcast::error::RecvError::Closed) => break,
-//                 }
-//             }
-//             // Handle client commands (binary) and control messages
-//             msg = ws_rx.next() => {
-//                 match msg {
-//                     Some(Ok(Message::Binary(bytes))) => {
-//                         // Client sent a command — parse and handle it
-//                         if let Some(response) = handle_client_message(&bytes, &mut client_state) {
-//                             if ws_tx.send(Message::Binary(response.into())).await.is_err() {
-//                                 break;
-//                             }
-//                         }
-//                     }
-//                     Some(Ok(Message::Close(_))) | None => break,
-//                     Some(Err(_)) => break,
-//                     _ => {}
-//                 }
-//             }
-//         }
-//     }
-//
-//     // Cleanup: remove all subscriptions for this client
-//     for symbol in client_state.subscriptions.keys() {
-//         info!("Cleaning up subscription: {symbol}");
-//         // engine.lock().await.remove_subscription(symbol);
-//     }
-//
-//     info!("Client disconnected");
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::envelope_generated::org_asm::envelope::{Envelope, FrameKind};
+
+    fn client() -> (BroadcastState, ClientState) {
+        let broadcast = BroadcastState::new(16);
+        let state = ClientState::new(broadcast.clone(), Principal::anonymous());
+        (broadcast, state)
+    }
+
+    /// A CommandMessage of `command_type` for `symbol` (None leaves the body
+    /// out). Every command's first field is `symbol`, so a Subscribe body
+    /// reads as any of them.
+    fn command_message(id: u64, command_type: Command, symbol: Option<&str>) -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+        let command = symbol.map(|symbol| {
+            let symbol = builder.create_string(symbol);
+            let args = SubscribeArgs {
+                symbol: Some(symbol),
+                ..SubscribeArgs::default()
+            };
+            Subscribe::create(&mut builder, &args).as_union_value()
+        });
+        let args = CommandMessageArgs {
+            id,
+            command_type,
+            command,
+        };
+        let message = CommandMessage::create(&mut builder, &args);
+        builder.finish(message, None);
+        builder.finished_data().to_vec()
+    }
+
+    /// Dispatch `bytes`, returning the decoded response's fields and the
+    /// command type `observe` saw.
+    fn dispatch(
+        state: &mut ClientState,
+        bytes: &[u8],
+    ) -> (u64, bool, ErrorCode, Option<String>, &'static str) {
+        let mut observed = None;
+        let response = dispatch_command_with(bytes, state, |command, _| {
+            observed = Some(command.variant_name().unwrap_or("Unknown"));
+        })
+        .unwrap();
+        assert!(flatbuffers::buffer_has_identifier(
+            &response,
+            RESPONSE_IDENTIFIER,
+            false
+        ));
+        let response = flatbuffers::root::<CommandResponse>(&response).unwrap();
+        (
+            response.id(),
+            response.ok(),
+            response.code(),
+            response.message().map(str::to_string),
+            observed.unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_dispatch_acks_command() {
+        let (_, mut state) = client();
+        let response = dispatch(
+            &mut state,
+            &command_message(7, Command::Subscribe, Some("BTC-USD")),
+        );
+        assert_eq!(response, (7, true, ErrorCode::None, None, "Subscribe"));
+        assert!(state.topics.contains("BTC-USD@20"));
+    }
+
+    #[test]
+    fn test_dispatch_reports_handler_error() {
+        let (_, mut state) = client();
+        let (id, ok, code, message, _) = dispatch(
+            &mut state,
+            &command_message(8, Command::Subscribe, Some("bad symbol!")),
+        );
+        assert_eq!((id, ok, code), (8, false, ErrorCode::InvalidSymbol));
+        assert_eq!(message.as_deref(), Some("invalid symbol 'bad symbol!'"));
+
+        let (_, ok, code, _, _) = dispatch(
+            &mut state,
+            &command_message(9, Command::Unsubscribe, Some("BTC-USD")),
+        );
+        assert_eq!((ok, code), (false, ErrorCode::NotSubscribed));
+    }
+
+    #[test]
+    fn test_dispatch_rejects_unknown_command() {
+        let (_, mut state) = client();
+        // A union member added after this server was built
+        let (id, ok, code, message, observed) = dispatch(
+            &mut state,
+            &command_message(10, Command(42), Some("BTC-USD")),
+        );
+        assert_eq!((id, ok, code), (10, false, ErrorCode::UnknownCommand));
+        assert_eq!(message.as_deref(), Some("unknown command type 42"));
+        assert_eq!(observed, "Unknown");
+
+        let (_, ok, code, _, _) = dispatch(&mut state, &command_message(11, Command::NONE, None));
+        assert_eq!((ok, code), (false, ErrorCode::UnknownCommand));
+        assert!(state.topics.is_empty());
+    }
+
+    #[test]
+    fn test_unreadable_message_has_no_response() {
+        let (_, mut state) = client();
+        assert!(dispatch_command(&[1, 2, 3], &mut state).is_none());
+        // A union type without its body fails verification
+        let no_body = command_message(9, Command::Subscribe, None);
+        assert!(dispatch_command(&no_body, &mut state).is_none());
+
+        let metrics = Metrics::new();
+        assert!(handle_client_message(&[1, 2, 3], &mut state, &metrics).is_none());
+        let text = metrics.render(&BroadcastState::new(16));
+        assert!(text.contains("orgasm_commands_total{command=\"Invalid\",result=\"rejected\"} 1"));
+    }
+
+    #[test]
+    fn test_response_is_enveloped() {
+        let (_, mut state) = client();
+        let metrics = Metrics::new();
+        let bytes = command_message(12, Command::Subscribe, Some("BTC-USD"));
+        let wrapped = handle_client_message(&bytes, &mut state, &metrics).unwrap();
+
+        let envelope = flatbuffers::root::<Envelope>(&wrapped).unwrap();
+        assert_eq!(envelope.kind(), FrameKind::Response);
+        let payload = envelope.payload().unwrap().bytes();
+        assert_eq!(payload, encode_response(12, &Ok(())));
+        assert_eq!(
+            flatbuffers::root::<CommandResponse>(payload).unwrap().id(),
+            12
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let error = CommandError::new(ErrorCode::Forbidden, "no");
+        let bytes = encode_response(u64::MAX, &Err(error));
+        assert_eq!(&bytes[4..8], RESPONSE_IDENTIFIER.as_bytes());
+        let response = flatbuffers::root::<CommandResponse>(&bytes).unwrap();
+        assert_eq!(response.id(), u64::MAX);
+        assert!(!response.ok());
+        assert_eq!(response.code(), ErrorCode::Forbidden);
+        assert_eq!(response.message(), Some("no"));
+    }
+
+    #[test]
+    fn test_subscribe_depth_and_bare_topics() {
+        let (broadcast, mut state) = client();
+
+        state.handle_subscribe(1, "BTC-USD", 20, 0).unwrap();
+        state.handle_subscribe(2, "ETH-USD", 0, 0).unwrap();
+
+        assert!(state.topics.contains("BTC-USD@20"));
+        assert!(state.topics.contains("ETH-USD"));
+        assert!(!state.topics.contains("ETH-USD@0"));
+        assert_eq!(broadcast.subscriber_count("ETH-USD"), 1);
+        assert_eq!(state.pending_snapshots, ["BTC-USD@20", "ETH-USD"]);
+
+        state.pending_snapshots.clear();
+        state.handle_request_snapshot(3, "ETH-USD").unwrap();
+        assert_eq!(state.pending_snapshots, ["ETH-USD"]);
+
+        state.handle_unsubscribe(4, "ETH-USD").unwrap();
+        assert!(!state.topics.contains("ETH-USD"));
+        assert_eq!(broadcast.subscriber_count("ETH-USD"), 0);
+    }
+
+    #[test]
+    fn test_subscribe_switches_between_depth_and_bare_topic() {
+        let (_, mut state) = client();
+
+        state.handle_subscribe(1, "BTC-USD", 0, 0).unwrap();
+        state.handle_subscribe(2, "BTC-USD", 5, 0).unwrap();
+        assert!(state.topics.contains("BTC-USD@5"));
+        assert!(!state.topics.contains("BTC-USD"));
+    }
+
+    #[test]
+    fn test_subscribe_rejects_depth_over_max() {
+        let (_, mut state) = client();
+
+        let err = state
+            .handle_subscribe(1, "BTC-USD", MAX_BOOK_DEPTH + 1, 0)
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidDepth);
+        assert!(state.topics.is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+//! # Frame Compression
+//!
+//! Optional per-connection LZ4 compression of frames and snapshots, using
+//! the envelope defined in the shared crate (`my_shared::compression`) so
+//! the client's WASM engine can decode it in `ingest_frame()`.
+//!
+//! ## Negotiation
+//!
+//! Browsers don't expose permessage-deflate settings, so compression is
+//! negotiated as a WebSocket subprotocol. A client that wants it connects
+//! with `protocols: ['orgasm.lz4']`; if `ServerState.compression` is set,
+//! the server accepts the subprotocol and every frame and snapshot on that
+//! connection is sent as an envelope. Clients that don't ask (or servers
+//! with compression off) get plain FlatBuffers. Command responses are never
+//! compressed, so `ResponseRegistry` sees them unchanged.
+//!
+//! ## Shared dictionary
+//!
+//! Small frames compress poorly on their own; against a dictionary of
+//! recent frames from the same feed, an orderbook frame shrinks 4-6x.
+//! `BroadcastState::send()` offers each broadcast frame to `Compression`
+//! once, on the engine thread, which samples it and rebuilds the dictionary
+//! from the newest samples every `rotate_interval`. The current dictionary
+//! is published through a `watch` channel: writer tasks only read it, so
+//! the number of clients doesn't add contention. Each connection sends the
+//! client a dictionary message before the first frame that uses it.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! let compression = Some(Compression::lz4(DictionaryConfig::default()));
+//! // Trains the dictionary on broadcast frames
+//! let broadcast = BroadcastState::new(1024).with_compression(compression.clone());
+//! ServerState {
+//!     compression,
+//!     // ...
+//! }
+//! ```
+//!
+//! Compression happens in each client's writer task: it costs CPU per
+//! connection (LZ4 runs at several hundred MB/s per core) in exchange for
+//! bandwidth.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderValue;
+use bytes::Bytes;
+use my_shared::compression::{encode_dictionary, encode_frame, MAX_DICTIONARY_LEN};
+use tokio::sync::watch;
+
+/// Subprotocol a client requests to receive LZ4 envelopes.
+pub const LZ4_PROTOCOL: &str = "orgasm.lz4";
+
+/// How the shared dictionary is trained.
+#[derive(Debug, Clone)]
+pub struct DictionaryConfig {
+    /// Dictionary size. LZ4 can't refer back further than 64 KiB.
+    pub max_len: usize,
+    /// Minimum time between samples.
+    pub sample_interval: Duration,
+    /// How often the dictionary is rebuilt. Every rebuild costs each
+    /// compressed client one dictionary message: the dictionary, up to
+    /// `max_len` bytes (64 KiB by default), LZ4-compressed. Samples of one
+    /// feed repeat a lot, so it usually shrinks well, but budget for up to
+    /// `max_len` per client per rotation and keep this in minutes.
+    pub rotate_interval: Duration,
+    /// Messages shorter than this aren't sampled.
+    pub min_sample_len: usize,
+}
+
+impl Default for DictionaryConfig {
+    fn default() -> Self {
+        Self {
+            max_len: MAX_DICTIONARY_LEN,
+            sample_interval: Duration::from_millis(250),
+            rotate_interval: Duration::from_secs(300),
+            min_sample_len: 256,
+        }
+    }
+}
+
+/// A trained dictionary. Ids start at 1 (0 means "no dictionary").
+pub struct Dictionary {
+    pub id: u32,
+    pub bytes: Vec<u8>,
+}
+
+type Current = Option<Arc<Dictionary>>;
+
+struct Trainer {
+    samples: VecDeque<Bytes>,
+    sampled_len: usize,
+    last_sample: Option<Instant>,
+    built_at: Instant,
+    /// Id of the last dictionary built, 0 before the first.
+    id: u32,
+    publish: watch::Sender<Current>,
+}
+
+/// Server-wide compression settings and the shared dictionary. Cheap to
+/// clone.
+#[derive(Clone)]
+pub struct Compression {
+    config: Arc<DictionaryConfig>,
+    /// Locked by `sample()` only, once per broadcast frame.
+    trainer: Arc<Mutex<Trainer>>,
+    dictionary: watch::Receiver<Current>,
+}
+
+impl Compression {
+    /// LZ4 with a dictionary trained on recent frames.
+    pub fn lz4(config: DictionaryConfig) -> Self {
+        let (publish, dictionary) = watch::channel(None);
+        Self {
+            config: Arc::new(config),
+            trainer: Arc::new(Mutex::new(Trainer {
+                samples: VecDeque::new(),
+                sampled_len: 0,
+                last_sample: None,
+                built_at: Instant::now(),
+                id: 0,
+                publish,
+            })),
+            dictionary,
+        }
+    }
+
+    /// LZ4 without a dictionary: each frame is compressed on its own.
+    pub fn lz4_without_dictionary() -> Self {
+        Self::lz4(DictionaryConfig { max_len: 0, ..DictionaryConfig::default() })
+    }
+
+    /// Whether a connection negotiated LZ4, from the subprotocol its
+    /// upgrade accepted.
+    pub fn negotiated(&self, protocol: Option<&HeaderValue>) -> bool {
+        protocol.is_some_and(|p| p.as_bytes() == LZ4_PROTOCOL.as_bytes())
+    }
+
+    /// Offer a broadcast frame as a dictionary sample, and publish a
+    /// rebuilt dictionary if one is due. Called by `BroadcastState::send()`.
+    pub fn sample(&self, bytes: &Bytes) {
+        let config = &self.config;
+        if config.max_len == 0 {
+            return;
+        }
+        let mut trainer = self.trainer.lock().unwrap();
+        let now = Instant::now();
+
+        let due = trainer.last_sample.is_none_or(|at| now - at >= config.sample_interval);
+        if due && bytes.len() >= config.min_sample_len {
+            trainer.last_sample = Some(now);
+            trainer.sampled_len += bytes.len();
+            trainer.samples.push_back(bytes.clone());
+            while trainer.sampled_len > config.max_len && trainer.samples.len() > 1 {
+                let oldest = trainer.samples.pop_front().unwrap();
+                trainer.sampled_len -= oldest.len();
+            }
+        }
+
+        // First dictionary as soon as there are samples; then on schedule
+        let rotate = match trainer.id {
+            0 => !trainer.samples.is_empty(),
+            _ => now - trainer.built_at >= config.rotate_interval,
+        };
+        if rotate {
+            trainer.built_at = now;
+            trainer.id = trainer.id.wrapping_add(1).max(1);
+            // Newest samples last: LZ4 finds nearby matches at the end of
+            // the dictionary cheaper to encode
+            let mut dict: Vec<u8> = trainer.samples.iter().flat_map(|s| s.iter().copied()).collect();
+            if dict.len() > config.max_len {
+                dict.drain(..dict.len() - config.max_len);
+            }
+            let dictionary = Dictionary { id: trainer.id, bytes: dict };
+            trainer.publish.send_replace(Some(Arc::new(dictionary)));
+        }
+    }
+}
+
+/// Per-connection encoder, owned by the client's writer task.
+pub struct Compressor {
+    dictionary: watch::Receiver<Current>,
+    /// Latest published dictionary, refreshed when the channel changes.
+    current: Current,
+    /// Dictionary the client has been sent.
+    sent_dictionary: Option<u32>,
+    buf: Vec<u8>,
+}
+
+impl Compressor {
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            dictionary: compression.dictionary,
+            current: None,
+            sent_dictionary: None,
+            buf: Vec::with_capacity(4096),
+        }
+    }
+
+    /// Encode one frame or snapshot. Returns the dictionary message to send
+    /// first, if the dictionary changed, and the envelope.
+    pub fn encode(&mut self, bytes: &Bytes) -> (Option<Bytes>, Bytes) {
+        // An atomic version check; the channel's lock is only taken when a
+        // new dictionary was published
+        if self.dictionary.has_changed().unwrap_or(false) {
+            self.current = self.dictionary.borrow_and_update().clone();
+        }
+        let dictionary = &self.current;
+        let mut dictionary_msg = None;
+        if let Some(dict) = dictionary {
+            if self.sent_dictionary != Some(dict.id) {
+                encode_dictionary(dict.id, &dict.bytes, &mut self.buf);
+                dictionary_msg = Some(Bytes::copy_from_slice(&self.buf));
+                self.sent_dictionary = Some(dict.id);
+            }
+        }
+        encode_frame(bytes, dictionary.as_ref().map(|d| (d.id, d.bytes.as_slice())), &mut self.buf);
+        (dictionary_msg, Bytes::copy_from_slice(&self.buf))
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use my_shared::compression::FrameDecoder;
+
+    #[test]
+    fn test_sampled_dictionary_reaches_compressors() {
+        let compression = Compression::lz4(DictionaryConfig {
+            min_sample_len: 4,
+            rotate_interval: Duration::ZERO,
+            ..DictionaryConfig::default()
+        });
+        let mut compressor = Compressor::new(compression.clone());
+        let mut decoder = FrameDecoder::new();
+        let frame = Bytes::from((0..512u32).map(|i| (i % 61) as u8).collect::<Vec<_>>());
+
+        // Nothing sampled yet: no dictionary
+        assert!(compressor.encode(&frame).0.is_none());
+
+        // The dictionary message precedes the first frame that uses it
+        compression.sample(&frame);
+        let (dictionary, envelope) = compressor.encode(&frame);
+        assert_eq!(decoder.decode(&dictionary.unwrap()).unwrap(), None);
+        assert_eq!(decoder.decode(&envelope).unwrap(), Some(&frame[..]));
+        assert!(compressor.encode(&frame).0.is_none());
+
+        // A rebuilt dictionary is sent again, and a new connection gets the
+        // current one
+        compression.sample(&frame);
+        assert!(compressor.encode(&frame).0.is_some());
+        assert!(Compressor::new(compression.clone()).encode(&frame).0.is_some());
+    }
+
+    #[test]
+    fn test_without_dictionary_nothing_is_sampled() {
+        let compression = Compression::lz4_without_dictionary();
+        let frame = Bytes::from(vec![1u8; 1024]);
+        compression.sample(&frame);
+        let (dictionary, envelope) = Compressor::new(compression).encode(&frame);
+        assert!(dictionary.is_none());
+        assert_eq!(FrameDecoder::new().decode(&envelope).unwrap(), Some(&frame[..]));
+    }
+}
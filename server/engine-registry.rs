@@ -0,0 +1,387 @@
+//! # Engine Registry
+//!
+//! Owns one `ServerEngine` instance per topic. Engines are created
+//! lazily when the first client subscribes to a topic and dropped after the
+//! last client unsubscribes, so a server that can serve 400 symbols only pays
+//! for the ones somebody is watching.
+//!
+//! ## Architecture
+//!
+//! ```text
+//! Exchange WS ──→ registry.ingest(msg)
+//!                      │  router(msg) → "ETH-USD"
+//!                      ▼
+//!          ┌── engines ──────────────────────┐
+//!          │ "BTC-USD@20" → OrderbookEngine  │
+//!          │ "ETH-USD@20" → OrderbookEngine  │ ◄── TopicEvent::Opened / Closed
+//!          │ "ETH-USD@5"  → OrderbookEngine  │     (from BroadcastState)
+//!          └─────────────────────────────────┘
+//!                      │
+//!          registry.tick_all(builder, |topic, frame| broadcast.send(topic, frame))
+//! ```
+//!
+//! ## Usage
+//!
+//! ```rust
+//! let (broadcast, mut topic_events) = BroadcastState::with_topic_events(1024);
+//! let mut registry = EngineRegistry::new(
+//!     |topic| OrderbookEngine::for_topic(topic),
+//!     route_by_symbol,
+//! );
+//!
+//! // Engine task, once per tick:
+//! while let Ok(event) = topic_events.try_recv() {
+//!     registry.apply(event);
+//! }
+//! registry.tick_all(&mut builder, |topic, frame| {
+//!     broadcast.send(topic, frame);
+//! });
+//! ```
+//!
+//! Engines are only ticked when `ingest()` reported a state change since
+//! their last frame. Quiet topics cost nothing until the heartbeat interval
+//! (`with_heartbeat()`) elapses, when they are ticked anyway so clients can
+//! tell a quiet market from a dead connection.
+//!
+//! Call `with_delta_frames()` to tick engines with `tick_delta()` instead
+//! of `tick()`. To run the registry, hand it to `EngineRunner::spawn()`
+//! (engine-runner.rs), which owns it on a dedicated thread and serves
+//! snapshots to lagging clients. For a simpler setup, share it as
+//! `Arc<Mutex<EngineRegistry<E>>>` — that handle also implements
+//! `SnapshotSource` and can be passed to `ServerState.engines`.
+//!
+//! ## Depth topics
+//!
+//! Subscribers pick an orderbook depth, and each depth is its own topic
+//! (`my_shared::depth_topic`, e.g. "BTC-USD@20") with its own engine, so
+//! every client gets frames and deltas for exactly the levels it asked
+//! for. The router still extracts the symbol: `ingest()` feeds a message to
+//! every open topic of that symbol. Topics without a depth ("BTC-USD") are
+//! routed by their whole name.
+//!
+//! Messages for topics nobody is subscribed to are dropped at `ingest()`.
+//! If your engine needs warm state before the first subscriber arrives
+//! (e.g. a book that must be built from a snapshot), call `open()` for
+//! those topics at startup.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use flatbuffers::FlatBufferBuilder;
+use my_shared::parse_topic;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::broadcast::{Frame, Snapshot, SnapshotSource, TopicEvent};
+use crate::engine_trait::ServerEngine;
+
+/// Creates a fresh engine for a topic.
+pub type EngineFactory<E> = Box<dyn Fn(&str) -> E + Send>;
+
+/// Extracts the routing key (the symbol) from a raw exchange message.
+///
+/// Returns a slice of the message itself (or a `&'static str` from a lookup
+/// table) so routing never allocates on the hot path. Return None for
+/// messages that belong to no topic (heartbeats, acks).
+pub type TopicRouter = Box<dyn for<'a> Fn(&'a [u8]) -> Option<&'a str> + Send>;
+
+/// An engine plus the dirty tracking the tick driver needs.
+struct Slot<E> {
+    engine: E,
+    /// `ingest()` reported a change since the last emitted frame.
+    dirty: bool,
+    last_emit: Instant,
+}
+
+/// Per-topic engine instances, keyed by topic.
+pub struct EngineRegistry<E: ServerEngine> {
+    engines: HashMap<String, Slot<E>>,
+    /// Open topics per symbol, e.g. "ETH-USD" → ["ETH-USD@20", "ETH-USD@5"].
+    routes: HashMap<String, Vec<String>>,
+    factory: EngineFactory<E>,
+    router: TopicRouter,
+    delta_frames: bool,
+    heartbeat: Option<Duration>,
+}
+
+impl<E: ServerEngine> EngineRegistry<E> {
+    pub fn new(
+        factory: impl Fn(&str) -> E + Send + 'static,
+        router: impl for<'a> Fn(&'a [u8]) -> Option<&'a str> + Send + 'static,
+    ) -> Self {
+        Self {
+            engines: HashMap::new(),
+            routes: HashMap::new(),
+            factory: Box::new(factory),
+            router: Box::new(router),
+            delta_frames: false,
+            heartbeat: None,
+        }
+    }
+
+    /// Tick unchanged engines at least this often so clients see a frame
+    /// (and a new sequence) even in a quiet market. None (the default)
+    /// never ticks an unchanged engine.
+    ///
+    /// Engines whose frames change without `ingest()` (time-based fields)
+    /// should set this to the tick interval.
+    pub fn with_heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Emit delta frames (`tick_delta()`) instead of full frames (`tick()`).
+    /// Engines that don't implement `tick_delta()` keep sending full frames.
+    pub fn with_delta_frames(mut self, enabled: bool) -> Self {
+        self.delta_frames = enabled;
+        self
+    }
+
+    /// Create the engine for `topic` if it doesn't exist yet. The factory
+    /// gets the whole topic, depth included.
+    /// Returns true if a new engine was created.
+    pub fn open(&mut self, topic: &str) -> bool {
+        if self.engines.contains_key(topic) {
+            return false;
+        }
+        info!("Engine created: {topic}");
+        let slot = Slot {
+            engine: (self.factory)(topic),
+            // New subscribers get a first frame on the next tick
+            dirty: true,
+            last_emit: Instant::now(),
+        };
+        self.engines.insert(topic.to_string(), slot);
+        let (symbol, _) = parse_topic(topic);
+        self.routes.entry(symbol.to_string()).or_default().push(topic.to_string());
+        true
+    }
+
+    /// Drop the engine for `topic`. Returns true if one existed.
+    pub fn close(&mut self, topic: &str) -> bool {
+        let removed = self.engines.remove(topic).is_some();
+        if removed {
+            info!("Engine dropped: {topic}");
+            let (symbol, _) = parse_topic(topic);
+            if let Some(topics) = self.routes.get_mut(symbol) {
+                topics.retain(|t| t != topic);
+                if topics.is_empty() {
+                    self.routes.remove(symbol);
+                }
+            }
+        }
+        removed
+    }
+
+    /// Apply a topic lifecycle event from `BroadcastState`.
+    pub fn apply(&mut self, event: TopicEvent) {
+        match event {
+            TopicEvent::Opened(topic) => self.open(&topic),
+            TopicEvent::Closed(topic) => self.close(&topic),
+        };
+    }
+
+    /// Route an exchange message to the engines of its symbol's topics.
+    ///
+    /// Returns true if an engine consumed the message and reported a state
+    /// change, which marks it for the next tick. Messages with no symbol, or
+    /// for symbols without open topics, are dropped.
+    pub fn ingest(&mut self, msg: &[u8]) -> bool {
+        let Some(symbol) = (self.router)(msg) else {
+            return false;
+        };
+        let Some(topics) = self.routes.get(symbol) else {
+            return false;
+        };
+        let mut changed = false;
+        for topic in topics {
+            if let Some(slot) = self.engines.get_mut(topic) {
+                let ingested = slot.engine.ingest(msg);
+                slot.dirty |= ingested;
+                changed |= ingested;
+            }
+        }
+        changed
+    }
+
+    /// Tick every changed engine (and any unchanged engine whose heartbeat
+    /// is due) and hand each frame to `emit`. Returns the number of frames
+    /// emitted.
+    ///
+    /// The builder is shared across engines; each frame's bytes are copied
+    /// out of it before the next engine ticks.
+    pub fn tick_all(
+        &mut self,
+        builder: &mut FlatBufferBuilder<'static>,
+        mut emit: impl FnMut(&str, Frame),
+    ) -> usize {
+        let now = Instant::now();
+        let mut emitted = 0;
+        for (topic, slot) in self.engines.iter_mut() {
+            let heartbeat_due = self
+                .heartbeat
+                .is_some_and(|interval| now.duration_since(slot.last_emit) >= interval);
+            if !slot.dirty && !heartbeat_due {
+                continue;
+            }
+            slot.dirty = false;
+            slot.last_emit = now;
+            emitted += 1;
+
+            let engine = &mut slot.engine;
+            let delta = match self.delta_frames {
+                true => engine.tick_delta(builder).map(<[u8]>::to_vec),
+                false => None,
+            };
+            // Engines without delta frames send full frames in delta mode too
+            let frame = match delta {
+                Some(bytes) => Frame::delta(engine.sequence(), bytes),
+                None => {
+                    let bytes = engine.tick(builder).to_vec();
+                    Frame::full(engine.sequence(), bytes)
+                }
+            };
+            emit(topic, frame.with_schema_version(engine.schema_version()));
+        }
+        emitted
+    }
+
+    /// Full snapshot of one topic's engine, tagged with its sequence.
+    pub fn snapshot(&self, topic: &str) -> Option<Snapshot> {
+        let engine = &self.engines.get(topic)?.engine;
+        let mut builder = FlatBufferBuilder::with_capacity(4096);
+        let bytes = engine.snapshot(&mut builder)?;
+        Some(Snapshot {
+            sequence: engine.sequence(),
+            schema_version: engine.schema_version(),
+            bytes,
+        })
+    }
+
+    pub fn get(&self, topic: &str) -> Option<&E> {
+        self.engines.get(topic).map(|slot| &slot.engine)
+    }
+
+    /// Mutable access to an engine. Marks it dirty, since the caller may
+    /// change state outside `ingest()`.
+    pub fn get_mut(&mut self, topic: &str) -> Option<&mut E> {
+        let slot = self.engines.get_mut(topic)?;
+        slot.dirty = true;
+        Some(&mut slot.engine)
+    }
+
+    /// Number of active engines.
+    pub fn len(&self) -> usize {
+        self.engines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.engines.is_empty()
+    }
+}
+
+/// Client tasks resync through the same lock the tick loop holds, so the
+/// snapshot's sequence is always consistent with the frames broadcast so far.
+impl<E: ServerEngine> SnapshotSource for Arc<Mutex<EngineRegistry<E>>> {
+    async fn snapshot(&self, topic: &str) -> Option<Snapshot> {
+        self.lock().await.snapshot(topic)
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::orderbook_generated::org_asm::orderbook::OrderbookFrame;
+    use crate::orderbook_engine::OrderbookEngine;
+    use my_shared::orderbook::Side;
+
+    fn route(msg: &[u8]) -> Option<&str> {
+        std::str::from_utf8(msg).ok()?.split('"').nth(3)
+    }
+
+    fn registry() -> EngineRegistry<OrderbookEngine> {
+        EngineRegistry::new(OrderbookEngine::for_topic, route)
+    }
+
+    const SNAPSHOT: &str = r#"{"symbol": "BTC-USD", "type": "snapshot", "sequence": 1, "bids": [[100, 1], [99, 1], [98, 1]], "asks": [[101, 1]]}"#;
+
+    #[test]
+    fn test_messages_reach_every_depth_of_their_symbol() {
+        let mut registry = registry();
+        registry.open("BTC-USD@2");
+        registry.open("BTC-USD@3");
+        registry.open("ETH-USD@2");
+        assert!(registry.ingest(SNAPSHOT.as_bytes()));
+
+        let mut frames = Vec::new();
+        registry.tick_all(&mut FlatBufferBuilder::new(), |topic, _| frames.push(topic.to_string()));
+        frames.sort();
+        assert_eq!(frames, ["BTC-USD@2", "BTC-USD@3", "ETH-USD@2"]);
+        assert_eq!(registry.get("BTC-USD@2").unwrap().book().levels(Side::Bid), 3);
+        assert!(registry.get("ETH-USD@2").unwrap().needs_snapshot());
+
+        // Closing one depth keeps the other routed
+        registry.close("BTC-USD@2");
+        assert!(registry.ingest(SNAPSHOT.replace(r#""sequence": 1"#, r#""sequence": 2"#).as_bytes()));
+        registry.close("BTC-USD@3");
+        assert!(!registry.ingest(SNAPSHOT.as_bytes()));
+        assert!(!registry.routes.contains_key("BTC-USD"));
+    }
+
+    #[test]
+    fn test_engines_publish_their_topics_depth() {
+        let mut registry = registry();
+        registry.open("BTC-USD@2");
+        registry.open("BTC-USD");
+        registry.ingest(SNAPSHOT.as_bytes());
+
+        let bids = |registry: &EngineRegistry<OrderbookEngine>, topic| {
+            let snapshot = registry.snapshot(topic).unwrap();
+            let frame = flatbuffers::root::<OrderbookFrame>(&snapshot.bytes).unwrap();
+            frame.bids().unwrap().len()
+        };
+        assert_eq!(bids(&registry, "BTC-USD@2"), 2);
+        assert_eq!(bids(&registry, "BTC-USD"), 3);
+    }
+
+    /// Only implements `tick()`.
+    struct FullOnly;
+
+    impl ServerEngine for FullOnly {
+        fn ingest(&mut self, _msg: &[u8]) -> bool {
+            true
+        }
+
+        fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
+            builder.reset();
+            let bytes = builder.create_vector(&[1u8]);
+            builder.finish_minimal(bytes);
+            builder.finished_data()
+        }
+    }
+
+    #[test]
+    fn test_delta_flag_only_for_engines_with_delta_frames() {
+        let mut builder = FlatBufferBuilder::new();
+        let mut deltas = Vec::new();
+
+        let mut full_only = EngineRegistry::new(|_: &str| FullOnly, |_: &[u8]| Some("t")).with_delta_frames(true);
+        full_only.open("t");
+        full_only.tick_all(&mut builder, |_, frame| deltas.push(frame.delta));
+
+        let mut books = registry().with_delta_frames(true);
+        books.open("BTC-USD@2");
+        books.tick_all(&mut builder, |_, frame| deltas.push(frame.delta));
+
+        let mut full_frames = registry();
+        full_frames.open("BTC-USD@2");
+        full_frames.tick_all(&mut builder, |_, frame| deltas.push(frame.delta));
+
+        assert_eq!(deltas, [false, true, false]);
+    }
+}
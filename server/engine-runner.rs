@@ -0,0 +1,504 @@
+//! # Engine Runner
+//!
+//! Runs an `EngineRegistry` on a dedicated thread that owns it outright.
+//! Exchange messages reach it through a bounded single-producer queue,
+//! snapshot requests through a second channel, and frames leave through
+//! `BroadcastState` — nothing is shared behind a lock, so the ingest task
+//! never waits for the tick loop to finish serializing.
+//!
+//! ## Architecture
+//!
+//! ```text
+//! Exchange WS ──→ IngestQueue::push(msg)          (ingest task, tokio runtime)
+//!                      │  bounded queue, stats: depth / high water / full / dropped
+//!                      ▼
+//!          ┌── engine thread (own current-thread runtime) ──┐
+//!          │ loop select! {                                 │
+//!          │   tick       → apply topic events, tick_all    │──→ broadcast.send(topic, frame)
+//!          │   snapshot   → registry.snapshot(topic)        │──→ oneshot reply
+//!          │   message    → registry.ingest(msg) (batched)  │
+//!          │ }                                              │
+//!          └────────────────────────────────────────────────┘
+//!                      ▲
+//! Client tasks ──→ EngineHandle::snapshot(topic)   (SnapshotSource)
+//! ```
+//!
+//! ## Usage
+//!
+//! ```rust
+//! let (broadcast, topic_events) = BroadcastState::with_topic_events(1024);
+//! let registry = EngineRegistry::new(OrderbookEngine::for_topic, route_by_symbol);
+//!
+//! let runner = EngineRunner::spawn(registry, broadcast.clone(), topic_events, RunnerConfig {
+//!     tick_interval: Duration::from_millis(20),
+//!     ..RunnerConfig::default()
+//! });
+//!
+//! // Ingest task (the only producer):
+//! let mut ingest = runner.ingest;
+//! ingest.push(msg_bytes).await;
+//!
+//! // Axum state — snapshots are served by the engine thread:
+//! ServerState { broadcast, engines: runner.handle }
+//! ```
+//!
+//! ## Backpressure
+//!
+//! The queue holds `RunnerConfig.queue_capacity` messages. When the engine
+//! falls behind, `Backpressure::Block` makes `push()` wait — the ingest task
+//! stops reading the socket and the exchange's TCP window absorbs the burst.
+//! `Backpressure::DropNewest` discards the message instead, for feeds where
+//! staleness is worse than a gap (the engine must then recover from the
+//! exchange's own sequence numbers). Either way `IngestStats` records it.
+//!
+//! The runner thread exits when the `IngestQueue` is dropped, after
+//! ingesting what was still queued and making one final tick, so clients
+//! receive the last state before a shutdown (see shutdown.rs).
+//!
+//! ## The queue is a tokio mpsc, not an SPSC ring
+//!
+//! The ingest queue is a bounded `tokio::sync::mpsc` channel, not a
+//! lock-free single-producer ring buffer (rtrb, crossbeam `ArrayQueue`).
+//! Its uncontended path takes no lock, but it is a linked list of
+//! blocks built for many producers, gated by a semaphore, so each message
+//! costs more atomics than an SPSC ring would. It is used because both
+//! ends need to await: `Backpressure::Block` parks the ingest task until
+//! there is room, and the engine thread sleeps in `select!` until a
+//! message, a tick or a snapshot request arrives. A plain ring would need
+//! spinning or a separate wakeup on both sides. Single-producer order is
+//! enforced by `IngestQueue` not being `Clone`. If profiling shows the
+//! channel on the hot path, swap in a ring behind `IngestQueue` and
+//! `EngineTask::messages`; nothing outside this file sees the channel.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use flatbuffers::FlatBufferBuilder;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::broadcast::{BroadcastState, Snapshot, SnapshotSource, TopicEvent};
+use crate::engine_registry::EngineRegistry;
+use crate::engine_trait::ServerEngine;
+use crate::metrics::Metrics;
+
+/// Most messages ingested back-to-back before the engine task yields to
+/// the tick and snapshot branches again.
+const INGEST_BATCH: usize = 256;
+
+/// What `IngestQueue::push()` does when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for space. Slows the upstream reader; nothing is lost.
+    Block,
+    /// Drop the incoming message and count it in `IngestStats::dropped`.
+    DropNewest,
+}
+
+#[derive(Debug, Clone)]
+pub struct RunnerConfig {
+    /// How often engines are ticked.
+    pub tick_interval: Duration,
+    /// Messages buffered between the ingest task and the engine thread.
+    pub queue_capacity: usize,
+    pub backpressure: Backpressure,
+    /// Receives tick durations, frame sizes and the ingest queue's stats.
+    pub metrics: Arc<Metrics>,
+}
+
+impl Default for RunnerConfig {
+    fn default() -> Self {
+        Self {
+            tick_interval: Duration::from_millis(20),
+            queue_capacity: 8192,
+            backpressure: Backpressure::Block,
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+}
+
+/// Ingest queue counters, updated lock-free by both ends of the queue.
+#[derive(Debug, Default)]
+pub struct IngestStats {
+    enqueued: AtomicU64,
+    dequeued: AtomicU64,
+    dropped: AtomicU64,
+    full: AtomicU64,
+    high_water: AtomicU64,
+}
+
+impl IngestStats {
+    /// Messages accepted by `push()`.
+    pub fn enqueued(&self) -> u64 {
+        self.enqueued.load(Ordering::Relaxed)
+    }
+
+    /// Messages handed to `EngineRegistry::ingest()`.
+    pub fn dequeued(&self) -> u64 {
+        self.dequeued.load(Ordering::Relaxed)
+    }
+
+    /// Messages discarded under `Backpressure::DropNewest`.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Times `push()` found the queue full (waited or dropped).
+    pub fn full(&self) -> u64 {
+        self.full.load(Ordering::Relaxed)
+    }
+
+    /// Deepest the queue has been since startup.
+    pub fn high_water(&self) -> u64 {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Messages currently waiting for the engine thread.
+    pub fn depth(&self) -> u64 {
+        self.enqueued().saturating_sub(self.dequeued())
+    }
+}
+
+/// Producer end of the ingest queue.
+///
+/// Deliberately not `Clone`: one upstream reader feeds one engine thread,
+/// so messages reach `ingest()` in the order the exchange sent them.
+pub struct IngestQueue {
+    tx: mpsc::Sender<Vec<u8>>,
+    policy: Backpressure,
+    stats: Arc<IngestStats>,
+}
+
+impl IngestQueue {
+    /// Queue a raw exchange message for the engine thread.
+    ///
+    /// Returns false if the message was dropped (queue full under
+    /// `DropNewest`) or the engine thread has stopped.
+    pub async fn push(&mut self, msg: Vec<u8>) -> bool {
+        let permit = match self.tx.try_reserve() {
+            Ok(permit) => permit,
+            Err(mpsc::error::TrySendError::Full(())) => {
+                self.stats.full.fetch_add(1, Ordering::Relaxed);
+                match self.policy {
+                    Backpressure::Block => match self.tx.reserve().await {
+                        Ok(permit) => permit,
+                        Err(_) => return false,
+                    },
+                    Backpressure::DropNewest => {
+                        self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                        return false;
+                    }
+                }
+            }
+            Err(mpsc::error::TrySendError::Closed(())) => return false,
+        };
+        // Count the message before the engine thread can see it, so
+        // `dequeued` never overtakes `enqueued`
+        let enqueued = self.stats.enqueued.fetch_add(1, Ordering::Relaxed) + 1;
+        let depth = enqueued.saturating_sub(self.stats.dequeued());
+        self.stats.high_water.fetch_max(depth, Ordering::Relaxed);
+        permit.send(msg);
+        true
+    }
+
+    pub fn stats(&self) -> Arc<IngestStats> {
+        self.stats.clone()
+    }
+}
+
+struct SnapshotRequest {
+    topic: String,
+    reply: oneshot::Sender<Option<Snapshot>>,
+}
+
+/// Cloneable handle to the engine thread, used as `ServerState.engines`.
+///
+/// Snapshots are built on the engine thread between ticks, so a snapshot's
+/// sequence is always consistent with the frames broadcast so far.
+#[derive(Clone)]
+pub struct EngineHandle {
+    snapshots: mpsc::Sender<SnapshotRequest>,
+    stats: Arc<IngestStats>,
+}
+
+impl EngineHandle {
+    pub fn stats(&self) -> Arc<IngestStats> {
+        self.stats.clone()
+    }
+}
+
+impl SnapshotSource for EngineHandle {
+    async fn snapshot(&self, topic: &str) -> Option<Snapshot> {
+        let (reply, rx) = oneshot::channel();
+        let request = SnapshotRequest { topic: topic.to_string(), reply };
+        self.snapshots.send(request).await.ok()?;
+        rx.await.ok().flatten()
+    }
+}
+
+/// A running engine thread and the two ways to reach it.
+pub struct EngineRunner {
+    pub ingest: IngestQueue,
+    pub handle: EngineHandle,
+    pub thread: JoinHandle<()>,
+}
+
+impl EngineRunner {
+    /// Move `registry` onto a new "engine" thread and start ticking it.
+    ///
+    /// The thread runs its own current-thread runtime, so tick timing isn't
+    /// disturbed by client tasks on the main runtime.
+    pub fn spawn<E: ServerEngine>(
+        registry: EngineRegistry<E>,
+        broadcast: BroadcastState,
+        topic_events: mpsc::UnboundedReceiver<TopicEvent>,
+        config: RunnerConfig,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_capacity);
+        let (snapshot_tx, snapshot_rx) = mpsc::channel(64);
+        let stats = Arc::new(IngestStats::default());
+        config.metrics.set_ingest_stats(stats.clone());
+
+        let engine = EngineTask {
+            registry,
+            broadcast,
+            topic_events,
+            messages: rx,
+            snapshots: snapshot_rx,
+            stats: stats.clone(),
+            metrics: config.metrics.clone(),
+        };
+        let tick_interval = config.tick_interval;
+        let thread = std::thread::Builder::new()
+            .name("engine".into())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .expect("engine runtime");
+                runtime.block_on(engine.run(tick_interval));
+            })
+            .expect("spawn engine thread");
+
+        Self {
+            ingest: IngestQueue { tx, policy: config.backpressure, stats: stats.clone() },
+            handle: EngineHandle { snapshots: snapshot_tx, stats },
+            thread,
+        }
+    }
+}
+
+/// State owned by the engine thread.
+struct EngineTask<E: ServerEngine> {
+    registry: EngineRegistry<E>,
+    broadcast: BroadcastState,
+    topic_events: mpsc::UnboundedReceiver<TopicEvent>,
+    messages: mpsc::Receiver<Vec<u8>>,
+    snapshots: mpsc::Receiver<SnapshotRequest>,
+    stats: Arc<IngestStats>,
+    metrics: Arc<Metrics>,
+}
+
+impl<E: ServerEngine> EngineTask<E> {
+    async fn run(mut self, tick_interval: Duration) {
+        let mut interval = tokio::time::interval(tick_interval);
+        // A late tick is followed by the next one on schedule, not a burst
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut builder = FlatBufferBuilder::with_capacity(4096);
+        info!("Engine thread started");
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = interval.tick() => self.tick(&mut builder),
+                Some(request) = self.snapshots.recv() => {
+                    // A subscriber's snapshot request can beat the next tick;
+                    // make sure its topic's engine exists first.
+                    self.apply_topic_events();
+                    let _ = request.reply.send(self.registry.snapshot(&request.topic));
+                }
+                msg = self.messages.recv() => {
+                    let Some(msg) = msg else { break };
+                    self.ingest(&msg);
+                    // Drain what's already queued without re-entering select!
+                    for _ in 1..INGEST_BATCH {
+                        match self.messages.try_recv() {
+                            Ok(msg) => self.ingest(&msg),
+                            Err(_) => break,
+                        }
+                    }
+                }
+            }
+        }
+        // Everything queued has been ingested; broadcast it before exiting
+        self.tick(&mut builder);
+        warn!("Ingest queue closed, engine thread stopping");
+    }
+
+    fn tick(&mut self, builder: &mut FlatBufferBuilder<'static>) {
+        self.apply_topic_events();
+        let started = Instant::now();
+        let (broadcast, metrics) = (&self.broadcast, &self.metrics);
+        self.registry.tick_all(builder, |topic, frame| {
+            let size = frame.bytes.len();
+            metrics.record_frame(size, broadcast.send(topic, frame));
+        });
+        metrics.record_tick(started.elapsed());
+    }
+
+    fn ingest(&mut self, msg: &[u8]) {
+        self.stats.dequeued.fetch_add(1, Ordering::Relaxed);
+        self.registry.ingest(msg);
+    }
+
+    fn apply_topic_events(&mut self) {
+        while let Ok(event) = self.topic_events.try_recv() {
+            self.registry.apply(event);
+        }
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts ingested messages; each frame's sequence is the count.
+    struct Counter {
+        count: u64,
+    }
+
+    impl ServerEngine for Counter {
+        fn ingest(&mut self, _msg: &[u8]) -> bool {
+            self.count += 1;
+            true
+        }
+
+        fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
+            builder.reset();
+            let count = builder.create_vector(&self.count.to_le_bytes());
+            builder.finish_minimal(count);
+            builder.finished_data()
+        }
+
+        fn sequence(&self) -> u64 {
+            self.count
+        }
+    }
+
+    fn queue(capacity: usize, policy: Backpressure) -> (IngestQueue, mpsc::Receiver<Vec<u8>>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (IngestQueue { tx, policy, stats: Arc::new(IngestStats::default()) }, rx)
+    }
+
+    /// What `EngineTask::ingest` does on the other end.
+    fn dequeue(rx: &mut mpsc::Receiver<Vec<u8>>, stats: &IngestStats) -> Vec<u8> {
+        let msg = rx.try_recv().unwrap();
+        stats.dequeued.fetch_add(1, Ordering::Relaxed);
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest_counts_drops() {
+        let (mut ingest, mut rx) = queue(2, Backpressure::DropNewest);
+        let stats = ingest.stats();
+
+        assert!(ingest.push(b"a".to_vec()).await);
+        assert!(ingest.push(b"b".to_vec()).await);
+        assert!(!ingest.push(b"c".to_vec()).await);
+        assert!(!ingest.push(b"d".to_vec()).await);
+        assert_eq!((stats.enqueued(), stats.dropped(), stats.full()), (2, 2, 2));
+
+        // The queued messages are the oldest ones
+        assert_eq!(dequeue(&mut rx, &stats), b"a");
+        assert!(ingest.push(b"e".to_vec()).await);
+        assert_eq!(dequeue(&mut rx, &stats), b"b");
+        assert_eq!(dequeue(&mut rx, &stats), b"e");
+        assert_eq!(stats.dropped(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_depth_and_high_water() {
+        let (mut ingest, mut rx) = queue(8, Backpressure::Block);
+        let stats = ingest.stats();
+
+        for msg in [b"a", b"b", b"c"] {
+            ingest.push(msg.to_vec()).await;
+        }
+        assert_eq!((stats.depth(), stats.high_water()), (3, 3));
+
+        dequeue(&mut rx, &stats);
+        dequeue(&mut rx, &stats);
+        assert_eq!((stats.depth(), stats.high_water()), (1, 3));
+
+        ingest.push(b"d".to_vec()).await;
+        assert_eq!((stats.depth(), stats.high_water()), (2, 3));
+        assert_eq!((stats.enqueued(), stats.dequeued(), stats.full()), (4, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_space() {
+        let (mut ingest, mut rx) = queue(1, Backpressure::Block);
+        let stats = ingest.stats();
+        ingest.push(b"a".to_vec()).await;
+
+        let pushing = tokio::spawn(async move { ingest.push(b"b".to_vec()).await });
+        tokio::task::yield_now().await;
+        assert!(!pushing.is_finished());
+        assert_eq!((stats.full(), stats.enqueued()), (1, 1));
+
+        dequeue(&mut rx, &stats);
+        assert!(pushing.await.unwrap());
+        assert_eq!(dequeue(&mut rx, &stats), b"b");
+        assert_eq!((stats.dropped(), stats.depth(), stats.high_water()), (0, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_push_fails_after_engine_stops() {
+        let (mut ingest, rx) = queue(1, Backpressure::Block);
+        drop(rx);
+        assert!(!ingest.push(b"a".to_vec()).await);
+        assert_eq!(ingest.stats().enqueued(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_closing_the_queue_ticks_once_more() {
+        let broadcast = BroadcastState::new(16);
+        let mut frames = broadcast.subscribe("t");
+        let mut registry = EngineRegistry::new(|_: &str| Counter { count: 0 }, |_: &[u8]| Some("t"));
+        registry.open("t");
+        // Emit the new engine's first frame now, so only the final tick is left
+        registry.tick_all(&mut FlatBufferBuilder::new(), |_, _| {});
+
+        let (mut ingest, messages) = queue(8, Backpressure::Block);
+        let (_snapshot_tx, snapshots) = mpsc::channel(1);
+        let (_event_tx, topic_events) = mpsc::unbounded_channel();
+        let task = EngineTask {
+            registry,
+            broadcast,
+            topic_events,
+            messages,
+            snapshots,
+            stats: ingest.stats(),
+            metrics: Arc::new(Metrics::new()),
+        };
+        for msg in [b"a", b"b", b"c"] {
+            ingest.push(msg.to_vec()).await;
+        }
+        let stats = ingest.stats();
+        drop(ingest);
+
+        // Interval ticks fire only at start, with nothing to emit yet
+        task.run(Duration::from_secs(3600)).await;
+
+        assert_eq!(stats.dequeued(), 3);
+        assert_eq!(frames.try_recv().unwrap().sequence, 3);
+        assert!(frames.try_recv().is_err());
+    }
+}
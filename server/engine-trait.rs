@@ -12,7 +12,7 @@
 //!                      │
 //!                 engine.tick(&mut builder)  (at 20-100Hz)
 //!                      │
-//!                 broadcast::send(bytes)  (fan-out to all clients)
+//!                 broadcast::send(bytes)  (wrapped in an Envelope, fan-out to all clients)
 //!                      │
 //!              Client WASM engine.ingest_frame(&[u8])
 //! ```
@@ -23,36 +23,49 @@
 //! 2. Implement `ingest()` to parse exchange messages and update state
 //! 3. Implement `tick()` to serialize state to FlatBuffer bytes
 //! 4. Optionally implement `snapshot()` for late-joining clients
+//! 5. Optionally implement `sequence()` + `tick_delta()` for delta frames
 //!
 //! ## Example: Orderbook Engine
 //!
+//! Condensed from orderbook-engine.rs, the complete version (exchange
+//! sequencing, delta frames, snapshots). The L2 book itself is
+//! `my_shared::orderbook::OrderBook`, shared with the WASM client.
+//!
 //! ```rust
 //! use flatbuffers::FlatBufferBuilder;
+//! use my_shared::orderbook::OrderBook;
 //!
 //! pub struct OrderbookEngine {
-//!     bids: Vec<(f64, f64)>,  // (price, size)
-//!     asks: Vec<(f64, f64)>,
+//!     book: OrderBook,
+//!     depth: usize,
 //!     sequence: u64,
-//!     dirty: bool,
 //! }
 //!
 //! impl ServerEngine for OrderbookEngine {
 //!     fn ingest(&mut self, msg: &[u8]) -> bool {
-//!         // Parse exchange-specific message format (JSON, binary, etc.)
-//!         // Update bids/asks
-//!         // Return true if state changed
-//!         self.dirty = true;
-//!         true
+//!         // Parse the exchange's message format (JSON, binary, etc.) into a
+//!         // snapshot or BookUpdate. Return true if the book changed; a
+//!         // sequence gap or crossed book is an error, not a change.
+//!         match parse_update(msg) {
+//!             Some(update) => self.book.apply_update(update).unwrap_or(false),
+//!             None => false,
+//!         }
 //!     }
 //!
 //!     fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
 //!         builder.reset();
-//!         // Serialize orderbook to FlatBuffer using schema/orderbook.fbs
-//!         // ... build bids/asks vectors, create OrderbookFrame ...
-//!         // builder.finished_data()
-//!         self.dirty = false;
+//!         self.sequence += 1;
+//!         // Serialize the top levels using schema/orderbook.fbs
+//!         let stats = self.book.stats(self.depth);
+//!         let bids: Vec<PriceLevel> =
+//!             self.book.bids().take(self.depth).map(|l| PriceLevel::new(l.price, l.size)).collect();
+//!         // ... asks likewise, create OrderbookFrame { best_bid: stats.best_bid, sequence, .. } ...
 //!         builder.finished_data()
 //!     }
+//!
+//!     fn sequence(&self) -> u64 {
+//!         self.sequence
+//!     }
 //! }
 //! ```
 
@@ -92,8 +105,50 @@ pub trait ServerEngine: Send + 'static {
     /// joining the live broadcast stream. This ensures they start with
     /// a complete view of the current state.
     ///
+    /// The snapshot must reflect state as of `sequence()` and carry that
+    /// sequence in the frame, so a client resyncing a delta stream knows
+    /// which buffered deltas are already folded in.
+    ///
     /// Default implementation returns None (no snapshot support).
     fn snapshot(&self, _builder: &mut FlatBufferBuilder<'static>) -> Option<Vec<u8>> {
         None
     }
+
+    /// Sequence number of the most recent frame produced by `tick()` or
+    /// `tick_delta()`.
+    ///
+    /// Must increase by exactly 1 per emitted frame and be written into the
+    /// frame itself (e.g. `OrderbookFrame.sequence`). The broadcast layer
+    /// uses it to detect gaps in a client's delta stream.
+    ///
+    /// Default implementation returns 0 (no sequencing).
+    fn sequence(&self) -> u64 {
+        0
+    }
+
+    /// Optional: serialize only what changed since the previous tick.
+    ///
+    /// Called instead of `tick()` when the tick loop runs in delta mode.
+    /// For an orderbook, emit only the price levels whose size changed
+    /// (size 0 = level removed) and set `is_delta = true`. Clients that miss
+    /// a delta are resynced with `snapshot()`, so delta engines must
+    /// implement `snapshot()` too.
+    ///
+    /// Default implementation returns None: the engine has no delta
+    /// frames, so the tick loop calls `tick()` and sends a full frame.
+    fn tick_delta<'a>(&mut self, _builder: &'a mut FlatBufferBuilder<'static>) -> Option<&'a [u8]> {
+        None
+    }
+
+    /// Version of the schema this engine's frames and snapshots are built
+    /// with, stamped into each `Envelope.schema_version` (envelope.rs) so
+    /// clients can refuse payloads they weren't built for. Return the
+    /// shared crate's hash of the schema, e.g.
+    /// `my_shared::schema::ORDERBOOK_SCHEMA_HASH`: the client compares it
+    /// with the hash it was compiled with.
+    ///
+    /// Default implementation returns 0 (unversioned).
+    fn schema_version(&self) -> u32 {
+        0
+    }
 }
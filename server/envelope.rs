@@ -0,0 +1,157 @@
+//! # Message Envelope
+//!
+//! Wraps every server -> client message in an `Envelope` (schema/envelope.fbs)
+//! so one WebSocket can carry frames for many topics and schemas alongside
+//! snapshots and command responses. The client reads the envelope header
+//! (kind, topic id, sequence, schema version) and then parses the payload
+//! with the root type that topic uses.
+//!
+//! ## Where messages are wrapped
+//!
+//! ```text
+//! EngineRegistry::tick_all ──→ BroadcastState::send()   kind = Frame, once per topic
+//! send_snapshot()          ──→ wrap()                   kind = Snapshot, per client
+//! handle_client_message()  ──→ wrap()                   kind = Response, per client
+//! ```
+//!
+//! Engines keep producing plain FlatBuffers; they never see the envelope.
+//! A frame is wrapped once in `BroadcastState::send()` and every subscriber
+//! shares the wrapped bytes.
+//!
+//! ## Client side
+//!
+//! In the WASM engine:
+//!
+//! ```rust
+//! let envelope = flatbuffers::root::<Envelope>(bytes)?;
+//! let payload = envelope.payload().map(|p| p.bytes()).unwrap_or_default();
+//! match envelope.kind() {
+//!     FrameKind::Frame | FrameKind::Snapshot => {
+//!         let frame = flatbuffers::root::<OrderbookFrame>(payload)?;
+//!         // ...
+//!     }
+//!     _ => {}
+//! }
+//! ```
+//!
+//! A client checks `schema_version()` against the hash of the schema it was
+//! built with (`my_shared::schema::ORDERBOOK_SCHEMA_HASH`, ...) before
+//! trusting the payload.
+//!
+//! In TypeScript, `EnvelopeRouter` dispatches by kind and topic without
+//! parsing the payload.
+
+use bytes::Bytes;
+use flatbuffers::FlatBufferBuilder;
+
+// Generated from schema/envelope.fbs. Replace this path with your actual
+// generated module.
+pub use crate::generated::envelope_generated::org_asm::envelope::FrameKind;
+use crate::generated::envelope_generated::org_asm::envelope::{Envelope, EnvelopeArgs};
+
+/// File identifier every envelope is finished with.
+pub const ENVELOPE_IDENTIFIER: &str = "OAEN";
+
+/// Everything in an envelope except the payload.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub kind: FrameKind,
+    /// `my_shared::topic_id(topic)`, or 0 for messages without a topic.
+    pub topic_id: u32,
+    pub sequence: u64,
+    /// Hash of the payload's schema (`my_shared::schema`), or 0.
+    pub schema_version: u32,
+    pub delta: bool,
+}
+
+impl Header {
+    /// Header for a command response.
+    pub fn response() -> Self {
+        Self {
+            kind: FrameKind::Response,
+            topic_id: 0,
+            sequence: 0,
+            schema_version: my_shared::schema::COMMANDS_SCHEMA_HASH,
+            delta: false,
+        }
+    }
+}
+
+/// Wrap `payload` (a finished FlatBuffer) in an envelope.
+pub fn wrap(header: Header, payload: &[u8]) -> Bytes {
+    let mut builder = FlatBufferBuilder::with_capacity(payload.len() + 64);
+    let payload = builder.create_vector(payload);
+    let envelope = Envelope::create(
+        &mut builder,
+        &EnvelopeArgs {
+            kind: header.kind,
+            topic_id: header.topic_id,
+            sequence: header.sequence,
+            schema_version: header.schema_version,
+            delta: header.delta,
+            payload: Some(payload),
+        },
+    );
+    builder.finish(envelope, Some(ENVELOPE_IDENTIFIER));
+    // Hand the builder's buffer over without copying it again
+    let (buf, head) = builder.collapse();
+    Bytes::from(buf).slice(head..)
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_round_trip() {
+        let header = Header {
+            kind: FrameKind::Snapshot,
+            topic_id: my_shared::topic_id("BTC-USD@20"),
+            sequence: (1 << 40) + 7,
+            schema_version: 0xdead_beef,
+            delta: true,
+        };
+        let bytes = wrap(header, &[1, 2, 3]);
+
+        assert!(flatbuffers::buffer_has_identifier(
+            &bytes,
+            ENVELOPE_IDENTIFIER,
+            false
+        ));
+        assert_eq!(&bytes[4..8], b"OAEN");
+        let envelope = flatbuffers::root::<Envelope>(&bytes).unwrap();
+        assert_eq!(envelope.kind(), FrameKind::Snapshot);
+        assert_eq!(envelope.topic_id(), my_shared::topic_id("BTC-USD@20"));
+        assert_eq!(envelope.sequence(), (1 << 40) + 7);
+        assert_eq!(envelope.schema_version(), 0xdead_beef);
+        assert!(envelope.delta());
+        assert_eq!(envelope.payload().unwrap().bytes(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_response_header() {
+        let bytes = wrap(Header::response(), b"response");
+
+        let envelope = flatbuffers::root::<Envelope>(&bytes).unwrap();
+        assert_eq!(envelope.kind(), FrameKind::Response);
+        assert_eq!(envelope.topic_id(), 0);
+        assert_eq!(envelope.sequence(), 0);
+        assert_eq!(
+            envelope.schema_version(),
+            my_shared::schema::COMMANDS_SCHEMA_HASH
+        );
+        assert!(!envelope.delta());
+        assert_eq!(envelope.payload().unwrap().bytes(), b"response");
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        let bytes = wrap(Header::response(), &[]);
+        let envelope = flatbuffers::root::<Envelope>(&bytes).unwrap();
+        assert_eq!(envelope.payload().map(|p| p.bytes()), Some(&[][..]));
+    }
+}
@@ -2,26 +2,44 @@
 //!
 //! Wiring template showing how to run a server engine with three concurrent tasks:
 //!
-//! 1. **Exchange ingest**: connects to exchange WS, feeds messages to engine
-//! 2. **Tick loop**: runs engine.tick() at a fixed rate, broadcasts FlatBuffer frames
-//! 3. **Axum server**: serves /ws endpoint for browser clients
+//! 1. **Upstream ingest**: reads every `UpstreamSource`, queues messages for the engine
+//! 2. **Engine thread**: `EngineRunner` ingests queued messages and runs tick_all()
+//!    at a fixed rate, broadcasting FlatBuffer frames
+//! 3. **Axum server**: serves /ws endpoint for browser clients, and /metrics
+//!    for Prometheus (see metrics.rs)
 //!
 //! ## How to use
 //!
 //! 1. Copy this file into your server crate
-//! 2. Implement `ServerEngine` for your domain (see engine-trait.rs)
-//! 3. Replace `YourEngine::new()` with your engine constructor
-//! 4. Replace the exchange WebSocket URL with your data source
-//! 5. Customize the tick rate and exchange message parsing
+//! 2. Pick the engine. The template serves L2 orderbooks with
+//!    `OrderbookEngine::for_topic` (orderbook_engine.rs). For trades and
+//!    OHLCV bars, uncomment `mod candle_engine;` and pass a `CandleEngine`
+//!    constructor to `EngineRegistry::new` instead (candle_engine.rs). For
+//!    anything else, implement `ServerEngine` in your_engine.rs (see
+//!    engine-trait.rs) and uncomment `mod your_engine;`
+//! 3. Replace the exchange WebSocket URL with your data sources (see upstream.rs)
+//! 4. Customize `route_by_symbol()`, the tick rate, and message parsing
+//! 5. Set `JWT_SECRET` (or load an RSA public key) to require authenticated
+//!    clients (see auth.rs)
+//! 6. Keep `COMPRESSION` on to let clients negotiate LZ4 frames (see
+//!    compression.rs)
+//!
+//! Run with `--replay session.rec` to feed a recording (see `RECORD_PATH`)
+//! to the engines instead of the exchange, at its original pace.
+//!
+//! On SIGTERM the server stops accepting clients, closes the upstream
+//! sources, lets the engine thread drain and tick one last time, then
+//! flushes every client and closes it with a reconnect hint (shutdown.rs).
 //!
 //! ## Architecture
 //!
 //! ```text
-//! Exchange WS ──→ engine.ingest()    (Task 1: ingest)
+//! Upstreams   ──→ ingest.push()      (Task 1: ingest, WS/TCP/file sources merged)
 //!                      │
-//!                 engine.tick()       (Task 2: tick loop at 50Hz)
+//!                 registry.ingest()  (Task 2: engine thread, routed by symbol)
+//!                 registry.tick_all() (        tick at 50Hz, changed topics only)
 //!                      │
-//!                 broadcast.send()   (fan-out)
+//!                 broadcast.send()   (fan-out to topic subscribers)
 //!                      │
 //!              Axum /ws endpoint     (Task 3: per-client forward)
 //!                      │
@@ -31,22 +49,55 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum::{extract::ws::Message, routing::get, Router};
-use flatbuffers::FlatBufferBuilder;
-use futures_util::{SinkExt, StreamExt};
+use axum::{routing::get, Router};
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
-use tokio_tungstenite::connect_async;
-use tracing::{error, info};
-use tracing_subscriber;
+use tracing::{info, warn};
 
-// Import your engine and broadcast module
+// Import your engine and broadcast module. Several are libraries: main()
+// uses part of their API and the rest (other upstream sources, RS256 keys,
+// replay helpers, ...) is there to pick from, so it isn't flagged as dead
+// code. Remove an `allow` to see what your server leaves unused.
+#[allow(dead_code)]
+mod auth;
+#[allow(dead_code)]
 mod broadcast;
+#[allow(dead_code)]
+mod command_handler;
+#[allow(dead_code)]
+mod compression;
+#[allow(dead_code)]
+mod engine_registry;
+#[allow(dead_code)]
+mod engine_runner;
 mod engine_trait;
-// mod your_engine;  // Your ServerEngine implementation
+mod envelope;
+mod metrics;
+#[allow(dead_code)]
+mod orderbook_engine;
+#[allow(dead_code)]
+mod recording;
+mod shutdown;
+#[allow(dead_code)]
+mod upstream;
+// FlatBuffer types from schema/*.fbs (flatc --rust -o src/generated/), with a
+// src/generated/mod.rs declaring each file, e.g. `pub mod commands_generated;`
+mod generated;
+// Other engines: uncomment the one you serve and pass its constructor to
+// EngineRegistry::new in main() in place of OrderbookEngine::for_topic.
+// #[allow(dead_code)]
+// mod candle_engine;  // Trades → OHLCV bars: |topic| CandleEngine::new(topic, CandleConfig::default())
+// mod your_engine;    // Your ServerEngine implementation: YourEngine::new
 
-use broadcast::{ws_handler, BroadcastState};
-use engine_trait::ServerEngine;
+use auth::{Authenticator, JwtAuthenticator, NoAuth};
+use broadcast::{ws_handler, BroadcastState, OutboundConfig, ServerState, SlowConsumerPolicy};
+use compression::{Compression, DictionaryConfig};
+use engine_registry::EngineRegistry;
+use engine_runner::{Backpressure, EngineRunner, RunnerConfig};
+use metrics::{metrics_handler, Metrics};
+use orderbook_engine::OrderbookEngine;
+use recording::{Recorder, ReplaySource, ReplaySpeed};
+use shutdown::Shutdown;
+use upstream::{Upstreams, WsSource};
 
 // ============================================
 // Configuration
@@ -61,146 +112,198 @@ const EXCHANGE_WS_URL: &str = "wss://stream.example.com/ws";
 /// Higher = less bandwidth but more latency.
 const TICK_INTERVAL_MS: u64 = 20;
 
+/// Heartbeat interval in milliseconds.
+/// Engines are only ticked when `ingest()` reported a change; an unchanged
+/// engine is still ticked this often so clients can detect liveness in a
+/// quiet market.
+const HEARTBEAT_INTERVAL_MS: u64 = 1000;
+
+/// Send only changed state each tick (`ServerEngine::tick_delta`) instead of
+/// the full state. Clients that miss a delta are resynced from `snapshot()`.
+/// Engines without `tick_delta()` keep sending full frames.
+const DELTA_FRAMES: bool = true;
+
+/// Append every upstream message to this file for replay in tests
+/// (recording.rs), e.g. Some("session.rec"). None disables recording.
+const RECORD_PATH: Option<&str> = None;
+
+/// Exchange messages buffered between the ingest task and the engine thread.
+const INGEST_QUEUE_CAPACITY: usize = 8192;
+
+/// Environment variable holding the HS256 secret client JWTs are signed
+/// with. When unset, every client is accepted as anonymous.
+const JWT_SECRET_ENV: &str = "JWT_SECRET";
+
+/// On shutdown, clients are told to wait at least this long before
+/// reconnecting (spread up to twice this), giving the next instance time to
+/// come up.
+const RECONNECT_AFTER_MS: u64 = 2000;
+
+/// How long clients get to receive their final frames on shutdown.
+const DRAIN_TIMEOUT_MS: u64 = 5000;
+
 /// Address to bind the WebSocket server
 const BIND_ADDR: &str = "0.0.0.0:9001";
 
-/// Broadcast channel capacity (frames buffered for slow clients)
+/// Broadcast channel capacity per topic (frames buffered for slow clients)
 const BROADCAST_CAPACITY: usize = 1024;
 
+/// Frames, responses and snapshots queued per client before the
+/// slow-consumer policy kicks in. Queued frames share the broadcast bytes,
+/// so this bounds handles, not copies.
+const OUTBOUND_QUEUE_CAPACITY: usize = 256;
+
+/// What to drop when a client's outbound queue is full. CoalesceLatest keeps
+/// the newest full frame per topic; use `Disconnect { max_lags }` to shed
+/// clients that never catch up.
+const SLOW_CONSUMER_POLICY: SlowConsumerPolicy = SlowConsumerPolicy::CoalesceLatest;
+
+/// Accept the `orgasm.lz4` subprotocol: clients that request it receive
+/// LZ4-compressed frames against a dictionary trained on recent frames.
+/// Clients that don't keep getting plain FlatBuffers.
+const COMPRESSION: bool = true;
+
 // ============================================
 // Main
 // ============================================
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::init();
-
-    // --- Shared state ---
-    // Engine wrapped in Arc<Mutex<>> for shared access between ingest and tick tasks.
-    // This is simple and correct. For ultra-low-latency, replace with:
-    //   - Separate ingest/tick engines connected by a channel
-    //   - Lock-free ring buffer for message passing
-    //   - Dedicated threads with core pinning
-    let engine = Arc::new(Mutex::new(YourEngine::new()));
-    let broadcast = BroadcastState::new(BROADCAST_CAPACITY);
-    let broadcast_for_tick = broadcast.clone();
-
-    // --- Task 1: Exchange ingest ---
-    // Connects to the exchange WebSocket and feeds raw messages to the engine.
-    let engine_for_ingest = engine.clone();
-    let ingest_handle = tokio::spawn(async move {
-        loop {
-            match connect_async(EXCHANGE_WS_URL).await {
-                Ok((ws_stream, _)) => {
-                    info!("Connected to exchange");
-                    let (_, mut read) = ws_stream.split();
-
-                    while let Some(msg) = read.next().await {
-                        match msg {
-                            Ok(tokio_tungstenite::tungstenite::Message::Text(text)) => {
-                                let mut eng = engine_for_ingest.lock().await;
-                                eng.ingest(text.as_bytes());
-                            }
-                            Ok(tokio_tungstenite::tungstenite::Message::Binary(bin)) => {
-                                let mut eng = engine_for_ingest.lock().await;
-                                eng.ingest(&bin);
-                            }
-                            Ok(tokio_tungstenite::tungstenite::Message::Close(_)) => {
-                                info!("Exchange connection closed");
-                                break;
-                            }
-                            Err(e) => {
-                                error!("Exchange WS error: {e}");
-                                break;
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to connect to exchange: {e}");
-                }
-            }
-
-            // Reconnect after delay
-            info!("Reconnecting to exchange in 3s...");
-            tokio::time::sleep(Duration::from_secs(3)).await;
-        }
-    });
+    tracing_subscriber::fmt::init();
 
-    // --- Task 2: Tick loop ---
-    // Runs the engine at a fixed rate and broadcasts serialized frames.
-    let engine_for_tick = engine.clone();
-    let tick_handle = tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_millis(TICK_INTERVAL_MS));
-        let mut builder = FlatBufferBuilder::with_capacity(4096);
+    let shutdown = Shutdown::new(
+        Duration::from_millis(RECONNECT_AFTER_MS),
+        Duration::from_millis(DRAIN_TIMEOUT_MS),
+    );
 
-        loop {
-            interval.tick().await;
+    // --- Engine thread ---
+    // One engine per subscribed topic (symbol and depth, "BTC-USD@20"),
+    // created on the first Subscribe and dropped after the last Unsubscribe
+    // (driven by topic events). Exchange messages are routed by symbol to
+    // every depth's engine.
+    // The runner moves the registry onto its own thread: ingest, tick and
+    // snapshots all happen there, so no lock is shared with the ingest task.
+    let registry = EngineRegistry::new(OrderbookEngine::for_topic, route_by_symbol)
+        .with_delta_frames(DELTA_FRAMES)
+        .with_heartbeat(Some(Duration::from_millis(HEARTBEAT_INTERVAL_MS)));
+    let compression = COMPRESSION.then(|| Compression::lz4(DictionaryConfig::default()));
+    let (broadcast, topic_events) = BroadcastState::with_topic_events(BROADCAST_CAPACITY);
+    // Broadcast frames train the compression dictionary, once per frame
+    let broadcast = broadcast.with_compression(compression.clone());
+    // Tick latency, frame sizes, fan-out, lag, ingest and command counts
+    let metrics = Arc::new(Metrics::new());
+    let runner = EngineRunner::spawn(
+        registry,
+        broadcast.clone(),
+        topic_events,
+        RunnerConfig {
+            tick_interval: Duration::from_millis(TICK_INTERVAL_MS),
+            queue_capacity: INGEST_QUEUE_CAPACITY,
+            backpressure: Backpressure::Block,
+            metrics: metrics.clone(),
+        },
+    );
 
-            let bytes = {
-                let mut eng = engine_for_tick.lock().await;
-                let data = eng.tick(&mut builder);
-                data.to_vec() // Copy out of builder before releasing lock
-            };
+    // --- Task 1: Upstream ingest ---
+    // Reads every upstream source and queues raw messages for the engine
+    // thread. Sources reconnect on their own (exponential backoff + jitter)
+    // and replay their subscription messages. When the queue is full,
+    // push() waits (Backpressure::Block) and the sources stop being read
+    // until the engine catches up.
+    //
+    // More sources feed the same engines, e.g.:
+    //   .with(WsSource::new("wss://ws.other-exchange.com")
+    //       .with_subscription(r#"{"op":"subscribe","args":["orderbook"]}"#))
+    //   .with(TcpLineSource::new("10.0.0.5:7000"))
+    //   .with(FileSource::new("fixtures/book.ndjson"))
+    let mut upstreams = match replay_path() {
+        Some(path) => {
+            let replay = ReplaySource::open(&path, ReplaySpeed::Original).expect("open replay file");
+            Upstreams::new().with(replay)
+        }
+        None => Upstreams::new().with(WsSource::new(EXCHANGE_WS_URL)),
+    };
+    if let Some(path) = RECORD_PATH {
+        let recorder = Recorder::open(path).expect("open recording file");
+        info!("Recording upstream messages to {path}");
+        upstreams = upstreams.record_to(recorder);
+    }
+    let ingest_handle = tokio::spawn(upstreams.run_until(runner.ingest, shutdown.upstreams_stopped()));
 
-            broadcast_for_tick.send(bytes);
+    // --- Client authentication ---
+    // Clients present a JWT (?token=, Authorization header, or first
+    // message); its `symbols` claim limits what they may subscribe to.
+    // For RS256 tokens from an identity provider:
+    //   Arc::new(JwtAuthenticator::rsa_pem_file("keys/jwt.pub.pem").expect("load JWT key"))
+    let auth: Arc<dyn Authenticator> = match std::env::var(JWT_SECRET_ENV) {
+        Ok(secret) => Arc::new(JwtAuthenticator::hmac(secret.as_bytes())),
+        Err(_) => {
+            warn!("{JWT_SECRET_ENV} not set, accepting unauthenticated clients");
+            Arc::new(NoAuth)
         }
-    });
+    };
 
     // --- Task 3: Axum WebSocket server ---
     // Serves the /ws endpoint. Each client gets its own forward task.
+    // The engine handle doubles as the SnapshotSource for client resyncs.
+    // /metrics serves Prometheus text; keep it off the public listener (or
+    // behind your proxy's auth) in production.
     let app = Router::new()
         .route("/ws", get(ws_handler))
-        .with_state(broadcast);
+        .route("/metrics", get(metrics_handler))
+        .with_state(ServerState {
+            broadcast,
+            engines: runner.handle,
+            outbound: OutboundConfig {
+                capacity: OUTBOUND_QUEUE_CAPACITY,
+                policy: SLOW_CONSUMER_POLICY,
+            },
+            auth,
+            shutdown: shutdown.clone(),
+            metrics,
+            compression,
+        });
 
     let listener = TcpListener::bind(BIND_ADDR).await.unwrap();
     info!("Server listening on {BIND_ADDR}");
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::signal())
+        .await
+        .unwrap();
+
+    // --- Shutdown ---
+    // Listener is closed. Stop the upstreams; the engine thread ingests what
+    // is still queued, ticks once more and exits. Then every client gets
+    // those final frames and a close with a reconnect hint.
+    shutdown.stop_upstreams();
+    ingest_handle.await.ok();
+    let engine_thread = runner.thread;
+    tokio::task::spawn_blocking(move || engine_thread.join()).await.ok();
+    shutdown.close_clients().await;
+    info!("Shutdown complete");
 }
 
 // ============================================
-// Replace this with your engine implementation
+// Topic routing
 // ============================================
 
-struct YourEngine {
-    // Your state here
-}
-
-impl YourEngine {
-    fn new() -> Self {
-        Self {}
+/// Extract the symbol from an exchange message so the registry can route it.
+///
+/// Borrows the symbol from the message bytes — no allocation per message.
+/// Replace with your exchange's format (e.g. a channel name, or a lookup
+/// table mapping exchange tickers to `&'static str` topic names).
+fn route_by_symbol(msg: &[u8]) -> Option<&str> {
+    #[derive(serde::Deserialize)]
+    struct Routed<'a> {
+        #[serde(borrow)]
+        symbol: &'a str,
     }
-}
 
-impl ServerEngine for YourEngine {
-    fn ingest(&mut self, _msg: &[u8]) -> bool {
-        // Parse exchange message, update state
-        // Return true if state changed
-        true
-    }
+    serde_json::from_slice::<Routed>(msg).ok().map(|m| m.symbol)
+}
 
-    fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
-        builder.reset();
-        // Serialize state to FlatBuffer
-        // Example with orderbook.fbs:
-        //   let bids = builder.create_vector(&self.bids);
-        //   let asks = builder.create_vector(&self.asks);
-        //   let frame = OrderbookFrame::create(&mut builder, &OrderbookFrameArgs {
-        //       best_bid: self.best_bid,
-        //       best_ask: self.best_ask,
-        //       mid_price: (self.best_bid + self.best_ask) / 2.0,
-        //       spread: self.best_ask - self.best_bid,
-        //       bids: Some(bids),
-        //       asks: Some(asks),
-        //       timestamp_ms: self.timestamp,
-        //       sequence: self.sequence,
-        //       bid_total_size: self.bid_total,
-        //       ask_total_size: self.ask_total,
-        //       imbalance: self.imbalance,
-        //   });
-        //   builder.finish(frame, None);
-        builder.finished_data()
-    }
+/// The recording passed as `--replay <path>`, if any.
+fn replay_path() -> Option<String> {
+    std::env::args().skip_while(|arg| arg != "--replay").nth(1)
 }
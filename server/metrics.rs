@@ -0,0 +1,573 @@
+//! # Server Metrics
+//!
+//! Counters and histograms for capacity planning, served as Prometheus text
+//! on `/metrics`. Everything is a lock-free atomic except the command
+//! counters, which are touched once per client command.
+//!
+//! ## What is measured
+//!
+//! | Metric | Type | Recorded by |
+//! |--------|------|-------------|
+//! | `orgasm_tick_duration_seconds` | histogram | engine thread, per `tick_all()` |
+//! | `orgasm_frame_size_bytes` | histogram | engine thread, per broadcast frame |
+//! | `orgasm_frames_broadcast_total` | counter | engine thread |
+//! | `orgasm_frame_deliveries_total` | counter | engine thread (frames × subscribers reached) |
+//! | `orgasm_connected_clients` | gauge | client tasks |
+//! | `orgasm_topic_subscribers{topic}` | gauge | `BroadcastState`, at scrape time |
+//! | `orgasm_client_lag_events_total` / `orgasm_client_lagged_frames_total` | counter | client tasks, on broadcast lag |
+//! | `orgasm_outbound_dropped_frames_total` | counter | client tasks, slow-consumer policy |
+//! | `orgasm_ingest_*` | counter / gauge | `IngestStats`, at scrape time |
+//! | `orgasm_commands_total{command, result}` | counter | `handle_client_message` |
+//!
+//! Ingest rate is `rate(orgasm_ingest_dequeued_total[1m])`; fan-out is
+//! `rate(orgasm_frame_deliveries_total[1m])`.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! let metrics = Arc::new(Metrics::new());
+//! let runner = EngineRunner::spawn(registry, broadcast.clone(), topic_events, RunnerConfig {
+//!     metrics: metrics.clone(),   // ticks, frames, ingest stats
+//!     ..RunnerConfig::default()
+//! });
+//!
+//! let app = Router::new()
+//!     .route("/ws", get(ws_handler))
+//!     .route("/metrics", get(metrics_handler))
+//!     .with_state(ServerState { metrics, ..state });
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::broadcast::{BroadcastState, ServerState, SnapshotSource};
+use crate::engine_runner::IngestStats;
+
+/// Tick duration bucket bounds, in seconds (100µs .. 100ms).
+const TICK_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+
+/// Frame size bucket bounds, in bytes (64 B .. 1 MiB).
+const FRAME_SIZE_BUCKETS: &[f64] = &[
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+];
+
+/// Fixed-bucket histogram. Observations are integers in a base unit
+/// (nanoseconds, bytes); `scale` converts them to the exported unit.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    scale: f64,
+    /// One count per bound plus +Inf, non-cumulative.
+    buckets: Box<[AtomicU64]>,
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64], scale: f64) -> Self {
+        Self {
+            bounds,
+            scale,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, raw: u64) {
+        let value = raw as f64 * self.scale;
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(raw, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            match self.bounds.get(i) {
+                Some(bound) => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+                }
+                None => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {cumulative}");
+                }
+            }
+        }
+        let sum = self.sum.load(Ordering::Relaxed) as f64 * self.scale;
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {}", self.count());
+    }
+}
+
+/// Server-wide metrics. Share one `Arc<Metrics>` between the engine runner
+/// (`RunnerConfig.metrics`) and `ServerState.metrics`.
+#[derive(Debug)]
+pub struct Metrics {
+    tick_duration: Histogram,
+    frame_size: Histogram,
+    frames_broadcast: AtomicU64,
+    frame_deliveries: AtomicU64,
+    connected_clients: AtomicI64,
+    lag_events: AtomicU64,
+    lagged_frames: AtomicU64,
+    outbound_dropped: AtomicU64,
+    /// (command type, accepted) -> count
+    commands: Mutex<HashMap<(&'static str, bool), u64>>,
+    /// Set by `EngineRunner::spawn`.
+    ingest: OnceLock<Arc<IngestStats>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            tick_duration: Histogram::new(TICK_BUCKETS, 1e-9),
+            frame_size: Histogram::new(FRAME_SIZE_BUCKETS, 1.0),
+            frames_broadcast: AtomicU64::new(0),
+            frame_deliveries: AtomicU64::new(0),
+            connected_clients: AtomicI64::new(0),
+            lag_events: AtomicU64::new(0),
+            lagged_frames: AtomicU64::new(0),
+            outbound_dropped: AtomicU64::new(0),
+            commands: Mutex::new(HashMap::new()),
+            ingest: OnceLock::new(),
+        }
+    }
+
+    /// One `tick_all()` pass took `elapsed`.
+    pub fn record_tick(&self, elapsed: Duration) {
+        self.tick_duration.observe(elapsed.as_nanos() as u64);
+    }
+
+    /// A frame of `bytes` was broadcast and reached `receivers` subscribers.
+    pub fn record_frame(&self, bytes: usize, receivers: usize) {
+        self.frame_size.observe(bytes as u64);
+        self.frames_broadcast.fetch_add(1, Ordering::Relaxed);
+        self.frame_deliveries
+            .fetch_add(receivers as u64, Ordering::Relaxed);
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// A client's broadcast receiver lagged and skipped `frames`.
+    pub fn record_lag(&self, frames: u64) {
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.lagged_frames.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// The slow-consumer policy dropped a queued frame (or a delta that
+    /// would have followed one).
+    pub fn record_outbound_drop(&self) {
+        self.outbound_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A client command of type `command` was handled.
+    pub fn record_command(&self, command: &'static str, accepted: bool) {
+        *self
+            .commands
+            .lock()
+            .unwrap()
+            .entry((command, accepted))
+            .or_insert(0) += 1;
+    }
+
+    /// Export the engine runner's ingest queue counters.
+    pub fn set_ingest_stats(&self, stats: Arc<IngestStats>) {
+        let _ = self.ingest.set(stats);
+    }
+
+    /// Prometheus text exposition of every metric. Per-topic subscriber
+    /// counts are read from `broadcast`.
+    pub fn render(&self, broadcast: &BroadcastState) -> String {
+        let mut out = String::with_capacity(4096);
+
+        self.tick_duration.render(
+            &mut out,
+            "orgasm_tick_duration_seconds",
+            "Time spent in one tick_all() pass.",
+        );
+        self.frame_size.render(
+            &mut out,
+            "orgasm_frame_size_bytes",
+            "Size of broadcast frames.",
+        );
+        counter(
+            &mut out,
+            "orgasm_frames_broadcast_total",
+            "Frames broadcast to topics.",
+            &self.frames_broadcast,
+        );
+        counter(
+            &mut out,
+            "orgasm_frame_deliveries_total",
+            "Frames times the subscribers they reached.",
+            &self.frame_deliveries,
+        );
+
+        gauge_header(
+            &mut out,
+            "orgasm_connected_clients",
+            "Connected WebSocket clients.",
+        );
+        let _ = writeln!(
+            out,
+            "orgasm_connected_clients {}",
+            self.connected_clients.load(Ordering::Relaxed)
+        );
+
+        gauge_header(
+            &mut out,
+            "orgasm_topic_subscribers",
+            "Clients subscribed per topic.",
+        );
+        let mut topics = broadcast.subscriber_counts();
+        topics.sort();
+        for (topic, subscribers) in topics {
+            let _ = writeln!(
+                out,
+                "orgasm_topic_subscribers{{topic=\"{}\"}} {subscribers}",
+                escape(&topic)
+            );
+        }
+
+        counter(
+            &mut out,
+            "orgasm_client_lag_events_total",
+            "Times a client fell behind its broadcast channel.",
+            &self.lag_events,
+        );
+        counter(
+            &mut out,
+            "orgasm_client_lagged_frames_total",
+            "Broadcast frames skipped by lagging clients.",
+            &self.lagged_frames,
+        );
+        counter(
+            &mut out,
+            "orgasm_outbound_dropped_frames_total",
+            "Frames dropped by the slow-consumer policy.",
+            &self.outbound_dropped,
+        );
+
+        if let Some(ingest) = self.ingest.get() {
+            let counters = [
+                (
+                    "orgasm_ingest_enqueued_total",
+                    "Upstream messages queued for the engine.",
+                    ingest.enqueued(),
+                ),
+                (
+                    "orgasm_ingest_dequeued_total",
+                    "Upstream messages ingested by the engine.",
+                    ingest.dequeued(),
+                ),
+                (
+                    "orgasm_ingest_dropped_total",
+                    "Upstream messages dropped on a full queue.",
+                    ingest.dropped(),
+                ),
+                (
+                    "orgasm_ingest_full_total",
+                    "Times the ingest queue was full.",
+                    ingest.full(),
+                ),
+            ];
+            for (name, help, value) in counters {
+                let _ = writeln!(
+                    out,
+                    "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+                );
+            }
+            gauge_header(
+                &mut out,
+                "orgasm_ingest_queue_depth",
+                "Messages waiting for the engine thread.",
+            );
+            let _ = writeln!(out, "orgasm_ingest_queue_depth {}", ingest.depth());
+            gauge_header(
+                &mut out,
+                "orgasm_ingest_queue_high_water",
+                "Deepest the ingest queue has been.",
+            );
+            let _ = writeln!(
+                out,
+                "orgasm_ingest_queue_high_water {}",
+                ingest.high_water()
+            );
+        }
+
+        let name = "orgasm_commands_total";
+        let help = "Client commands by type and result.";
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        let mut commands: Vec<_> = self
+            .commands
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        commands.sort();
+        for ((command, accepted), count) in commands {
+            let result = if accepted { "ok" } else { "rejected" };
+            let _ = writeln!(
+                out,
+                "orgasm_commands_total{{command=\"{command}\",result=\"{result}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}",
+        value.load(Ordering::Relaxed)
+    );
+}
+
+fn gauge_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+}
+
+/// Escape a label value (backslash, quote, newline).
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Axum handler for `/metrics`. Mount next to `/ws`:
+/// ```rust
+/// Router::new().route("/metrics", get(metrics_handler))
+/// ```
+pub async fn metrics_handler<S: SnapshotSource>(
+    State(state): State<ServerState<S>>,
+) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&state.broadcast),
+    )
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(histogram: &Histogram) -> Vec<String> {
+        let mut out = String::new();
+        histogram.render(&mut out, "h", "Test histogram.");
+        out.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let histogram = Histogram::new(&[1.0, 10.0, 100.0], 1.0);
+        for raw in [0, 1, 5, 10, 50, 1000] {
+            histogram.observe(raw);
+        }
+        assert_eq!(
+            rendered(&histogram),
+            [
+                "# HELP h Test histogram.",
+                "# TYPE h histogram",
+                // Bounds are inclusive, counts cumulative
+                "h_bucket{le=\"1\"} 2",
+                "h_bucket{le=\"10\"} 4",
+                "h_bucket{le=\"100\"} 5",
+                "h_bucket{le=\"+Inf\"} 6",
+                "h_sum 1066",
+                "h_count 6",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_histogram_scales_to_exported_unit() {
+        let metrics = Metrics::new();
+        metrics.record_tick(Duration::from_micros(2000));
+        metrics.record_tick(Duration::from_secs(1));
+
+        let lines = rendered(&metrics.tick_duration);
+        assert!(lines.contains(&"h_bucket{le=\"0.001\"} 0".to_string()));
+        assert!(lines.contains(&"h_bucket{le=\"0.0025\"} 1".to_string()));
+        assert!(lines.contains(&"h_bucket{le=\"0.1\"} 1".to_string()));
+        assert!(lines.contains(&"h_bucket{le=\"+Inf\"} 2".to_string()));
+        assert!(lines.contains(&"h_sum 1.002".to_string()));
+        assert!(lines.contains(&"h_count 2".to_string()));
+    }
+
+    fn is_metric_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    }
+
+    /// Parse `{key="value",...}`, unescaping values.
+    fn parse_labels(labels: &str) -> Vec<(String, String)> {
+        let mut parsed = Vec::new();
+        let mut rest = labels;
+        while !rest.is_empty() {
+            let (key, after) = rest.split_once("=\"").expect("label without =\"");
+            assert!(is_metric_name(key), "bad label name {key:?}");
+            let mut value = String::new();
+            let mut chars = after.char_indices();
+            let end = loop {
+                match chars.next().expect("unterminated label value") {
+                    (i, '"') => break i,
+                    (_, '\\') => match chars.next().unwrap().1 {
+                        'n' => value.push('\n'),
+                        c @ ('\\' | '"') => value.push(c),
+                        c => panic!("bad escape \\{c}"),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            parsed.push((key.to_string(), value));
+            rest = &after[end + 1..];
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+        parsed
+    }
+
+    #[test]
+    fn test_render_is_valid_prometheus_text() {
+        let (broadcast, _events) = BroadcastState::with_topic_events(16);
+        let _rx = broadcast.subscribe("BTC-USD@20");
+        let _rx2 = broadcast.subscribe("a\"b\\c");
+        let metrics = Metrics::new();
+        metrics.set_ingest_stats(Arc::new(IngestStats::default()));
+        metrics.record_frame(300, 2);
+        metrics.record_tick(Duration::from_micros(300));
+        metrics.client_connected();
+        metrics.record_command("Subscribe", true);
+        metrics.record_command("Subscribe", false);
+
+        let text = metrics.render(&broadcast);
+        assert!(text.ends_with('\n'));
+
+        let mut help = HashMap::new();
+        let mut types = HashMap::new();
+        let mut samples = HashMap::new();
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let (keyword, rest) = comment.split_once(' ').unwrap();
+                let (name, value) = rest.split_once(' ').unwrap();
+                assert!(is_metric_name(name), "bad name in {line:?}");
+                let seen = match keyword {
+                    "HELP" => help.insert(name.to_string(), value.to_string()),
+                    "TYPE" => {
+                        assert!(
+                            ["counter", "gauge", "histogram"].contains(&value),
+                            "{line:?}"
+                        );
+                        assert!(help.contains_key(name), "TYPE before HELP: {line:?}");
+                        types.insert(name.to_string(), value.to_string())
+                    }
+                    _ => panic!("unexpected comment {line:?}"),
+                };
+                assert!(seen.is_none(), "duplicate {keyword} for {name}");
+                continue;
+            }
+
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            assert!(
+                value == "+Inf" || value.parse::<f64>().is_ok(),
+                "bad value in {line:?}"
+            );
+            let (name, labels) = match series.split_once('{') {
+                Some((name, labels)) => (name, parse_labels(labels.strip_suffix('}').unwrap())),
+                None => (series, Vec::new()),
+            };
+            assert!(is_metric_name(name), "bad name in {line:?}");
+            let family = ["_bucket", "_sum", "_count"]
+                .iter()
+                .find_map(|suffix| {
+                    name.strip_suffix(suffix)
+                        .filter(|f| types.get(*f).is_some_and(|t| t == "histogram"))
+                })
+                .unwrap_or(name);
+            assert!(
+                types.contains_key(family),
+                "sample before its TYPE: {line:?}"
+            );
+            samples.insert((name.to_string(), labels), value.to_string());
+        }
+
+        let sample = |name: &str, labels: &[(&str, &str)]| {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            samples.get(&(name.to_string(), labels)).map(String::as_str)
+        };
+        assert_eq!(types["orgasm_frame_size_bytes"], "histogram");
+        assert_eq!(
+            sample("orgasm_frame_size_bytes_bucket", &[("le", "256")]),
+            Some("0")
+        );
+        assert_eq!(
+            sample("orgasm_frame_size_bytes_bucket", &[("le", "1024")]),
+            Some("1")
+        );
+        assert_eq!(
+            sample("orgasm_frame_size_bytes_bucket", &[("le", "+Inf")]),
+            Some("1")
+        );
+        assert_eq!(sample("orgasm_frame_size_bytes_sum", &[]), Some("300"));
+        assert_eq!(sample("orgasm_frame_deliveries_total", &[]), Some("2"));
+        assert_eq!(sample("orgasm_connected_clients", &[]), Some("1"));
+        assert_eq!(
+            sample("orgasm_topic_subscribers", &[("topic", "BTC-USD@20")]),
+            Some("1")
+        );
+        assert_eq!(
+            sample("orgasm_topic_subscribers", &[("topic", "a\"b\\c")]),
+            Some("1")
+        );
+        assert_eq!(sample("orgasm_ingest_queue_depth", &[]), Some("0"));
+        let command = |result| {
+            sample(
+                "orgasm_commands_total",
+                &[("command", "Subscribe"), ("result", result)],
+            )
+        };
+        assert_eq!((command("ok"), command("rejected")), (Some("1"), Some("1")));
+    }
+}
@@ -0,0 +1,454 @@
+//! # Orderbook Engine
+//!
+//! Reference `ServerEngine` that maintains an L2 book per symbol from
+//! exchange snapshots and updates (`my_shared::orderbook::OrderBook`) and
+//! serializes its top levels with `schema/orderbook.fbs`.
+//!
+//! ## Architecture
+//!
+//! ```text
+//! Exchange book ──→ ingest(msg) ──→ OrderBook (shared crate)
+//!   snapshot            │             sorted levels, sequence checks,
+//!   update              │             crossed-book detection
+//!                       ▼
+//!             top `depth` levels per side + best/mid/spread/totals/imbalance
+//!                       │
+//!     tick()       ──→ full frame
+//!     tick_delta() ──→ levels that changed within the window since the last frame
+//!     snapshot()   ──→ full frame (subscribe, resync)
+//! ```
+//!
+//! ```rust
+//! let registry = EngineRegistry::new(OrderbookEngine::for_topic, route_by_symbol)
+//!     .with_delta_frames(true);
+//! ```
+//!
+//! ## Depth
+//!
+//! Frames carry the best `depth` levels per side (`with_depth()`, by
+//! default `MAX_BOOK_DEPTH`), and the totals and imbalance cover exactly
+//! those levels. Each depth a client asks for in `Subscribe` is its own
+//! topic, "BTC-USD@20" (`my_shared::depth_topic`), served by its own engine:
+//! `for_topic()` reads the depth from the topic. Clients subscribed at the
+//! same depth share one frame, and every client's frames hold exactly the
+//! levels it asked for.
+//!
+//! A delta frame holds every level whose size changed within the window,
+//! plus the levels that slid in (a better level was removed) or out (size
+//! 0), so a client applying deltas holds exactly the top `depth` levels.
+//!
+//! ## Exact prices
+//!
+//! `with_instrument()` snaps every price to the symbol's tick size
+//! (`my_shared::decimal::Instrument`), so float noise from the feed can't
+//! split one tick into two levels. Clients build their book with the same
+//! instrument.
+//!
+//! ## Exchange sequencing
+//!
+//! Updates are checked against the exchange's own sequence numbers. On a
+//! gap, a crossed book or a malformed level the engine logs the error and
+//! freezes its frames until the exchange sends a new snapshot: ticks repeat
+//! the last good levels (empty deltas) and `snapshot()` returns them, so
+//! clients never see a crossed or partial book. Updates received meanwhile
+//! are replayed on top of the new snapshot. Feeds whose book channel starts
+//! with a snapshot recover when the upstream reconnects; for feeds with
+//! REST snapshots, fetch one when `needs_snapshot()` and push it into the
+//! `IngestQueue` as a snapshot message. The engine's frame `sequence` is
+//! its own and keeps increasing by 1 across exchange resyncs.
+//!
+//! ## Input
+//!
+//! `ingest()` expects JSON in the shape `route_by_symbol()` in main.rs
+//! already routes, levels as `[price, size]` (size 0 removes the level):
+//!
+//! ```text
+//! {"symbol": "BTC-USD", "type": "snapshot", "sequence": 41, "bids": [[64000.5, 1.2]], "asks": [[64001.0, 0.8]]}
+//! {"symbol": "BTC-USD", "type": "update", "sequence": 42, "bids": [[64000.5, 0]], "asks": [], "timestamp_ms": 1700000000123}
+//! ```
+//!
+//! `first_sequence` may be given for updates that span a range of exchange
+//! sequence numbers. Adapt `parse_book_message()` to your exchange, or call
+//! `apply()` directly.
+
+use flatbuffers::FlatBufferBuilder;
+use my_shared::decimal::Instrument;
+use my_shared::orderbook::{level_changes, BookStats, BookUpdate, Level, OrderBook, Side};
+use my_shared::{parse_topic, MAX_BOOK_DEPTH};
+use tracing::warn;
+
+use crate::engine_trait::ServerEngine;
+// Generated from schema/orderbook.fbs. Replace this path with your actual
+// generated module.
+use crate::generated::orderbook_generated::org_asm::orderbook::{
+    OrderbookFrame, OrderbookFrameArgs, PriceLevel,
+};
+
+/// A parsed exchange book message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookMessage {
+    /// The full book (replace) rather than changed levels.
+    pub snapshot: bool,
+    pub update: BookUpdate,
+    /// Exchange time of the message, if it carries one.
+    pub timestamp_ms: Option<u64>,
+}
+
+/// L2 book for one symbol.
+pub struct OrderbookEngine {
+    symbol: String,
+    book: OrderBook,
+    /// Levels per side in each frame.
+    depth: usize,
+    /// Levels in the last emitted frame, best first — what clients hold.
+    sent_bids: Vec<Level>,
+    sent_asks: Vec<Level>,
+    sent_stats: BookStats,
+    timestamp_ms: u64,
+    sequence: u64,
+}
+
+impl OrderbookEngine {
+    pub fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            book: OrderBook::new(),
+            depth: MAX_BOOK_DEPTH as usize,
+            sent_bids: Vec::new(),
+            sent_asks: Vec::new(),
+            sent_stats: BookStats::default(),
+            timestamp_ms: 0,
+            sequence: 0,
+        }
+    }
+
+    /// Engine for a depth topic ("BTC-USD@20", `my_shared::depth_topic`),
+    /// publishing that many levels per side, or `MAX_BOOK_DEPTH` for a
+    /// topic without a depth. The factory to give `EngineRegistry`.
+    pub fn for_topic(topic: &str) -> Self {
+        let (symbol, depth) = parse_topic(topic);
+        let depth = depth.unwrap_or(MAX_BOOK_DEPTH).min(MAX_BOOK_DEPTH);
+        Self::new(symbol).with_depth(depth as usize)
+    }
+
+    /// Publish the best `depth` levels per side instead of
+    /// `MAX_BOOK_DEPTH`. Clients can't get more levels than this.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    /// Snap exchange prices to `instrument`'s tick grid, so a level sent
+    /// as 0.30000000000000004 and one sent as 0.3 are the same level here
+    /// and in every client book built from the frames.
+    pub fn with_instrument(mut self, instrument: Instrument) -> Self {
+        self.book = self.book.with_instrument(instrument);
+        self
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Waiting for an exchange snapshot: none yet, or the last one was
+    /// followed by a gap, a crossed book or a malformed level.
+    pub fn needs_snapshot(&self) -> bool {
+        self.book.needs_snapshot()
+    }
+
+    /// Apply one exchange message. Returns true if the book changed.
+    pub fn apply(&mut self, msg: BookMessage) -> bool {
+        let BookMessage {
+            snapshot,
+            update,
+            timestamp_ms,
+        } = msg;
+        let result = if snapshot {
+            self.book
+                .apply_snapshot(update.sequence, &update.bids, &update.asks)
+                .map(|()| true)
+        } else {
+            self.book.apply_update(update)
+        };
+        match result {
+            Ok(changed) => {
+                if let Some(timestamp_ms) = timestamp_ms.filter(|_| changed) {
+                    self.timestamp_ms = timestamp_ms;
+                }
+                changed
+            }
+            Err(e) => {
+                warn!("{}: {e}, waiting for a snapshot", self.symbol);
+                false
+            }
+        }
+    }
+
+    /// Best `depth` levels per side, best first, and their stats. While
+    /// the book waits for a snapshot it may be crossed or missing updates,
+    /// so this is the last frame's window instead.
+    fn top(&self) -> (Vec<Level>, Vec<Level>, BookStats) {
+        if self.needs_snapshot() {
+            return (
+                self.sent_bids.clone(),
+                self.sent_asks.clone(),
+                self.sent_stats,
+            );
+        }
+        (
+            self.book.bids().take(self.depth).collect(),
+            self.book.asks().take(self.depth).collect(),
+            self.book.stats(self.depth),
+        )
+    }
+
+    /// Serialize a frame holding `bids` and `asks` (the whole window, or
+    /// the changes within it).
+    fn build_frame(
+        &self,
+        builder: &mut FlatBufferBuilder<'static>,
+        bids: &[Level],
+        asks: &[Level],
+        stats: &BookStats,
+        delta: bool,
+    ) {
+        let to_fb = |levels: &[Level]| {
+            levels
+                .iter()
+                .map(|l| PriceLevel::new(l.price, l.size))
+                .collect::<Vec<_>>()
+        };
+        let bids = builder.create_vector(&to_fb(bids));
+        let asks = builder.create_vector(&to_fb(asks));
+        let frame = OrderbookFrame::create(
+            builder,
+            &OrderbookFrameArgs {
+                best_bid: stats.best_bid,
+                best_ask: stats.best_ask,
+                mid_price: stats.mid_price,
+                spread: stats.spread,
+                bids: Some(bids),
+                asks: Some(asks),
+                timestamp_ms: self.timestamp_ms,
+                sequence: self.sequence,
+                bid_total_size: stats.bid_total_size,
+                ask_total_size: stats.ask_total_size,
+                imbalance: stats.imbalance,
+                is_delta: delta,
+            },
+        );
+        builder.finish(frame, None);
+    }
+}
+
+impl ServerEngine for OrderbookEngine {
+    fn ingest(&mut self, msg: &[u8]) -> bool {
+        match parse_book_message(msg) {
+            Some(msg) => self.apply(msg),
+            None => false,
+        }
+    }
+
+    fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
+        builder.reset();
+        self.sequence += 1;
+        let (bids, asks, stats) = self.top();
+        self.build_frame(builder, &bids, &asks, &stats, false);
+        self.sent_bids = bids;
+        self.sent_asks = asks;
+        self.sent_stats = stats;
+        builder.finished_data()
+    }
+
+    fn tick_delta<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> Option<&'a [u8]> {
+        builder.reset();
+        self.sequence += 1;
+        let (bids, asks, stats) = self.top();
+        let (mut bid_changes, mut ask_changes) = (Vec::new(), Vec::new());
+        level_changes(&self.sent_bids, &bids, Side::Bid, &mut bid_changes);
+        level_changes(&self.sent_asks, &asks, Side::Ask, &mut ask_changes);
+        self.build_frame(builder, &bid_changes, &ask_changes, &stats, true);
+        self.sent_bids = bids;
+        self.sent_asks = asks;
+        self.sent_stats = stats;
+        Some(builder.finished_data())
+    }
+
+    /// The current window, or while waiting for an exchange snapshot the
+    /// last one sent. None if that leaves nothing good to send: no frame
+    /// emitted yet and no valid book.
+    fn snapshot(&self, builder: &mut FlatBufferBuilder<'static>) -> Option<Vec<u8>> {
+        if self.needs_snapshot() && self.sequence == 0 {
+            return None;
+        }
+        builder.reset();
+        let (bids, asks, stats) = self.top();
+        self.build_frame(builder, &bids, &asks, &stats, false);
+        Some(builder.finished_data().to_vec())
+    }
+
+    fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    fn schema_version(&self) -> u32 {
+        my_shared::schema::ORDERBOOK_SCHEMA_HASH
+    }
+}
+
+/// Parse one exchange book message. Returns None for anything that isn't a
+/// book snapshot or update (heartbeats, other channels). Levels aren't
+/// validated here; the book rejects bad ones.
+pub fn parse_book_message(msg: &[u8]) -> Option<BookMessage> {
+    #[derive(serde::Deserialize)]
+    struct BookMsg<'a> {
+        #[serde(rename = "type")]
+        kind: &'a str,
+        sequence: u64,
+        #[serde(default)]
+        first_sequence: Option<u64>,
+        #[serde(default)]
+        bids: Vec<(f64, f64)>,
+        #[serde(default)]
+        asks: Vec<(f64, f64)>,
+        #[serde(default)]
+        timestamp_ms: Option<u64>,
+    }
+
+    let msg: BookMsg = serde_json::from_slice(msg).ok()?;
+    let snapshot = match msg.kind {
+        "snapshot" => true,
+        "update" => false,
+        _ => return None,
+    };
+    let levels = |levels: Vec<(f64, f64)>| {
+        levels
+            .into_iter()
+            .map(|(price, size)| Level::new(price, size))
+            .collect()
+    };
+    let update = BookUpdate {
+        first_sequence: msg.first_sequence.unwrap_or(msg.sequence),
+        sequence: msg.sequence,
+        bids: levels(msg.bids),
+        asks: levels(msg.asks),
+    };
+    Some(BookMessage {
+        snapshot,
+        update,
+        timestamp_ms: msg.timestamp_ms,
+    })
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(
+        snapshot: bool,
+        sequence: u64,
+        bids: &[(f64, f64)],
+        asks: &[(f64, f64)],
+    ) -> BookMessage {
+        let levels =
+            |levels: &[(f64, f64)]| levels.iter().map(|&(p, s)| Level::new(p, s)).collect();
+        BookMessage {
+            snapshot,
+            update: BookUpdate::new(sequence, levels(bids), levels(asks)),
+            timestamp_ms: Some(sequence * 1000),
+        }
+    }
+
+    /// `(price, size)` pairs, best first.
+    type Levels = Vec<(f64, f64)>;
+
+    fn levels(levels: Option<flatbuffers::Vector<PriceLevel>>) -> Levels {
+        levels
+            .unwrap()
+            .iter()
+            .map(|l| (l.price(), l.size_()))
+            .collect()
+    }
+
+    /// `(bids, asks)` of a frame.
+    fn book(frame: &[u8]) -> (Levels, Levels) {
+        let frame = flatbuffers::root::<OrderbookFrame>(frame).unwrap();
+        (levels(frame.bids()), levels(frame.asks()))
+    }
+
+    #[test]
+    fn test_crossed_book_freezes_frames_until_a_snapshot() {
+        let mut engine = OrderbookEngine::new("BTC-USD");
+        let mut builder = FlatBufferBuilder::new();
+        assert!(engine.snapshot(&mut builder).is_none());
+
+        let good = (
+            vec![(100.0, 1.0), (99.0, 2.0)],
+            vec![(101.0, 1.0), (102.0, 3.0)],
+        );
+        assert!(engine.apply(message(true, 1, &good.0, &good.1)));
+        assert_eq!(book(engine.tick(&mut builder)), good);
+
+        // A bid above the best ask crosses the book
+        assert!(!engine.apply(message(false, 2, &[(101.5, 1.0)], &[])));
+        assert!(engine.needs_snapshot());
+
+        let delta = engine.tick_delta(&mut builder).unwrap().to_vec();
+        assert_eq!(book(&delta), (vec![], vec![]));
+        let frame = flatbuffers::root::<OrderbookFrame>(&delta).unwrap();
+        assert!(frame.is_delta());
+        assert_eq!(frame.sequence(), 2);
+        assert_eq!((frame.best_bid(), frame.best_ask()), (100.0, 101.0));
+        assert_eq!(frame.timestamp_ms(), 1000);
+
+        assert_eq!(book(engine.tick(&mut builder)), good);
+        let snapshot = engine.snapshot(&mut builder).unwrap();
+        assert_eq!(book(&snapshot), good);
+        assert_eq!(
+            flatbuffers::root::<OrderbookFrame>(&snapshot)
+                .unwrap()
+                .sequence(),
+            3
+        );
+
+        // Updates buffered meanwhile don't leak either
+        assert!(!engine.apply(message(false, 6, &[(98.0, 1.0)], &[])));
+        assert_eq!(
+            book(engine.tick_delta(&mut builder).unwrap()),
+            (vec![], vec![])
+        );
+
+        // The new snapshot is diffed against the frozen levels
+        assert!(engine.apply(message(
+            true,
+            5,
+            &[(100.0, 4.0)],
+            &[(101.0, 1.0), (102.0, 3.0)]
+        )));
+        assert!(!engine.needs_snapshot());
+        assert_eq!(
+            book(engine.tick_delta(&mut builder).unwrap()),
+            (vec![(100.0, 4.0), (99.0, 0.0), (98.0, 1.0)], vec![])
+        );
+    }
+
+    #[test]
+    fn test_no_snapshot_before_a_valid_book() {
+        let mut engine = OrderbookEngine::new("BTC-USD");
+        let mut builder = FlatBufferBuilder::new();
+        // A crossed exchange snapshot never becomes the book
+        assert!(!engine.apply(message(true, 1, &[(101.0, 1.0)], &[(100.0, 1.0)])));
+        assert!(engine.snapshot(&mut builder).is_none());
+
+        // Frames emitted meanwhile are empty, and so is the book clients hold
+        assert_eq!(book(engine.tick(&mut builder)), (vec![], vec![]));
+        assert_eq!(
+            book(&engine.snapshot(&mut builder).unwrap()),
+            (vec![], vec![])
+        );
+    }
+}
@@ -0,0 +1,686 @@
+//! # Record and Replay
+//!
+//! Captures exactly what the engines ingested and plays it back, so an
+//! engine can be regression-tested against a real market session without a
+//! live exchange.
+//!
+//! ## Architecture
+//!
+//! ```text
+//! Live:    Upstreams ──→ Recorder::append(msg) ──→ session.rec   (append-only)
+//!              │
+//!              └──→ IngestQueue ──→ engines
+//!
+//! Test:    session.rec ──→ ReplayDriver ──→ engine.ingest() / engine.tick()
+//!                                   │
+//!                                   └──→ Vec<ReplayFrame>  ──→ compare with golden.rec
+//! ```
+//!
+//! ## File format
+//!
+//! An 8-byte header (`MAGIC`), then one record per message:
+//!
+//! ```text
+//! timestamp_us: u64 LE   (microseconds since the Unix epoch)
+//! len:          u32 LE
+//! bytes:        [u8; len]
+//! ```
+//!
+//! Records are only ever appended. A record cut short by a crash is ignored
+//! when reading, so a recording is always readable up to its last complete
+//! message. Golden frame files use the same format, with the virtual tick
+//! time as the timestamp.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! // Recording (main.rs):
+//! let upstreams = Upstreams::new()
+//!     .with(WsSource::new(EXCHANGE_WS_URL))
+//!     .record_to(Recorder::open("session.rec")?);
+//!
+//! // Golden test (the body of a #[test] fn in tests/orderbook.rs):
+//! let engine = OrderbookEngine::new("BTC-USD");
+//! let mut replay = ReplayDriver::open(engine, "tests/data/session.rec", Duration::from_millis(20)).unwrap();
+//! let frames = replay.run_to_end().unwrap();
+//! // First run: write_frames("tests/data/session.golden.rec", &frames)
+//! assert!(frames == read_frames("tests/data/session.golden.rec").unwrap());
+//! ```
+//!
+//! ## Determinism
+//!
+//! The replay driver never looks at the wall clock. Ticks happen on the
+//! recording's own timeline — every `tick_interval` of recorded time, for
+//! engines whose `ingest()` reported a change — so the same recording always
+//! produces the same frames, whether it is replayed at original speed,
+//! accelerated, or one step at a time.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use flatbuffers::FlatBufferBuilder;
+use tracing::warn;
+
+use crate::engine_trait::ServerEngine;
+use crate::upstream::UpstreamSource;
+
+/// File header: format name plus a version byte.
+pub const MAGIC: &[u8; 8] = b"OAREC\0\0\x01";
+
+/// One recorded message (or captured frame).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp_us: u64,
+    pub bytes: Vec<u8>,
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+// ============================================
+// Writing
+// ============================================
+
+/// Appends timestamped messages to a recording file.
+///
+/// Writes are buffered; call `flush()` to force them to disk. Anything not
+/// flushed when the process dies is lost, but everything before it stays
+/// readable.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Open `path` for appending, writing the header if the file is new.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+        } else {
+            // Refuse to append to something that isn't a recording
+            RecordingReader::open(path)?;
+        }
+        Ok(Self {
+            writer: BufWriter::with_capacity(64 * 1024, file),
+        })
+    }
+
+    /// Record `msg` with the current time.
+    pub fn append(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.append_at(now_us(), msg)
+    }
+
+    /// Record `msg` with an explicit timestamp.
+    pub fn append_at(&mut self, timestamp_us: u64, msg: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(msg.len())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "message over 4 GiB"))?;
+        self.writer.write_all(&timestamp_us.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(msg)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// ============================================
+// Reading
+// ============================================
+
+/// Reads records back in the order they were written.
+pub struct RecordingReader {
+    reader: BufReader<File>,
+}
+
+impl RecordingReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a recording (bad header)",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    /// The next complete record, or None at the end of the file (including
+    /// a truncated final record).
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8; 12];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let timestamp_us = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+        let mut bytes = vec![0u8; len];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => Ok(Some(Record {
+                timestamp_us,
+                bytes,
+            })),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                warn!("Recording ends with a truncated record, ignoring it");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+// ============================================
+// Replay
+// ============================================
+
+/// How fast recorded messages are fed back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Wait out the recorded gap between messages.
+    Original,
+    /// Recorded gaps divided by this factor (10.0 = ten times faster). A
+    /// factor that isn't a positive number replays like `Stepwise`.
+    Accelerated(f64),
+    /// No waiting: messages are fed as fast as they are consumed. Use
+    /// `ReplayDriver::step()` to advance one message at a time.
+    Stepwise,
+}
+
+impl ReplaySpeed {
+    /// Real time to wait for a recorded gap of `gap_us`.
+    fn delay(self, gap_us: u64) -> Option<Duration> {
+        match self {
+            ReplaySpeed::Original => Some(Duration::from_micros(gap_us)),
+            // NaN fails the comparison too. Saturate rather than overflow
+            // for tiny factors.
+            ReplaySpeed::Accelerated(factor) if factor > 0.0 => Some(
+                Duration::try_from_secs_f64(gap_us as f64 / 1e6 / factor).unwrap_or(Duration::MAX),
+            ),
+            ReplaySpeed::Accelerated(_) | ReplaySpeed::Stepwise => None,
+        }
+    }
+}
+
+/// A frame emitted by `tick()` during replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayFrame {
+    /// Recorded time of the tick that produced this frame.
+    pub timestamp_us: u64,
+    pub sequence: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Feeds a recording into one engine and captures the frames it emits.
+///
+/// Ticks follow the recording's timeline: before each message is ingested,
+/// every tick boundary up to its timestamp is processed, and a tick emits a
+/// frame only if `ingest()` reported a change since the previous one (as
+/// `EngineRegistry::tick_all` does without a heartbeat).
+pub struct ReplayDriver<E: ServerEngine> {
+    engine: E,
+    reader: RecordingReader,
+    tick_interval_us: u64,
+    next_tick_us: Option<u64>,
+    last_message_us: Option<u64>,
+    dirty: bool,
+    delta_frames: bool,
+    builder: FlatBufferBuilder<'static>,
+}
+
+impl<E: ServerEngine> ReplayDriver<E> {
+    pub fn open(engine: E, path: impl AsRef<Path>, tick_interval: Duration) -> io::Result<Self> {
+        Ok(Self {
+            engine,
+            reader: RecordingReader::open(path)?,
+            tick_interval_us: (tick_interval.as_micros() as u64).max(1),
+            next_tick_us: None,
+            last_message_us: None,
+            dirty: false,
+            delta_frames: false,
+            builder: FlatBufferBuilder::with_capacity(4096),
+        })
+    }
+
+    /// Capture `tick_delta()` frames instead of `tick()` frames (for engines
+    /// that implement it).
+    pub fn with_delta_frames(mut self, enabled: bool) -> Self {
+        self.delta_frames = enabled;
+        self
+    }
+
+    /// Ingest the next message, emitting any ticks due before it. Returns
+    /// None once the recording is exhausted — after a final tick for changes
+    /// made by the last message.
+    pub fn step(&mut self) -> io::Result<Option<Vec<ReplayFrame>>> {
+        let mut frames = Vec::new();
+        let Some(record) = self.reader.next_record()? else {
+            if let Some(at) = self.next_tick_us.take() {
+                // Flush state changed by the last messages
+                self.tick(at, &mut frames);
+                return Ok(Some(frames));
+            }
+            return Ok(None);
+        };
+
+        let mut next_tick = self
+            .next_tick_us
+            .unwrap_or(record.timestamp_us + self.tick_interval_us);
+        while next_tick <= record.timestamp_us {
+            self.tick(next_tick, &mut frames);
+            next_tick += self.tick_interval_us;
+        }
+        self.next_tick_us = Some(next_tick);
+
+        self.dirty |= self.engine.ingest(&record.bytes);
+        self.last_message_us = Some(record.timestamp_us);
+        Ok(Some(frames))
+    }
+
+    /// Replay the whole recording without waiting and return every frame.
+    pub fn run_to_end(&mut self) -> io::Result<Vec<ReplayFrame>> {
+        let mut frames = Vec::new();
+        while let Some(mut emitted) = self.step()? {
+            frames.append(&mut emitted);
+        }
+        Ok(frames)
+    }
+
+    /// Replay the whole recording, pacing messages at `speed`. The frames are
+    /// identical to `run_to_end()`; only the wall-clock duration differs.
+    pub async fn run(&mut self, speed: ReplaySpeed) -> io::Result<Vec<ReplayFrame>> {
+        let mut frames = Vec::new();
+        let mut previous_us = None;
+        while let Some(mut emitted) = self.step()? {
+            frames.append(&mut emitted);
+            // Sleep the gap between the message just ingested and the one before
+            if let (Some(prev), Some(current)) = (previous_us, self.last_message_us) {
+                if let Some(delay) = speed.delay(current.saturating_sub(prev)) {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            previous_us = self.last_message_us;
+        }
+        Ok(frames)
+    }
+
+    fn tick(&mut self, timestamp_us: u64, frames: &mut Vec<ReplayFrame>) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let delta = match self.delta_frames {
+            true => self
+                .engine
+                .tick_delta(&mut self.builder)
+                .map(<[u8]>::to_vec),
+            false => None,
+        };
+        let bytes = delta.unwrap_or_else(|| self.engine.tick(&mut self.builder).to_vec());
+        frames.push(ReplayFrame {
+            timestamp_us,
+            sequence: self.engine.sequence(),
+            bytes,
+        });
+    }
+
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+}
+
+/// Write replayed frames as a golden file (same format as a recording).
+pub fn write_frames(path: impl AsRef<Path>, frames: &[ReplayFrame]) -> io::Result<()> {
+    let _ = std::fs::remove_file(path.as_ref());
+    let mut recorder = Recorder::open(path)?;
+    for frame in frames {
+        recorder.append_at(frame.timestamp_us, &frame.bytes)?;
+    }
+    recorder.flush()
+}
+
+/// Read a golden file written by `write_frames()`.
+///
+/// Sequences are not stored; compare them separately if your engine's
+/// sequence isn't already part of the frame bytes.
+pub fn read_frames(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    RecordingReader::open(path)?.collect()
+}
+
+impl PartialEq<Record> for ReplayFrame {
+    fn eq(&self, other: &Record) -> bool {
+        self.timestamp_us == other.timestamp_us && self.bytes == other.bytes
+    }
+}
+
+// ============================================
+// Replay as an upstream
+// ============================================
+
+/// Plays a recording into a running server, for demos and load tests
+/// against a real session. Finishes at the end of the recording.
+pub struct ReplaySource {
+    name: String,
+    reader: RecordingReader,
+    speed: ReplaySpeed,
+    previous_us: Option<u64>,
+}
+
+impl ReplaySource {
+    pub fn open(path: impl AsRef<Path>, speed: ReplaySpeed) -> io::Result<Self> {
+        Ok(Self {
+            name: path.as_ref().display().to_string(),
+            reader: RecordingReader::open(path)?,
+            speed,
+            previous_us: None,
+        })
+    }
+}
+
+impl UpstreamSource for ReplaySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn next(&mut self) -> Option<Vec<u8>> {
+        let record = match self.reader.next_record() {
+            Ok(record) => record?,
+            Err(e) => {
+                warn!("{}: {e}", self.name);
+                return None;
+            }
+        };
+        if let Some(previous) = self.previous_us.replace(record.timestamp_us) {
+            if let Some(delay) = self
+                .speed
+                .delay(record.timestamp_us.saturating_sub(previous))
+            {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        Some(record.bytes)
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A file in the temp dir, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("org-asm-{}-{name}.rec", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn record(timestamp_us: u64, bytes: &[u8]) -> Record {
+        Record {
+            timestamp_us,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    fn write(file: &TempFile, records: &[Record]) {
+        let mut recorder = Recorder::open(&file.0).unwrap();
+        for r in records {
+            recorder.append_at(r.timestamp_us, &r.bytes).unwrap();
+        }
+        recorder.flush().unwrap();
+    }
+
+    /// Adds each message's first byte to a total; a 0 byte changes nothing.
+    /// Frames are the total as a one-byte vector.
+    struct Summer {
+        total: u8,
+        sequence: u64,
+    }
+
+    impl ServerEngine for Summer {
+        fn ingest(&mut self, msg: &[u8]) -> bool {
+            self.total += msg[0];
+            msg[0] != 0
+        }
+
+        fn tick<'a>(&mut self, builder: &'a mut FlatBufferBuilder<'static>) -> &'a [u8] {
+            builder.reset();
+            let total = builder.create_vector(&[self.total]);
+            builder.finish_minimal(total);
+            self.sequence += 1;
+            builder.finished_data()
+        }
+
+        fn sequence(&self) -> u64 {
+            self.sequence
+        }
+    }
+
+    fn total(frame: &ReplayFrame) -> u8 {
+        flatbuffers::root::<flatbuffers::Vector<u8>>(&frame.bytes)
+            .unwrap()
+            .get(0)
+    }
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    /// Ticks are due at 11ms (first message + TICK), 21ms, 31ms.
+    fn session(name: &str) -> TempFile {
+        let file = TempFile::new(name);
+        write(
+            &file,
+            &[
+                record(1_000, &[1]),
+                record(5_000, &[2]),
+                record(25_000, &[0]),
+                record(26_000, &[4]),
+            ],
+        );
+        file
+    }
+
+    #[test]
+    fn test_recorder_round_trip() {
+        let file = TempFile::new("round-trip");
+        let records = [
+            record(1, b"first"),
+            record(2, b""),
+            record(u64::MAX, &[0xff; 300]),
+        ];
+        write(&file, &records[..1]);
+
+        // Reopening appends after what's there
+        write(&file, &records[1..]);
+        let read: Vec<Record> = RecordingReader::open(&file.0)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_truncated_final_record_is_ignored() {
+        let file = TempFile::new("truncated");
+        write(&file, &[record(1, b"complete")]);
+        let mut raw = OpenOptions::new().append(true).open(&file.0).unwrap();
+
+        // Header promising 10 bytes, then only 3 of them
+        raw.write_all(&2u64.to_le_bytes()).unwrap();
+        raw.write_all(&10u32.to_le_bytes()).unwrap();
+        raw.write_all(b"abc").unwrap();
+        assert_eq!(read_frames(&file.0).unwrap(), [record(1, b"complete")]);
+
+        // A header cut short too
+        let file = TempFile::new("truncated-header");
+        write(&file, &[record(1, b"complete")]);
+        let mut raw = OpenOptions::new().append(true).open(&file.0).unwrap();
+        raw.write_all(&[0; 5]).unwrap();
+        assert_eq!(read_frames(&file.0).unwrap(), [record(1, b"complete")]);
+    }
+
+    #[test]
+    fn test_bad_magic_is_rejected() {
+        let file = TempFile::new("bad-magic");
+        std::fs::write(&file.0, b"NOTAREC\x01 and then some").unwrap();
+        let err = RecordingReader::open(&file.0).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // The recorder won't append to it either, and leaves it untouched
+        assert_eq!(
+            Recorder::open(&file.0).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            std::fs::read(&file.0).unwrap(),
+            b"NOTAREC\x01 and then some"
+        );
+    }
+
+    #[test]
+    fn test_replay_ticks_on_the_recorded_timeline() {
+        let file = session("timeline");
+        let engine = Summer {
+            total: 0,
+            sequence: 0,
+        };
+        let mut replay = ReplayDriver::open(engine, &file.0, TICK).unwrap();
+
+        // No tick is due before the first two messages
+        assert_eq!(replay.step().unwrap(), Some(vec![]));
+        assert_eq!(replay.step().unwrap(), Some(vec![]));
+
+        // 25ms passes the 11ms and 21ms boundaries; only 11ms has changes
+        let frames = replay.step().unwrap().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            (
+                frames[0].timestamp_us,
+                frames[0].sequence,
+                total(&frames[0])
+            ),
+            (11_000, 1, 3)
+        );
+
+        // The last message's change is flushed at the next boundary
+        assert_eq!(replay.step().unwrap(), Some(vec![]));
+        let frames = replay.step().unwrap().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            (
+                frames[0].timestamp_us,
+                frames[0].sequence,
+                total(&frames[0])
+            ),
+            (31_000, 2, 7)
+        );
+        assert_eq!(replay.step().unwrap(), None);
+        assert_eq!(replay.step().unwrap(), None);
+    }
+
+    #[test]
+    fn test_final_flush_without_changes_emits_nothing() {
+        let file = TempFile::new("no-changes");
+        write(&file, &[record(1_000, &[0]), record(50_000, &[0])]);
+        let mut replay = ReplayDriver::open(
+            Summer {
+                total: 0,
+                sequence: 0,
+            },
+            &file.0,
+            TICK,
+        )
+        .unwrap();
+        assert!(replay.run_to_end().unwrap().is_empty());
+        assert_eq!(replay.engine().sequence(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_paced_replay_matches_golden_frames() {
+        let file = session("paced");
+        let golden = TempFile::new("paced-golden");
+        let mut replay = ReplayDriver::open(
+            Summer {
+                total: 0,
+                sequence: 0,
+            },
+            &file.0,
+            TICK,
+        )
+        .unwrap();
+        write_frames(&golden.0, &replay.run_to_end().unwrap()).unwrap();
+
+        // Pacing changes how long it takes, not the frames
+        let mut replay = ReplayDriver::open(
+            Summer {
+                total: 0,
+                sequence: 0,
+            },
+            &file.0,
+            TICK,
+        )
+        .unwrap();
+        let frames = replay.run(ReplaySpeed::Accelerated(100.0)).await.unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames == read_frames(&golden.0).unwrap());
+    }
+
+    #[test]
+    fn test_replay_speed_delay() {
+        assert_eq!(
+            ReplaySpeed::Original.delay(1_500),
+            Some(Duration::from_micros(1_500))
+        );
+        assert_eq!(
+            ReplaySpeed::Accelerated(10.0).delay(1_500),
+            Some(Duration::from_micros(150))
+        );
+        assert_eq!(
+            ReplaySpeed::Accelerated(1e-300).delay(1_500),
+            Some(Duration::MAX)
+        );
+        assert_eq!(ReplaySpeed::Stepwise.delay(1_500), None);
+
+        // Factors that would panic in Duration::div_f64 replay stepwise
+        for factor in [0.0, -2.0, f64::NAN] {
+            assert_eq!(
+                ReplaySpeed::Accelerated(factor).delay(1_500),
+                None,
+                "{factor}"
+            );
+            assert_eq!(ReplaySpeed::Accelerated(factor).delay(0), None, "{factor}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! # Graceful Shutdown
+//!
+//! Coordinates a clean stop on SIGTERM (or Ctrl-C) so a rolling deploy
+//! never cuts a client off mid-frame.
+//!
+//! ## Sequence
+//!
+//! ```text
+//! SIGTERM
+//!   1. axum stops accepting; ws_handler answers late upgrades with 503
+//!   2. stop_upstreams()    → sources close their connections, ingest queue closes
+//!   3. engine thread        → drains the queue, final tick, exits
+//!   4. close_clients()     → each client queues its remaining frames, then
+//!                             close 1012 "reconnect-after=<ms>", writer flushes
+//! ```
+//!
+//! Clients see every frame produced from the data received before the
+//! signal, then a Service Restart close whose reason tells them when to
+//! reconnect. `WebSocketPipeline` waits that long instead of its usual
+//! backoff. The delay is spread per client over
+//! `[reconnect_after, 2 × reconnect_after)` so the next instance isn't hit
+//! by every client at once.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! let shutdown = Shutdown::new(Duration::from_secs(2), Duration::from_secs(5));
+//! let ingest_handle = tokio::spawn(upstreams.run_until(runner.ingest, shutdown.upstreams_stopped()));
+//!
+//! axum::serve(listener, app)
+//!     .with_graceful_shutdown(shutdown::signal())
+//!     .await?;
+//!
+//! shutdown.stop_upstreams();
+//! ingest_handle.await?;
+//! tokio::task::spawn_blocking(move || runner.thread.join()).await?;
+//! shutdown.close_clients().await;
+//! ```
+
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tokio_util::task::task_tracker::TaskTrackerToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+/// Shutdown state shared by `main`, `Upstreams` and every client task.
+/// Cheap to clone.
+#[derive(Clone)]
+pub struct Shutdown {
+    upstreams: CancellationToken,
+    clients: CancellationToken,
+    tracker: TaskTracker,
+    reconnect_after: Duration,
+    drain_timeout: Duration,
+}
+
+impl Shutdown {
+    /// `reconnect_after`: minimum delay suggested to clients before they
+    /// reconnect. `drain_timeout`: how long clients get to receive their
+    /// remaining frames before they are dropped.
+    pub fn new(reconnect_after: Duration, drain_timeout: Duration) -> Self {
+        Self {
+            upstreams: CancellationToken::new(),
+            clients: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+            reconnect_after,
+            drain_timeout,
+        }
+    }
+
+    /// Resolves once `stop_upstreams()` is called. Pass to
+    /// `Upstreams::run_until`.
+    pub fn upstreams_stopped(&self) -> WaitForCancellationFutureOwned {
+        self.upstreams.clone().cancelled_owned()
+    }
+
+    /// Ask every upstream source to close.
+    pub fn stop_upstreams(&self) {
+        info!("Stopping upstream sources");
+        self.upstreams.cancel();
+    }
+
+    /// Register a client connection. Hold the token for the connection's
+    /// lifetime; `close_clients()` waits for all of them. None once clients
+    /// are being closed.
+    pub fn client_guard(&self) -> Option<TaskTrackerToken> {
+        (!self.clients.is_cancelled()).then(|| self.tracker.token())
+    }
+
+    /// Resolves when clients should send their final frames and close.
+    pub fn clients_closing(&self) -> WaitForCancellationFutureOwned {
+        self.clients.clone().cancelled_owned()
+    }
+
+    /// Bound on a closing client's final flush.
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Close frame for a client disconnected by shutdown: 1012 (Service
+    /// Restart) with a jittered `reconnect-after=<ms>` reason.
+    pub fn close_frame(&self) -> CloseFrame {
+        let spread = self.reconnect_after.mul_f64(rand::random::<f64>());
+        let delay = self.reconnect_after + spread;
+        CloseFrame {
+            code: close_code::RESTART,
+            reason: format!("reconnect-after={}", delay.as_millis()).into(),
+        }
+    }
+
+    /// Tell every client to flush and close, then wait for them (at most
+    /// `drain_timeout` plus a second for the close handshakes).
+    pub async fn close_clients(&self) {
+        self.clients.cancel();
+        self.tracker.close();
+        info!("Closing {} clients", self.tracker.len());
+        let wait = self.drain_timeout + Duration::from_secs(1);
+        if tokio::time::timeout(wait, self.tracker.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} clients still open after {wait:?}, exiting anyway",
+                self.tracker.len()
+            );
+        }
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C. Pass to `axum::serve(..).with_graceful_shutdown`.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Ctrl-C received, shutting down"),
+        _ = terminate => info!("SIGTERM received, shutting down"),
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    fn shutdown() -> Shutdown {
+        Shutdown::new(Duration::from_secs(2), Duration::from_secs(5))
+    }
+
+    #[test]
+    fn test_close_frame_is_restart_with_jittered_delay() {
+        let shutdown = shutdown();
+        for _ in 0..200 {
+            let frame = shutdown.close_frame();
+            assert_eq!(frame.code, 1012);
+            let ms: u64 = frame
+                .reason
+                .strip_prefix("reconnect-after=")
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((2000..4000).contains(&ms), "reconnect-after={ms}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_close_clients_signals_clients_before_waiting() {
+        let shutdown = shutdown();
+        let guard = shutdown.client_guard().unwrap();
+        let closing = shutdown.clients_closing();
+        let client = tokio::spawn(async move {
+            closing.await;
+            drop(guard);
+        });
+
+        let started = Instant::now();
+        shutdown.close_clients().await;
+        // Returned as soon as the client left, not after the drain timeout
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert!(client.is_finished());
+        assert!(shutdown.client_guard().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_close_clients_gives_up_after_drain_timeout() {
+        let shutdown = shutdown();
+        let _stuck = shutdown.client_guard().unwrap();
+
+        let started = Instant::now();
+        shutdown.close_clients().await;
+        assert_eq!(started.elapsed(), Duration::from_secs(6));
+    }
+
+    #[tokio::test]
+    async fn test_stop_upstreams_resolves_waiters() {
+        let shutdown = shutdown();
+        let stopped = shutdown.upstreams_stopped();
+        shutdown.stop_upstreams();
+        stopped.await;
+        // Clients are closed separately
+        assert!(shutdown.client_guard().is_some());
+    }
+}
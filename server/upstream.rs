@@ -0,0 +1,623 @@
+//! # Upstream Sources
+//!
+//! Where raw messages come from before they reach `ServerEngine::ingest()`.
+//! Each `UpstreamSource` owns its connection and reconnect logic and yields
+//! one raw message at a time; `Upstreams` merges any number of them into the
+//! engine's single `IngestQueue`.
+//!
+//! ## Architecture
+//!
+//! ```text
+//! WsSource("wss://exchange-a")  ──┐
+//! WsSource("wss://exchange-b")  ──┼──→ Upstreams::run() ──→ IngestQueue ──→ engine thread
+//! TcpLineSource("10.0.0.5:7000")──┘     (one task, merged)
+//! ```
+//!
+//! Messages from one source keep their order. Messages from different
+//! sources interleave in arrival order — route them to different topics
+//! (see `TopicRouter` in engine_registry.rs) if their order matters.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! let upstreams = Upstreams::new()
+//!     .with(WsSource::new("wss://stream.exchange-a.com/ws")
+//!         .with_subscription(r#"{"op":"subscribe","channel":"book"}"#))
+//!     .with(WsSource::new("wss://ws.exchange-b.com"))
+//!     .with(TcpLineSource::new("10.0.0.5:7000"));
+//!
+//! tokio::spawn(upstreams.run(runner.ingest));
+//! ```
+//!
+//! ## Stopping
+//!
+//! `run_until(ingest, stop)` stops reading when `stop` resolves: each
+//! source's `close()` is called between messages (a WebSocket sends its
+//! close frame), the recording is flushed, and the `IngestQueue` is
+//! dropped, which lets the engine thread drain and exit.
+//!
+//! ## Implementing a source
+//!
+//! `next()` returns the next raw message, reconnecting internally as
+//! needed. Return None only when the source is exhausted for good (end of a
+//! file); network sources retry forever. Override `close()` if the
+//! connection should be shut down politely.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures_util::stream::{self, BoxStream, SelectAll};
+use futures_util::{SinkExt, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tracing::{error, info, warn};
+
+use crate::engine_runner::IngestQueue;
+use crate::recording::Recorder;
+
+/// A stream of raw upstream messages.
+pub trait UpstreamSource: Send + 'static {
+    /// Short name for logs (usually the URL or address).
+    fn name(&self) -> &str;
+
+    /// Wait for the next raw message. None means the source is finished.
+    fn next(&mut self) -> impl Future<Output = Option<Vec<u8>>> + Send;
+
+    /// Shut the connection down on shutdown. `next()` won't be called again.
+    fn close(&mut self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+// ============================================
+// Merging
+// ============================================
+
+/// A set of upstream sources feeding one engine.
+pub struct Upstreams {
+    sources: SelectAll<BoxStream<'static, Vec<u8>>>,
+    recorder: Option<Recorder>,
+    /// Cancelled by `run_until` to make every source close.
+    stop: CancellationToken,
+}
+
+impl Upstreams {
+    pub fn new() -> Self {
+        Self { sources: SelectAll::new(), recorder: None, stop: CancellationToken::new() }
+    }
+
+    /// Append every message to a recording (recording.rs), in the order the
+    /// engine receives it, for replay in tests.
+    pub fn record_to(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn with(mut self, source: impl UpstreamSource) -> Self {
+        info!("Upstream added: {}", source.name());
+        let stop = self.stop.clone();
+        let messages = stream::unfold(source, move |mut source| {
+            let stop = stop.clone();
+            async move {
+                let msg = tokio::select! {
+                    biased;
+                    _ = stop.cancelled() => {
+                        source.close().await;
+                        info!("Upstream stopped: {}", source.name());
+                        return None;
+                    }
+                    msg = source.next() => msg,
+                };
+                if msg.is_none() {
+                    info!("Upstream finished: {}", source.name());
+                }
+                msg.map(|msg| (msg, source))
+            }
+        });
+        self.sources.push(messages.boxed());
+        self
+    }
+
+    /// Push every message from every source into `ingest` until all sources
+    /// are finished. With `Backpressure::Block`, a full queue pauses reading
+    /// from all sources.
+    pub async fn run(self, ingest: IngestQueue) {
+        self.run_until(ingest, std::future::pending()).await
+    }
+
+    /// `run()`, but close every source once `stop` resolves. Messages
+    /// already read are still pushed before the queue is dropped.
+    pub async fn run_until(mut self, mut ingest: IngestQueue, stop: impl Future<Output = ()>) {
+        tokio::pin!(stop);
+        let mut stopping = false;
+        loop {
+            let msg = tokio::select! {
+                _ = &mut stop, if !stopping => {
+                    stopping = true;
+                    self.stop.cancel();
+                    continue;
+                }
+                msg = self.sources.next() => msg,
+            };
+            let Some(msg) = msg else { break };
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(e) = recorder.append(&msg) {
+                    error!("Recording failed, stopped recording: {e}");
+                    self.recorder = None;
+                }
+            }
+            ingest.push(msg).await;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            let _ = recorder.flush();
+        }
+        info!("All upstream sources {}", if stopping { "stopped" } else { "finished" });
+    }
+}
+
+impl Default for Upstreams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================
+// Reconnect backoff
+// ============================================
+
+/// Exponential reconnect delay with jitter.
+///
+/// Each attempt after the first doubles the delay up to `max`; the actual
+/// sleep is randomized between half and all of it, so a fleet of servers
+/// restarted together doesn't reconnect to the exchange in lockstep.
+///
+/// A successful connect doesn't reset the delay: an upstream that accepts
+/// and hangs up at once would otherwise be reconnected to in a hot loop.
+/// The delay resets once a connection has carried messages for
+/// `stable_after`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+    stable_after: Duration,
+    /// When the current connection was made, until it counts as stable.
+    connected_at: Option<Instant>,
+    /// An attempt was made before, so the next one waits.
+    attempted: bool,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+            stable_after: Duration::from_secs(10),
+            connected_at: None,
+            attempted: false,
+        }
+    }
+
+    /// How long a connection must carry messages before the delay resets.
+    pub fn with_stable_after(mut self, stable_after: Duration) -> Self {
+        self.stable_after = stable_after;
+        self
+    }
+
+    /// Delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+
+    /// Delay before a connection attempt: none for the first one,
+    /// `next_delay()` for every later one, whether the previous attempt
+    /// failed or its connection dropped.
+    pub fn attempt_delay(&mut self) -> Option<Duration> {
+        if !std::mem::replace(&mut self.attempted, true) {
+            return None;
+        }
+        Some(self.next_delay())
+    }
+
+    /// Call when a connection is established.
+    pub fn connected(&mut self) {
+        self.connected_at = Some(Instant::now());
+    }
+
+    /// Call for each message received. Resets the delay once the
+    /// connection has been up for `stable_after`.
+    pub fn received(&mut self) {
+        if self.connected_at.is_some_and(|at| at.elapsed() >= self.stable_after) {
+            self.connected_at = None;
+            self.reset();
+        }
+    }
+
+    /// Start over from the initial delay.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(250), Duration::from_secs(30))
+    }
+}
+
+// ============================================
+// WebSocket
+// ============================================
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Exchange WebSocket feed. Text and binary messages are both forwarded as
+/// raw bytes.
+///
+/// Subscription messages are sent after every (re)connect, so the exchange
+/// resumes the same channels without the engine noticing the reconnect
+/// beyond a gap in the data.
+pub struct WsSource {
+    url: String,
+    subscriptions: Vec<String>,
+    backoff: Backoff,
+    stream: Option<WsStream>,
+}
+
+impl WsSource {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            subscriptions: Vec::new(),
+            backoff: Backoff::default(),
+            stream: None,
+        }
+    }
+
+    /// Send `msg` (e.g. a channel subscribe request) on every connect.
+    pub fn with_subscription(mut self, msg: impl Into<String>) -> Self {
+        self.subscriptions.push(msg.into());
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Connect and replay subscriptions, retrying with backoff until it works.
+    async fn connect(&mut self) -> WsStream {
+        loop {
+            if let Some(delay) = self.backoff.attempt_delay() {
+                info!("{}: reconnecting in {delay:?}", self.url);
+                tokio::time::sleep(delay).await;
+            }
+            match connect_async(self.url.as_str()).await {
+                Ok((mut ws, _)) => {
+                    let mut replayed = true;
+                    for msg in &self.subscriptions {
+                        if let Err(e) = ws.send(Message::Text(msg.clone())).await {
+                            error!("{}: subscription replay failed: {e}", self.url);
+                            replayed = false;
+                            break;
+                        }
+                    }
+                    if replayed {
+                        info!("{}: connected, {} subscriptions sent", self.url, self.subscriptions.len());
+                        self.backoff.connected();
+                        return ws;
+                    }
+                }
+                Err(e) => error!("{}: connect failed: {e}", self.url),
+            }
+        }
+    }
+}
+
+impl UpstreamSource for WsSource {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn close(&mut self) {
+        if let Some(mut ws) = self.stream.take() {
+            // Don't let an unresponsive exchange hold up shutdown
+            let _ = tokio::time::timeout(Duration::from_secs(1), ws.close(None)).await;
+        }
+    }
+
+    async fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let ws = match &mut self.stream {
+                Some(ws) => ws,
+                None => {
+                    let ws = self.connect().await;
+                    self.stream.insert(ws)
+                }
+            };
+            let msg = match ws.next().await {
+                Some(Ok(Message::Text(text))) => text.into_bytes(),
+                Some(Ok(Message::Binary(bin))) => bin,
+                // Pings are answered by tungstenite while reading
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                Some(Ok(Message::Close(frame))) => {
+                    warn!("{}: closed by upstream: {frame:?}", self.url);
+                    self.stream = None;
+                    continue;
+                }
+                Some(Err(e)) => {
+                    error!("{}: {e}", self.url);
+                    self.stream = None;
+                    continue;
+                }
+                None => {
+                    warn!("{}: connection ended", self.url);
+                    self.stream = None;
+                    continue;
+                }
+            };
+            self.backoff.received();
+            return Some(msg);
+        }
+    }
+}
+
+// ============================================
+// Raw TCP line feed
+// ============================================
+
+/// Newline-delimited feed over plain TCP (internal market data gateways,
+/// FIX-over-text bridges). Each line, without its terminator, is one
+/// message.
+pub struct TcpLineSource {
+    addr: String,
+    backoff: Backoff,
+    reader: Option<BufReader<TcpStream>>,
+    line: Vec<u8>,
+}
+
+impl TcpLineSource {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            backoff: Backoff::default(),
+            reader: None,
+            line: Vec::with_capacity(4096),
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    async fn connect(&mut self) -> BufReader<TcpStream> {
+        loop {
+            if let Some(delay) = self.backoff.attempt_delay() {
+                info!("{}: reconnecting in {delay:?}", self.addr);
+                tokio::time::sleep(delay).await;
+            }
+            match TcpStream::connect(&self.addr).await {
+                Ok(stream) => {
+                    info!("{}: connected", self.addr);
+                    self.backoff.connected();
+                    return BufReader::new(stream);
+                }
+                Err(e) => error!("{}: connect failed: {e}", self.addr),
+            }
+        }
+    }
+}
+
+impl UpstreamSource for TcpLineSource {
+    fn name(&self) -> &str {
+        &self.addr
+    }
+
+    async fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => {
+                    let reader = self.connect().await;
+                    self.reader.insert(reader)
+                }
+            };
+            self.line.clear();
+            match reader.read_until(b'\n', &mut self.line).await {
+                Ok(0) => {
+                    warn!("{}: connection ended", self.addr);
+                    self.reader = None;
+                }
+                Ok(_) => {
+                    if let Some(msg) = trim_line(&self.line) {
+                        self.backoff.received();
+                        return Some(msg.to_vec());
+                    }
+                }
+                Err(e) => {
+                    error!("{}: {e}", self.addr);
+                    self.reader = None;
+                }
+            }
+        }
+    }
+}
+
+/// Strip the line terminator; None for blank lines.
+fn trim_line(line: &[u8]) -> Option<&[u8]> {
+    let end = line.iter().rposition(|&b| b != b'\n' && b != b'\r')? + 1;
+    Some(&line[..end])
+}
+
+// ============================================
+// File
+// ============================================
+
+/// Newline-delimited messages from a file — a captured feed, or fixtures
+/// for a local run. Finishes at end of file.
+///
+/// Messages are emitted as fast as the engine takes them unless an
+/// interval is set.
+pub struct FileSource {
+    path: PathBuf,
+    name: String,
+    interval: Option<Duration>,
+    reader: Option<BufReader<tokio::fs::File>>,
+    line: Vec<u8>,
+    done: bool,
+}
+
+impl FileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            name: path.display().to_string(),
+            path,
+            interval: None,
+            reader: None,
+            line: Vec::with_capacity(4096),
+            done: false,
+        }
+    }
+
+    /// Wait this long between messages.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+}
+
+impl UpstreamSource for FileSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn next(&mut self) -> Option<Vec<u8>> {
+        if self.done {
+            return None;
+        }
+        if self.reader.is_none() {
+            match tokio::fs::File::open(&self.path).await {
+                Ok(file) => self.reader = Some(BufReader::new(file)),
+                Err(e) => {
+                    error!("{}: {e}", self.name);
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+        let reader = self.reader.as_mut()?;
+        loop {
+            self.line.clear();
+            match reader.read_until(b'\n', &mut self.line).await {
+                Ok(0) => break,
+                Ok(_) => {
+                    if let Some(msg) = trim_line(&self.line) {
+                        if let Some(interval) = self.interval {
+                            tokio::time::sleep(interval).await;
+                        }
+                        return Some(msg.to_vec());
+                    }
+                }
+                Err(e) => {
+                    error!("{}: {e}", self.name);
+                    break;
+                }
+            }
+        }
+        self.done = true;
+        self.reader = None;
+        None
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_millis(800);
+
+    /// Assert `delay` is within the jitter range of `nominal`: half to all of it.
+    fn assert_jittered(delay: Duration, nominal: Duration) {
+        assert!(delay >= nominal / 2 && delay <= nominal, "{delay:?} not in {nominal:?}/2..={nominal:?}");
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(INITIAL, MAX);
+        for nominal in [100, 200, 400, 800, 800, 800] {
+            assert_jittered(backoff.next_delay(), Duration::from_millis(nominal));
+        }
+    }
+
+    #[test]
+    fn test_backoff_jitter_spreads_delays() {
+        let delays: std::collections::HashSet<Duration> = (0..20)
+            .map(|_| Backoff::new(Duration::from_secs(1), MAX).next_delay())
+            .collect();
+        assert!(delays.len() > 1);
+        for delay in delays {
+            assert_jittered(delay, Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_backoff_waits_before_every_attempt_but_the_first() {
+        let mut backoff = Backoff::new(INITIAL, MAX);
+        assert_eq!(backoff.attempt_delay(), None);
+        assert_jittered(backoff.attempt_delay().unwrap(), INITIAL);
+        assert_jittered(backoff.attempt_delay().unwrap(), INITIAL * 2);
+
+        backoff.reset();
+        assert_jittered(backoff.attempt_delay().unwrap(), INITIAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backoff_resets_only_on_a_stable_connection() {
+        let mut backoff = Backoff::new(INITIAL, MAX).with_stable_after(Duration::from_secs(5));
+        backoff.attempt_delay();
+        backoff.attempt_delay();
+        backoff.attempt_delay();
+
+        // Connected and receiving, but not for long enough
+        backoff.connected();
+        tokio::time::advance(Duration::from_secs(4)).await;
+        backoff.received();
+        assert_jittered(backoff.attempt_delay().unwrap(), INITIAL * 4);
+
+        // Long enough, but no message since: nothing proves it works yet
+        backoff.connected();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_jittered(backoff.attempt_delay().unwrap(), MAX);
+
+        backoff.connected();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        backoff.received();
+        assert_jittered(backoff.attempt_delay().unwrap(), INITIAL);
+    }
+
+    #[test]
+    fn test_trim_line() {
+        assert_eq!(trim_line(b"{\"a\":1}\n"), Some(&b"{\"a\":1}"[..]));
+        assert_eq!(trim_line(b"abc\r\n"), Some(&b"abc"[..]));
+        assert_eq!(trim_line(b"abc"), Some(&b"abc"[..]));
+        assert_eq!(trim_line(b"a\rb\n"), Some(&b"a\rb"[..]));
+        assert_eq!(trim_line(b"\r\n"), None);
+        assert_eq!(trim_line(b"\n"), None);
+        assert_eq!(trim_line(b""), None);
+    }
+}
@@ -0,0 +1,269 @@
+// =============================================================================
+// Frame Compression — LZ4 envelope shared by server and WASM client
+// =============================================================================
+//
+// Broadcast frames and snapshots can be sent LZ4-compressed, optionally
+// against a dictionary built from recent frames (orderbook frames repeat
+// most of their structure, so a dictionary of recent frames compresses them
+// several times smaller). The server encodes, the client WASM engine decodes
+// inside `ingest_frame()` — this module is the single definition of the
+// format both sides use.
+//
+// Copy to `src/compression.rs` in your shared crate (lib.rs declares
+// `pub mod compression;`) and add to its Cargo.toml:
+//
+//   lz4_flex = { version = "0.11", default-features = false, features = ["std", "safe-encode", "safe-decode"] }
+//
+// ENVELOPE (16-byte header, little-endian):
+//
+//   [0]      kind: 0 = stored, 1 = lz4, 2 = dictionary
+//   [1..4]   reserved, 0
+//   [4..8]   "OAZ1" — in the FlatBuffers file identifier slot, so it can't
+//            be mistaken for a CommandResponse ("OARS")
+//   [8..12]  dictionary id (lz4: dictionary used, 0 = none;
+//            dictionary: id being defined)
+//   [12..16] uncompressed length
+//   [16..]   payload (dictionary payloads are LZ4-compressed too)
+//
+// A message without the "OAZ1" identifier is a plain FlatBuffer, so the
+// decoder can sit in front of `ingest_frame()` whether or not compression
+// was negotiated.
+//
+// CLIENT USAGE (WASM engine):
+//
+//   pub fn ingest_frame(&mut self, bytes: &[u8]) {
+//       let Ok(Some(bytes)) = self.decoder.decode(bytes) else { return };
+//       let envelope = flatbuffers::root::<Envelope>(bytes).unwrap();
+//       ...
+//   }
+//
+// =============================================================================
+
+/// File identifier of an envelope, at bytes 4..8.
+pub const ENVELOPE_IDENTIFIER: &[u8; 4] = b"OAZ1";
+
+/// Header length; the payload starts here.
+pub const ENVELOPE_HEADER_LEN: usize = 16;
+
+/// LZ4 can only refer back 64 KiB, so a longer dictionary is wasted.
+pub const MAX_DICTIONARY_LEN: usize = 64 * 1024;
+
+/// Largest frame the decoder will inflate (guards against corrupt lengths).
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Dictionaries kept by the decoder. The server switches dictionaries
+/// between frames, but frames compressed just before a switch may still be
+/// in flight behind the new dictionary.
+const DECODER_DICTIONARIES: usize = 2;
+
+const KIND_STORED: u8 = 0;
+const KIND_LZ4: u8 = 1;
+const KIND_DICTIONARY: u8 = 2;
+
+/// Why a message couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Shorter than the header, or an unknown kind.
+    Malformed,
+    /// Compressed against a dictionary this decoder never received.
+    UnknownDictionary(u32),
+    /// Declared length over `MAX_FRAME_LEN`.
+    TooLarge(usize),
+    /// LZ4 data didn't inflate to the declared length.
+    Corrupt,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Malformed => write!(f, "malformed envelope"),
+            DecodeError::UnknownDictionary(id) => write!(f, "unknown dictionary {id}"),
+            DecodeError::TooLarge(len) => write!(f, "frame of {len} bytes exceeds limit"),
+            DecodeError::Corrupt => write!(f, "corrupt compressed data"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Whether `bytes` is an envelope (as opposed to a plain FlatBuffer).
+pub fn is_envelope(bytes: &[u8]) -> bool {
+    bytes.len() >= ENVELOPE_HEADER_LEN && &bytes[4..8] == ENVELOPE_IDENTIFIER
+}
+
+fn write_header(out: &mut Vec<u8>, kind: u8, dictionary_id: u32, raw_len: usize) {
+    out.clear();
+    out.extend_from_slice(&[kind, 0, 0, 0]);
+    out.extend_from_slice(ENVELOPE_IDENTIFIER);
+    out.extend_from_slice(&dictionary_id.to_le_bytes());
+    out.extend_from_slice(&(raw_len as u32).to_le_bytes());
+}
+
+/// Append LZ4 data for `raw` (against `dictionary`, may be empty) to `out`.
+fn compress_into(raw: &[u8], dictionary: &[u8], out: &mut Vec<u8>) {
+    let start = out.len();
+    out.resize(start + lz4_flex::block::get_maximum_output_size(raw.len()), 0);
+    let written = lz4_flex::block::compress_into_with_dict(raw, &mut out[start..], dictionary)
+        .expect("output sized by get_maximum_output_size");
+    out.truncate(start + written);
+}
+
+/// Encode a frame into `out`, compressed against `dictionary` (`(id, bytes)`)
+/// if given. Falls back to a stored envelope when compression doesn't help.
+pub fn encode_frame(raw: &[u8], dictionary: Option<(u32, &[u8])>, out: &mut Vec<u8>) {
+    let (id, dict) = dictionary.unwrap_or((0, &[]));
+    write_header(out, KIND_LZ4, id, raw.len());
+    compress_into(raw, dict, out);
+    if out.len() >= ENVELOPE_HEADER_LEN + raw.len() {
+        write_header(out, KIND_STORED, 0, raw.len());
+        out.extend_from_slice(raw);
+    }
+}
+
+/// Encode the message that defines dictionary `id` for the decoder.
+pub fn encode_dictionary(id: u32, dictionary: &[u8], out: &mut Vec<u8>) {
+    write_header(out, KIND_DICTIONARY, id, dictionary.len());
+    compress_into(dictionary, &[], out);
+}
+
+/// Client-side decoder. Keeps the dictionaries the server has sent and a
+/// reusable output buffer, so steady-state decoding doesn't allocate.
+#[derive(Default)]
+pub struct FrameDecoder {
+    dictionaries: Vec<(u32, Vec<u8>)>,
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode one WebSocket message.
+    ///
+    /// Returns the FlatBuffer bytes, or `Ok(None)` for a dictionary message
+    /// (stored for later frames, nothing to ingest). Plain FlatBuffers pass
+    /// through unchanged.
+    pub fn decode<'a>(&'a mut self, bytes: &'a [u8]) -> Result<Option<&'a [u8]>, DecodeError> {
+        if !is_envelope(bytes) {
+            return Ok(Some(bytes));
+        }
+        let kind = bytes[0];
+        let id = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let raw_len = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        let payload = &bytes[ENVELOPE_HEADER_LEN..];
+        if raw_len > MAX_FRAME_LEN {
+            return Err(DecodeError::TooLarge(raw_len));
+        }
+
+        match kind {
+            KIND_STORED => Ok(Some(payload)),
+            KIND_LZ4 => {
+                let dictionary: &[u8] = match id {
+                    0 => &[],
+                    id => self
+                        .dictionaries
+                        .iter()
+                        .find(|(known, _)| *known == id)
+                        .map(|(_, dict)| dict.as_slice())
+                        .ok_or(DecodeError::UnknownDictionary(id))?,
+                };
+                self.buf.resize(raw_len, 0);
+                let n = lz4_flex::block::decompress_into_with_dict(payload, &mut self.buf, dictionary)
+                    .map_err(|_| DecodeError::Corrupt)?;
+                if n != raw_len {
+                    return Err(DecodeError::Corrupt);
+                }
+                Ok(Some(&self.buf))
+            }
+            KIND_DICTIONARY => {
+                if raw_len > MAX_DICTIONARY_LEN {
+                    return Err(DecodeError::TooLarge(raw_len));
+                }
+                let mut dictionary = vec![0; raw_len];
+                let n = lz4_flex::block::decompress_into(payload, &mut dictionary)
+                    .map_err(|_| DecodeError::Corrupt)?;
+                if n != raw_len {
+                    return Err(DecodeError::Corrupt);
+                }
+                self.dictionaries.retain(|(known, _)| *known != id);
+                if self.dictionaries.len() == DECODER_DICTIONARIES {
+                    self.dictionaries.remove(0);
+                }
+                self.dictionaries.push((id, dictionary));
+                Ok(None)
+            }
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(seed: u8) -> Vec<u8> {
+        (0..2000u32).map(|i| ((i % 97) as u8).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn test_plain_flatbuffer_passes_through() {
+        let mut decoder = FrameDecoder::new();
+        let raw = [12, 0, 0, 0, b'O', b'A', b'R', b'S', 1, 2, 3, 4, 5, 6, 7, 8];
+        assert!(!is_envelope(&raw));
+        assert_eq!(decoder.decode(&raw).unwrap(), Some(&raw[..]));
+    }
+
+    #[test]
+    fn test_round_trip_without_dictionary() {
+        let raw = frame(0);
+        let mut out = Vec::new();
+        encode_frame(&raw, None, &mut out);
+        assert!(is_envelope(&out));
+        assert!(out.len() < raw.len() / 4);
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(decoder.decode(&out).unwrap(), Some(&raw[..]));
+    }
+
+    #[test]
+    fn test_round_trip_with_dictionary() {
+        let dictionary = frame(1);
+        let raw = frame(1);
+        let mut msg = Vec::new();
+        let mut decoder = FrameDecoder::new();
+
+        encode_frame(&raw, Some((7, &dictionary)), &mut msg);
+        assert_eq!(decoder.decode(&msg), Err(DecodeError::UnknownDictionary(7)));
+
+        let mut dict_msg = Vec::new();
+        encode_dictionary(7, &dictionary, &mut dict_msg);
+        assert_eq!(decoder.decode(&dict_msg).unwrap(), None);
+        assert_eq!(decoder.decode(&msg).unwrap(), Some(&raw[..]));
+
+        // Older dictionaries age out
+        for id in 8..8 + DECODER_DICTIONARIES as u32 {
+            encode_dictionary(id, &dictionary, &mut dict_msg);
+            decoder.decode(&dict_msg).unwrap();
+        }
+        assert_eq!(decoder.decode(&msg), Err(DecodeError::UnknownDictionary(7)));
+    }
+
+    #[test]
+    fn test_incompressible_frame_is_stored() {
+        let raw: Vec<u8> = (0..64u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let mut out = Vec::new();
+        encode_frame(&raw, None, &mut out);
+        assert_eq!(out[0], KIND_STORED);
+        assert_eq!(FrameDecoder::new().decode(&out).unwrap(), Some(&raw[..]));
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let mut out = Vec::new();
+        encode_frame(&frame(0), None, &mut out);
+        out.truncate(ENVELOPE_HEADER_LEN + 4);
+        assert_eq!(FrameDecoder::new().decode(&out), Err(DecodeError::Corrupt));
+        out[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(FrameDecoder::new().decode(&out), Err(DecodeError::TooLarge(_))));
+    }
+}
@@ -0,0 +1,626 @@
+// =============================================================================
+// Exact Decimals — fixed-point prices and sizes for server and client alike
+// =============================================================================
+//
+// f64 can't represent most decimal prices exactly, and two sides that reach
+// "the same" price by different arithmetic (parsing "0.1" vs 0.3 - 0.2)
+// end up with different bits: the server's level at 64000.1 and the
+// client's never compare equal, and the UI shows two rows. A `Decimal` is
+// an integer count of 10^-scale units, so parsing, comparing, adding and
+// formatting are exact and identical on native and wasm32.
+//
+// Copy to `src/decimal.rs` in your shared crate (lib.rs declares
+// `pub mod decimal;`).
+//
+//   let btc = Instrument::new("0.01".parse()?, "0.00001".parse()?);
+//   let price = btc.parse_price("64000.10")?;         // 6400010 units, scale 2
+//   let size = btc.round_size(raw_size, Rounding::Down)?;
+//   let notional = price.checked_mul(size)?;         // exact, scale 7
+//   price.to_string()                                 // "64000.10"
+//
+// SCALE: the number of decimal places, 0..=MAX_SCALE. Values of different
+// scales compare and add exactly (1.5 == 1.50); an `Instrument` fixes the
+// scales its prices and sizes are published in, from its tick and lot sizes.
+//
+// ROUNDING: arithmetic that can't be exact (division, rescaling down,
+// rounding to a tick) takes a `Rounding`. Round bids `Down` and asks `Up`
+// to keep a book from crossing. Checked operations return None on i64
+// overflow rather than wrapping.
+//
+// ON THE WIRE: schema/decimal.fbs has the FlatBuffer structs — `Decimal`
+// (units + scale) and `DecimalLevel` (price and size units in the
+// instrument's scales). Convert with `Decimal::new(fb.units(), fb.scale())`
+// and `fb::Decimal::new(d.units(), d.scale())`. `to_f64()` is for rendering
+// and charting only; compare and key on the Decimal.
+//
+// =============================================================================
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Most decimal places a `Decimal` can carry (10^18 still fits an i64).
+pub const MAX_SCALE: u8 = 18;
+
+const POW10: [i64; MAX_SCALE as usize + 1] = {
+    let mut table = [1i64; MAX_SCALE as usize + 1];
+    let mut i = 1;
+    while i < table.len() {
+        table[i] = table[i - 1] * 10;
+        i += 1;
+    }
+    table
+};
+
+/// How to round a result that falls between two representable values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Toward negative infinity (floor).
+    Down,
+    /// Toward positive infinity (ceiling).
+    Up,
+    /// To the nearest value, halves away from zero.
+    Nearest,
+}
+
+/// Why a string or value was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalError {
+    /// Not a decimal number (`-12.50`; no exponents, no separators).
+    Invalid,
+    /// Too large for an i64 at this scale.
+    Overflow,
+    /// More decimal places than the scale allows.
+    Precision { scale: u8 },
+    /// Not a multiple of the instrument's tick or lot size.
+    OffIncrement,
+}
+
+impl fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecimalError::Invalid => write!(f, "invalid decimal"),
+            DecimalError::Overflow => write!(f, "decimal out of range"),
+            DecimalError::Precision { scale } => write!(f, "more than {scale} decimal places"),
+            DecimalError::OffIncrement => write!(f, "not a multiple of the tick or lot size"),
+        }
+    }
+}
+
+impl std::error::Error for DecimalError {}
+
+/// A fixed-point decimal: `units / 10^scale`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Decimal {
+    units: i64,
+    scale: u8,
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { units: 0, scale: 0 };
+
+    /// `units / 10^scale`. Panics if `scale > MAX_SCALE`.
+    pub const fn new(units: i64, scale: u8) -> Self {
+        assert!(scale <= MAX_SCALE, "decimal scale out of range");
+        Self { units, scale }
+    }
+
+    pub const fn units(self) -> i64 {
+        self.units
+    }
+
+    pub const fn scale(self) -> u8 {
+        self.scale
+    }
+
+    /// The decimal nearest to `value` with `scale` places. None for NaN,
+    /// infinities and values out of range.
+    pub fn from_f64(value: f64, scale: u8) -> Option<Self> {
+        if !value.is_finite() || scale > MAX_SCALE {
+            return None;
+        }
+        // Formatting rounds from the exact binary value, so 0.1 becomes
+        // "0.10" rather than whatever 0.1 * 100.0 happens to be
+        format!("{value:.prec$}", prec = scale as usize)
+            .parse()
+            .ok()
+    }
+
+    /// Nearest f64, for rendering. Every decimal converts to the same f64
+    /// on every platform, but arithmetic on the result is inexact again.
+    pub fn to_f64(self) -> f64 {
+        self.units as f64 / POW10[self.scale as usize] as f64
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.units == 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.units > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.units < 0
+    }
+
+    /// The same value with `scale` places, rounding if that drops digits.
+    pub fn rescale(self, scale: u8, rounding: Rounding) -> Option<Self> {
+        if scale > MAX_SCALE {
+            return None;
+        }
+        let units = if scale >= self.scale {
+            self.units
+                .checked_mul(POW10[(scale - self.scale) as usize])?
+        } else {
+            let divisor = POW10[(self.scale - scale) as usize];
+            i64::try_from(div_round(self.units.into(), divisor.into(), rounding)).ok()?
+        };
+        Some(Self { units, scale })
+    }
+
+    /// The same value with trailing zero places removed.
+    pub fn normalize(self) -> Self {
+        let mut d = self;
+        while d.scale > 0 && d.units % 10 == 0 {
+            d.units /= 10;
+            d.scale -= 1;
+        }
+        d
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        Some(Self {
+            units: self.units.checked_neg()?,
+            scale: self.scale,
+        })
+    }
+
+    pub fn checked_abs(self) -> Option<Self> {
+        Some(Self {
+            units: self.units.checked_abs()?,
+            scale: self.scale,
+        })
+    }
+
+    /// Exact sum, at the larger of the two scales.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        let (a, b, scale) = align(self, rhs)?;
+        Some(Self {
+            units: a.checked_add(b)?,
+            scale,
+        })
+    }
+
+    /// Exact difference, at the larger of the two scales.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        let (a, b, scale) = align(self, rhs)?;
+        Some(Self {
+            units: a.checked_sub(b)?,
+            scale,
+        })
+    }
+
+    /// Product, at the sum of the two scales — exact unless that exceeds
+    /// `MAX_SCALE`, in which case it is rounded to the nearest.
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        let mut units = i128::from(self.units) * i128::from(rhs.units);
+        let mut scale = self.scale + rhs.scale;
+        if scale > MAX_SCALE {
+            units = div_round(
+                units,
+                POW10[(scale - MAX_SCALE) as usize].into(),
+                Rounding::Nearest,
+            );
+            scale = MAX_SCALE;
+        }
+        Some(Self {
+            units: i64::try_from(units).ok()?,
+            scale,
+        })
+    }
+
+    /// Quotient with `scale` places. None on division by zero or overflow.
+    pub fn checked_div(self, rhs: Self, scale: u8, rounding: Rounding) -> Option<Self> {
+        if rhs.units == 0 || scale > MAX_SCALE {
+            return None;
+        }
+        // self / rhs = (a / 10^sa) / (b / 10^sb); scaled by 10^scale
+        let numerator = i128::from(self.units)
+            .checked_mul(i128::from(POW10[scale as usize]))?
+            .checked_mul(i128::from(POW10[rhs.scale as usize]))?;
+        let denominator = i128::from(rhs.units) * i128::from(POW10[self.scale as usize]);
+        let units = div_round(numerator, denominator, rounding);
+        Some(Self {
+            units: i64::try_from(units).ok()?,
+            scale,
+        })
+    }
+
+    /// The nearest multiple of `increment` (a tick or lot size) in the
+    /// given direction, at the larger of the two scales. None if
+    /// `increment` isn't positive.
+    pub fn round_to(self, increment: Self, rounding: Rounding) -> Option<Self> {
+        if !increment.is_positive() {
+            return None;
+        }
+        let (a, step, scale) = align(self, increment)?;
+        let steps = div_round(a.into(), step.into(), rounding);
+        let units = i64::try_from(steps.checked_mul(step.into())?).ok()?;
+        Some(Self { units, scale })
+    }
+
+    /// Whether `self` is a whole number of `increment`s.
+    pub fn is_multiple_of(self, increment: Self) -> bool {
+        match align(self, increment) {
+            Some((_, 0, _)) | None => false,
+            Some((a, step, _)) => a % step == 0,
+        }
+    }
+
+    /// Both values at a common scale, as i128 so any two i64 decimals fit.
+    fn widened(self, other: Self) -> (i128, i128) {
+        let scale = self.scale.max(other.scale);
+        (
+            i128::from(self.units) * i128::from(POW10[(scale - self.scale) as usize]),
+            i128::from(other.units) * i128::from(POW10[(scale - other.scale) as usize]),
+        )
+    }
+}
+
+/// Both units at the larger scale, or None if one overflows.
+fn align(a: Decimal, b: Decimal) -> Option<(i64, i64, u8)> {
+    let scale = a.scale.max(b.scale);
+    let a_units = a.units.checked_mul(POW10[(scale - a.scale) as usize])?;
+    let b_units = b.units.checked_mul(POW10[(scale - b.scale) as usize])?;
+    Some((a_units, b_units, scale))
+}
+
+/// `n / d` rounded as asked. `d` must not be 0.
+fn div_round(n: i128, d: i128, rounding: Rounding) -> i128 {
+    let (q, r) = (n / d, n % d);
+    if r == 0 {
+        return q;
+    }
+    // The exact quotient lies between q and q + sign
+    let sign = if (n < 0) == (d < 0) { 1 } else { -1 };
+    match rounding {
+        Rounding::Down if sign < 0 => q - 1,
+        Rounding::Up if sign > 0 => q + 1,
+        Rounding::Nearest if 2 * r.unsigned_abs() >= d.unsigned_abs() => q + sign,
+        _ => q,
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = self.widened(*other);
+        a.cmp(&b)
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal values hash alike whatever their scale
+        let d = self.normalize();
+        d.units.hash(state);
+        d.scale.hash(state);
+    }
+}
+
+/// Exactly `scale` decimal places: `Decimal::new(-5, 2)` is `-0.05`.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.units < 0 { "-" } else { "" };
+        let magnitude = self.units.unsigned_abs();
+        if self.scale == 0 {
+            return write!(f, "{sign}{magnitude}");
+        }
+        let divisor = POW10[self.scale as usize] as u64;
+        let (whole, fraction) = (magnitude / divisor, magnitude % divisor);
+        write!(
+            f,
+            "{sign}{whole}.{fraction:0width$}",
+            width = self.scale as usize
+        )
+    }
+}
+
+/// Parses `[-+]digits[.digits]`, keeping the number of places given
+/// ("1.50" has scale 2).
+impl FromStr for Decimal {
+    type Err = DecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !all_digits(whole) || !all_digits(fraction)
+        {
+            return Err(DecimalError::Invalid);
+        }
+        if fraction.len() > MAX_SCALE as usize {
+            return Err(DecimalError::Precision { scale: MAX_SCALE });
+        }
+
+        let mut units: i64 = 0;
+        for b in whole.bytes().chain(fraction.bytes()) {
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(i64::from(b - b'0')))
+                .ok_or(DecimalError::Overflow)?;
+        }
+        Ok(Self {
+            units: if negative { -units } else { units },
+            scale: fraction.len() as u8,
+        })
+    }
+}
+
+/// Price and size increments of one instrument, and the scales its values
+/// are published in (those of `tick_size` and `lot_size` as written:
+/// a tick of "0.50" publishes prices with 2 places).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instrument {
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+}
+
+impl Instrument {
+    pub fn new(tick_size: Decimal, lot_size: Decimal) -> Self {
+        Self {
+            tick_size,
+            lot_size,
+        }
+    }
+
+    pub fn price_scale(&self) -> u8 {
+        self.tick_size.scale()
+    }
+
+    pub fn size_scale(&self) -> u8 {
+        self.lot_size.scale()
+    }
+
+    /// Parse a price that must already be on the tick grid.
+    pub fn parse_price(&self, s: &str) -> Result<Decimal, DecimalError> {
+        on_grid(s.parse()?, self.tick_size)
+    }
+
+    /// Parse a size that must already be a whole number of lots.
+    pub fn parse_size(&self, s: &str) -> Result<Decimal, DecimalError> {
+        on_grid(s.parse()?, self.lot_size)
+    }
+
+    /// Round a price to the tick grid, at the instrument's price scale.
+    pub fn round_price(&self, price: Decimal, rounding: Rounding) -> Option<Decimal> {
+        price
+            .round_to(self.tick_size, rounding)?
+            .rescale(self.price_scale(), rounding)
+    }
+
+    /// Round a size to whole lots, at the instrument's size scale.
+    pub fn round_size(&self, size: Decimal, rounding: Rounding) -> Option<Decimal> {
+        size.round_to(self.lot_size, rounding)?
+            .rescale(self.size_scale(), rounding)
+    }
+
+    /// The tick nearest to an f64 price from a feed that sends floats.
+    pub fn price_from_f64(&self, price: f64) -> Option<Decimal> {
+        nearest_multiple(price, self.tick_size)
+    }
+
+    /// The lot nearest to an f64 size.
+    pub fn size_from_f64(&self, size: f64) -> Option<Decimal> {
+        nearest_multiple(size, self.lot_size)
+    }
+}
+
+/// The multiple of `increment` nearest to `value`, at the increment's
+/// scale. Cheap enough for every level of a book feed.
+fn nearest_multiple(value: f64, increment: Decimal) -> Option<Decimal> {
+    let steps = (value / increment.to_f64()).round();
+    // Beyond 2^53 steps f64 can't tell neighbouring multiples apart
+    if !increment.is_positive() || !steps.is_finite() || steps.abs() > 9.0e15 {
+        return None;
+    }
+    let units = (steps as i64).checked_mul(increment.units)?;
+    Some(Decimal {
+        units,
+        scale: increment.scale,
+    })
+}
+
+/// `value` at the increment's scale, if it is a multiple of the increment.
+fn on_grid(value: Decimal, increment: Decimal) -> Result<Decimal, DecimalError> {
+    if !value.is_multiple_of(increment) {
+        return Err(DecimalError::OffIncrement);
+    }
+    // A multiple of the increment has no places beyond the increment's
+    value
+        .rescale(increment.scale(), Rounding::Nearest)
+        .ok_or(DecimalError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format_round_trip() {
+        for s in [
+            "0",
+            "1.50",
+            "-0.05",
+            "64000.10",
+            "0.00000001",
+            "9223372036854775807",
+        ] {
+            assert_eq!(d(s).to_string(), s);
+        }
+        assert_eq!(d("+.5").to_string(), "0.5");
+        assert_eq!(d("3.").to_string(), "3");
+        assert_eq!(Decimal::new(-5, 2).to_string(), "-0.05");
+        assert_eq!("".parse::<Decimal>(), Err(DecimalError::Invalid));
+        assert_eq!(".".parse::<Decimal>(), Err(DecimalError::Invalid));
+        assert_eq!("1e5".parse::<Decimal>(), Err(DecimalError::Invalid));
+        assert_eq!("1.2.3".parse::<Decimal>(), Err(DecimalError::Invalid));
+        assert_eq!(
+            "9223372036854775808".parse::<Decimal>(),
+            Err(DecimalError::Overflow)
+        );
+        assert_eq!(
+            "0.1234567890123456789".parse::<Decimal>(),
+            Err(DecimalError::Precision { scale: MAX_SCALE })
+        );
+    }
+
+    #[test]
+    fn test_equality_and_order_across_scales() {
+        assert_eq!(d("1.5"), d("1.500"));
+        assert!(d("1.49") < d("1.5"));
+        assert!(d("-2") < d("-1.99"));
+
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |v: Decimal| {
+            let mut h = DefaultHasher::new();
+            v.hash(&mut h);
+            h.finish()
+        };
+        assert_eq!(hash(d("1.5")), hash(d("1.50")));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        // Exact where f64 isn't: 0.1 + 0.2 == 0.3
+        assert_eq!(d("0.1").checked_add(d("0.2")), Some(d("0.3")));
+        assert_eq!(d("0.3").checked_sub(d("0.2")), Some(d("0.1")));
+        assert_eq!(
+            d("64000.10").checked_mul(d("0.00012")).unwrap().to_string(),
+            "7.6800120"
+        );
+        assert_eq!(
+            d("10")
+                .checked_div(d("3"), 4, Rounding::Down)
+                .unwrap()
+                .to_string(),
+            "3.3333"
+        );
+        assert_eq!(
+            d("10")
+                .checked_div(d("3"), 4, Rounding::Up)
+                .unwrap()
+                .to_string(),
+            "3.3334"
+        );
+        assert_eq!(
+            d("-10")
+                .checked_div(d("4"), 0, Rounding::Nearest)
+                .unwrap()
+                .to_string(),
+            "-3"
+        );
+        assert_eq!(d("1").checked_div(d("0"), 2, Rounding::Down), None);
+        assert_eq!(Decimal::new(i64::MAX, 0).checked_add(d("1")), None);
+        assert_eq!(Decimal::new(i64::MAX, 0).checked_mul(d("2")), None);
+        assert_eq!(Decimal::new(i64::MIN, 0).checked_neg(), None);
+    }
+
+    #[test]
+    fn test_rounding_and_rescale() {
+        assert_eq!(d("1.25").round_to(d("0.5"), Rounding::Down), Some(d("1.0")));
+        assert_eq!(d("1.25").round_to(d("0.5"), Rounding::Up), Some(d("1.5")));
+        assert_eq!(
+            d("1.25").round_to(d("0.5"), Rounding::Nearest),
+            Some(d("1.5"))
+        );
+        assert_eq!(
+            d("-1.25").round_to(d("0.5"), Rounding::Down),
+            Some(d("-1.5"))
+        );
+        assert_eq!(d("1.25").round_to(d("0"), Rounding::Down), None);
+
+        assert_eq!(
+            d("1.005")
+                .rescale(2, Rounding::Nearest)
+                .unwrap()
+                .to_string(),
+            "1.01"
+        );
+        assert_eq!(
+            d("1.005").rescale(2, Rounding::Down).unwrap().to_string(),
+            "1.00"
+        );
+        assert_eq!(
+            d("1.5").rescale(3, Rounding::Down).unwrap().to_string(),
+            "1.500"
+        );
+        assert_eq!(d("1.500").normalize().to_string(), "1.5");
+        assert!(d("3.00").is_multiple_of(d("0.75")));
+        assert!(!d("3.01").is_multiple_of(d("0.75")));
+    }
+
+    #[test]
+    fn test_f64_conversion() {
+        assert_eq!(Decimal::from_f64(0.1, 2), Some(d("0.10")));
+        assert_eq!(Decimal::from_f64(0.3 - 0.2, 8), Some(d("0.1")));
+        assert_eq!(Decimal::from_f64(f64::NAN, 2), None);
+        assert_eq!(Decimal::from_f64(1e30, 2), None);
+        assert_eq!(d("64000.10").to_f64(), 64000.1);
+    }
+
+    #[test]
+    fn test_instrument() {
+        let btc = Instrument::new(d("0.50"), d("0.001"));
+        assert_eq!((btc.price_scale(), btc.size_scale()), (2, 3));
+        assert_eq!(btc.parse_price("64000.5").unwrap().to_string(), "64000.50");
+        assert_eq!(btc.parse_price("64000.25"), Err(DecimalError::OffIncrement));
+        assert_eq!(btc.parse_size("0.0015"), Err(DecimalError::OffIncrement));
+        assert_eq!(
+            btc.round_price(d("64000.3"), Rounding::Down)
+                .unwrap()
+                .to_string(),
+            "64000.00"
+        );
+        assert_eq!(
+            btc.round_price(d("64000.3"), Rounding::Up)
+                .unwrap()
+                .to_string(),
+            "64000.50"
+        );
+        assert_eq!(
+            btc.round_size(d("0.0015"), Rounding::Down)
+                .unwrap()
+                .to_string(),
+            "0.001"
+        );
+        assert_eq!(
+            btc.price_from_f64(64000.1 + 0.2).unwrap().to_string(),
+            "64000.50"
+        );
+        assert_eq!(btc.size_from_f64(0.1 + 0.2).unwrap().to_string(), "0.300");
+    }
+}
@@ -0,0 +1,170 @@
+// =============================================================================
+// Delta — which frame fields changed since JS last saw them
+// =============================================================================
+//
+// tick() runs at 60fps whether or not anything moved. ChangeTracker compares
+// the values a frame would carry with the values of the last frame it
+// reported, and returns a bitmask of the fields that moved by more than their
+// epsilon. The engine returns that mask from tick():
+//
+//   - 0: nothing changed — skip the builder entirely; the previous frame is
+//     still in it and JS keeps using it
+//   - otherwise: rebuild the frame; JS skips DOM writes for fields whose bit
+//     is clear (EffectApplicator's `dependsOn()`)
+//
+// Copy to `src/delta.rs` in your shared crate (lib.rs declares
+// `pub mod delta;`).
+//
+// Field i is bit `1 << i`. Number fields in a `#[wasm_bindgen]` enum so JS
+// uses the same indices. A mask is a u32 because JS bitwise operators work
+// on 32 bits, so a tracker holds at most 32 fields.
+//
+// PUBLISHED VALUES: a field is compared with the value it had when its bit
+// was last set, not with the previous tick. A value creeping towards its
+// target by less than epsilon per tick is still reported once the total
+// movement exceeds epsilon, so what JS shows never lags by more than that.
+//
+// EPSILON: per field. 0 for bools and bytes (encode them as 0.0/1.0 and
+// their integer value), the smallest visible step for continuous values, and
+// f64::INFINITY for fields that aren't numbers (structs, lists) — those are
+// only reported when `mark()`ed, e.g. when new data arrives.
+//
+// NaN: NaN to NaN is unchanged; a number to NaN or back is a change.
+//
+// =============================================================================
+
+/// Most fields a tracker can hold: one bit each in a u32 mask.
+pub const MAX_FIELDS: usize = 32;
+
+/// Tracks which of `N` frame fields changed since they were last reported.
+#[derive(Debug, Clone)]
+pub struct ChangeTracker<const N: usize> {
+    epsilons: [f64; N],
+    published: [f64; N],
+    /// Fields reported as changed by the next update() regardless of value.
+    marked: u32,
+    mask: u32,
+}
+
+impl<const N: usize> ChangeTracker<N> {
+    /// A tracker with one epsilon per field. The first `update()` reports
+    /// every field.
+    ///
+    /// # Panics
+    /// If `N` is more than `MAX_FIELDS`.
+    pub fn new(epsilons: [f64; N]) -> Self {
+        assert!(N <= MAX_FIELDS, "a change mask holds at most {MAX_FIELDS} fields");
+        Self { epsilons, published: [f64::NAN; N], marked: Self::all(), mask: 0 }
+    }
+
+    /// Mask with every field's bit set.
+    pub fn all() -> u32 {
+        u32::MAX.checked_shr((MAX_FIELDS - N) as u32).unwrap_or(0)
+    }
+
+    /// Report `field` as changed on the next update(), whatever its value.
+    pub fn mark(&mut self, field: usize) {
+        debug_assert!(field < N, "field {field} out of range");
+        self.marked |= 1 << field;
+    }
+
+    /// Report every field on the next update() — when JS needs a full
+    /// frame again (a remount, a new consumer).
+    pub fn mark_all(&mut self) {
+        self.marked = Self::all();
+    }
+
+    /// Compare `values` with the published ones, publish the fields that
+    /// changed, and return their mask.
+    pub fn update(&mut self, values: [f64; N]) -> u32 {
+        let mut mask = std::mem::take(&mut self.marked);
+        for (field, value) in values.into_iter().enumerate() {
+            let bit = 1 << field;
+            if mask & bit != 0 || moved(self.published[field], value, self.epsilons[field]) {
+                self.published[field] = value;
+                mask |= bit;
+            }
+        }
+        self.mask = mask;
+        mask
+    }
+
+    /// Mask returned by the last update().
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Whether `field` changed in the last update().
+    pub fn changed(&self, field: usize) -> bool {
+        field < N && self.mask & (1 << field) != 0
+    }
+}
+
+fn moved(published: f64, value: f64, epsilon: f64) -> bool {
+    if published.is_nan() || value.is_nan() {
+        published.is_nan() != value.is_nan()
+    } else {
+        (value - published).abs() > epsilon
+    }
+}
+
+// ============================================
+// Tests
+// ============================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_update_reports_every_field() {
+        let mut tracker = ChangeTracker::new([0.01, 0.0, 0.0]);
+        assert_eq!(tracker.update([1.0, 0.0, 255.0]), 0b111);
+        assert_eq!(tracker.update([1.0, 0.0, 255.0]), 0);
+        assert_eq!(tracker.mask(), 0);
+        assert_eq!(ChangeTracker::<32>::all(), u32::MAX);
+        assert_eq!(ChangeTracker::<0>::all(), 0);
+    }
+
+    #[test]
+    fn test_epsilon_is_measured_from_the_published_value() {
+        let mut tracker = ChangeTracker::new([0.01, 0.0]);
+        tracker.update([1.0, 0.0]);
+
+        // Creeping by less than epsilon per tick still gets reported once the
+        // total exceeds it
+        assert_eq!(tracker.update([1.004, 0.0]), 0);
+        assert_eq!(tracker.update([1.008, 0.0]), 0);
+        assert_eq!(tracker.update([1.012, 0.0]), 0b01);
+        assert!(tracker.changed(0));
+        assert!(!tracker.changed(1));
+        assert_eq!(tracker.update([1.016, 0.0]), 0);
+
+        // Epsilon 0: any change
+        assert_eq!(tracker.update([1.016, 1.0]), 0b10);
+    }
+
+    #[test]
+    fn test_nan_transitions() {
+        let mut tracker = ChangeTracker::new([0.5]);
+        tracker.update([f64::NAN]);
+        assert_eq!(tracker.update([f64::NAN]), 0);
+        assert_eq!(tracker.update([3.0]), 1);
+        assert_eq!(tracker.update([f64::NAN]), 1);
+    }
+
+    #[test]
+    fn test_marked_fields() {
+        let mut tracker = ChangeTracker::new([0.0, f64::INFINITY]);
+        tracker.update([0.0, 0.0]);
+
+        // An infinite epsilon only reports marked fields
+        assert_eq!(tracker.update([0.0, 1e300]), 0);
+        tracker.mark(1);
+        assert_eq!(tracker.update([0.0, 0.0]), 0b10);
+        assert_eq!(tracker.update([0.0, 0.0]), 0);
+
+        tracker.mark_all();
+        assert_eq!(tracker.update([0.0, 0.0]), 0b11);
+    }
+}
@@ -0,0 +1,705 @@
+// =============================================================================
+// Indicators — streaming technical indicators, O(1) per data point
+// =============================================================================
+//
+// Each indicator folds in one point at a time and keeps only what it needs
+// to produce the next value: a running average, a window of `period`
+// points, a pair of deques. The WASM engine updates them as data arrives
+// and writes their values into the frame in tick(), so JS never recomputes
+// them. The server can use the same code (e.g. indicators over candles),
+// and both sides get identical numbers.
+//
+// Copy to `src/indicators.rs` in your shared crate (lib.rs declares
+// `pub mod indicators;`).
+//
+// CONTRACT: `update(x)` returns the value after folding in `x`, or None
+// while the indicator has seen fewer points than it needs (the warm-up);
+// `value()` returns the same without updating. Inputs must be finite —
+// `IndicatorSet::update` filters the rest.
+//
+//   Ema        exponential average, seeded with the SMA of the first `period`
+//   Sma        simple average over `period`
+//   StdDev     population standard deviation over `period`
+//   Bollinger  SMA ± k standard deviations
+//   MinMax     lowest and highest over `period` (amortized O(1))
+//   Vwap       volume-weighted average price since the last reset()
+//   Rsi        Wilder's relative strength index, 0..=100
+//   Macd       EMA(fast) - EMA(slow), its EMA(signal) and the difference
+//
+// DRIFT: rolling sums are recomputed from their window every `period`
+// points, so rounding error can't accumulate over millions of updates.
+//
+// =============================================================================
+
+use std::collections::VecDeque;
+
+/// Default periods used by `IndicatorSet::new()`.
+pub const DEFAULT_PERIOD: usize = 20;
+pub const DEFAULT_RSI_PERIOD: usize = 14;
+pub const DEFAULT_BOLLINGER_K: f64 = 2.0;
+/// MACD fast, slow and signal periods.
+pub const DEFAULT_MACD: (usize, usize, usize) = (12, 26, 9);
+
+/// The last `period` points, with a running sum.
+#[derive(Debug, Clone)]
+struct Window {
+    points: VecDeque<f64>,
+    period: usize,
+    sum: f64,
+    /// Updates until the sum is recomputed.
+    until_resync: usize,
+}
+
+impl Window {
+    fn new(period: usize) -> Self {
+        let period = period.max(1);
+        Self {
+            points: VecDeque::with_capacity(period),
+            period,
+            sum: 0.0,
+            until_resync: period,
+        }
+    }
+
+    /// Add a point; returns the one that fell out of the window.
+    fn push(&mut self, x: f64) -> Option<f64> {
+        let evicted = if self.points.len() == self.period {
+            self.points.pop_front()
+        } else {
+            None
+        };
+        self.points.push_back(x);
+        self.sum += x - evicted.unwrap_or(0.0);
+        self.until_resync -= 1;
+        if self.until_resync == 0 {
+            self.sum = self.points.iter().sum();
+            self.until_resync = self.period;
+        }
+        evicted
+    }
+
+    fn is_full(&self) -> bool {
+        self.points.len() == self.period
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.points.len() as f64
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new(self.period);
+    }
+}
+
+/// Exponential moving average with `alpha = 2 / (period + 1)`.
+#[derive(Debug, Clone)]
+pub struct Ema {
+    alpha: f64,
+    period: usize,
+    /// Sum of the first `period` points, until the seed is complete.
+    seed_sum: f64,
+    seen: usize,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(period: usize) -> Self {
+        let period = period.max(1);
+        Self {
+            alpha: 2.0 / (period as f64 + 1.0),
+            period,
+            seed_sum: 0.0,
+            seen: 0,
+            value: None,
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> Option<f64> {
+        match self.value {
+            Some(value) => self.value = Some(value + self.alpha * (x - value)),
+            None => {
+                self.seed_sum += x;
+                self.seen += 1;
+                if self.seen == self.period {
+                    self.value = Some(self.seed_sum / self.period as f64);
+                }
+            }
+        }
+        self.value
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.period);
+    }
+}
+
+/// Simple moving average.
+#[derive(Debug, Clone)]
+pub struct Sma {
+    window: Window,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Self {
+        Self {
+            window: Window::new(period),
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> Option<f64> {
+        self.window.push(x);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.window.is_full().then(|| self.window.mean())
+    }
+
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Rolling population standard deviation, by Welford's method over a
+/// sliding window (no `sum of squares - square of sum` cancellation at
+/// price-sized values).
+#[derive(Debug, Clone)]
+pub struct StdDev {
+    window: Window,
+    mean: f64,
+    /// Sum of squared deviations from `mean`.
+    m2: f64,
+}
+
+impl StdDev {
+    pub fn new(period: usize) -> Self {
+        Self {
+            window: Window::new(period),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> Option<f64> {
+        let resync = self.window.until_resync == 1;
+        match self.window.push(x) {
+            Some(old) => {
+                let mean = self.mean + (x - old) / self.window.period as f64;
+                self.m2 += (x - old) * (x - mean + old - self.mean);
+                self.mean = mean;
+            }
+            None => {
+                let n = self.window.points.len() as f64;
+                let mean = self.mean + (x - self.mean) / n;
+                self.m2 += (x - self.mean) * (x - mean);
+                self.mean = mean;
+            }
+        }
+        if resync {
+            self.mean = self.window.mean();
+            self.m2 = self
+                .window
+                .points
+                .iter()
+                .map(|p| (p - self.mean).powi(2))
+                .sum();
+        }
+        self.value()
+    }
+
+    /// Mean of the window, once full.
+    pub fn mean(&self) -> Option<f64> {
+        self.window.is_full().then_some(self.mean)
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        let variance = self.m2.max(0.0) / self.window.period as f64;
+        self.window.is_full().then(|| variance.sqrt())
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.window.period);
+    }
+}
+
+/// Bollinger bands: `middle ± k * stddev` over `period`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bands {
+    pub upper: f64,
+    pub middle: f64,
+    pub lower: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bollinger {
+    stddev: StdDev,
+    k: f64,
+}
+
+impl Bollinger {
+    pub fn new(period: usize, k: f64) -> Self {
+        Self {
+            stddev: StdDev::new(period),
+            k,
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> Option<Bands> {
+        self.stddev.update(x);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<Bands> {
+        let (middle, stddev) = (self.stddev.mean()?, self.stddev.value()?);
+        Some(Bands {
+            upper: middle + self.k * stddev,
+            middle,
+            lower: middle - self.k * stddev,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.stddev.reset();
+    }
+}
+
+/// Lowest and highest of the last `period` points. Available from the first
+/// point (over what has been seen so far).
+#[derive(Debug, Clone)]
+pub struct MinMax {
+    period: usize,
+    /// Index of the next point.
+    index: usize,
+    /// Candidates for the minimum: increasing values, oldest first.
+    mins: VecDeque<(usize, f64)>,
+    /// Candidates for the maximum: decreasing values, oldest first.
+    maxs: VecDeque<(usize, f64)>,
+}
+
+impl MinMax {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period: period.max(1),
+            index: 0,
+            mins: VecDeque::new(),
+            maxs: VecDeque::new(),
+        }
+    }
+
+    /// Returns `(min, max)`.
+    pub fn update(&mut self, x: f64) -> Option<(f64, f64)> {
+        while self.mins.back().is_some_and(|&(_, v)| v >= x) {
+            self.mins.pop_back();
+        }
+        while self.maxs.back().is_some_and(|&(_, v)| v <= x) {
+            self.maxs.pop_back();
+        }
+        self.mins.push_back((self.index, x));
+        self.maxs.push_back((self.index, x));
+        self.index += 1;
+        // Drop candidates that left the window
+        let oldest = self.index.saturating_sub(self.period);
+        while self.mins.front().is_some_and(|&(i, _)| i < oldest) {
+            self.mins.pop_front();
+        }
+        while self.maxs.front().is_some_and(|&(i, _)| i < oldest) {
+            self.maxs.pop_front();
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<(f64, f64)> {
+        Some((self.mins.front()?.1, self.maxs.front()?.1))
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.period);
+    }
+}
+
+/// Volume-weighted average price. Cumulative: call `reset()` at the start
+/// of each session.
+#[derive(Debug, Clone, Default)]
+pub struct Vwap {
+    price_volume: f64,
+    volume: f64,
+}
+
+impl Vwap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold in a trade. Non-positive volumes are ignored.
+    pub fn update(&mut self, price: f64, volume: f64) -> Option<f64> {
+        if volume > 0.0 {
+            self.price_volume += price * volume;
+            self.volume += volume;
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        (self.volume > 0.0).then(|| self.price_volume / self.volume)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Relative strength index with Wilder's smoothing.
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    period: usize,
+    previous: Option<f64>,
+    /// Changes seen while seeding the averages.
+    seen: usize,
+    avg_gain: f64,
+    avg_loss: f64,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period: period.max(1),
+            previous: None,
+            seen: 0,
+            avg_gain: 0.0,
+            avg_loss: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> Option<f64> {
+        // The first point has no change to measure
+        let previous = self.previous.replace(x)?;
+        let change = x - previous;
+        let (gain, loss) = (change.max(0.0), (-change).max(0.0));
+        let period = self.period as f64;
+        if self.seen < self.period {
+            // Seed with the plain average of the first `period` changes
+            self.seen += 1;
+            self.avg_gain += gain / period;
+            self.avg_loss += loss / period;
+        } else {
+            self.avg_gain = (self.avg_gain * (period - 1.0) + gain) / period;
+            self.avg_loss = (self.avg_loss * (period - 1.0) + loss) / period;
+        }
+        self.value()
+    }
+
+    /// 100 with no losses in the period, 50 when the price didn't move.
+    pub fn value(&self) -> Option<f64> {
+        if self.seen < self.period {
+            return None;
+        }
+        Some(match (self.avg_gain > 0.0, self.avg_loss > 0.0) {
+            (_, true) => 100.0 - 100.0 / (1.0 + self.avg_gain / self.avg_loss),
+            (true, false) => 100.0,
+            (false, false) => 50.0,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.period);
+    }
+}
+
+/// MACD line, signal line and histogram.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacdValue {
+    /// EMA(fast) - EMA(slow).
+    pub macd: f64,
+    /// EMA(signal) of the MACD line.
+    pub signal: f64,
+    /// `macd - signal`.
+    pub histogram: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Macd {
+    fast: Ema,
+    slow: Ema,
+    signal: Ema,
+    macd: Option<f64>,
+}
+
+impl Macd {
+    pub fn new(fast: usize, slow: usize, signal: usize) -> Self {
+        Self {
+            fast: Ema::new(fast),
+            slow: Ema::new(slow),
+            signal: Ema::new(signal),
+            macd: None,
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> Option<MacdValue> {
+        let fast = self.fast.update(x);
+        let slow = self.slow.update(x);
+        if let (Some(fast), Some(slow)) = (fast, slow) {
+            self.macd = Some(fast - slow);
+            self.signal.update(fast - slow);
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<MacdValue> {
+        let (macd, signal) = (self.macd?, self.signal.value()?);
+        Some(MacdValue {
+            macd,
+            signal,
+            histogram: macd - signal,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        for ema in [&mut self.fast, &mut self.slow, &mut self.signal] {
+            ema.reset();
+        }
+        self.macd = None;
+    }
+}
+
+/// Every indicator's current value, NaN while it is warming up — the shape
+/// the engine copies into the frame's `Indicators` struct (schema/frame.fbs).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndicatorValues {
+    pub ema: f64,
+    pub sma: f64,
+    pub vwap: f64,
+    pub bollinger_upper: f64,
+    pub bollinger_middle: f64,
+    pub bollinger_lower: f64,
+    pub rsi: f64,
+    pub macd: f64,
+    pub macd_signal: f64,
+    pub macd_histogram: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// One of each indicator over the same stream. Fields are public: replace
+/// one to change its period, e.g. `set.ema = Ema::new(50)`.
+#[derive(Debug, Clone)]
+pub struct IndicatorSet {
+    pub ema: Ema,
+    pub sma: Sma,
+    pub vwap: Vwap,
+    pub bollinger: Bollinger,
+    pub rsi: Rsi,
+    pub macd: Macd,
+    pub stddev: StdDev,
+    pub min_max: MinMax,
+}
+
+impl Default for IndicatorSet {
+    fn default() -> Self {
+        let (fast, slow, signal) = DEFAULT_MACD;
+        Self {
+            ema: Ema::new(DEFAULT_PERIOD),
+            sma: Sma::new(DEFAULT_PERIOD),
+            vwap: Vwap::new(),
+            bollinger: Bollinger::new(DEFAULT_PERIOD, DEFAULT_BOLLINGER_K),
+            rsi: Rsi::new(DEFAULT_RSI_PERIOD),
+            macd: Macd::new(fast, slow, signal),
+            stddev: StdDev::new(DEFAULT_PERIOD),
+            min_max: MinMax::new(DEFAULT_PERIOD),
+        }
+    }
+}
+
+impl IndicatorSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold in one point. `volume` feeds VWAP only; pass 0 for series
+    /// without volume. Non-finite prices are ignored.
+    pub fn update(&mut self, price: f64, volume: f64) {
+        if !price.is_finite() {
+            return;
+        }
+        self.ema.update(price);
+        self.sma.update(price);
+        if volume.is_finite() {
+            self.vwap.update(price, volume);
+        }
+        self.bollinger.update(price);
+        self.rsi.update(price);
+        self.macd.update(price);
+        self.stddev.update(price);
+        self.min_max.update(price);
+    }
+
+    pub fn values(&self) -> IndicatorValues {
+        let bands = self.bollinger.value();
+        let macd = self.macd.value();
+        let min_max = self.min_max.value();
+        IndicatorValues {
+            ema: self.ema.value().unwrap_or(f64::NAN),
+            sma: self.sma.value().unwrap_or(f64::NAN),
+            vwap: self.vwap.value().unwrap_or(f64::NAN),
+            bollinger_upper: bands.map_or(f64::NAN, |b| b.upper),
+            bollinger_middle: bands.map_or(f64::NAN, |b| b.middle),
+            bollinger_lower: bands.map_or(f64::NAN, |b| b.lower),
+            rsi: self.rsi.value().unwrap_or(f64::NAN),
+            macd: macd.map_or(f64::NAN, |m| m.macd),
+            macd_signal: macd.map_or(f64::NAN, |m| m.signal),
+            macd_histogram: macd.map_or(f64::NAN, |m| m.histogram),
+            stddev: self.stddev.value().unwrap_or(f64::NAN),
+            min: min_max.map_or(f64::NAN, |(min, _)| min),
+            max: min_max.map_or(f64::NAN, |(_, max)| max),
+        }
+    }
+
+    /// Forget every point (e.g. on a symbol change).
+    pub fn reset(&mut self) {
+        self.ema.reset();
+        self.sma.reset();
+        self.vwap.reset();
+        self.bollinger.reset();
+        self.rsi.reset();
+        self.macd.reset();
+        self.stddev.reset();
+        self.min_max.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    /// Reference standard deviation of a slice.
+    fn stddev(points: &[f64]) -> f64 {
+        let mean = points.iter().sum::<f64>() / points.len() as f64;
+        (points.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / points.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_sma_and_ema() {
+        let mut sma = Sma::new(3);
+        let mut ema = Ema::new(3);
+        assert_eq!(sma.update(1.0), None);
+        assert_eq!(ema.update(1.0), None);
+        sma.update(2.0);
+        ema.update(2.0);
+        assert_eq!(sma.update(3.0), Some(2.0));
+        // Seeded with the SMA, then alpha = 0.5
+        assert_eq!(ema.update(3.0), Some(2.0));
+        assert_eq!(sma.update(10.0), Some(5.0));
+        assert_eq!(ema.update(10.0), Some(6.0));
+    }
+
+    #[test]
+    fn test_stddev_matches_reference_at_price_scale() {
+        let points: Vec<f64> = (0..10_000)
+            .map(|i| 64_000.0 + ((i * 7919) % 101) as f64 * 0.01)
+            .collect();
+        let mut sd = StdDev::new(50);
+        for (i, &p) in points.iter().enumerate() {
+            let value = sd.update(p);
+            if i >= 49 {
+                let expected = stddev(&points[i - 49..=i]);
+                assert!(
+                    (value.unwrap() - expected).abs() < 1e-6,
+                    "{i}: {value:?} vs {expected}"
+                );
+            }
+        }
+        let mut bb = Bollinger::new(2, 2.0);
+        bb.update(1.0);
+        assert_eq!(
+            bb.update(3.0),
+            Some(Bands {
+                upper: 4.0,
+                middle: 2.0,
+                lower: 0.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_min_max_window() {
+        let mut mm = MinMax::new(3);
+        let out: Vec<_> = [5.0, 1.0, 4.0, 3.0, 2.0, 6.0]
+            .iter()
+            .map(|&x| mm.update(x).unwrap())
+            .collect();
+        assert_eq!(
+            out,
+            vec![
+                (5.0, 5.0),
+                (1.0, 5.0),
+                (1.0, 5.0),
+                (1.0, 4.0),
+                (2.0, 4.0),
+                (2.0, 6.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vwap() {
+        let mut vwap = Vwap::new();
+        assert_eq!(vwap.update(100.0, 0.0), None);
+        vwap.update(100.0, 1.0);
+        assert_eq!(vwap.update(110.0, 3.0), Some(107.5));
+        vwap.reset();
+        assert_eq!(vwap.value(), None);
+    }
+
+    #[test]
+    fn test_rsi() {
+        let mut rsi = Rsi::new(2);
+        assert_eq!(rsi.update(10.0), None);
+        assert_eq!(rsi.update(11.0), None);
+        // Average gain 0.5, average loss 0.5
+        assert_eq!(rsi.update(10.0), Some(50.0));
+        // Wilder: gain (0.5 + 2) / 2, loss 0.5 / 2
+        assert!(close(
+            rsi.update(12.0).unwrap(),
+            100.0 - 100.0 / (1.0 + 1.25 / 0.25)
+        ));
+        let mut flat = Rsi::new(2);
+        for _ in 0..4 {
+            flat.update(1.0);
+        }
+        assert_eq!(flat.value(), Some(50.0));
+    }
+
+    #[test]
+    fn test_macd_and_set() {
+        let mut macd = Macd::new(2, 3, 2);
+        let out: Vec<_> = [1.0, 2.0, 3.0, 4.0]
+            .iter()
+            .map(|&x| macd.update(x))
+            .collect();
+        assert_eq!(out[..3], [None, None, None]);
+        let m = out[3].unwrap();
+        assert!(close(m.histogram, m.macd - m.signal));
+
+        let mut set = IndicatorSet::new();
+        assert!(set.values().ema.is_nan());
+        for i in 0..40 {
+            set.update(100.0 + i as f64, 1.0);
+        }
+        set.update(f64::NAN, 1.0);
+        let values = set.values();
+        assert!(close(values.sma, 129.5));
+        assert_eq!((values.min, values.max), (120.0, 139.0));
+        assert_eq!(values.rsi, 100.0);
+        assert!(!values.macd_histogram.is_nan());
+        set.reset();
+        assert!(set.values().vwap.is_nan());
+    }
+}
@@ -43,6 +43,41 @@
 //! my-shared = { path = "../shared" }
 //! ```
 
+// ============================================
+// Modules
+// ============================================
+
+/// LZ4 frame envelope: encoded by the server, decoded in the client's
+/// `ingest_frame()`. Copy shared/compression-template.rs to
+/// src/compression.rs.
+pub mod compression;
+
+/// Fixed-point decimals for prices and sizes, exact on both sides. Copy
+/// shared/decimal-template.rs to src/decimal.rs.
+pub mod decimal;
+
+/// Per-field change masks, so tick() can skip unchanged frames and JS
+/// unchanged DOM nodes. Copy shared/delta-template.rs to src/delta.rs.
+pub mod delta;
+
+/// Streaming technical indicators (EMA, SMA, VWAP, Bollinger, RSI, MACD,
+/// ...), O(1) per point. Copy shared/indicators-template.rs to
+/// src/indicators.rs.
+pub mod indicators;
+
+/// L2 orderbook with sequenced snapshot/update ingestion, used by the
+/// server's OrderbookEngine and the client alike. Copy
+/// shared/orderbook-template.rs to src/orderbook.rs.
+pub mod orderbook;
+
+/// Compile-time hashes of schema/*.fbs and the connect handshake that
+/// compares them. Copy shared/schema-template.rs to src/schema.rs.
+pub mod schema;
+
+/// Ring-buffered chart series with min/max and LTTB downsampling, for the
+/// WASM engine. Copy shared/timeseries-template.rs to src/timeseries.rs.
+pub mod timeseries;
+
 // ============================================
 // Constants
 //
@@ -62,6 +97,12 @@ pub const HISTORY_WINDOW_SEC: f64 = 30.0;
 /// Tolerance for floating-point comparisons.
 pub const EPSILON: f64 = 1e-10;
 
+/// Longest symbol a client may subscribe to.
+pub const MAX_SYMBOL_LEN: usize = 32;
+
+/// Most orderbook levels a client may request per side.
+pub const MAX_BOOK_DEPTH: u16 = 100;
+
 // ============================================
 // Domain Types
 //
@@ -107,6 +148,16 @@ pub fn validate_identifier(s: &str, max_len: usize) -> bool {
             .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'/' || b == b'.')
 }
 
+/// Validate a trading symbol (e.g. "BTC-USD") from a client command.
+pub fn validate_symbol(symbol: &str) -> bool {
+    validate_identifier(symbol, MAX_SYMBOL_LEN)
+}
+
+/// Validate a requested orderbook depth: 1..=MAX_BOOK_DEPTH levels.
+pub fn validate_depth(depth: u16) -> bool {
+    (1..=MAX_BOOK_DEPTH).contains(&depth)
+}
+
 // ============================================
 // Computation Helpers
 //
@@ -127,6 +178,43 @@ pub fn lerp(a: f64, b: f64, t: f64) -> f64 {
     a + (b - a) * t.clamp(0.0, 1.0)
 }
 
+/// Id of a topic in `Envelope.topic_id` (schema/envelope.fbs): 32-bit
+/// FNV-1a of the topic name. The server stamps it on every frame and
+/// snapshot; the client computes it from the symbol it subscribed to
+/// (`topicId()` in the TS controller is the same function). Never 0, which
+/// marks messages without a topic.
+pub fn topic_id(topic: &str) -> u32 {
+    let hash = topic
+        .bytes()
+        .fold(0x811c_9dc5_u32, |hash, b| (hash ^ u32::from(b)).wrapping_mul(0x0100_0193));
+    hash.max(1)
+}
+
+/// Topic of a symbol's book at one depth, e.g. "BTC-USD@20". Clients that
+/// subscribe to a symbol at different depths get different topics, each
+/// served by an engine publishing exactly that many levels per side.
+/// Depth 0 means no depth: the topic is the bare symbol, for engines
+/// without levels (candles). Symbols can't contain '@' (`validate_symbol`),
+/// so this is unambiguous.
+pub fn depth_topic(symbol: &str, depth: u16) -> String {
+    match depth {
+        0 => symbol.to_string(),
+        depth => format!("{symbol}@{depth}"),
+    }
+}
+
+/// Split a topic into its symbol and depth: "BTC-USD@20" is
+/// `("BTC-USD", Some(20))`, a topic without a depth is `(topic, None)`.
+pub fn parse_topic(topic: &str) -> (&str, Option<u16>) {
+    match topic.rsplit_once('@') {
+        Some((symbol, depth)) => match depth.parse() {
+            Ok(depth) => (symbol, Some(depth)),
+            Err(_) => (topic, None),
+        },
+        None => (topic, None),
+    }
+}
+
 // ============================================
 // Tests
 // ============================================
@@ -154,6 +242,23 @@ mod tests {
         assert!(!validate_identifier("has spaces", 32));
     }
 
+    #[test]
+    fn test_validate_symbol() {
+        assert!(validate_symbol("BTC-USD"));
+        assert!(validate_symbol("ETH/USDT"));
+        assert!(!validate_symbol(""));
+        assert!(!validate_symbol("BTC USD"));
+        assert!(!validate_symbol(&"X".repeat(MAX_SYMBOL_LEN + 1)));
+    }
+
+    #[test]
+    fn test_validate_depth() {
+        assert!(validate_depth(1));
+        assert!(validate_depth(MAX_BOOK_DEPTH));
+        assert!(!validate_depth(0));
+        assert!(!validate_depth(MAX_BOOK_DEPTH + 1));
+    }
+
     #[test]
     fn test_normalize() {
         assert!((normalize(50.0, 0.0, 100.0) - 0.5).abs() < EPSILON);
@@ -167,4 +272,26 @@ mod tests {
         assert!((lerp(0.0, 100.0, 0.0) - 0.0).abs() < EPSILON);
         assert!((lerp(0.0, 100.0, 1.0) - 100.0).abs() < EPSILON);
     }
+
+    #[test]
+    fn test_topic_id() {
+        // FNV-1a reference values
+        assert_eq!(topic_id(""), 0x811c_9dc5);
+        assert_eq!(topic_id("a"), 0xe40c_292c);
+        // Pinned in controller/__tests__/EnvelopeRouter.test.ts as well
+        assert_eq!(topic_id("BTC-USD"), 0x76ed_0931);
+        assert_eq!(topic_id("BTC-USD"), topic_id("BTC-USD"));
+        assert_ne!(topic_id("BTC-USD"), topic_id("ETH-USD"));
+    }
+
+    #[test]
+    fn test_depth_topic() {
+        assert_eq!(depth_topic("BTC-USD", 20), "BTC-USD@20");
+        assert_eq!(depth_topic("BTC-USD", 0), "BTC-USD");
+        assert_eq!(parse_topic(&depth_topic("BTC-USD", 0)), ("BTC-USD", None));
+        assert_eq!(parse_topic("BTC-USD@20"), ("BTC-USD", Some(20)));
+        assert_eq!(parse_topic("ETH/USDT"), ("ETH/USDT", None));
+        assert_eq!(parse_topic("BTC-USD@x"), ("BTC-USD@x", None));
+        assert!(!validate_symbol("BTC-USD@20"));
+    }
 }
@@ -0,0 +1,605 @@
+// =============================================================================
+// L2 Orderbook — price levels maintained the same way by server and client
+// =============================================================================
+//
+// An aggregated book: one size per price on each side, kept sorted in a
+// BTreeMap so an update is O(log n) and the best levels are read in order
+// without sorting. The server's OrderbookEngine (server/orderbook-engine.rs)
+// builds it from exchange snapshots and updates; the WASM client builds the
+// same book from the server's OrderbookFrames and reads the top `depth`
+// levels it subscribed to.
+//
+// Copy to `src/orderbook.rs` in your shared crate (lib.rs declares
+// `pub mod orderbook;`).
+//
+// SEQUENCING:
+//
+//   apply_snapshot(sequence, bids, asks)  replace the book, synced at sequence
+//   apply_update(update)                  patch it (size 0 = remove level):
+//
+//     update.sequence <= sequence          stale, ignored      Ok(false)
+//     update.first_sequence > sequence + 1 gap                 Err(Gap)
+//     otherwise                            applied             Ok(true)
+//
+//   A feed with one sequence number per message uses BookUpdate::new()
+//   (first_sequence = sequence); feeds that number every change in a
+//   message give the range.
+//
+//   Updates that arrive before the first snapshot, or after an error, are
+//   buffered and replayed on top of the next snapshot — the usual
+//   "subscribe to the stream, then fetch a snapshot" startup, and the same
+//   recovery after a gap.
+//
+// EXACT PRICES: f64 prices from different sources (an exchange feed, a
+// float computation) can differ in the last bit and land on two levels.
+// With `with_instrument()`, every price is snapped to the instrument's tick
+// grid (my_shared::decimal), so the server and client books key the same
+// tick identically.
+//
+// CROSSED BOOKS: a best bid at or above the best ask means an update was
+// missed. The update is applied and reported as Err(Crossed); like any
+// error, the book then needs a snapshot (`needs_snapshot()`).
+//
+// CLIENT USAGE (WASM engine, from an OrderbookFrame):
+//
+//   let levels = |v: Vector<PriceLevel>| {
+//       v.iter().map(|l| Level::new(l.price(), l.size_())).collect::<Vec<_>>()
+//   };
+//   let result = if frame.is_delta() {
+//       self.book.apply_update(BookUpdate::new(frame.sequence(), levels(frame.bids()), levels(frame.asks())))
+//   } else {
+//       self.book.apply_snapshot(frame.sequence(), &levels(frame.bids()), &levels(frame.asks()))
+//   };
+//   // Err: send RequestSnapshot; deltas are buffered until it arrives
+//   let bids: Vec<Level> = self.book.bids().take(self.depth).collect();
+//
+// =============================================================================
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use crate::decimal::Instrument;
+use crate::{validate_non_negative, validate_positive};
+
+/// Updates kept while waiting for a snapshot; the oldest are dropped first.
+pub const MAX_BUFFERED_UPDATES: usize = 1024;
+
+/// One price level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    pub size: f64,
+}
+
+impl Level {
+    pub fn new(price: f64, size: f64) -> Self {
+        Self { price, size }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// Changed levels, in the order an exchange (or the server) sent them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookUpdate {
+    /// Sequence of the first change in this update.
+    pub first_sequence: u64,
+    /// Sequence of the last change; the book's sequence afterwards.
+    pub sequence: u64,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl BookUpdate {
+    /// An update carrying a single sequence number.
+    pub fn new(sequence: u64, bids: Vec<Level>, asks: Vec<Level>) -> Self {
+        Self {
+            first_sequence: sequence,
+            sequence,
+            bids,
+            asks,
+        }
+    }
+}
+
+/// Why an update or snapshot left the book needing a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookError {
+    /// Updates between `expected` and `got` were missed.
+    Gap { expected: u64, got: u64 },
+    /// The best bid is at or above the best ask.
+    Crossed { bid: f64, ask: f64 },
+    /// A non-positive price or a negative size.
+    InvalidLevel { price: f64, size: f64 },
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookError::Gap { expected, got } => {
+                write!(f, "sequence gap: expected {expected}, got {got}")
+            }
+            BookError::Crossed { bid, ask } => write!(f, "crossed book: bid {bid} >= ask {ask}"),
+            BookError::InvalidLevel { price, size } => write!(f, "invalid level: {size} @ {price}"),
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+/// Top-of-book figures over the best `depth` levels, as carried in
+/// `OrderbookFrame`. Prices are 0 while a side is empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BookStats {
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub mid_price: f64,
+    pub spread: f64,
+    pub bid_total_size: f64,
+    pub ask_total_size: f64,
+    /// (bid_total - ask_total) / (bid_total + ask_total), in -1..=1.
+    pub imbalance: f64,
+}
+
+/// BTreeMap key: prices are validated finite, so `total_cmp` orders them
+/// like numbers.
+#[derive(Debug, Clone, Copy)]
+struct Price(f64);
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// An L2 book with sequence tracking.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBook {
+    bids: BTreeMap<Price, f64>,
+    asks: BTreeMap<Price, f64>,
+    sequence: u64,
+    synced: bool,
+    buffered: VecDeque<BookUpdate>,
+    /// Snaps prices to a tick grid when set.
+    instrument: Option<Instrument>,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snap every price to the nearest tick of `instrument` on the way in.
+    pub fn with_instrument(mut self, instrument: Instrument) -> Self {
+        self.instrument = Some(instrument);
+        self
+    }
+
+    /// Sequence of the last snapshot or update applied.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// No snapshot yet, or an error since the last one. Updates are
+    /// buffered until the next snapshot.
+    pub fn needs_snapshot(&self) -> bool {
+        !self.synced
+    }
+
+    /// Replace the book, then replay the buffered updates that follow it.
+    pub fn apply_snapshot(
+        &mut self,
+        sequence: u64,
+        bids: &[Level],
+        asks: &[Level],
+    ) -> Result<(), BookError> {
+        self.synced = false;
+        validate(bids.iter().chain(asks))?;
+        self.bids = bids
+            .iter()
+            .filter(|l| l.size > 0.0)
+            .map(|l| (self.key(l.price), l.size))
+            .collect();
+        self.asks = asks
+            .iter()
+            .filter(|l| l.size > 0.0)
+            .map(|l| (self.key(l.price), l.size))
+            .collect();
+        self.sequence = sequence;
+        self.check_crossed()?;
+        self.synced = true;
+
+        let mut pending = std::mem::take(&mut self.buffered);
+        while let Some(update) = pending.pop_front() {
+            if let Err(e) = self.apply_update(update) {
+                self.buffered.extend(pending);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply an update in sequence. Returns true if it changed the book.
+    pub fn apply_update(&mut self, update: BookUpdate) -> Result<bool, BookError> {
+        if !self.synced {
+            self.buffer(update);
+            return Ok(false);
+        }
+        if update.sequence <= self.sequence {
+            return Ok(false);
+        }
+        if update.first_sequence > self.sequence + 1 {
+            let error = BookError::Gap {
+                expected: self.sequence + 1,
+                got: update.first_sequence,
+            };
+            self.synced = false;
+            self.buffer(update);
+            return Err(error);
+        }
+        if let Err(e) = validate(update.bids.iter().chain(&update.asks)) {
+            self.synced = false;
+            return Err(e);
+        }
+
+        for level in &update.bids {
+            let price = self.key(level.price);
+            set_level(&mut self.bids, price, level.size);
+        }
+        for level in &update.asks {
+            let price = self.key(level.price);
+            set_level(&mut self.asks, price, level.size);
+        }
+        self.sequence = update.sequence;
+        if let Err(e) = self.check_crossed() {
+            self.synced = false;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Bids, best (highest) first.
+    pub fn bids(&self) -> impl Iterator<Item = Level> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .map(|(price, &size)| Level::new(price.0, size))
+    }
+
+    /// Asks, best (lowest) first.
+    pub fn asks(&self) -> impl Iterator<Item = Level> + '_ {
+        self.asks
+            .iter()
+            .map(|(price, &size)| Level::new(price.0, size))
+    }
+
+    pub fn best_bid(&self) -> Option<Level> {
+        self.bids().next()
+    }
+
+    pub fn best_ask(&self) -> Option<Level> {
+        self.asks().next()
+    }
+
+    /// Number of price levels on one side.
+    pub fn levels(&self, side: Side) -> usize {
+        match side {
+            Side::Bid => self.bids.len(),
+            Side::Ask => self.asks.len(),
+        }
+    }
+
+    /// Best prices, and totals over the best `depth` levels of each side.
+    pub fn stats(&self, depth: usize) -> BookStats {
+        let bid_total_size: f64 = self.bids().take(depth).map(|l| l.size).sum();
+        let ask_total_size: f64 = self.asks().take(depth).map(|l| l.size).sum();
+        let total = bid_total_size + ask_total_size;
+        let mut stats = BookStats {
+            bid_total_size,
+            ask_total_size,
+            imbalance: if total > 0.0 {
+                (bid_total_size - ask_total_size) / total
+            } else {
+                0.0
+            },
+            ..BookStats::default()
+        };
+        if let Some(bid) = self.best_bid() {
+            stats.best_bid = bid.price;
+        }
+        if let Some(ask) = self.best_ask() {
+            stats.best_ask = ask.price;
+        }
+        if stats.best_bid > 0.0 && stats.best_ask > 0.0 {
+            stats.mid_price = (stats.best_bid + stats.best_ask) / 2.0;
+            stats.spread = stats.best_ask - stats.best_bid;
+        }
+        stats
+    }
+
+    /// Remove every level and forget the sequence; the book needs a
+    /// snapshot.
+    pub fn clear(&mut self) {
+        *self = Self {
+            instrument: self.instrument,
+            ..Self::default()
+        };
+    }
+
+    /// Map key of a validated price: the nearest tick if an instrument is
+    /// set.
+    fn key(&self, price: f64) -> Price {
+        let tick = self.instrument.and_then(|i| i.price_from_f64(price));
+        Price(tick.map_or(price, |tick| tick.to_f64()))
+    }
+
+    fn buffer(&mut self, update: BookUpdate) {
+        if self.buffered.len() >= MAX_BUFFERED_UPDATES {
+            self.buffered.pop_front();
+        }
+        self.buffered.push_back(update);
+    }
+
+    fn check_crossed(&self) -> Result<(), BookError> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) if bid.price >= ask.price => Err(BookError::Crossed {
+                bid: bid.price,
+                ask: ask.price,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn validate<'a>(mut levels: impl Iterator<Item = &'a Level>) -> Result<(), BookError> {
+    match levels.find(|l| !validate_positive(l.price) || !validate_non_negative(l.size)) {
+        Some(l) => Err(BookError::InvalidLevel {
+            price: l.price,
+            size: l.size,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn set_level(side: &mut BTreeMap<Price, f64>, price: Price, size: f64) {
+    if size > 0.0 {
+        side.insert(price, size);
+    } else {
+        side.remove(&price);
+    }
+}
+
+/// Append to `out` the levels that turn `previous` into `current`: new or
+/// resized levels, and removed ones with size 0. Both slices are one side's
+/// top levels, best first (e.g. `book.bids().take(depth)`), so a level that
+/// slides into or out of the window is included too. This is what a delta
+/// frame carries for a client holding `previous`.
+pub fn level_changes(previous: &[Level], current: &[Level], side: Side, out: &mut Vec<Level>) {
+    // Ordering of two prices by which comes first on this side
+    let order = |a: f64, b: f64| match side {
+        Side::Bid => b.total_cmp(&a),
+        Side::Ask => a.total_cmp(&b),
+    };
+    let (mut i, mut j) = (0, 0);
+    loop {
+        match (previous.get(i), current.get(j)) {
+            (Some(old), Some(new)) => match order(old.price, new.price) {
+                Ordering::Less => {
+                    out.push(Level::new(old.price, 0.0));
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    out.push(*new);
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    if old.size != new.size {
+                        out.push(*new);
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            },
+            (Some(old), None) => {
+                out.push(Level::new(old.price, 0.0));
+                i += 1;
+            }
+            (None, Some(new)) => {
+                out.push(*new);
+                j += 1;
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(pairs: &[(f64, f64)]) -> Vec<Level> {
+        pairs
+            .iter()
+            .map(|&(price, size)| Level::new(price, size))
+            .collect()
+    }
+
+    fn synced_book() -> OrderBook {
+        let mut book = OrderBook::new();
+        let bids = levels(&[(99.0, 1.0), (100.0, 2.0), (98.0, 3.0)]);
+        book.apply_snapshot(10, &bids, &levels(&[(101.0, 1.0), (102.0, 1.0)]))
+            .unwrap();
+        book
+    }
+
+    #[test]
+    fn test_snapshot_orders_levels_and_computes_stats() {
+        let book = synced_book();
+        assert!(!book.needs_snapshot());
+        assert_eq!(
+            book.bids().map(|l| l.price).collect::<Vec<_>>(),
+            vec![100.0, 99.0, 98.0]
+        );
+        assert_eq!(
+            book.asks().map(|l| l.price).collect::<Vec<_>>(),
+            vec![101.0, 102.0]
+        );
+
+        let stats = book.stats(2);
+        assert_eq!((stats.best_bid, stats.best_ask), (100.0, 101.0));
+        assert_eq!((stats.mid_price, stats.spread), (100.5, 1.0));
+        assert_eq!((stats.bid_total_size, stats.ask_total_size), (3.0, 2.0));
+        assert!((stats.imbalance - 0.2).abs() < crate::EPSILON);
+        assert_eq!(OrderBook::new().stats(10), BookStats::default());
+    }
+
+    #[test]
+    fn test_update_sequencing() {
+        let mut book = synced_book();
+        assert_eq!(
+            book.apply_update(BookUpdate::new(11, levels(&[(100.0, 0.0)]), vec![])),
+            Ok(true)
+        );
+        assert_eq!(book.best_bid(), Some(Level::new(99.0, 1.0)));
+        // Stale
+        assert_eq!(
+            book.apply_update(BookUpdate::new(11, levels(&[(99.5, 1.0)]), vec![])),
+            Ok(false)
+        );
+        // A range overlapping the current sequence applies
+        let update = BookUpdate {
+            first_sequence: 10,
+            sequence: 13,
+            bids: levels(&[(99.5, 1.0)]),
+            asks: vec![],
+        };
+        assert_eq!(book.apply_update(update), Ok(true));
+        assert_eq!(book.sequence(), 13);
+
+        assert_eq!(
+            book.apply_update(BookUpdate::new(15, levels(&[(97.0, 1.0)]), vec![])),
+            Err(BookError::Gap {
+                expected: 14,
+                got: 15
+            })
+        );
+        assert!(book.needs_snapshot());
+    }
+
+    #[test]
+    fn test_buffered_updates_replay_after_snapshot() {
+        let mut book = OrderBook::new();
+        for sequence in 9..=12 {
+            let update =
+                BookUpdate::new(sequence, levels(&[(90.0 + sequence as f64, 1.0)]), vec![]);
+            assert_eq!(book.apply_update(update), Ok(false));
+        }
+        book.apply_snapshot(10, &levels(&[(95.0, 1.0)]), &levels(&[(105.0, 1.0)]))
+            .unwrap();
+        assert_eq!(book.sequence(), 12);
+        assert_eq!(
+            book.bids().map(|l| l.price).collect::<Vec<_>>(),
+            vec![102.0, 101.0, 95.0]
+        );
+
+        // A snapshot that doesn't reach the buffered updates leaves a gap
+        let mut book = OrderBook::new();
+        book.apply_update(BookUpdate::new(20, vec![], vec![]))
+            .unwrap();
+        assert_eq!(
+            book.apply_snapshot(10, &[], &[]),
+            Err(BookError::Gap {
+                expected: 11,
+                got: 20
+            })
+        );
+        assert!(book.needs_snapshot());
+        book.apply_snapshot(19, &[], &[]).unwrap();
+        assert_eq!(book.sequence(), 20);
+    }
+
+    #[test]
+    fn test_crossed_and_invalid_levels() {
+        let mut book = synced_book();
+        assert_eq!(
+            book.apply_update(BookUpdate::new(11, levels(&[(101.0, 1.0)]), vec![])),
+            Err(BookError::Crossed {
+                bid: 101.0,
+                ask: 101.0
+            })
+        );
+        assert!(book.needs_snapshot());
+
+        let mut book = synced_book();
+        assert_eq!(
+            book.apply_update(BookUpdate::new(11, vec![], levels(&[(-1.0, 1.0)]))),
+            Err(BookError::InvalidLevel {
+                price: -1.0,
+                size: 1.0
+            })
+        );
+        assert_eq!(book.best_ask(), Some(Level::new(101.0, 1.0)));
+        assert!(book
+            .apply_snapshot(1, &levels(&[(100.0, f64::NAN)]), &[])
+            .is_err());
+        assert!(book
+            .apply_snapshot(1, &levels(&[(102.0, 1.0)]), &levels(&[(101.0, 1.0)]))
+            .is_err());
+        assert!(book.needs_snapshot());
+    }
+
+    #[test]
+    fn test_instrument_snaps_prices_to_ticks() {
+        let instrument =
+            crate::decimal::Instrument::new("0.01".parse().unwrap(), "0.001".parse().unwrap());
+        let mut book = OrderBook::new().with_instrument(instrument);
+        book.apply_snapshot(1, &levels(&[(0.1 + 0.2, 1.0)]), &[])
+            .unwrap();
+        // 0.30000000000000004 and 0.3 are the same tick
+        book.apply_update(BookUpdate::new(2, levels(&[(0.3, 2.0)]), vec![]))
+            .unwrap();
+        assert_eq!(book.bids().collect::<Vec<_>>(), levels(&[(0.3, 2.0)]));
+    }
+
+    #[test]
+    fn test_level_changes() {
+        let previous = levels(&[(100.0, 1.0), (99.0, 2.0), (98.0, 3.0)]);
+        // 100 removed, 99 resized, 97 slid into the window
+        let current = levels(&[(99.0, 5.0), (98.0, 3.0), (97.0, 1.0)]);
+        let mut out = Vec::new();
+        level_changes(&previous, &current, Side::Bid, &mut out);
+        assert_eq!(out, levels(&[(100.0, 0.0), (99.0, 5.0), (97.0, 1.0)]));
+
+        out.clear();
+        level_changes(
+            &levels(&[(101.0, 1.0)]),
+            &levels(&[(100.5, 1.0), (101.0, 1.0)]),
+            Side::Ask,
+            &mut out,
+        );
+        assert_eq!(out, levels(&[(100.5, 1.0)]));
+
+        out.clear();
+        level_changes(&current, &current, Side::Bid, &mut out);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+// =============================================================================
+// Schema Versions — which .fbs contracts each side was built from
+// =============================================================================
+//
+// Server and WASM client each compile their own FlatBuffers code from
+// schema/*.fbs. If the two builds come from different versions of a schema,
+// nothing fails: a reordered field is read from the wrong vtable slot and the
+// client renders the wrong number. This module gives every schema a hash,
+// computed at compile time from the .fbs file itself, so each build knows
+// exactly which contract it speaks:
+//
+//   - every Envelope carries the hash of its payload's schema in
+//     `schema_version` (the server stamps `ServerEngine::schema_version()`)
+//   - clients announce their hashes when connecting, and the server closes
+//     the connection with SCHEMA_MISMATCH_CLOSE_CODE if one differs
+//
+// Copy to `src/schema.rs` in your shared crate (lib.rs declares
+// `pub mod schema;`). The `include_str!` paths below assume the scaffold
+// layout: this file at crates/shared/src/schema.rs, schemas in schema/.
+//
+// HANDSHAKE (query parameter on the WebSocket URL):
+//
+//   wss://host/ws?schemas=commands:1a2b3c4d,envelope:5e6f7a8b,orderbook:9c0d1e2f
+//
+//   Names the server doesn't know, and schemas the client doesn't list, are
+//   ignored; a client that sends no `schemas` parameter is not checked.
+//
+// The hash covers the schema text with comments and whitespace removed, so
+// editing a comment keeps it and any change to a declaration changes it.
+// Whether a change is *compatible* is a separate question — see
+// tools/schema-diff, which compares two versions of a .fbs file.
+//
+// schema/frame.fbs isn't listed: it is produced and consumed inside one
+// build (WASM engine -> JS), so the two sides can't drift apart.
+//
+// A schema's hash doesn't cover the files it `include`s, so included
+// schemas (decimal.fbs) are listed on their own.
+//
+// =============================================================================
+
+use std::fmt;
+
+/// Hash of `candles.fbs` (trades and OHLCV bars).
+pub const CANDLES_SCHEMA_HASH: u32 = schema_hash(include_str!("../../../schema/candles.fbs"));
+
+/// Hash of `commands.fbs` (client -> server commands and their responses).
+pub const COMMANDS_SCHEMA_HASH: u32 = schema_hash(include_str!("../../../schema/commands.fbs"));
+
+/// Hash of `decimal.fbs` (exact decimal structs, included by other schemas).
+pub const DECIMAL_SCHEMA_HASH: u32 = schema_hash(include_str!("../../../schema/decimal.fbs"));
+
+/// Hash of `envelope.fbs` (the wrapper around every server message).
+pub const ENVELOPE_SCHEMA_HASH: u32 = schema_hash(include_str!("../../../schema/envelope.fbs"));
+
+/// Hash of `orderbook.fbs` (orderbook frames and snapshots).
+pub const ORDERBOOK_SCHEMA_HASH: u32 = schema_hash(include_str!("../../../schema/orderbook.fbs"));
+
+/// Schemas exchanged over the WebSocket, by name, as announced in the
+/// handshake. Add an entry for each schema you add.
+pub const SCHEMAS: &[(&str, u32)] = &[
+    ("candles", CANDLES_SCHEMA_HASH),
+    ("commands", COMMANDS_SCHEMA_HASH),
+    ("decimal", DECIMAL_SCHEMA_HASH),
+    ("envelope", ENVELOPE_SCHEMA_HASH),
+    ("orderbook", ORDERBOOK_SCHEMA_HASH),
+];
+
+/// WebSocket close code the server sends when a client's schemas don't
+/// match (4000-4999 is reserved for applications). Reconnecting won't help;
+/// the client needs a new build.
+pub const SCHEMA_MISMATCH_CLOSE_CODE: u16 = 4001;
+
+/// 32-bit FNV-1a over the text of a `.fbs` file, skipping `//` and `/* */`
+/// comments and all whitespace. `const`, so a crate can embed the hash of
+/// the schema it was compiled with.
+pub const fn schema_hash(fbs: &str) -> u32 {
+    let bytes = fbs.as_bytes();
+    let mut hash = 0x811c_9dc5_u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let next = if i + 1 < bytes.len() { bytes[i + 1] } else { 0 };
+        if b == b'/' && next == b'/' {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if b == b'/' && next == b'*' {
+            i += 2;
+            while i < bytes.len() && !(bytes[i] == b'*' && i + 1 < bytes.len() && bytes[i + 1] == b'/') {
+                i += 1;
+            }
+            i += 2;
+        } else {
+            if !b.is_ascii_whitespace() {
+                hash = (hash ^ b as u32).wrapping_mul(0x0100_0193);
+            }
+            i += 1;
+        }
+    }
+    hash
+}
+
+/// The `schemas` query parameter for `known`, e.g. `commands:1a2b3c4d,...`.
+/// The WASM client exports this so the TS side can build its URL.
+pub fn schemas_param(known: &[(&str, u32)]) -> String {
+    known
+        .iter()
+        .map(|(name, hash)| format!("{name}:{hash:08x}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Why a client's `schemas` parameter was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    /// The client was built from a different version of `name`.
+    Mismatch { name: String, expected: u32, offered: u32 },
+    /// An entry wasn't `name:hex`.
+    Malformed,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Mismatch { name, expected, offered } => {
+                write!(f, "schema mismatch: {name} is {expected:08x}, client has {offered:08x}")
+            }
+            SchemaError::Malformed => write!(f, "malformed schemas parameter"),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// Check a client's `schemas` parameter against the schemas this side was
+/// built from (usually `SCHEMAS`).
+pub fn check_schemas(offered: &str, known: &[(&str, u32)]) -> Result<(), SchemaError> {
+    for entry in offered.split(',').filter(|e| !e.is_empty()) {
+        let (name, hash) = entry.split_once(':').ok_or(SchemaError::Malformed)?;
+        let offered = u32::from_str_radix(hash, 16).map_err(|_| SchemaError::Malformed)?;
+        if let Some(&(_, expected)) = known.iter().find(|(known, _)| *known == name) {
+            if expected != offered {
+                return Err(SchemaError::Mismatch { name: name.to_string(), expected, offered });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_hash_ignores_comments_and_whitespace() {
+        let a = "table T {\n  a: double;\n  b: bool = false;\n}\n";
+        let b = "// T holds two fields\ntable T { a: double; /* price */ b: bool = false; }";
+        assert_eq!(schema_hash(a), schema_hash(b));
+        assert_eq!(schema_hash(""), 0x811c_9dc5);
+    }
+
+    #[test]
+    fn test_schema_hash_changes_with_declarations() {
+        let original = schema_hash("table T { a: double; b: double; }");
+        assert_ne!(original, schema_hash("table T { b: double; a: double; }"));
+        assert_ne!(original, schema_hash("table T { a: double; b: float; }"));
+        assert_ne!(original, schema_hash("table T { a: double; b: double = 1; }"));
+    }
+
+    #[test]
+    fn test_schemas_param_round_trip() {
+        let param = schemas_param(SCHEMAS);
+        assert_eq!(param.split(',').count(), SCHEMAS.len());
+        assert_eq!(check_schemas(&param, SCHEMAS), Ok(()));
+        assert_eq!(schemas_param(&[("a", 0x1f)]), "a:0000001f");
+    }
+
+    #[test]
+    fn test_check_schemas() {
+        let known = [("commands", 0x1234), ("orderbook", 0xabcd)];
+        assert_eq!(check_schemas("", &known), Ok(()));
+        // Unknown names and unlisted schemas are fine
+        assert_eq!(check_schemas("trades:00000001,orderbook:0000abcd", &known), Ok(()));
+        assert_eq!(
+            check_schemas("commands:00001234,orderbook:0000abce", &known),
+            Err(SchemaError::Mismatch { name: "orderbook".into(), expected: 0xabcd, offered: 0xabce })
+        );
+        assert_eq!(check_schemas("orderbook", &known), Err(SchemaError::Malformed));
+        assert_eq!(check_schemas("orderbook:xyz", &known), Err(SchemaError::Malformed));
+    }
+}
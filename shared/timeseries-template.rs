@@ -0,0 +1,420 @@
+// =============================================================================
+// Time Series — fixed-capacity ring buffers with chart downsampling
+// =============================================================================
+//
+// The WASM engine's chart data: one `Series` per line (price, volume, an
+// indicator), each keeping its newest `capacity` points. Pushing is O(1)
+// with no shifting, and memory never grows past the capacity.
+//
+// Copy to `src/timeseries.rs` in your shared crate (lib.rs declares
+// `pub mod timeseries;`).
+//
+// LAYOUT: a mirrored ring. Once full, every point is written twice, at slot
+// i and i + capacity, so the live window is always one contiguous slice of
+// the buffer:
+//
+//   slots:  [ 0  1  2  3 | 0' 1' 2' 3' ]     capacity 4, head = 2
+//                  └──────────┘
+//                  timestamps() = &buf[head..head + len]
+//
+//   JS reads it with a Float64Array view at `timestamps().as_ptr()` — no
+//   copy, whatever the size. Until the series first fills up it is a plain
+//   Vec (no second copy); after that the buffers never reallocate.
+//
+// ORDERING: timestamps must not decrease (equal is fine). An older point is
+// rejected by `push()`, which keeps binary search and downsampling valid.
+//
+// DOWNSAMPLING: a 1M-point series drawn into 1200 pixels needs ~2400 points.
+//
+//   downsample_minmax  min and max of each pixel column in a time range —
+//                      every spike survives; for line/area charts
+//   downsample_lttb    Largest-Triangle-Three-Buckets: `threshold` points
+//                      that keep the visual shape; for sparklines
+//
+//   Both write into caller-owned Vecs that are cleared and reused, so a
+//   redraw allocates nothing after the first.
+//
+// =============================================================================
+
+use std::ops::Range;
+
+/// One line of a chart: timestamps and values, oldest first.
+#[derive(Debug, Clone)]
+pub struct Series {
+    timestamps: Vec<f64>,
+    values: Vec<f64>,
+    /// Slot of the oldest point.
+    head: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl Series {
+    /// An empty series keeping the newest `capacity` points (at least 1).
+    pub fn new(capacity: usize) -> Self {
+        Self { timestamps: Vec::new(), values: Vec::new(), head: 0, len: 0, capacity: capacity.max(1) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a point, evicting the oldest when full. Returns false (and
+    /// drops the point) if `timestamp` is older than the newest point or
+    /// isn't a number.
+    pub fn push(&mut self, timestamp: f64, value: f64) -> bool {
+        if timestamp.is_nan() || self.last().is_some_and(|(last, _)| timestamp < last) {
+            return false;
+        }
+        let capacity = self.capacity;
+        if self.timestamps.len() < capacity {
+            // Still filling: the window runs to the end of the Vec
+            self.timestamps.push(timestamp);
+            self.values.push(value);
+            self.len += 1;
+            return true;
+        }
+        if self.timestamps.len() == capacity {
+            // First wrap: lay down the mirror half
+            self.timestamps.extend_from_within(..);
+            self.values.extend_from_within(..);
+        }
+        if self.len == capacity {
+            self.head = (self.head + 1) % capacity;
+            self.len -= 1;
+        }
+        let slot = (self.head + self.len) % capacity;
+        self.timestamps[slot] = timestamp;
+        self.timestamps[slot + capacity] = timestamp;
+        self.values[slot] = value;
+        self.values[slot + capacity] = value;
+        self.len += 1;
+        true
+    }
+
+    /// Timestamps, oldest first, as one contiguous slice.
+    pub fn timestamps(&self) -> &[f64] {
+        &self.timestamps[self.head..self.head + self.len]
+    }
+
+    /// Values, parallel to `timestamps()`.
+    pub fn values(&self) -> &[f64] {
+        &self.values[self.head..self.head + self.len]
+    }
+
+    /// The newest point.
+    pub fn last(&self) -> Option<(f64, f64)> {
+        if self.len == 0 {
+            return None;
+        }
+        let i = self.head + self.len - 1;
+        Some((self.timestamps[i], self.values[i]))
+    }
+
+    /// Indices of the points with `start <= timestamp <= end`.
+    pub fn range(&self, start: f64, end: f64) -> Range<usize> {
+        let timestamps = self.timestamps();
+        let from = timestamps.partition_point(|&t| t < start);
+        let to = timestamps.partition_point(|&t| t <= end);
+        from..to.max(from)
+    }
+
+    /// Drop points older than `cutoff`. Returns how many were dropped.
+    pub fn drop_before(&mut self, cutoff: f64) -> usize {
+        let count = self.timestamps().partition_point(|&t| t < cutoff);
+        self.head += count;
+        self.len -= count;
+        // Past the first half, the same window starts one capacity earlier
+        if self.head >= self.capacity {
+            self.head -= self.capacity;
+        }
+        count
+    }
+
+    /// Remove every point. Keeps the allocation.
+    pub fn clear(&mut self) {
+        self.timestamps.clear();
+        self.values.clear();
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+/// Several series, addressed by the index `add_series()` returned (a plain
+/// `usize`, so it crosses the WASM boundary as a number).
+#[derive(Debug, Clone, Default)]
+pub struct TimeSeriesStore {
+    series: Vec<Series>,
+}
+
+impl TimeSeriesStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a series keeping the newest `capacity` points; returns its index.
+    pub fn add_series(&mut self, capacity: usize) -> usize {
+        self.series.push(Series::new(capacity));
+        self.series.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    pub fn series(&self, index: usize) -> Option<&Series> {
+        self.series.get(index)
+    }
+
+    pub fn series_mut(&mut self, index: usize) -> Option<&mut Series> {
+        self.series.get_mut(index)
+    }
+
+    /// Append a point to one series. False for an unknown index or a point
+    /// `Series::push` rejects.
+    pub fn push(&mut self, index: usize, timestamp: f64, value: f64) -> bool {
+        self.series.get_mut(index).is_some_and(|s| s.push(timestamp, value))
+    }
+
+    /// Drop points older than `cutoff` from every series.
+    pub fn drop_before(&mut self, cutoff: f64) {
+        for series in &mut self.series {
+            series.drop_before(cutoff);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Series> {
+        self.series.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Series> {
+        self.series.iter_mut()
+    }
+}
+
+/// Min/max decimation: split `start..=end` into `width` equal time columns
+/// and keep the lowest and highest point of each, in time order (one point
+/// if they coincide). At most `2 * width` points; NaN values are skipped.
+pub fn downsample_minmax(
+    timestamps: &[f64],
+    values: &[f64],
+    start: f64,
+    end: f64,
+    width: usize,
+    out_timestamps: &mut Vec<f64>,
+    out_values: &mut Vec<f64>,
+) {
+    out_timestamps.clear();
+    out_values.clear();
+    let from = timestamps.partition_point(|&t| t < start);
+    let to = timestamps.partition_point(|&t| t <= end).max(from);
+    let (timestamps, values) = (&timestamps[from..to], &values[from..to]);
+    if width == 0 || timestamps.is_empty() {
+        return;
+    }
+    if timestamps.len() <= 2 * width || end <= start {
+        out_timestamps.extend_from_slice(timestamps);
+        out_values.extend_from_slice(values);
+        return;
+    }
+
+    let column_width = (end - start) / width as f64;
+    let mut i = 0;
+    while i < timestamps.len() {
+        let column = (((timestamps[i] - start) / column_width) as usize).min(width - 1);
+        let (mut min, mut max): (Option<usize>, Option<usize>) = (None, None);
+        while i < timestamps.len()
+            && (((timestamps[i] - start) / column_width) as usize).min(width - 1) == column
+        {
+            let v = values[i];
+            if !v.is_nan() {
+                if min.is_none_or(|m| v < values[m]) {
+                    min = Some(i);
+                }
+                if max.is_none_or(|m| v > values[m]) {
+                    max = Some(i);
+                }
+            }
+            i += 1;
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            let (first, second) = (min.min(max), min.max(max));
+            out_timestamps.push(timestamps[first]);
+            out_values.push(values[first]);
+            if second != first {
+                out_timestamps.push(timestamps[second]);
+                out_values.push(values[second]);
+            }
+        }
+    }
+}
+
+/// Largest-Triangle-Three-Buckets: `threshold` points (at least 3) that
+/// keep the shape of the line, always including the first and last. Copies
+/// everything when there are no more than `threshold` points.
+pub fn downsample_lttb(
+    timestamps: &[f64],
+    values: &[f64],
+    threshold: usize,
+    out_timestamps: &mut Vec<f64>,
+    out_values: &mut Vec<f64>,
+) {
+    out_timestamps.clear();
+    out_values.clear();
+    let n = timestamps.len().min(values.len());
+    if n <= threshold.max(3) {
+        out_timestamps.extend_from_slice(&timestamps[..n]);
+        out_values.extend_from_slice(&values[..n]);
+        return;
+    }
+    let threshold = threshold.max(3);
+
+    // Every point but the first and last falls into one of threshold - 2
+    // buckets; each bucket contributes the point forming the largest
+    // triangle with the previous pick and the next bucket's average.
+    let bucket_size = (n - 2) as f64 / (threshold - 2) as f64;
+    let bucket_start = |b: usize| (1 + (b as f64 * bucket_size) as usize).min(n - 1);
+    let mut a = 0;
+    out_timestamps.push(timestamps[0]);
+    out_values.push(values[0]);
+    for b in 0..threshold - 2 {
+        let (from, to) = (bucket_start(b), bucket_start(b + 1));
+        let next = to..bucket_start(b + 2).max(to + 1).min(n);
+        let count = next.len() as f64;
+        let avg_t = timestamps[next.clone()].iter().sum::<f64>() / count;
+        let avg_v = values[next].iter().sum::<f64>() / count;
+
+        let (at, av) = (timestamps[a], values[a]);
+        let mut best = from;
+        let mut best_area = -1.0;
+        for i in from..to {
+            let area = ((at - avg_t) * (values[i] - av) - (at - timestamps[i]) * (avg_v - av)).abs();
+            if area > best_area {
+                best_area = area;
+                best = i;
+            }
+        }
+        out_timestamps.push(timestamps[best]);
+        out_values.push(values[best]);
+        a = best;
+    }
+    out_timestamps.push(timestamps[n - 1]);
+    out_values.push(values[n - 1]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(series: &Series) -> Vec<(f64, f64)> {
+        series.timestamps().iter().copied().zip(series.values().iter().copied()).collect()
+    }
+
+    #[test]
+    fn test_ring_stays_contiguous_across_wraps() {
+        let mut series = Series::new(4);
+        for t in 0..11_u32 {
+            assert!(series.push(t as f64, t as f64 * 10.0));
+            let expected: Vec<_> = (t.saturating_sub(3)..=t).map(|t| (t as f64, t as f64 * 10.0)).collect();
+            assert_eq!(points(&series), expected);
+        }
+        assert_eq!(series.last(), Some((10.0, 100.0)));
+        // Wrapped: the buffer no longer reallocates
+        let buffer = series.timestamps.as_ptr();
+        for t in 11..20 {
+            series.push(t as f64, 0.0);
+        }
+        assert_eq!(series.timestamps.as_ptr(), buffer);
+        assert_eq!(series.timestamps(), &[16.0, 17.0, 18.0, 19.0]);
+    }
+
+    #[test]
+    fn test_push_rejects_older_points() {
+        let mut series = Series::new(8);
+        assert!(series.push(5.0, 1.0));
+        assert!(series.push(5.0, 2.0));
+        assert!(!series.push(4.0, 3.0));
+        assert!(!series.push(f64::NAN, 3.0));
+        assert_eq!(series.len(), 2);
+    }
+
+    #[test]
+    fn test_drop_before_and_range() {
+        let mut series = Series::new(4);
+        for t in 0..6 {
+            series.push(t as f64, 0.0);
+        }
+        assert_eq!(series.range(3.0, 4.5), 1..3);
+        assert_eq!(series.range(9.0, 10.0), 4..4);
+        assert_eq!(series.drop_before(4.0), 2);
+        assert_eq!(series.timestamps(), &[4.0, 5.0]);
+        // Dropping while still filling, then wrapping
+        let mut series = Series::new(3);
+        series.push(0.0, 0.0);
+        series.push(1.0, 0.0);
+        series.drop_before(1.0);
+        for t in 2..5 {
+            series.push(t as f64, 0.0);
+        }
+        assert_eq!(series.timestamps(), &[2.0, 3.0, 4.0]);
+        series.clear();
+        assert!(series.is_empty() && series.push(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_store_series_are_independent() {
+        let mut store = TimeSeriesStore::new();
+        let price = store.add_series(10);
+        let volume = store.add_series(2);
+        for t in 0..3 {
+            store.push(price, t as f64, 100.0 + t as f64);
+            store.push(volume, t as f64, 1.0);
+        }
+        assert!(!store.push(7, 0.0, 0.0));
+        assert_eq!(store.series(price).unwrap().len(), 3);
+        assert_eq!(store.series(volume).unwrap().timestamps(), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_minmax_keeps_spikes() {
+        let timestamps: Vec<f64> = (0..1000).map(f64::from).collect();
+        let mut values = vec![1.0; 1000];
+        values[500] = 50.0;
+        values[501] = -50.0;
+        let (mut ts, mut vs) = (Vec::new(), Vec::new());
+        downsample_minmax(&timestamps, &values, 0.0, 999.0, 10, &mut ts, &mut vs);
+        assert!(ts.len() <= 20);
+        assert!(vs.contains(&50.0) && vs.contains(&-50.0));
+        assert!(ts.windows(2).all(|w| w[0] <= w[1]));
+        // A range with fewer points than columns is copied
+        downsample_minmax(&timestamps, &values, 10.0, 12.0, 10, &mut ts, &mut vs);
+        assert_eq!(ts, vec![10.0, 11.0, 12.0]);
+    }
+
+    #[test]
+    fn test_lttb() {
+        let timestamps: Vec<f64> = (0..100).map(f64::from).collect();
+        let mut values: Vec<f64> = (0..100).map(|i| (i % 10) as f64 * 0.01).collect();
+        values[42] = 10.0;
+        let (mut ts, mut vs) = (Vec::new(), Vec::new());
+        downsample_lttb(&timestamps, &values, 10, &mut ts, &mut vs);
+        assert_eq!(ts.len(), 10);
+        assert_eq!((ts[0], ts[9]), (0.0, 99.0));
+        assert!(ts.windows(2).all(|w| w[0] < w[1]));
+        assert!(vs.contains(&10.0));
+        downsample_lttb(&timestamps[..5], &values[..5], 10, &mut ts, &mut vs);
+        assert_eq!(ts, &timestamps[..5]);
+    }
+}
@@ -0,0 +1,767 @@
+//! # OrgFrame
+//!
+//! `#[derive(OrgFrame)]` makes a plain Rust struct the single source of
+//! truth for a FlatBuffers frame. From the struct it generates:
+//!
+//! - `serialize_into(&self, builder)` — builds and finishes the table; the
+//!   bytes are what flatc-generated code would write, so any FlatBuffers
+//!   reader can parse them
+//! - `fbs_schema()` — the matching `.fbs` text
+//! - `ts_module()` — a TypeScript reader with flatc's API
+//!   (`Frame.getRootAsFrame(bb).valueA()`), so `flatBufferTickAdapter()`
+//!   works unchanged
+//!
+//! ```rust,ignore
+//! use org_frame::OrgFrame;
+//!
+//! #[derive(OrgFrame, Default)]
+//! #[org_frame(namespace = "OrgAsm")]
+//! pub struct Frame {
+//!     pub value_a: f64,
+//!     pub value_b: f64,
+//!     pub state_flag: bool,
+//!     pub color_r: u8,
+//!     pub indicators: Indicators,
+//! }
+//!
+//! #[derive(OrgFrame, Default, Clone, Copy)]
+//! #[org_frame(inline)]
+//! pub struct Indicators {
+//!     pub ema: f64,
+//!     pub rsi: f64,
+//! }
+//!
+//! // tick():
+//! self.builder.reset();
+//! Frame { value_a: self.smooth_value, ..Default::default() }.serialize_into(&mut self.builder);
+//! ```
+//!
+//! ## Field types
+//!
+//! `bool`, `u8`..`u64`, `i8`..`i64`, `f32` and `f64`, with flatc's defaults
+//! (0 / false, not written when equal), and any type deriving OrgFrame with
+//! `#[org_frame(inline)]` — a FlatBuffers `struct`: scalars only, fixed
+//! layout, stored inside the table and always written.
+//!
+//! ## Field order
+//!
+//! Readers find table fields by id, and ids follow declaration order, so
+//! reordering or removing fields breaks clients built from the previous
+//! schema. Append new fields at the end, or pin ids:
+//!
+//! ```rust,ignore
+//! pub struct Frame {
+//!     #[org_frame(id = 1)]
+//!     pub value_b: f64,
+//!     #[org_frame(id = 0)]
+//!     pub value_a: f64,
+//!     // id 2 was removed; the schema keeps its slot as a deprecated field
+//!     #[org_frame(id = 3)]
+//!     pub state_flag: bool,
+//! }
+//! ```
+//!
+//! If any field has an id, all must. Inline structs have no ids: their
+//! layout is their declaration order.
+//!
+//! ## Keeping the files in sync
+//!
+//! Commit the generated files and check them in a test of the engine crate,
+//! so a struct change that isn't reflected in them fails CI (and
+//! `tools/schema-diff` can compare the schema with the previous version):
+//!
+//! ```rust,ignore
+//! #[test]
+//! fn generated_frame_files_are_current() {
+//!     for (path, text) in [("../../schema/frame.fbs", Frame::fbs_schema()), ("../../src/generated/frame.ts", Frame::ts_module())] {
+//!         if std::env::var_os("UPDATE_FRAME").is_some() {
+//!             std::fs::write(path, &text).unwrap();
+//!         }
+//!         assert_eq!(std::fs::read_to_string(path).unwrap(), text, "run with UPDATE_FRAME=1");
+//!     }
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitInt, LitStr, Path, Type};
+
+#[proc_macro_derive(OrgFrame, attributes(org_frame))]
+pub fn derive_org_frame(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match Model::parse(&input) {
+        Ok(model) => model.expand().into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+const HEADER: &str = "Generated by #[derive(OrgFrame)]. Do not edit.";
+
+// ============================================
+// Model
+// ============================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn from_type(ty: &Type) -> Option<Self> {
+        let Type::Path(path) = ty else { return None };
+        let ident = path.path.get_ident()?.to_string();
+        Some(match ident.as_str() {
+            "bool" => Scalar::Bool,
+            "u8" => Scalar::U8,
+            "i8" => Scalar::I8,
+            "u16" => Scalar::U16,
+            "i16" => Scalar::I16,
+            "u32" => Scalar::U32,
+            "i32" => Scalar::I32,
+            "u64" => Scalar::U64,
+            "i64" => Scalar::I64,
+            "f32" => Scalar::F32,
+            "f64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn fbs(self) -> &'static str {
+        match self {
+            Scalar::Bool => "bool",
+            Scalar::U8 => "ubyte",
+            Scalar::I8 => "byte",
+            Scalar::U16 => "ushort",
+            Scalar::I16 => "short",
+            Scalar::U32 => "uint",
+            Scalar::I32 => "int",
+            Scalar::U64 => "ulong",
+            Scalar::I64 => "long",
+            Scalar::F32 => "float",
+            Scalar::F64 => "double",
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::Bool | Scalar::U8 | Scalar::I8 => 1,
+            Scalar::U16 | Scalar::I16 => 2,
+            Scalar::U32 | Scalar::I32 | Scalar::F32 => 4,
+            Scalar::U64 | Scalar::I64 | Scalar::F64 => 8,
+        }
+    }
+
+    /// flatbuffers.ByteBuffer method reading this type.
+    fn ts_read(self) -> &'static str {
+        match self {
+            Scalar::Bool | Scalar::I8 => "readInt8",
+            Scalar::U8 => "readUint8",
+            Scalar::U16 => "readUint16",
+            Scalar::I16 => "readInt16",
+            Scalar::U32 => "readUint32",
+            Scalar::I32 => "readInt32",
+            Scalar::U64 => "readUint64",
+            Scalar::I64 => "readInt64",
+            Scalar::F32 => "readFloat32",
+            Scalar::F64 => "readFloat64",
+        }
+    }
+
+    fn ts_type(self) -> &'static str {
+        match self {
+            Scalar::Bool => "boolean",
+            Scalar::U64 | Scalar::I64 => "bigint",
+            _ => "number",
+        }
+    }
+
+    fn ts_default(self) -> &'static str {
+        match self {
+            Scalar::Bool => "false",
+            Scalar::U64 | Scalar::I64 => "BigInt('0')",
+            _ => "0",
+        }
+    }
+}
+
+#[derive(Clone)]
+enum FieldType {
+    Scalar(Scalar),
+    /// A type deriving OrgFrame with `#[org_frame(inline)]`.
+    Inline(Path),
+}
+
+impl FieldType {
+    /// Name in the schema and the TypeScript module.
+    fn name(&self) -> String {
+        match self {
+            FieldType::Scalar(s) => s.fbs().to_string(),
+            FieldType::Inline(path) => path
+                .segments
+                .last()
+                .expect("path segment")
+                .ident
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Field {
+    ident: Ident,
+    ty: FieldType,
+    /// Table: vtable slot. Inline struct: byte offset.
+    id: usize,
+}
+
+#[derive(Clone)]
+enum Kind {
+    Table {
+        namespace: Option<String>,
+        identifier: Option<String>,
+    },
+    Inline {
+        size: usize,
+        align: usize,
+    },
+}
+
+#[derive(Clone)]
+struct Model {
+    name: Ident,
+    kind: Kind,
+    /// Tables: ordered by id. Inline structs: declaration order.
+    fields: Vec<Field>,
+}
+
+impl Model {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        if !input.generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &input.generics,
+                "OrgFrame structs can't be generic",
+            ));
+        }
+        let Data::Struct(data) = &input.data else {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "OrgFrame can only be derived for structs",
+            ));
+        };
+        let Fields::Named(named) = &data.fields else {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "OrgFrame needs named fields",
+            ));
+        };
+
+        let (mut namespace, mut identifier, mut inline) = (None, None, false);
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("org_frame"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("namespace") {
+                    namespace = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("identifier") {
+                    let lit = meta.value()?.parse::<LitStr>()?;
+                    if lit.value().len() != 4 || !lit.value().is_ascii() {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            "a file identifier is 4 ASCII characters",
+                        ));
+                    }
+                    identifier = Some(lit.value());
+                } else if meta.path.is_ident("inline") {
+                    inline = true;
+                } else {
+                    return Err(meta.error("expected `namespace`, `identifier` or `inline`"));
+                }
+                Ok(())
+            })?;
+        }
+        if inline && (namespace.is_some() || identifier.is_some()) {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "inline structs take the namespace of the table that contains them",
+            ));
+        }
+
+        let mut fields = Vec::new();
+        let mut ids = Vec::new();
+        for field in &named.named {
+            let ident = field.ident.clone().expect("named field");
+            let mut id = None;
+            for attr in field
+                .attrs
+                .iter()
+                .filter(|a| a.path().is_ident("org_frame"))
+            {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("id") {
+                        id = Some((
+                            meta.value()?.parse::<LitInt>()?.base10_parse::<usize>()?,
+                            ident.clone(),
+                        ));
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `id = n`"))
+                    }
+                })?;
+            }
+            let ty = match (Scalar::from_type(&field.ty), &field.ty) {
+                (Some(scalar), _) => FieldType::Scalar(scalar),
+                (None, _) if inline => {
+                    return Err(syn::Error::new_spanned(
+                        &field.ty,
+                        "inline struct fields must be scalars",
+                    ));
+                }
+                (None, Type::Path(path))
+                    if path.qself.is_none()
+                        && path.path.segments.iter().all(|s| s.arguments.is_none()) =>
+                {
+                    FieldType::Inline(path.path.clone())
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &field.ty,
+                        "OrgFrame fields are scalars or #[org_frame(inline)] structs",
+                    ));
+                }
+            };
+            ids.push(id);
+            fields.push(Field { ident, ty, id: 0 });
+        }
+
+        if inline {
+            if let Some((_, ident)) = ids.iter().flatten().next() {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "inline struct fields have no ids",
+                ));
+            }
+            let (size, align) = layout(&mut fields);
+            return Ok(Model {
+                name: input.ident.clone(),
+                kind: Kind::Inline { size, align },
+                fields,
+            });
+        }
+
+        assign_ids(&mut fields, &ids)?;
+        fields.sort_by_key(|f| f.id);
+        Ok(Model {
+            name: input.ident.clone(),
+            kind: Kind::Table {
+                namespace,
+                identifier,
+            },
+            fields,
+        })
+    }
+
+    /// The type's own `.fbs` declaration.
+    fn fbs_decl(&self) -> String {
+        let keyword = if matches!(self.kind, Kind::Inline { .. }) {
+            "struct"
+        } else {
+            "table"
+        };
+        let mut out = format!("{keyword} {} {{\n", self.name);
+        let mut next = 0;
+        for field in &self.fields {
+            if matches!(self.kind, Kind::Table { .. }) {
+                // Keep the slots of removed fields
+                for gap in next..field.id {
+                    out.push_str(&format!("  _removed_{gap}: ubyte (deprecated);\n"));
+                }
+                next = field.id + 1;
+            }
+            out.push_str(&format!("  {}: {};\n", field.ident, field.ty.name()));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The type's TypeScript reader class, in the shape flatc generates.
+    fn ts_decl(&self) -> String {
+        let name = &self.name;
+        let mut out = format!(
+            "export class {name} {{\n  bb: flatbuffers.ByteBuffer | null = null;\n  bb_pos = 0;\n\n  \
+             __init(i: number, bb: flatbuffers.ByteBuffer): {name} {{\n    this.bb_pos = i;\n    this.bb = bb;\n    \
+             return this;\n  }}\n"
+        );
+        if let Kind::Table { identifier, .. } = &self.kind {
+            out.push_str(&format!(
+                "\n  static getRootAs{name}(bb: flatbuffers.ByteBuffer, obj?: {name}): {name} {{\n    \
+                 return (obj || new {name}()).__init(bb.readInt32(bb.position()) + bb.position(), bb);\n  }}\n"
+            ));
+            if let Some(identifier) = identifier {
+                out.push_str(&format!(
+                    "\n  static bufferHasIdentifier(bb: flatbuffers.ByteBuffer): boolean {{\n    \
+                     return bb.__has_identifier('{identifier}');\n  }}\n"
+                ));
+            }
+        }
+        for field in &self.fields {
+            let accessor = camel_case(&field.ident.to_string());
+            let body = match (&self.kind, &field.ty) {
+                (Kind::Inline { .. }, FieldType::Scalar(s)) => {
+                    let read = format!("this.bb!.{}(this.bb_pos + {})", s.ts_read(), field.id);
+                    let read = if *s == Scalar::Bool { format!("!!{read}") } else { read };
+                    format!("  {accessor}(): {} {{\n    return {read};\n  }}\n", s.ts_type())
+                }
+                (Kind::Table { .. }, FieldType::Scalar(s)) => {
+                    let read = format!("this.bb!.{}(this.bb_pos + offset)", s.ts_read());
+                    let read = if *s == Scalar::Bool { format!("!!{read}") } else { read };
+                    format!(
+                        "  {accessor}(): {} {{\n    const offset = this.bb!.__offset(this.bb_pos, {});\n    \
+                         return offset ? {read} : {};\n  }}\n",
+                        s.ts_type(),
+                        vtable_offset(field.id),
+                        s.ts_default()
+                    )
+                }
+                (_, ty @ FieldType::Inline(_)) => format!(
+                    "  {accessor}(obj?: {ty}): {ty} | null {{\n    const offset = this.bb!.__offset(this.bb_pos, {});\n    \
+                     return offset ? (obj || new {ty}()).__init(this.bb_pos + offset, this.bb!) : null;\n  }}\n",
+                    vtable_offset(field.id),
+                    ty = ty.name()
+                ),
+            };
+            out.push('\n');
+            out.push_str(&body);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Inline struct types used by this table, first use first.
+    fn inline_types(&self) -> Vec<&Path> {
+        let mut types: Vec<&Path> = Vec::new();
+        for field in &self.fields {
+            if let FieldType::Inline(ty) = &field.ty {
+                if !types.iter().any(|t| {
+                    t.segments.last().map(|s| &s.ident) == ty.segments.last().map(|s| &s.ident)
+                }) {
+                    types.push(ty);
+                }
+            }
+        }
+        types
+    }
+
+    fn expand(&self) -> TokenStream2 {
+        let name = &self.name;
+        let fbs_decl = self.fbs_decl();
+        let ts_decl = self.ts_decl();
+        let decls = quote! {
+            /// This type's `.fbs` declaration.
+            pub const FBS_DECL: &'static str = #fbs_decl;
+            /// This type's TypeScript reader class.
+            pub const TS_DECL: &'static str = #ts_decl;
+        };
+
+        match &self.kind {
+            Kind::Inline { size, align } => {
+                let writes = self.fields.iter().map(|f| {
+                    let ident = &f.ident;
+                    let offset = f.id;
+                    let FieldType::Scalar(scalar) = f.ty else {
+                        unreachable!("inline fields are scalars")
+                    };
+                    let end = offset + scalar.size();
+                    if scalar == Scalar::Bool {
+                        quote! { dst[#offset] = self.#ident as u8; }
+                    } else {
+                        quote! { dst[#offset..#end].copy_from_slice(&self.#ident.to_le_bytes()); }
+                    }
+                });
+                quote! {
+                    impl #name {
+                        #decls
+                    }
+
+                    impl ::flatbuffers::Push for #name {
+                        type Output = #name;
+
+                        #[inline]
+                        unsafe fn push(&self, dst: &mut [u8], _written_len: usize) {
+                            let dst = &mut dst[..#size];
+                            dst.fill(0);
+                            #(#writes)*
+                        }
+
+                        #[inline]
+                        fn size() -> usize {
+                            #size
+                        }
+
+                        #[inline]
+                        fn alignment() -> ::flatbuffers::PushAlignment {
+                            ::flatbuffers::PushAlignment::new(#align)
+                        }
+                    }
+                }
+            }
+            Kind::Table {
+                namespace,
+                identifier,
+            } => {
+                // Largest first, as flatc does, to minimize padding
+                let mut order: Vec<&Field> = self.fields.iter().collect();
+                order.sort_by_key(|f| std::cmp::Reverse(self.field_size(f)));
+                let pushes = order.iter().map(|f| {
+                    let ident = &f.ident;
+                    let slot = vtable_offset(f.id) as u16;
+                    match &f.ty {
+                        FieldType::Scalar(_) => {
+                            quote! { builder.push_slot(#slot, self.#ident, ::core::default::Default::default()); }
+                        }
+                        FieldType::Inline(ty) => quote! { builder.push_slot_always::<&#ty>(#slot, &self.#ident); },
+                    }
+                });
+                let identifier = match identifier {
+                    Some(id) => quote! { Some(#id) },
+                    None => quote! { None },
+                };
+                let mut head = format!("// {HEADER}\n\n");
+                if let Some(namespace) = namespace {
+                    head.push_str(&format!("namespace {namespace};\n\n"));
+                }
+                let mut tail = String::new();
+                if let Kind::Table {
+                    identifier: Some(id),
+                    ..
+                } = &self.kind
+                {
+                    tail.push_str(&format!("file_identifier \"{id}\";\n"));
+                }
+                tail.push_str(&format!("root_type {name};\n"));
+                let ts_head =
+                    format!("// {HEADER}\n\nimport * as flatbuffers from 'flatbuffers';\n\n");
+                let inline_types = self.inline_types();
+
+                quote! {
+                    impl #name {
+                        #decls
+
+                        /// Build this frame as the root table and finish the
+                        /// buffer. Call `builder.reset()` first.
+                        pub fn serialize_into(&self, builder: &mut ::flatbuffers::FlatBufferBuilder<'_>) {
+                            let start = builder.start_table();
+                            #(#pushes)*
+                            let end = builder.end_table(start);
+                            builder.finish(end, #identifier);
+                        }
+
+                        /// The `.fbs` schema this type serializes to.
+                        pub fn fbs_schema() -> String {
+                            let mut out = String::from(#head);
+                            #( out.push_str(#inline_types::FBS_DECL); out.push('\n'); )*
+                            out.push_str(Self::FBS_DECL);
+                            out.push('\n');
+                            out.push_str(#tail);
+                            out
+                        }
+
+                        /// A TypeScript module reading this frame (and its
+                        /// inline structs), with flatc's accessor API.
+                        pub fn ts_module() -> String {
+                            let mut out = String::from(#ts_head);
+                            #( out.push_str(#inline_types::TS_DECL); out.push('\n'); )*
+                            out.push_str(Self::TS_DECL);
+                            out
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bytes a field takes in the table; inline structs count as 8 so they
+    /// are written with the widest scalars.
+    fn field_size(&self, field: &Field) -> usize {
+        match field.ty {
+            FieldType::Scalar(s) => s.size(),
+            FieldType::Inline(_) => 8,
+        }
+    }
+}
+
+/// Vtable offset of the field with this id.
+fn vtable_offset(id: usize) -> usize {
+    4 + 2 * id
+}
+
+/// Declaration order, or the explicit `id = n` of every field.
+fn assign_ids(fields: &mut [Field], ids: &[Option<(usize, Ident)>]) -> syn::Result<()> {
+    let explicit = ids.iter().filter(|id| id.is_some()).count();
+    if explicit == 0 {
+        for (i, field) in fields.iter_mut().enumerate() {
+            field.id = i;
+        }
+        return Ok(());
+    }
+    if explicit != fields.len() {
+        let missing = fields
+            .iter()
+            .zip(ids)
+            .find(|(_, id)| id.is_none())
+            .map(|(f, _)| &f.ident);
+        return Err(syn::Error::new_spanned(
+            missing.expect("a field without id"),
+            "if any field has an `id`, every field needs one",
+        ));
+    }
+    for (field, id) in fields.iter_mut().zip(ids) {
+        let (id, ident) = id.clone().expect("checked above");
+        if ids
+            .iter()
+            .flatten()
+            .filter(|(other, _)| *other == id)
+            .count()
+            > 1
+        {
+            return Err(syn::Error::new_spanned(
+                ident,
+                format!("id {id} is used twice"),
+            ));
+        }
+        field.id = id;
+    }
+    Ok(())
+}
+
+/// FlatBuffers struct layout: each field aligned to its size, the struct
+/// to its largest field. Stores byte offsets in `id`; returns (size, align).
+fn layout(fields: &mut [Field]) -> (usize, usize) {
+    let (mut offset, mut align) = (0_usize, 1);
+    for field in fields.iter_mut() {
+        let FieldType::Scalar(scalar) = field.ty else {
+            unreachable!("inline fields are scalars")
+        };
+        let size = scalar.size();
+        offset = offset.next_multiple_of(size);
+        field.id = offset;
+        offset += size;
+        align = align.max(size);
+    }
+    (offset.next_multiple_of(align), align)
+}
+
+/// flatc's accessor name: `bollinger_upper` -> `bollingerUpper`.
+fn camel_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = !out.is_empty();
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(src: &str) -> syn::Result<Model> {
+        Model::parse(&syn::parse_str::<DeriveInput>(src).unwrap())
+    }
+
+    #[test]
+    fn test_table_schema_in_declaration_order() {
+        let m = model(
+            "#[org_frame(namespace = \"OrgAsm\")] struct Frame { value_a: f64, state_flag: bool, color_r: u8, indicators: Indicators }",
+        )
+        .unwrap();
+        assert_eq!(
+            m.fbs_decl(),
+            "table Frame {\n  value_a: double;\n  state_flag: bool;\n  color_r: ubyte;\n  indicators: Indicators;\n}\n"
+        );
+        assert_eq!(m.inline_types().len(), 1);
+        let ts = m.ts_decl();
+        assert!(
+            ts.contains("static getRootAsFrame(bb: flatbuffers.ByteBuffer, obj?: Frame): Frame")
+        );
+        assert!(ts
+            .contains("valueA(): number {\n    const offset = this.bb!.__offset(this.bb_pos, 4);"));
+        assert!(ts.contains("return offset ? !!this.bb!.readInt8(this.bb_pos + offset) : false;"));
+        assert!(ts.contains("indicators(obj?: Indicators): Indicators | null"));
+    }
+
+    #[test]
+    fn test_explicit_ids_keep_slots() {
+        let m = model("struct Frame { #[org_frame(id = 3)] b: i64, #[org_frame(id = 0)] a: f32 }")
+            .unwrap();
+        assert_eq!(
+            m.fbs_decl(),
+            "table Frame {\n  a: float;\n  _removed_1: ubyte (deprecated);\n  _removed_2: ubyte (deprecated);\n  b: long;\n}\n"
+        );
+        assert!(m
+            .ts_decl()
+            .contains("b(): bigint {\n    const offset = this.bb!.__offset(this.bb_pos, 10);"));
+    }
+
+    #[test]
+    fn test_id_errors() {
+        let err = |src| model(src).err().expect("an error").to_string();
+        assert!(err("struct F { #[org_frame(id = 0)] a: f64, b: f64 }")
+            .contains("every field needs one"));
+        assert!(
+            err("struct F { #[org_frame(id = 0)] a: f64, #[org_frame(id = 0)] b: f64 }")
+                .contains("used twice")
+        );
+        assert!(
+            err("#[org_frame(inline)] struct S { #[org_frame(id = 0)] a: f64 }").contains("no ids")
+        );
+        assert!(err("#[org_frame(inline)] struct S { a: Other }").contains("must be scalars"));
+        assert!(err("struct F { a: Vec<f64> }").contains("scalars or"));
+        assert!(
+            err("#[org_frame(identifier = \"TOOLONG\")] struct F { a: f64 }").contains("4 ASCII")
+        );
+    }
+
+    #[test]
+    fn test_inline_struct_layout() {
+        let m =
+            model("#[org_frame(inline)] struct S { flag: bool, price: f64, count: u16 }").unwrap();
+        let offsets: Vec<_> = m.fields.iter().map(|f| f.id).collect();
+        assert_eq!(offsets, vec![0, 8, 16]);
+        assert!(matches!(m.kind, Kind::Inline { size: 24, align: 8 }));
+        assert_eq!(
+            m.fbs_decl(),
+            "struct S {\n  flag: bool;\n  price: double;\n  count: ushort;\n}\n"
+        );
+        assert!(m
+            .ts_decl()
+            .contains("price(): number {\n    return this.bb!.readFloat64(this.bb_pos + 8);"));
+    }
+
+    #[test]
+    fn test_camel_case() {
+        assert_eq!(camel_case("bollinger_upper"), "bollingerUpper");
+        assert_eq!(camel_case("color_r"), "colorR");
+        assert_eq!(camel_case("rsi"), "rsi");
+        assert_eq!(camel_case("_private_x"), "privateX");
+    }
+}
@@ -0,0 +1,1171 @@
+//! # Schema Diff
+//!
+//! Compares two versions of a FlatBuffers schema and reports every change,
+//! marked breaking when a reader built from the old version would misread
+//! data written with the new one (or the reverse).
+//!
+//! ```text
+//! schema-diff <old.fbs> <new.fbs>    exit 0: compatible, 1: breaking, 2: error
+//! schema-diff hash <file.fbs>...     print my_shared::schema::schema_hash of each
+//! ```
+//!
+//! ## What breaks
+//!
+//! Tables find fields by id — their position in the table, or an explicit
+//! `(id: n)` — so anything that changes a field's id, type or default
+//! breaks old readers:
+//!
+//! - a field moved, or inserted before existing fields
+//! - a field removed instead of marked `(deprecated)`
+//! - a field's type or default changed
+//! - any change to a struct's fields (structs are inline, fixed layout)
+//! - an enum value or union member removed or renumbered, including
+//!   union members reordered (the discriminant is the position)
+//! - a type removed, a root type or file identifier changed
+//!
+//! Appending fields, enum values or union members, adding types,
+//! deprecating fields and renaming fields in place are compatible (renames
+//! still change generated accessor names).
+//!
+//! ## CI
+//!
+//! ```bash
+//! for f in schema/*.fbs; do
+//!   cargo run -q -p schema-diff -- <(git show origin/main:$f) $f || exit 1
+//! done
+//! ```
+//!
+//! `include`d files aren't followed; diff each file on its own.
+
+use std::fmt;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: schema-diff <old.fbs> <new.fbs>\n       schema-diff hash <file.fbs>...";
+
+// ============================================
+// Schema model
+// ============================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Table,
+    Struct,
+    Enum,
+    Union,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Table => "table",
+            Kind::Struct => "struct",
+            Kind::Enum => "enum",
+            Kind::Union => "union",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    ty: String,
+    default: Option<String>,
+    /// Slot in the table's vtable. Union fields take two slots (type and
+    /// value); this is the value slot, as with `(id: n)`.
+    id: usize,
+    explicit_id: Option<usize>,
+    deprecated: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Member {
+    name: String,
+    value: i64,
+}
+
+#[derive(Debug, Clone)]
+struct Decl {
+    kind: Kind,
+    namespace: String,
+    name: String,
+    /// Enum underlying type (`ubyte`, `int`, ...).
+    underlying: Option<String>,
+    /// Tables and structs.
+    fields: Vec<Field>,
+    /// Enums and unions.
+    members: Vec<Member>,
+}
+
+#[derive(Debug, Default)]
+struct Schema {
+    decls: Vec<Decl>,
+    root_type: Option<String>,
+    file_identifier: Option<String>,
+}
+
+// ============================================
+// Parser
+// ============================================
+
+#[derive(Debug)]
+struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Identifier, keyword or number (`OrgAsm.Orderbook`, `uint64`, `-1.5`).
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '+')
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+    let unterminated = |line, what: &str| ParseError {
+        line,
+        message: format!("unterminated {what}"),
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = '\0';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            prev = c;
+                        }
+                        None => return Err(unterminated(line, "comment")),
+                    }
+                }
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => return Err(unterminated(line, "string")),
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push((Token::Str(s), line));
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | ';' | ',' | '=' => {
+                tokens.push((Token::Punct(c), line))
+            }
+            c if is_word_char(c) => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|&c| is_word_char(c)) {
+                    word.push(c);
+                }
+                tokens.push((Token::Word(word), line));
+            }
+            c => {
+                return Err(ParseError {
+                    line,
+                    message: format!("unexpected character {c:?}"),
+                })
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line);
+        Err(ParseError {
+            line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        match self.tokens.get(self.pos) {
+            Some((token, _)) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => self.error("unexpected end of file"),
+        }
+    }
+
+    /// Consume `c` if it is the next token.
+    fn eat(&mut self, c: char) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some((Token::Punct(p), _)) if *p == c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{c}`"))
+        }
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            other => {
+                self.pos -= 1;
+                self.error(format!("expected a name, found {other:?}"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        match self.next()? {
+            Token::Str(s) => Ok(s),
+            other => {
+                self.pos -= 1;
+                self.error(format!("expected a string, found {other:?}"))
+            }
+        }
+    }
+
+    /// A default or attribute value: a word or a string.
+    fn value(&mut self) -> Result<String, ParseError> {
+        match self.next()? {
+            Token::Word(s) | Token::Str(s) => Ok(s),
+            other => {
+                self.pos -= 1;
+                self.error(format!("expected a value, found {other:?}"))
+            }
+        }
+    }
+
+    /// Optional `(key, key: value, ...)` attribute list.
+    fn metadata(&mut self) -> Result<Vec<(String, Option<String>)>, ParseError> {
+        let mut attributes = Vec::new();
+        if !self.eat('(') {
+            return Ok(attributes);
+        }
+        while !self.eat(')') {
+            let key = self.word()?;
+            let value = if self.eat(':') {
+                Some(self.value()?)
+            } else {
+                None
+            };
+            attributes.push((key, value));
+            if !self.eat(',') {
+                self.expect(')')?;
+                break;
+            }
+        }
+        Ok(attributes)
+    }
+
+    /// `type`, `[type]` or `[type:length]` (struct arrays).
+    fn ty(&mut self) -> Result<String, ParseError> {
+        if !self.eat('[') {
+            return self.word();
+        }
+        let inner = self.word()?;
+        let ty = if self.eat(':') {
+            format!("[{inner}:{}]", self.word()?)
+        } else {
+            format!("[{inner}]")
+        };
+        self.expect(']')?;
+        Ok(ty)
+    }
+
+    /// Body of a table or struct, after its name.
+    fn fields(&mut self) -> Result<Vec<Field>, ParseError> {
+        self.metadata()?;
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            let name = self.word()?;
+            self.expect(':')?;
+            let ty = self.ty()?;
+            let default = if self.eat('=') {
+                Some(self.value()?)
+            } else {
+                None
+            };
+            let attributes = self.metadata()?;
+            self.expect(';')?;
+            let explicit_id = match attributes.iter().find(|(key, _)| key == "id") {
+                Some((_, Some(id))) => match id.parse() {
+                    Ok(id) => Some(id),
+                    Err(_) => return self.error(format!("field `{name}`: bad id `{id}`")),
+                },
+                Some((_, None)) => return self.error(format!("field `{name}`: id needs a value")),
+                None => None,
+            };
+            let deprecated = attributes.iter().any(|(key, _)| key == "deprecated");
+            fields.push(Field {
+                name,
+                ty,
+                default,
+                id: 0,
+                explicit_id,
+                deprecated,
+            });
+        }
+        Ok(fields)
+    }
+
+    /// Body of an enum or union, after its name (and underlying type).
+    fn members(&mut self, kind: Kind) -> Result<Vec<Member>, ParseError> {
+        self.metadata()?;
+        self.expect('{')?;
+        let mut members = Vec::new();
+        // Union member 0 is the implicit NONE
+        let mut next = if kind == Kind::Union { 1 } else { 0 };
+        while !self.eat('}') {
+            let name = self.word()?;
+            if kind == Kind::Union && self.eat(':') {
+                // `Alias: Type` — the alias names the member
+                self.word()?;
+            }
+            if self.eat('=') {
+                let value = self.word()?;
+                next = match parse_int(&value) {
+                    Some(value) => value,
+                    None => return self.error(format!("`{name}`: bad value `{value}`")),
+                };
+            }
+            members.push(Member { name, value: next });
+            next += 1;
+            if !self.eat(',') {
+                self.expect('}')?;
+                break;
+            }
+        }
+        Ok(members)
+    }
+
+    /// Skip a `{ ... }` block (rpc_service).
+    fn skip_block(&mut self) -> Result<(), ParseError> {
+        self.expect('{')?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                Token::Punct('{') => depth += 1,
+                Token::Punct('}') => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_int(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Last segment of a possibly qualified name.
+fn base_name(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+fn parse(src: &str) -> Result<Schema, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+    let mut schema = Schema::default();
+    let mut namespace = String::new();
+
+    while parser.pos < parser.tokens.len() {
+        let keyword = parser.word()?;
+        match keyword.as_str() {
+            "namespace" => {
+                namespace = parser.word()?;
+                parser.expect(';')?;
+            }
+            "include" | "attribute" | "file_extension" => {
+                parser.string()?;
+                parser.expect(';')?;
+            }
+            "file_identifier" => {
+                schema.file_identifier = Some(parser.string()?);
+                parser.expect(';')?;
+            }
+            "root_type" => {
+                schema.root_type = Some(parser.word()?);
+                parser.expect(';')?;
+            }
+            "table" | "struct" => {
+                let kind = if keyword == "table" {
+                    Kind::Table
+                } else {
+                    Kind::Struct
+                };
+                let name = parser.word()?;
+                let fields = parser.fields()?;
+                schema.decls.push(Decl {
+                    kind,
+                    namespace: namespace.clone(),
+                    name,
+                    underlying: None,
+                    fields,
+                    members: Vec::new(),
+                });
+            }
+            "enum" | "union" => {
+                let kind = if keyword == "enum" {
+                    Kind::Enum
+                } else {
+                    Kind::Union
+                };
+                let name = parser.word()?;
+                let underlying = if kind == Kind::Enum {
+                    parser.expect(':')?;
+                    Some(parser.word()?)
+                } else {
+                    None
+                };
+                let members = parser.members(kind)?;
+                schema.decls.push(Decl {
+                    kind,
+                    namespace: namespace.clone(),
+                    name,
+                    underlying,
+                    fields: Vec::new(),
+                    members,
+                });
+            }
+            "rpc_service" => {
+                parser.word()?;
+                parser.skip_block()?;
+            }
+            other => {
+                parser.pos -= 1;
+                return parser.error(format!("unknown declaration `{other}`"));
+            }
+        }
+    }
+
+    assign_ids(&mut schema);
+    Ok(schema)
+}
+
+/// Give every table field its vtable slot. Without explicit ids, fields
+/// are numbered in order and a union field takes two slots.
+fn assign_ids(schema: &mut Schema) {
+    let unions: Vec<String> = schema
+        .decls
+        .iter()
+        .filter(|d| d.kind == Kind::Union)
+        .map(|d| d.name.clone())
+        .collect();
+    for decl in schema.decls.iter_mut().filter(|d| d.kind == Kind::Table) {
+        let mut next = 0;
+        for field in &mut decl.fields {
+            if unions.iter().any(|u| u == base_name(&field.ty)) {
+                next += 1;
+            }
+            field.id = field.explicit_id.unwrap_or(next);
+            next = field.id + 1;
+        }
+    }
+}
+
+// ============================================
+// Diff
+// ============================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Compatible,
+    Breaking,
+}
+
+#[derive(Debug)]
+struct Change {
+    severity: Severity,
+    /// `Table.field`, `Enum.VALUE`, `Table`, or `schema`.
+    path: String,
+    message: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Breaking => "BREAKING  ",
+            Severity::Compatible => "compatible",
+        };
+        write!(f, "{severity} {}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Default)]
+struct Report(Vec<Change>);
+
+impl Report {
+    fn breaking(&mut self, path: &str, message: impl Into<String>) {
+        self.0.push(Change {
+            severity: Severity::Breaking,
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+
+    fn compatible(&mut self, path: &str, message: impl Into<String>) {
+        self.0.push(Change {
+            severity: Severity::Compatible,
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+}
+
+/// Every change from `old` to `new`, in declaration order.
+fn diff(old: &Schema, new: &Schema) -> Vec<Change> {
+    let mut report = Report::default();
+
+    if let Some(before) = &old.root_type {
+        if new.root_type.as_deref().map(base_name) != Some(base_name(before)) {
+            report.breaking(
+                "schema",
+                format!(
+                    "root_type changed from {before} to {}",
+                    show(&new.root_type)
+                ),
+            );
+        }
+    }
+    if let Some(before) = &old.file_identifier {
+        if new.file_identifier.as_ref() != Some(before) {
+            report.breaking(
+                "schema",
+                format!(
+                    "file_identifier changed from \"{before}\" to {}",
+                    show(&new.file_identifier)
+                ),
+            );
+        }
+    }
+
+    for before in &old.decls {
+        let path = before.name.as_str();
+        let Some(after) = new.decls.iter().find(|d| d.name == before.name) else {
+            report.breaking(path, format!("{} removed", before.kind));
+            continue;
+        };
+        if after.kind != before.kind {
+            report.breaking(
+                path,
+                format!("changed from {} to {}", before.kind, after.kind),
+            );
+            continue;
+        }
+        if after.namespace != before.namespace {
+            report.breaking(
+                path,
+                format!(
+                    "moved from namespace {} to {}; generated code paths change",
+                    before.namespace, after.namespace
+                ),
+            );
+        }
+        match before.kind {
+            Kind::Table => diff_table(path, before, after, &mut report),
+            Kind::Struct => diff_struct(path, before, after, &mut report),
+            Kind::Enum | Kind::Union => diff_members(path, before, after, &mut report),
+        }
+    }
+    for after in &new.decls {
+        if !old.decls.iter().any(|d| d.name == after.name) {
+            report.compatible(&after.name, format!("{} added", after.kind));
+        }
+    }
+    report.0
+}
+
+fn show(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "none".to_string())
+}
+
+/// Whether two defaults read the same. An absent default is 0 / false.
+fn same_default(a: &Option<String>, b: &Option<String>) -> bool {
+    fn normalize(default: &Option<String>) -> String {
+        match default.as_deref() {
+            None | Some("false") => "0".to_string(),
+            Some("true") => "1".to_string(),
+            Some(value) => value
+                .parse::<f64>()
+                .map_or_else(|_| value.to_string(), |n| n.to_string()),
+        }
+    }
+    normalize(a) == normalize(b)
+}
+
+fn find<'a>(fields: &'a [Field], name: &str) -> Option<&'a Field> {
+    fields.iter().find(|f| f.name == name)
+}
+
+/// A field whose name is gone but whose id and type carry on under a new
+/// name: `(old, new)`.
+fn renamed<'a>(old: &'a Decl, new: &'a Decl) -> Vec<(&'a Field, &'a Field)> {
+    old.fields
+        .iter()
+        .filter(|o| find(&new.fields, &o.name).is_none())
+        .filter_map(|o| {
+            new.fields
+                .iter()
+                .find(|n| n.id == o.id && n.ty == o.ty && find(&old.fields, &n.name).is_none())
+                .map(|n| (o, n))
+        })
+        .collect()
+}
+
+fn diff_table(path: &str, old: &Decl, new: &Decl, report: &mut Report) {
+    let renames = renamed(old, new);
+
+    for before in &old.fields {
+        let field = format!("{path}.{}", before.name);
+        let Some(after) = find(&new.fields, &before.name) else {
+            if let Some((_, after)) = renames.iter().find(|(o, _)| o.name == before.name) {
+                report.compatible(
+                    &format!("{path}.{}", after.name),
+                    format!(
+                        "renamed from {} (same id and type; generated accessors change)",
+                        before.name
+                    ),
+                );
+            } else if before.deprecated {
+                report.compatible(&field, "deprecated field removed");
+            } else {
+                report.breaking(
+                    &field,
+                    "removed; mark it (deprecated) instead so later fields keep their ids",
+                );
+            }
+            continue;
+        };
+        if after.id != before.id {
+            report.breaking(
+                &field,
+                format!(
+                    "moved from id {} to {}; old readers read another field's slot",
+                    before.id, after.id
+                ),
+            );
+        }
+        if after.ty != before.ty {
+            report.breaking(
+                &field,
+                format!("type changed from {} to {}", before.ty, after.ty),
+            );
+        }
+        if !same_default(&before.default, &after.default) {
+            report.breaking(
+                &field,
+                format!(
+                    "default changed from {} to {}; absent values read differently on each side",
+                    show(&before.default),
+                    show(&after.default)
+                ),
+            );
+        }
+        if after.deprecated && !before.deprecated {
+            report.compatible(&field, "deprecated; old readers get its default");
+        }
+    }
+
+    let last_id = old.fields.iter().map(|f| f.id).max();
+    for after in &new.fields {
+        if find(&old.fields, &after.name).is_some()
+            || renames.iter().any(|(_, n)| n.name == after.name)
+        {
+            continue;
+        }
+        let field = format!("{path}.{}", after.name);
+        if last_id.is_some_and(|last| after.id <= last) {
+            let taken = old
+                .fields
+                .iter()
+                .find(|f| f.id == after.id)
+                .map_or("another field", |f| f.name.as_str());
+            report.breaking(
+                &field,
+                format!(
+                    "added at id {}, which old readers use for {taken}",
+                    after.id
+                ),
+            );
+        } else {
+            report.compatible(&field, format!("added at id {}", after.id));
+        }
+    }
+}
+
+fn diff_struct(path: &str, old: &Decl, new: &Decl, report: &mut Report) {
+    let layout = |decl: &Decl| {
+        decl.fields
+            .iter()
+            .map(|f| f.ty.clone())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if layout(old) != layout(new) {
+        report.breaking(
+            path,
+            format!(
+                "layout changed from ({}) to ({}); structs are stored inline",
+                layout(old),
+                layout(new)
+            ),
+        );
+        return;
+    }
+    for (before, after) in old.fields.iter().zip(&new.fields) {
+        if before.name == after.name {
+            continue;
+        }
+        if find(&new.fields, &before.name).is_some() {
+            report.breaking(
+                &format!("{path}.{}", before.name),
+                "moved to another offset; old readers read another field's bytes",
+            );
+        } else {
+            report.compatible(
+                &format!("{path}.{}", after.name),
+                format!("renamed from {}", before.name),
+            );
+        }
+    }
+}
+
+fn diff_members(path: &str, old: &Decl, new: &Decl, report: &mut Report) {
+    let (term, effect) = match old.kind {
+        Kind::Union => ("member", "old readers decode it as a different type"),
+        _ => ("value", "old readers see a different value"),
+    };
+    if old.underlying != new.underlying {
+        report.breaking(
+            path,
+            format!(
+                "underlying type changed from {} to {}",
+                show(&old.underlying),
+                show(&new.underlying)
+            ),
+        );
+    }
+
+    let by_name =
+        |members: &[Member], name: &str| members.iter().find(|m| m.name == name).map(|m| m.value);
+    let by_value = |members: &[Member], value: i64| {
+        members
+            .iter()
+            .find(|m| m.value == value)
+            .map(|m| m.name.clone())
+    };
+
+    for before in &old.members {
+        let member = format!("{path}.{}", before.name);
+        match by_name(&new.members, &before.name) {
+            Some(value) if value != before.value => {
+                report.breaking(
+                    &member,
+                    format!("{term} changed from {} to {value}; {effect}", before.value),
+                );
+            }
+            Some(_) => {}
+            None => match by_value(&new.members, before.value) {
+                Some(name) if by_name(&old.members, &name).is_none() => {
+                    report.compatible(
+                        &format!("{path}.{name}"),
+                        format!("renamed from {}", before.name),
+                    );
+                }
+                _ => report.breaking(
+                    &member,
+                    format!(
+                        "{term} {} removed; old data using it no longer decodes",
+                        before.value
+                    ),
+                ),
+            },
+        }
+    }
+    for after in &new.members {
+        if by_name(&old.members, &after.name).is_some() {
+            continue;
+        }
+        let renamed = by_value(&old.members, after.value)
+            .is_some_and(|name| by_name(&new.members, &name).is_none());
+        if !renamed {
+            report.compatible(
+                &format!("{path}.{}", after.name),
+                format!(
+                    "{term} added as {}; old readers see an unknown {term}",
+                    after.value
+                ),
+            );
+        }
+    }
+}
+
+// ============================================
+// CLI
+// ============================================
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))
+}
+
+fn read_schema(path: &str) -> Result<Schema, String> {
+    parse(&read(path)?).map_err(|e| format!("{path}: {e}"))
+}
+
+fn compare(old: &str, new: &str) -> ExitCode {
+    let (old, new) = match (read_schema(old), read_schema(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+    let changes = diff(&old, &new);
+    for change in &changes {
+        println!("{change}");
+    }
+    let breaking = changes
+        .iter()
+        .filter(|c| c.severity == Severity::Breaking)
+        .count();
+    if changes.is_empty() {
+        println!("no changes");
+    } else {
+        println!(
+            "{breaking} breaking, {} compatible",
+            changes.len() - breaking
+        );
+    }
+    if breaking > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn hash(paths: &[&str]) -> ExitCode {
+    for path in paths {
+        match read(path) {
+            Ok(src) => println!("{:08x}  {path}", my_shared::schema::schema_hash(&src)),
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["hash", paths @ ..] if !paths.is_empty() => hash(paths),
+        [old, new] => compare(old, new),
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERBOOK: &str = r#"
+        namespace OrgAsm.Orderbook;
+        struct PriceLevel { price: double; size: double; }
+        table OrderbookFrame {
+          best_bid: double;
+          bids: [PriceLevel];
+          imbalance: double;        // precomputed
+          is_delta: bool = false;
+        }
+        root_type OrderbookFrame;
+        file_identifier "OAOB";
+    "#;
+
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        diff(&parse(old).unwrap(), &parse(new).unwrap())
+            .iter()
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    fn breaking(old: &str, new: &str) -> usize {
+        diff(&parse(old).unwrap(), &parse(new).unwrap())
+            .iter()
+            .filter(|c| c.severity == Severity::Breaking)
+            .count()
+    }
+
+    #[test]
+    fn test_parse() {
+        let schema = parse(ORDERBOOK).unwrap();
+        assert_eq!(schema.root_type.as_deref(), Some("OrderbookFrame"));
+        assert_eq!(schema.file_identifier.as_deref(), Some("OAOB"));
+        let frame = &schema.decls[1];
+        assert_eq!(frame.namespace, "OrgAsm.Orderbook");
+        assert_eq!(
+            frame.fields.iter().map(|f| f.id).collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+        assert_eq!(frame.fields[1].ty, "[PriceLevel]");
+        assert_eq!(frame.fields[3].default.as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("table T { a: double }").unwrap_err().line, 1);
+        assert_eq!(parse("table T {\n  a double;\n}").unwrap_err().line, 2);
+        assert!(parse("/* open").is_err());
+        assert!(parse("message T {}").is_err());
+    }
+
+    #[test]
+    fn test_union_fields_take_two_slots() {
+        let schema =
+            parse("union U { A, B } table A {} table B {} table T { a: int; u: U; b: int; }")
+                .unwrap();
+        let table = schema.decls.iter().find(|d| d.name == "T").unwrap();
+        assert_eq!(
+            table.fields.iter().map(|f| f.id).collect::<Vec<_>>(),
+            [0, 2, 3]
+        );
+        let union = &schema.decls[0];
+        assert_eq!(
+            union.members.iter().map(|m| m.value).collect::<Vec<_>>(),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn test_identical_and_comment_only_changes() {
+        assert!(changes(ORDERBOOK, ORDERBOOK).is_empty());
+        let commented = ORDERBOOK.replace("// precomputed", "// (bid - ask) / (bid + ask)");
+        assert!(changes(ORDERBOOK, &commented).is_empty());
+    }
+
+    #[test]
+    fn test_reordered_fields_break() {
+        let reordered = ORDERBOOK.replace(
+            "imbalance: double;        // precomputed\n          is_delta: bool = false;",
+            "is_delta: bool = false;\n          imbalance: double;",
+        );
+        let found = changes(ORDERBOOK, &reordered);
+        assert_eq!(found.len(), 2, "{found:?}");
+        assert!(found[0].starts_with("BREAKING   OrderbookFrame.imbalance: moved from id 2 to 3"));
+    }
+
+    #[test]
+    fn test_appended_field_is_compatible() {
+        let appended = ORDERBOOK.replace(
+            "is_delta: bool = false;",
+            "is_delta: bool = false;\n spread_bps: double;",
+        );
+        assert_eq!(
+            changes(ORDERBOOK, &appended),
+            ["compatible OrderbookFrame.spread_bps: added at id 4"]
+        );
+    }
+
+    #[test]
+    fn test_field_changes() {
+        let inserted =
+            ORDERBOOK.replace("best_bid: double;", "best_bid: double; best_ask: double;");
+        assert!(changes(ORDERBOOK, &inserted)
+            .iter()
+            .any(|c| c.contains("added at id 1, which old readers use for bids")));
+
+        let removed = ORDERBOOK.replace("imbalance: double;", "");
+        assert_eq!(breaking(ORDERBOOK, &removed), 2); // imbalance removed, is_delta moved
+        let deprecated = ORDERBOOK.replace("imbalance: double;", "imbalance: double (deprecated);");
+        assert_eq!(breaking(ORDERBOOK, &deprecated), 0);
+
+        let retyped = ORDERBOOK.replace("imbalance: double;", "imbalance: float;");
+        assert_eq!(
+            changes(ORDERBOOK, &retyped),
+            ["BREAKING   OrderbookFrame.imbalance: type changed from double to float"]
+        );
+        let redefaulted = ORDERBOOK.replace("bool = false", "bool = true");
+        assert_eq!(breaking(ORDERBOOK, &redefaulted), 1);
+        let same_default = ORDERBOOK.replace("bool = false", "bool");
+        assert!(changes(ORDERBOOK, &same_default).is_empty());
+
+        let renamed = ORDERBOOK.replace("imbalance:", "book_imbalance:");
+        assert_eq!(
+            changes(ORDERBOOK, &renamed),
+            ["compatible OrderbookFrame.book_imbalance: renamed from imbalance (same id and type; generated accessors change)"]
+        );
+    }
+
+    #[test]
+    fn test_explicit_ids() {
+        let old = "table T { a: int (id: 0); b: int (id: 1); }";
+        assert!(changes(old, "table T { b: int (id: 1); a: int (id: 0); }").is_empty());
+        assert_eq!(
+            breaking(old, "table T { b: int (id: 0); a: int (id: 1); }"),
+            2
+        );
+    }
+
+    #[test]
+    fn test_struct_changes_break() {
+        let resized = ORDERBOOK.replace("size: double; }", "size: double; count: uint32; }");
+        assert_eq!(breaking(ORDERBOOK, &resized), 1);
+        // Same layout, but each name now reads the other's bytes
+        let swapped = ORDERBOOK.replace(
+            "price: double; size: double;",
+            "size: double; price: double;",
+        );
+        assert_eq!(breaking(ORDERBOOK, &swapped), 2);
+        let renamed = ORDERBOOK.replace("size: double; }", "quantity: double; }");
+        assert_eq!(
+            changes(ORDERBOOK, &renamed),
+            ["compatible PriceLevel.quantity: renamed from size"]
+        );
+    }
+
+    #[test]
+    fn test_union_members() {
+        let old = "union Command { Subscribe, Unsubscribe, Snapshot }";
+        let reordered = changes(old, "union Command { Subscribe, Snapshot, Unsubscribe }");
+        assert_eq!(
+            reordered,
+            [
+                "BREAKING   Command.Unsubscribe: member changed from 2 to 3; old readers decode it as a different type",
+                "BREAKING   Command.Snapshot: member changed from 3 to 2; old readers decode it as a different type",
+            ]
+        );
+        assert_eq!(breaking(old, "union Command { Subscribe, Unsubscribe }"), 1);
+        assert_eq!(
+            breaking(
+                old,
+                "union Command { Subscribe, Unsubscribe, Snapshot, Ping }"
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn test_enum_values() {
+        let old = "enum ErrorCode : ushort { None = 0, InvalidSymbol, NotSubscribed }";
+        assert_eq!(
+            breaking(
+                old,
+                "enum ErrorCode : ushort { None = 0, NotSubscribed, InvalidSymbol }"
+            ),
+            2
+        );
+        assert_eq!(
+            breaking(
+                old,
+                "enum ErrorCode : ubyte { None = 0, InvalidSymbol, NotSubscribed }"
+            ),
+            1
+        );
+        assert_eq!(
+            breaking(
+                old,
+                "enum ErrorCode : ushort { None = 0, InvalidSymbol, NotSubscribed, Forbidden }"
+            ),
+            0
+        );
+        assert_eq!(
+            changes(
+                old,
+                "enum ErrorCode : ushort { None = 0, BadSymbol, NotSubscribed }"
+            ),
+            ["compatible ErrorCode.BadSymbol: renamed from InvalidSymbol"]
+        );
+        assert_eq!(
+            breaking(
+                old,
+                "enum ErrorCode : ushort { None = 0, InvalidSymbol = 0x10, NotSubscribed }"
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn test_schema_level_changes() {
+        let moved = ORDERBOOK.replace(
+            "root_type OrderbookFrame;",
+            "table Other {} root_type Other;",
+        );
+        let found = changes(ORDERBOOK, &moved);
+        assert!(found.contains(
+            &"BREAKING   schema: root_type changed from OrderbookFrame to Other".to_string()
+        ));
+        assert!(found.contains(&"compatible Other: table added".to_string()));
+        let removed = ORDERBOOK.replace("struct PriceLevel { price: double; size: double; }", "");
+        assert!(changes(ORDERBOOK, &removed)
+            .contains(&"BREAKING   PriceLevel: struct removed".to_string()));
+    }
+
+    #[test]
+    fn test_repo_schemas_parse() {
+        // Every schema in schema/ must parse and compare equal to itself
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../schema");
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return;
+        };
+        for entry in entries
+            .flatten()
+            .filter(|e| e.path().extension().is_some_and(|x| x == "fbs"))
+        {
+            let src = std::fs::read_to_string(entry.path()).unwrap();
+            let schema = parse(&src).unwrap_or_else(|e| panic!("{}: {e}", entry.path().display()));
+            assert!(diff(&schema, &schema).is_empty());
+        }
+    }
+}
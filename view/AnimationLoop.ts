@@ -3,7 +3,8 @@
  *
  * Architecture:
  * 1. Call engine.tick(now) — ONE WASM call per frame
- * 2. Distribute frame data to registered consumers
+ * 2. Distribute frame data to registered consumers, with the engine's
+ *    change mask (engine.changeMask) so they can skip unchanged fields
  * 3. requestAnimationFrame for next frame
  *
  * The pattern here is "single tick, fan-out": the engine is the sole source of truth,
@@ -27,14 +28,22 @@
  */
 
 import type { IFrameConsumer } from '../core/interfaces';
+import { ALL_FIELDS_CHANGED } from '../core/FrameBuffer';
+
+/** What the loop ticks: flatBufferTickAdapter() or any { tick(nowMs): F }. */
+interface TickSource<F> {
+  tick(nowMs: number): F;
+  /** Fields changed by the last tick(); every field when absent. */
+  readonly changeMask?: number;
+}
 
 export class AnimationLoop<F = Float64Array> {
   private _running = false;
   private frameId: number | null = null;
   private consumers: IFrameConsumer<F>[] = [];
-  private engine: { tick(nowMs: number): F };
+  private engine: TickSource<F>;
 
-  constructor(engine: { tick(nowMs: number): F }) {
+  constructor(engine: TickSource<F>) {
     this.engine = engine;
   }
 
@@ -60,9 +69,10 @@ export class AnimationLoop<F = Float64Array> {
 
       const nowMs = Date.now();
       const frame = this.engine.tick(nowMs);
+      const changed = this.engine.changeMask ?? ALL_FIELDS_CHANGED;
 
       for (const consumer of this.consumers) {
-        consumer.onFrame(frame, nowMs);
+        consumer.onFrame(frame, nowMs, changed);
       }
 
       this.frameId = requestAnimationFrame(animate);
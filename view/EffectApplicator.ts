@@ -25,6 +25,13 @@
  * - Transform: Computes a transform string from a frame value (e.g., shake -> translate)
  * - Conditional: Switches between binding sets based on a boolean extractor
  *
+ * Skipping unchanged fields: engines that return a change mask from tick()
+ * (see flatBufferTickAdapter) tell the applicator which frame fields moved.
+ * A binding declared with dependsOn(...fields) is only applied on frames
+ * where one of its fields changed, so idle elements cost no DOM writes.
+ * Bindings without dependsOn are applied every frame. After bind() or a new
+ * binding, the next frame is applied in full.
+ *
  * Usage:
  *   const effects = new EffectApplicator<MyFrame>();
  *   effects.bindCSSProperty('root', '--vignette-alpha', f => f.vigAlpha);
@@ -33,12 +40,14 @@
  *     const sy = (Math.random() - 0.5) * 2 * v;
  *     return `translate(${sx}px, ${sy}px)`;
  *   });
+ *   effects.bindStyle('bar', 'opacity', f => f.valueB()).dependsOn(FrameField.ValueB);
  *   effects.bind('root', document.getElementById('app')!);
  *   // Then in animation loop: effects.onFrame(frame, nowMs);
  */
 
 import type { IFrameConsumer } from '../core/interfaces';
 import type { CSSEffect, FieldExtractor, BoolExtractor } from '../core/types';
+import { ALL_FIELDS_CHANGED } from '../core/FrameBuffer';
 
 /** A binding that sets a CSS custom property via element.style.setProperty() */
 interface CSSPropertyBinding<F> {
@@ -47,6 +56,8 @@ interface CSSPropertyBinding<F> {
   property: string;
   extract: FieldExtractor<F>;
   format?: (value: number) => string;
+  /** Change mask of the frame fields read; unset: applied every frame */
+  fields?: number;
 }
 
 /** A binding that sets an inline style property via direct assignment */
@@ -56,6 +67,8 @@ interface StylePropertyBinding<F> {
   property: string;
   extract: FieldExtractor<F>;
   format?: (value: number) => string;
+  /** Change mask of the frame fields read; unset: applied every frame */
+  fields?: number;
 }
 
 /** A binding that computes a CSS transform from a frame value */
@@ -65,6 +78,8 @@ interface TransformBinding<F> {
   extract: FieldExtractor<F>;
   compute: (value: number) => string;
   threshold?: number;
+  /** Change mask of the frame fields read; unset: applied every frame */
+  fields?: number;
 }
 
 /** A binding that switches between sub-bindings based on a boolean extractor */
@@ -73,6 +88,8 @@ interface ConditionalBinding<F> {
   flagExtract: BoolExtractor<F>;
   onTrue: SimpleBinding<F>[];
   onFalse?: SimpleBinding<F>[];
+  /** Change mask of the flag's fields: when one changes, the active branch is applied in full */
+  fields?: number;
 }
 
 /** Any non-conditional binding (used inside ConditionalBinding) */
@@ -85,10 +102,13 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
   readonly priority = 10;
   private elements = new Map<string, HTMLElement>();
   private bindings: Binding<F>[] = [];
+  /** Apply every binding on the next frame, whatever changed. */
+  private stale = true;
 
   /** Bind a named DOM element for effect application. */
   bind(name: string, element: HTMLElement): void {
     this.elements.set(name, element);
+    this.stale = true;
   }
 
   /** Remove a named element binding. */
@@ -111,6 +131,7 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
    */
   bindCSSProperty(elementName: string, property: string, extract: FieldExtractor<F>, format?: (v: number) => string): this {
     this.bindings.push({ type: 'css', elementName, property, extract, format });
+    this.stale = true;
     return this;
   }
 
@@ -128,6 +149,7 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
    */
   bindStyle(elementName: string, property: string, extract: FieldExtractor<F>, format?: (v: number) => string): this {
     this.bindings.push({ type: 'style', elementName, property, extract, format });
+    this.stale = true;
     return this;
   }
 
@@ -147,6 +169,7 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
    */
   bindTransform(elementName: string, extract: FieldExtractor<F>, compute: (v: number) => string, threshold = 0): this {
     this.bindings.push({ type: 'transform', elementName, extract, compute, threshold });
+    this.stale = true;
     return this;
   }
 
@@ -164,6 +187,28 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
    */
   bindConditional(flagExtract: BoolExtractor<F>, onTrue: SimpleBinding<F>[], onFalse?: SimpleBinding<F>[]): this {
     this.bindings.push({ type: 'conditional', flagExtract, onTrue, onFalse });
+    this.stale = true;
+    return this;
+  }
+
+  /**
+   * Declare the frame fields the most recently added binding reads.
+   *
+   * Fields are the engine's field numbers (its FrameField enum); the
+   * binding is then skipped on frames whose change mask has none of their
+   * bits set. For a conditional binding, these are the flag's fields; its
+   * branch bindings can carry their own `fields` mask.
+   *
+   * @param fields - Frame field numbers, 0..31
+   * @returns this, for fluent chaining
+   * @throws Error if no binding has been added yet
+   */
+  dependsOn(...fields: number[]): this {
+    const binding = this.bindings[this.bindings.length - 1];
+    if (!binding) {
+      throw new Error('dependsOn() must follow a bind*() call');
+    }
+    binding.fields = fields.reduce((mask, field) => mask | (1 << field), 0);
     return this;
   }
 
@@ -181,14 +226,25 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
     return effects;
   }
 
-  /** Apply all bindings for a frame. Called at 60fps by the animation loop. */
-  onFrame(frame: F, _nowMs: number): void {
+  /**
+   * Apply the bindings for a frame. Called at 60fps by the animation loop,
+   * which passes the engine's change mask; bindings whose fields didn't
+   * change are skipped.
+   */
+  onFrame(frame: F, _nowMs: number, changed: number = ALL_FIELDS_CHANGED): void {
+    if (this.stale) {
+      changed = ALL_FIELDS_CHANGED;
+      this.stale = false;
+    }
     for (const binding of this.bindings) {
-      this.applyBinding(binding, frame);
+      this.applyBinding(binding, frame, changed);
     }
   }
 
-  private applyBinding(binding: Binding<F>, frame: F): void {
+  private applyBinding(binding: Binding<F>, frame: F, changed: number): void {
+    if (binding.type !== 'conditional' && binding.fields !== undefined && (binding.fields & changed) === 0) {
+      return;
+    }
     switch (binding.type) {
       case 'css': {
         const el = this.elements.get(binding.elementName);
@@ -222,8 +278,10 @@ export class EffectApplicator<F = Float64Array> implements IFrameConsumer<F> {
       case 'conditional': {
         const flag = binding.flagExtract(frame);
         const activeBindings = flag ? binding.onTrue : (binding.onFalse ?? []);
+        // The flag may have flipped: the branch it switched to is applied in full
+        const flagChanged = binding.fields === undefined || (binding.fields & changed) !== 0;
         for (const b of activeBindings) {
-          this.applyBinding(b, frame);
+          this.applyBinding(b, frame, flagChanged ? ALL_FIELDS_CHANGED : changed);
         }
         break;
       }
@@ -20,9 +20,10 @@
  */
 
 import type { IAnimationLoop, IFrameConsumer } from '../core/interfaces';
+import { ALL_FIELDS_CHANGED } from '../core/FrameBuffer';
 
 interface EngineSlot {
-  engine: { tick(nowMs: number): unknown };
+  engine: { tick(nowMs: number): unknown; readonly changeMask?: number };
   consumers: IFrameConsumer<unknown>[];
 }
 
@@ -84,7 +85,7 @@ export class MultiAnimationLoop {
   private _slots: EngineSlot[] = [];
 
   /** Register an engine tick source. Returns a handle for managing per-engine consumers. */
-  addEngine<F>(engine: { tick(nowMs: number): F }): EngineHandle<F> {
+  addEngine<F>(engine: { tick(nowMs: number): F; readonly changeMask?: number }): EngineHandle<F> {
     const slot: EngineSlot = { engine, consumers: [] };
     this._slots.push(slot);
     return new EngineHandle<F>(this, slot);
@@ -108,8 +109,9 @@ export class MultiAnimationLoop {
 
       for (const slot of this._slots) {
         const frame = slot.engine.tick(nowMs);
+        const changed = slot.engine.changeMask ?? ALL_FIELDS_CHANGED;
         for (const consumer of slot.consumers) {
-          consumer.onFrame(frame, nowMs);
+          consumer.onFrame(frame, nowMs, changed);
         }
       }
 
@@ -0,0 +1,140 @@
+import { EffectApplicator } from '../EffectApplicator';
+import { ALL_FIELDS_CHANGED } from '../../core/FrameBuffer';
+
+interface TestFrame {
+  a: number;
+  b: number;
+  flag: boolean;
+}
+
+/** The engine's FrameField numbers for TestFrame. */
+const Field = { A: 0, B: 1, Flag: 2 } as const;
+const changed = (...fields: number[]) => fields.reduce((mask, f) => mask | (1 << f), 0);
+
+function frame(a: number, b: number, flag = false): TestFrame {
+  return { a, b, flag };
+}
+
+function setup() {
+  const element = document.createElement('div');
+  const effects = new EffectApplicator<TestFrame>();
+  return { element, effects };
+}
+
+const prop = (element: HTMLElement, name: string) => element.style.getPropertyValue(name);
+
+describe('EffectApplicator change masks', () => {
+  it('applies bindings only when one of their fields changed', () => {
+    const { element, effects } = setup();
+    const onA = vi.fn((f: TestFrame) => f.a);
+    effects
+      .bindCSSProperty('el', '--a', onA)
+      .dependsOn(Field.A)
+      .bindCSSProperty('el', '--b', (f) => f.b)
+      .dependsOn(Field.B)
+      .bindCSSProperty('el', '--sum', (f) => f.a + f.b);
+    effects.bind('el', element);
+
+    // First frame after bind() is applied in full, whatever the mask
+    effects.onFrame(frame(1, 2), 0, 0);
+    expect([prop(element, '--a'), prop(element, '--b'), prop(element, '--sum')]).toEqual(['1', '2', '3']);
+
+    effects.onFrame(frame(5, 6), 16, changed(Field.B));
+    expect([prop(element, '--a'), prop(element, '--b'), prop(element, '--sum')]).toEqual(['1', '6', '11']);
+
+    effects.onFrame(frame(7, 8), 32, 0);
+    expect([prop(element, '--a'), prop(element, '--b'), prop(element, '--sum')]).toEqual(['1', '6', '15']);
+    expect(onA).toHaveBeenCalledTimes(1);
+
+    effects.onFrame(frame(7, 8), 48, changed(Field.A, Field.Flag));
+    expect(prop(element, '--a')).toBe('7');
+  });
+
+  it('applies a binding that depends on several fields when any of them changed', () => {
+    const { element, effects } = setup();
+    effects.bindStyle('el', 'opacity', (f) => f.a / 10).dependsOn(Field.A, Field.B);
+    effects.bind('el', element);
+    effects.onFrame(frame(1, 0), 0);
+
+    effects.onFrame(frame(5, 0), 16, changed(Field.Flag));
+    expect(element.style.opacity).toBe('0.1');
+    effects.onFrame(frame(5, 0), 32, changed(Field.B));
+    expect(element.style.opacity).toBe('0.5');
+  });
+
+  it('applies every binding when no mask is passed', () => {
+    const { element, effects } = setup();
+    effects.bindCSSProperty('el', '--a', (f) => f.a).dependsOn(Field.A);
+    effects.bind('el', element);
+    effects.onFrame(frame(1, 0), 0, 0);
+
+    effects.onFrame(frame(2, 0), 16);
+    expect(prop(element, '--a')).toBe('2');
+    effects.onFrame(frame(3, 0), 32, ALL_FIELDS_CHANGED);
+    expect(prop(element, '--a')).toBe('3');
+  });
+
+  it('applies the next frame in full after a new binding or element', () => {
+    const { element, effects } = setup();
+    effects.bindCSSProperty('el', '--a', (f) => f.a).dependsOn(Field.A);
+    effects.bind('el', element);
+    effects.onFrame(frame(1, 0), 0, 0);
+
+    effects.bindCSSProperty('el', '--b', (f) => f.b).dependsOn(Field.B);
+    effects.onFrame(frame(2, 3), 16, 0);
+    expect([prop(element, '--a'), prop(element, '--b')]).toEqual(['2', '3']);
+
+    const other = document.createElement('span');
+    effects.bind('other', other);
+    effects.onFrame(frame(4, 5), 32, 0);
+    expect(prop(element, '--a')).toBe('4');
+  });
+
+  it('throws when dependsOn() has no binding to attach to', () => {
+    const { effects } = setup();
+    expect(() => effects.dependsOn(Field.A)).toThrow('dependsOn() must follow a bind*() call');
+  });
+
+  describe('conditional bindings', () => {
+    function conditional() {
+      const { element, effects } = setup();
+      effects
+        .bindConditional(
+          (f) => f.flag,
+          [{ type: 'css', elementName: 'el', property: '--on', extract: (f) => f.a, fields: changed(Field.A) }],
+          [{ type: 'css', elementName: 'el', property: '--off', extract: (f) => f.b, fields: changed(Field.B) }],
+        )
+        .dependsOn(Field.Flag);
+      effects.bind('el', element);
+      effects.onFrame(frame(1, 2, false), 0, 0);
+      return { element, effects };
+    }
+
+    it('applies the active branch by its own fields while the flag is unchanged', () => {
+      const { element, effects } = conditional();
+      expect([prop(element, '--on'), prop(element, '--off')]).toEqual(['', '2']);
+
+      effects.onFrame(frame(3, 4, false), 16, changed(Field.A));
+      expect([prop(element, '--on'), prop(element, '--off')]).toEqual(['', '2']);
+
+      effects.onFrame(frame(3, 4, false), 32, changed(Field.B));
+      expect(prop(element, '--off')).toBe('4');
+    });
+
+    it('applies the branch the flag flipped to in full', () => {
+      const { element, effects } = conditional();
+
+      // Only the flag changed, but the true branch hasn't been applied yet
+      effects.onFrame(frame(5, 2, true), 16, changed(Field.Flag));
+      expect([prop(element, '--on'), prop(element, '--off')]).toEqual(['5', '2']);
+
+      effects.onFrame(frame(6, 7, true), 32, changed(Field.B));
+      expect(prop(element, '--on')).toBe('5');
+      effects.onFrame(frame(8, 7, true), 48, changed(Field.A));
+      expect(prop(element, '--on')).toBe('8');
+
+      effects.onFrame(frame(8, 9, false), 64, changed(Field.Flag));
+      expect([prop(element, '--on'), prop(element, '--off')]).toEqual(['8', '9']);
+    });
+  });
+});